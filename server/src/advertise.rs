@@ -0,0 +1,171 @@
+//! Announce the server on the local network via mDNS, as `_zpl-print._tcp`.
+//!
+//! Clients such as kiosks can then find the server without a configured URL. The TXT records list
+//! the printers with their label sizes, such that a client can also pick a printer. Each record
+//! holds at most 255 bytes, printers that do not fit are left out of the records.
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+use crate::configuration::{AdvertiseConfiguration, Configuration};
+
+const SERVICE_TYPE: &str = "_zpl-print._tcp.local.";
+
+/// The longest TXT record, as `key=value`.
+const TXT_MAX: usize = 255;
+
+/// A registered service, withdrawn again when dropped.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Advertisement {
+    pub fn register(
+        advertise: &AdvertiseConfiguration,
+        configuration: &Configuration,
+        port: u16,
+    ) -> anyhow::Result<Self> {
+        let name = advertise.name.as_deref().unwrap_or("zpl-print");
+        let machine = hostname::get()
+            .map(|machine| machine.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let host = host_name(&machine, name);
+        let properties = properties(configuration);
+
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            name,
+            &host,
+            "",
+            port,
+            &properties[..],
+        )?
+        .enable_addr_auto();
+
+        let fullname = service.get_fullname().to_string();
+        let daemon = ServiceDaemon::new()?;
+        daemon.register(service)?;
+
+        Ok(Advertisement { daemon, fullname })
+    }
+}
+
+/// The mDNS host name of the machine, or of the instance if the machine's is unknown.
+fn host_name(machine: &str, instance: &str) -> String {
+    let label = |name: &str| {
+        name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .take(63)
+            .collect::<String>()
+    };
+
+    // The machine may know its name qualified by its domain.
+    let machine = machine.split('.').next().unwrap_or_default();
+    let host = match label(machine.trim()) {
+        host if host.trim_matches('-').is_empty() => label(instance),
+        host => host,
+    };
+
+    format!("{host}.local.")
+}
+
+/// The TXT records: where the API is, the printers, and the label size of each.
+fn properties(configuration: &Configuration) -> Vec<(String, String)> {
+    let fits = |key: &str, value: &str| key.len() + 1 + value.len() <= TXT_MAX;
+
+    let mut names: Vec<_> = configuration.printers.keys().collect();
+    names.sort();
+
+    // As many printers as fit, rather than a name cut off.
+    let mut printers = String::new();
+    for name in &names {
+        let listed = match printers.is_empty() {
+            true => name.to_string(),
+            false => format!("{printers},{name}"),
+        };
+
+        if !fits("printers", &listed) {
+            tracing::warn!("Not all printers fit into the mDNS TXT records");
+            break;
+        }
+        printers = listed;
+    }
+
+    let mut properties = vec![
+        ("path".to_string(), "/api/v1".to_string()),
+        ("printers".to_string(), printers),
+    ];
+
+    for name in names {
+        let printer = &configuration.printers[name];
+        let Some(label) = configuration.labels.get(&printer.label) else {
+            continue;
+        };
+
+        let dimensions = &label.dimensions;
+        let key = format!("printer.{name}");
+        let value = format!("{}x{}mm", dimensions.width, dimensions.height);
+        if fits(&key, &value) {
+            properties.push((key, value));
+        }
+    }
+
+    properties
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        if let Err(error) = self.daemon.unregister(&self.fullname) {
+            tracing::warn!("Failed to withdraw mDNS advertisement: {error}");
+        }
+
+        let _ = self.daemon.shutdown();
+    }
+}
+
+#[test]
+fn records_fit() {
+    let long = "p".repeat(200);
+    let configuration: Configuration =
+        serde_json::from_value(serde_json::json!({
+            "labels": {
+                "51mm": {
+                    "dimensions": {
+                        "width": 51.0,
+                        "height": 25.0,
+                        "margin_left": 1.0,
+                        "margin_right": 1.0,
+                        "margin_top": 1.0,
+                        "margin_bottom": 1.0
+                    }
+                }
+            },
+            "printers": {
+                "front": { "label": "51mm", "addr": "127.0.0.1:9100" },
+                "back": { "label": "51mm", "addr": "127.0.0.1:9101" },
+                long.clone(): { "label": "51mm", "addr": "127.0.0.1:9102" },
+                "x".repeat(300): { "label": "51mm", "addr": "127.0.0.1:9103" },
+            }
+        }))
+        .unwrap();
+
+    let properties = properties(&configuration);
+    assert!(properties
+        .iter()
+        .all(|(key, value)| key.len() + 1 + value.len() <= TXT_MAX));
+
+    let value = |key: &str| {
+        let found = properties.iter().find(|(k, _)| k == key);
+        found.map(|(_, value)| value.as_str())
+    };
+    assert_eq!(
+        value("printers"),
+        Some(format!("back,front,{long}").as_str())
+    );
+    assert_eq!(value("printer.front"), Some("51x25mm"));
+    assert_eq!(value(&format!("printer.{long}")), Some("51x25mm"));
+    assert_eq!(properties.len(), 5);
+
+    assert_eq!(host_name("label-pc.example.org", "zpl"), "label-pc.local.");
+    assert_eq!(host_name("Shop Floor_2", "zpl"), "Shop-Floor-2.local.");
+    assert_eq!(host_name("", "zpl print"), "zpl-print.local.");
+}
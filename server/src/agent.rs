@@ -0,0 +1,281 @@
+//! An on-site agent printing jobs it pulls from a central server.
+//!
+//! Used for printers the server can not connect to, e.g. on sites behind NAT. The agent keeps a
+//! connection to each local printer and polls the server for jobs queued for it, reporting the
+//! result of each back.
+// The agent logs alike, but keeps no support bundles.
+#[allow(dead_code)]
+mod logs;
+mod pull_api;
+
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::Context as _;
+use clap::Parser;
+use tracing::{info, warn};
+
+use zpl::{
+    command::{CommandSequence, ResponseSpec, ZplCommand},
+    device::ZplPrinter,
+};
+
+use crate::pull_api::{PollQuery, PullResult, PulledJob};
+
+#[derive(clap::Parser)]
+struct Agent {
+    /// Base URL of the print server, e.g. `https://print.example.com`.
+    #[clap(long, env = "ZPL_AGENT_SERVER")]
+    server: reqwest::Url,
+
+    /// A printer to serve, as `name=address:port` with the name configured on the server.
+    #[clap(long = "printer", required = true, value_parser = parse_printer)]
+    printers: Vec<(String, SocketAddr)>,
+
+    /// Bearer token configured for the printers on the server.
+    #[clap(long, env = "ZPL_AGENT_TOKEN")]
+    token: Option<String>,
+
+    /// How to write log messages, as filtered by `RUST_LOG`.
+    #[clap(long, env = "ZPL_LOG_FORMAT", value_enum, default_value_t)]
+    log_format: logs::LogFormat,
+}
+
+fn parse_printer(arg: &str) -> Result<(String, SocketAddr), String> {
+    let (name, addr) = arg
+        .split_once('=')
+        .ok_or_else(|| "expected `name=address:port`".to_string())?;
+    let addr = addr.parse().map_err(|err| format!("{err}"))?;
+    Ok((name.to_string(), addr))
+}
+
+struct Serving {
+    client: reqwest::Client,
+    /// The base URL of the server, ending in a slash.
+    server: reqwest::Url,
+    token: Option<String>,
+    name: String,
+    addr: SocketAddr,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let agent = Agent::parse();
+    logs::init(agent.log_format);
+
+    let client = reqwest::Client::new();
+    let server = base_url(agent.server);
+    let mut serving = tokio::task::JoinSet::new();
+
+    for (name, addr) in agent.printers {
+        serving.spawn(
+            Serving {
+                client: client.clone(),
+                server: server.clone(),
+                token: agent.token.clone(),
+                name,
+                addr,
+            }
+            .run(),
+        );
+    }
+
+    while let Some(done) = serving.join_next().await {
+        done?;
+    }
+
+    Ok(())
+}
+
+/// The URL with a trailing slash, such that paths joined onto it are appended rather than
+/// replacing its last segment, as for a server behind a prefix.
+fn base_url(mut url: reqwest::Url) -> reqwest::Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url
+}
+
+impl Serving {
+    async fn run(self) {
+        let retry = Duration::from_secs(5);
+
+        loop {
+            if let Err(error) = self.serve_connection().await {
+                warn!("[{}]: {error:?}", self.name);
+            }
+
+            info!("[{}]: Reconnecting in {retry:?}", self.name);
+            tokio::time::sleep(retry).await;
+        }
+    }
+
+    /// Serve jobs until the printer connection fails.
+    async fn serve_connection(&self) -> anyhow::Result<()> {
+        let mut printer = ZplPrinter::with_address(self.addr).await?;
+        let dpmm = printer.request_device_status().await?.identification.dpmm;
+        info!("[{}]: Connected to printer at {}", self.name, self.addr);
+
+        loop {
+            let Some(job) = self.poll(dpmm).await? else {
+                continue;
+            };
+
+            info!("[{}]: Printing job {}", self.name, job.id);
+            let commands = CommandSequence(vec![ZplCommand::Raw {
+                command: job.zpl,
+                response: ResponseSpec::None,
+            }]);
+
+            let printed = printer.send(commands).await;
+            let result = match &printed {
+                Ok(()) => PullResult::Printed,
+                Err(error) => PullResult::Failed {
+                    reason: error.to_string(),
+                },
+            };
+
+            let reported = self
+                .report(job.id, &result)
+                .await
+                .with_context(|| format!("Failed to report job {}", job.id));
+
+            // The printer failing is what to recover from, by reconnecting to it.
+            if let Err(error) = printed {
+                if let Err(error) = reported {
+                    warn!("[{}]: {error:?}", self.name);
+                }
+                return Err(error.into());
+            }
+            reported?;
+        }
+    }
+
+    fn job_url(&self, path: &str) -> anyhow::Result<reqwest::Url> {
+        Ok(self
+            .server
+            .join(&format!("api/v1/agent/{}/job{path}", self.name))?)
+    }
+
+    async fn poll(&self, dpmm: u32) -> anyhow::Result<Option<PulledJob>> {
+        let query = PollQuery { dpmm: Some(dpmm) };
+        let response = self
+            .authorized(self.client.get(self.job_url("")?))
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        if response.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+
+        Ok(Some(response.json().await?))
+    }
+
+    async fn report(&self, id: u64, result: &PullResult) -> anyhow::Result<()> {
+        let url = self.job_url(&format!("/{id}"))?;
+        let response = self
+            .authorized(self.client.post(url))
+            .json(result)
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::GONE {
+            warn!("[{}]: Server no longer awaited job {id}", self.name);
+            return Ok(());
+        }
+
+        response.error_for_status()?;
+        Ok(())
+    }
+
+    fn authorized(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+#[cfg(test)]
+async fn mock_server(
+    status: axum::http::StatusCode,
+) -> (reqwest::Url, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+    use axum::{extract::Request, response::IntoResponse as _, routing::any};
+    use std::sync::{Arc, Mutex};
+
+    let requests = Arc::new(Mutex::new(vec![]));
+    let seen = requests.clone();
+    let app = axum::Router::new().fallback(any(move |request: Request| {
+        let line = format!("{} {}", request.method(), request.uri());
+        seen.lock().unwrap().push(line);
+        async move {
+            let polled = request.uri().path().ends_with("/job");
+            if polled && status == axum::http::StatusCode::OK {
+                let job = PulledJob {
+                    id: 7,
+                    zpl: "^XA^XZ".into(),
+                };
+                axum::Json(job).into_response()
+            } else {
+                status.into_response()
+            }
+        }
+    }));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let url = format!("http://{addr}/print").parse().unwrap();
+    (url, requests)
+}
+
+#[cfg(test)]
+fn serving(server: reqwest::Url) -> Serving {
+    Serving {
+        client: reqwest::Client::new(),
+        server: base_url(server),
+        token: Some("secret".into()),
+        name: "front".into(),
+        addr: "127.0.0.1:9100".parse().unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn polls_and_reports_below_the_base_url() {
+    let (url, requests) = mock_server(axum::http::StatusCode::OK).await;
+    let serving = serving(url);
+
+    let job = serving.poll(8).await.unwrap().unwrap();
+    assert_eq!((job.id, job.zpl.as_str()), (7, "^XA^XZ"));
+    serving.report(job.id, &PullResult::Printed).await.unwrap();
+
+    assert_eq!(
+        *requests.lock().unwrap(),
+        [
+            "GET /print/api/v1/agent/front/job?dpmm=8",
+            "POST /print/api/v1/agent/front/job/7",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn report_errors_are_returned() {
+    let (url, _) = mock_server(axum::http::StatusCode::NO_CONTENT).await;
+    assert!(serving(url).poll(8).await.unwrap().is_none());
+
+    // A job the server gave up on is no reason to reconnect.
+    let (url, _) = mock_server(axum::http::StatusCode::GONE).await;
+    assert!(serving(url).report(7, &PullResult::Printed).await.is_ok());
+
+    let (url, _) =
+        mock_server(axum::http::StatusCode::INTERNAL_SERVER_ERROR).await;
+    let serving = serving(url);
+    assert!(serving.poll(8).await.is_err());
+    assert!(serving.report(7, &PullResult::Printed).await.is_err());
+}
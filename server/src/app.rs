@@ -5,4 +5,8 @@ pub struct App {
 
     #[clap(long, env = "ZPL_CONFIGURATION", default_value = "server.json")]
     pub configuration: String,
+
+    /// How to write log messages, as filtered by `RUST_LOG`.
+    #[clap(long, env = "ZPL_LOG_FORMAT", value_enum, default_value_t)]
+    pub log_format: crate::logs::LogFormat,
 }
@@ -0,0 +1,140 @@
+//! A content-addressed store for submitted payloads and rendered output.
+//!
+//! Everything is stored once under its SHA-256 and referenced by that hash, e.g. from the job log.
+//! Storing the same content again refreshes it, and garbage collection removes what was not
+//! stored for longer than the configured retention.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+pub struct ArtifactStore {
+    root: PathBuf,
+    retention: Duration,
+}
+
+/// The hex encoded SHA-256 of an artifact.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct ArtifactId(pub String);
+
+pub fn digest(data: &[u8]) -> ArtifactId {
+    ArtifactId(format!("{:x}", Sha256::digest(data)))
+}
+
+impl ArtifactStore {
+    pub fn new(root: PathBuf, retention: Duration) -> Self {
+        ArtifactStore { root, retention }
+    }
+
+    pub async fn put(&self, data: &[u8]) -> std::io::Result<ArtifactId> {
+        let id = digest(data);
+        let path = self.path(&id).expect("digests are valid identifiers");
+        let data = data.to_vec();
+
+        // Neither touching a file nor persisting one has an asynchronous equivalent.
+        tokio::task::spawn_blocking(move || store(&path, &data))
+            .await
+            .map_err(std::io::Error::other)??;
+
+        Ok(id)
+    }
+
+    pub async fn get(
+        &self,
+        id: &ArtifactId,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let Some(path) = self.path(id) else {
+            return Ok(None);
+        };
+
+        match tokio::fs::read(path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Remove all artifacts not stored within the retention period.
+    pub async fn collect_garbage(&self) -> std::io::Result<usize> {
+        let cutoff = SystemTime::now() - self.retention;
+        let mut removed = 0;
+
+        let mut shards = match tokio::fs::read_dir(&self.root).await {
+            Ok(shards) => shards,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(0);
+            }
+            Err(err) => return Err(err),
+        };
+
+        while let Some(shard) = shards.next_entry().await? {
+            if !shard.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let mut entries = tokio::fs::read_dir(shard.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let modified = entry.metadata().await?.modified()?;
+
+                if modified < cutoff {
+                    tokio::fs::remove_file(entry.path()).await?;
+                    removed += 1;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Where an artifact lives, or `None` if the identifier is not a digest.
+    fn path(&self, id: &ArtifactId) -> Option<PathBuf> {
+        let hex = &id.0;
+        let is_digest = hex.len() == 64
+            && hex.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'));
+
+        if !is_digest {
+            return None;
+        }
+
+        Some(self.root.join(&hex[..2]).join(hex))
+    }
+}
+
+/// Write content to its path, or refresh it if already stored.
+fn store(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    if let Ok(file) = std::fs::File::options().append(true).open(path) {
+        return file.set_modified(SystemTime::now());
+    }
+
+    let dir = path.parent().expect("artifacts are stored in a shard");
+    std::fs::create_dir_all(dir)?;
+
+    // Write aside and rename, such that readers never see partial content.
+    let mut partial = tempfile::NamedTempFile::new_in(dir)?;
+    std::io::Write::write_all(&mut partial, data)?;
+    partial.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_and_collect() {
+    let dir = tempfile::tempdir().unwrap();
+    let store =
+        ArtifactStore::new(dir.path().to_owned(), Duration::from_secs(60));
+
+    let id = store.put(b"^XA^XZ").await.unwrap();
+    assert_eq!(id, store.put(b"^XA^XZ").await.unwrap());
+    assert_eq!(
+        store.get(&id).await.unwrap().as_deref(),
+        Some(&b"^XA^XZ"[..])
+    );
+    assert_eq!(store.get(&ArtifactId("../x".into())).await.unwrap(), None);
+
+    assert_eq!(store.collect_garbage().await.unwrap(), 0);
+    let expired = ArtifactStore::new(dir.path().to_owned(), Duration::ZERO);
+    assert_eq!(expired.collect_garbage().await.unwrap(), 1);
+    assert_eq!(store.get(&id).await.unwrap(), None);
+}
@@ -1,24 +1,339 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use std::{collections::HashMap, net::SocketAddr, path::Path, sync::Arc};
+use zpl::config::{merge_defined, Includes};
+pub use zpl::config::{
+    LabelCalibration, LabelDimensions, LabelStock, PrinterAddress,
+};
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 #[derive(Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Configuration {
+    /// Further configuration files whose labels and printers are merged into this one.
+    ///
+    /// Like the main file, these are read as TOML or YAML by their extension and as JSON
+    /// otherwise, with `${VAR}` in any string replaced by the environment variable. Relative
+    /// paths are resolved against the directory of the file naming them. Included files hold
+    /// nothing but `include`, `labels` and `printers`, and each label and printer name must be
+    /// defined exactly once across all files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    #[serde(default)]
     pub labels: HashMap<LabelIdentifier, Arc<Label>>,
+    #[serde(default)]
     pub printers: HashMap<String, Arc<LabelPrinter>>,
+    /// Append a record of every queued job to this file, as JSON lines.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub job_log: Option<PathBuf>,
+    /// Keep submitted content and rendered output in a content-addressed store.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub artifacts: Option<ArtifactConfiguration>,
+    /// Reload automatically when any of the configuration files changes.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub watch: bool,
+    /// Send e-mails about failed jobs and printers going down.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub notifications: Option<NotificationConfiguration>,
+    /// Announce the server on the local network.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub advertise: Option<AdvertiseConfiguration>,
+    /// Expose each printer as an IPP destination at `/ipp/<name>`, for system print dialogs.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub ipp: bool,
+    /// The largest document accepted via IPP, in bytes, 2 MiB by default.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub ipp_max_document: Option<usize>,
+    /// How many jobs may be rendered at once.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub rendering: RenderConfiguration,
+    /// Periodically test rendering and printers, and recover from failures without a restart.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfiguration>,
+    /// Print jobs submitted again within a while only once.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub idempotency: IdempotencyConfiguration,
+    /// Bearer token required by administrative endpoints, which are disabled without one.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Directories of fonts for SVG content, besides those installed on the system.
+    ///
+    /// Changes are picked up by `/api/v1/reload-fonts` as well as by a reload. Only honored in
+    /// the main configuration file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub font_directories: Vec<PathBuf>,
+    /// Set text only in the fonts of the configured directories, not in those installed.
+    ///
+    /// Replicas then render alike regardless of the fonts of their hosts. Only honored in the
+    /// main configuration file.
+    #[serde(default)]
+    pub exclude_system_fonts: bool,
+    /// Which images outside the document SVG content may refer to.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub svg_resources: SvgResourcePolicy,
+    /// The unit of lengths shown by the API and web interface, and given by jobs unless they name
+    /// another. Lengths are kept in mm regardless.
+    ///
+    /// Only allowed in the main configuration file.
+    #[serde(default)]
+    pub units: zpl::length::LengthUnit,
+    /// Minutes ahead of UTC of the site, for the dates of expiry labels.
+    ///
+    /// Daylight saving time is not followed, jobs printed elsewhere or in summer name their own
+    /// offset. Only allowed in the main configuration file.
+    #[serde(default)]
+    pub utc_offset: i32,
+    /// The templates jobs are made from, to keep render statistics of each.
+    ///
+    /// Jobs naming no template or another one are counted together, as ad hoc. Only honored in
+    /// the main configuration file.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub templates: HashSet<String>,
+    /// All files this configuration was loaded from, canonicalized.
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationConfiguration {
+    pub smtp: SmtpConfiguration,
+    /// The sender address of notifications.
+    pub from: String,
+    /// Recipients for printers which do not name their own.
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Named lists of addresses, which printers may refer to instead of addresses.
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfiguration {
+    pub host: String,
+    /// Defaults to the standard port of the chosen security.
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Implicit TLS, usually on port 465.
+    #[default]
+    Tls,
+    /// Upgrade a plain connection, usually on port 587.
+    StartTls,
+    /// Plain text, only for relays on a trusted network.
+    None,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArtifactConfiguration {
+    /// The directory to store artifacts in.
+    pub path: PathBuf,
+    /// Remove artifacts that were not stored again for this long.
+    pub retention: std::time::Duration,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdvertiseConfiguration {
+    /// The instance name shown to clients, `zpl-print` by default.
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Renders share a budget of `capacity`, each job taking the weight of its kind from it.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RenderConfiguration {
+    pub capacity: u32,
+    /// The share of the capacity taken by one job of each kind.
+    ///
+    /// Weights above the capacity are capped to it, such that those jobs render alone.
+    pub weights: RenderWeights,
+    /// How many rasterized content items to keep for jobs printing the same content, 0 to
+    /// render every job afresh.
+    pub cache: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RenderWeights {
+    pub svg: u32,
+    pub image: u32,
+}
+
+impl Default for RenderConfiguration {
+    fn default() -> Self {
+        RenderConfiguration {
+            capacity: 8,
+            weights: RenderWeights::default(),
+            cache: 64,
+        }
+    }
+}
+
+impl Default for RenderWeights {
+    fn default() -> Self {
+        RenderWeights { svg: 4, image: 1 }
+    }
+}
+
+/// A self-test renders a small label without printing it and queries each printer's status.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct WatchdogConfiguration {
+    /// The time between self-tests.
+    pub interval: std::time::Duration,
+    /// How long each part of a self-test may take before it counts as failed.
+    pub timeout: std::time::Duration,
+    /// Consecutive failures after which rendering is restarted or a printer is flagged.
+    pub failures: u32,
+}
+
+/// Repeated submissions of a job are answered with the response to the first one.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct IdempotencyConfiguration {
+    /// How long a submission is remembered.
+    pub window: std::time::Duration,
+    /// Take jobs of the same content for one printer as repeats, without an `Idempotency-Key`.
+    ///
+    /// Their options are not compared, nor are jobs which name a key.
+    pub hash_payload: bool,
+}
+
+impl Default for IdempotencyConfiguration {
+    fn default() -> Self {
+        IdempotencyConfiguration {
+            window: std::time::Duration::from_secs(300),
+            hash_payload: false,
+        }
+    }
+}
+
+impl Default for WatchdogConfiguration {
+    fn default() -> Self {
+        WatchdogConfiguration {
+            interval: std::time::Duration::from_secs(60),
+            timeout: std::time::Duration::from_secs(30),
+            failures: 3,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct Label {
     pub dimensions: LabelDimensions,
+    /// Regions of the stock that come pre-printed, such as logos, where nothing must be printed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclusion_zones: Vec<ExclusionZone>,
+    /// How the printer finds the end of each label.
+    #[serde(default)]
+    pub stock: LabelStock,
+    /// What the printer does with each label, unless a job asks otherwise. Cutting by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_print: Option<crate::job::PostPrint>,
+}
+
+/// A rectangle in mm from the top left of the label, as by printed direction.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExclusionZone {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// What to do with jobs printing into the zone.
+    #[serde(default)]
+    pub policy: ExclusionPolicy,
+    /// A picture of what is pre-printed, drawn into the zone in previews.
+    #[serde(default)]
+    pub underlay: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExclusionPolicy {
+    /// Refuse jobs with content in the zone.
+    #[default]
+    Reject,
+    /// Print the job with the zone left blank.
+    Mask,
+}
+
+/// The fonts text in SVG content is set in, for all printers.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FontConfiguration {
+    /// Load the fonts installed on the system.
+    pub system: bool,
+    pub directories: Vec<PathBuf>,
+}
+
+/// Where images referenced by SVG content may be loaded from.
+///
+/// Documents arrive from clients that need not be trusted with the files of the server, so by
+/// default only images embedded as data URIs are drawn.
+#[derive(Deserialize, Serialize, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum SvgResourcePolicy {
+    /// Draw embedded images only, skipping any reference to a file or URL.
+    #[default]
+    DataOnly,
+    /// Also draw image files within this directory, named relative to it.
+    Directory(PathBuf),
 }
 
 /// Identifies a label type.
 #[derive(Deserialize, Serialize, Clone, Eq, PartialEq, Hash)]
 pub struct LabelIdentifier(pub String);
 
+impl std::fmt::Display for LabelIdentifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 /// Identifies a logical printer, i.e. one the server opens a connection to.
+#[allow(dead_code)]
 #[derive(Deserialize, Serialize, Clone, Eq, PartialEq, Hash)]
 pub struct PrinterIdentifier(pub String);
 
@@ -28,8 +343,8 @@ pub struct LabelPrinter {
     /// How to refer to this printer in the server.
     pub label: LabelIdentifier,
 
-    /// How to reach this printer?
-    pub addr: SocketAddr,
+    /// How to reach this printer, as `host:port` with an IP address or a host name.
+    pub addr: PrinterAddress,
 
     /// How to refer to this printer for the user.
     #[serde(default)]
@@ -41,6 +356,135 @@ pub struct LabelPrinter {
     #[serde(default)]
     /// If this is not a physical printer, how do we handle it?
     pub virtualization: LabelVirtualization,
+
+    /// How to produce mirrored labels when a job asks for them.
+    #[serde(default)]
+    pub mirroring: MirrorMethod,
+
+    /// How to put QR codes on labels, by default as the quirks known of the model call for.
+    #[serde(default)]
+    pub qr_codes: Option<zpl::label::QrRendering>,
+
+    /// How text beyond ASCII is sent, UTF-8 unless the firmware predates it.
+    ///
+    /// The characters print only if the fonts used have glyphs for them.
+    #[serde(default)]
+    pub character_set: zpl::command::CharacterSet,
+
+    /// Directories of fonts for SVG content on this printer, besides those of the deployment.
+    ///
+    /// Fonts are loaded for the first job after a change, and afresh by
+    /// `/api/v1/reload-fonts`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub font_directories: Vec<PathBuf>,
+
+    /// How to encode rasterized content, unless a job asks otherwise.
+    ///
+    /// Hex passes intact through every print server, some corrupt the Base64 encodings.
+    /// Printers with firmware too old for these get compressed hex instead.
+    #[serde(default)]
+    pub image_compression: zpl::util::image::ImageCompression,
+
+    /// Bounds on the printer settings a job may override.
+    #[serde(default)]
+    pub limits: PrintLimits,
+
+    /// What jobs of raw ZPL may contain.
+    #[serde(default)]
+    pub passthrough: PassthroughLimits,
+
+    /// Who to notify about failures, as addresses or notification group names.
+    ///
+    /// Falls back to the default recipients of the notification configuration.
+    #[serde(default)]
+    pub notify: Vec<String>,
+
+    /// Changes to every rendered label, in order, for quirks of this printer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<zpl::util::transform::Transform>,
+
+    /// Where the printer has the origin of its labels, e.g. when fed from the right.
+    #[serde(default)]
+    pub coordinates: zpl::label::CoordinateTransform,
+
+    /// Keep this many of the bytes last exchanged with the printer, for administrators to read
+    /// at `/api/v1/printer/<name>/tap` when it misbehaves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap: Option<usize>,
+
+    /// How status requests and jobs failing for the connection are tried again, and how long to
+    /// wait between connection attempts. Once out of attempts, connections are tried every
+    /// `max` of the backoff. By default, requests are tried once and connections every second.
+    ///
+    /// Printers on flaky networks are better off with a few attempts and a wait growing at
+    /// random, e.g. `{"max_attempts": 4, "backoff": {"initial": {"secs": 1, "nanos": 0},
+    /// "max": {"secs": 30, "nanos": 0}, "jitter": 0.5}}`.
+    #[serde(default)]
+    pub retry: zpl::device::retry::RetryPolicy,
+
+    /// Wait for the labels of each job to come out before taking the next one. When the printer
+    /// runs out of media or ribbon, is opened, or is paused meanwhile, the job is suspended: the
+    /// printer holds its labels, so it is not kept to be printed again.
+    ///
+    /// By default jobs count as printed once sent. With `{}`, the status is polled every half
+    /// second for up to two minutes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait_for_printed: Option<zpl::device::WaitForPrinted>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct PrintLimits {
+    /// Inclusive range of darkness (`~SD`) a job may ask for.
+    pub darkness: (u32, u32),
+    /// Inclusive range of print speed (`^PR`) a job may ask for, in inches per second.
+    pub speed: (u32, u32),
+    /// Whether the printer has a cutter, such that jobs may ask for cutting.
+    pub cutter: bool,
+}
+
+impl Default for PrintLimits {
+    fn default() -> Self {
+        PrintLimits {
+            darkness: (0, 30),
+            speed: (2, 6),
+            cutter: true,
+        }
+    }
+}
+
+/// Raw ZPL is sent as submitted, so only what could harm the printer is checked.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct PassthroughLimits {
+    /// The largest accepted code, in bytes.
+    pub max_bytes: usize,
+    /// Commands rejected anywhere in the code, such as `^JUF` or `~JR`. Matched regardless of case.
+    ///
+    /// Code changing the command prefixes (`^CC`, `^CT`) is always rejected, as that could hide
+    /// commands from this check.
+    pub forbidden: Vec<String>,
+}
+
+impl Default for PassthroughLimits {
+    fn default() -> Self {
+        PassthroughLimits {
+            max_bytes: 1 << 20,
+            forbidden: ["^JUF", "^JUN", "^JUA", "~JR"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum MirrorMethod {
+    /// The firmware mirrors the label (`^PM`).
+    #[default]
+    Native,
+    /// Mirror rasterized content before sending it, for firmware lacking `^PM`.
+    Raster,
 }
 
 #[derive(Deserialize, Serialize, Default)]
@@ -59,81 +503,93 @@ pub enum LabelVirtualization {
         dpmm: Option<u32>,
         persist: Option<std::path::PathBuf>,
         wait_time: std::time::Duration,
+        /// Pictures of each label to write next to its persisted ZPL, for people to review.
+        #[serde(default)]
+        previews: Vec<PreviewFormat>,
+    },
+    /// Do not connect to the printer, an on-site agent fetches rendered jobs instead.
+    Pulled {
+        /// Resolution to render with until the agent reports the printer's own.
+        dpmm: Option<u32>,
+        /// Secret the agent must present as a bearer token.
+        token: Option<String>,
+        /// How long to wait for the agent to fetch a job and report its result.
+        timeout: std::time::Duration,
     },
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct LabelDimensions {
-    /// Width of the label in mm.
-    pub width: f32,
-    /// Height of the label in mm.
-    pub height: f32,
-    /// Space to reserve on the left of the label, as by printed direction.
-    pub margin_left: f32,
-    /// Space to reserve on the right of the label, as by printed direction.
-    pub margin_right: f32,
-    /// Space to reserve on top of the label, as by printed direction.
-    pub margin_top: f32,
-    /// Space to reserve at the bottom of the label, as by printed direction.
-    pub margin_bottom: f32,
+/// The file types a picture of a simulated label is written as.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewFormat {
+    Png,
+    /// A page of the label's physical size.
+    Pdf,
 }
 
-#[derive(Default, Deserialize, Serialize)]
-pub struct LabelCalibration {
-    /// Offset of the label towards the right (positive width) in mm.
-    pub home_x: f32,
+impl PreviewFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            PreviewFormat::Png => "png",
+            PreviewFormat::Pdf => "pdf",
+        }
+    }
 }
 
 impl Configuration {
-    pub async fn from_file(path: &Path) -> anyhow::Result<Self> {
-        let data = tokio::fs::read(path).await?;
-        Ok(serde_json::de::from_slice(&data)?)
+    pub fn fonts(&self) -> FontConfiguration {
+        FontConfiguration {
+            system: !self.exclude_system_fonts,
+            directories: self.font_directories.clone(),
+        }
     }
-}
 
-impl LabelDimensions {
-    /// Compare sizes, approximately considering json serialization semantics on either side of
-    /// Rust or HTML / JS may not be exactly the same. That is, only compare like 5 digits which is
-    /// far too good for measurement anyhow.
-    pub fn approx_cmp(&self, other: &Self) -> bool {
-        fn to_5digits(lhs: f32, rhs: f32) -> Option<core::cmp::Ordering> {
-            // Can underflow but that's fine. An 'eps' of 0.0 is just a very harsh requirement.
-            let eps = lhs.abs().max(rhs.abs()) * 2.0f32.powi(-16);
-            let diff = lhs - rhs;
-
-            if diff.abs() <= eps {
-                Some(core::cmp::Ordering::Equal)
-            } else if diff < 0.0 {
-                Some(core::cmp::Ordering::Less)
-            } else if diff > 0.0 {
-                Some(core::cmp::Ordering::Greater)
-            } else {
-                None
-            }
-        }
+    /// Load a configuration file, together with all files it includes.
+    pub async fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let mut root: Self = read_single_file(path).await?;
+        let mut includes = Includes::new(path)?;
+        includes.extend(path, std::mem::take(&mut root.include));
 
-        fn to_5digits_eq(lhs: f32, rhs: f32) -> bool {
-            matches!(to_5digits(lhs, rhs), Some(core::cmp::Ordering::Equal))
+        while let Some(next) = includes.next_file()? {
+            let included: IncludedFile = read_single_file(&next).await?;
+            includes.extend(&next, included.include);
+            merge_defined(&mut root.labels, included.labels, "Label", &next)?;
+            merge_defined(
+                &mut root.printers,
+                included.printers,
+                "Printer",
+                &next,
+            )?;
         }
 
-        let LabelDimensions {
-            width,
-            height,
-            margin_left,
-            margin_right,
-            margin_top,
-            margin_bottom,
-        } = *self;
-
-        to_5digits_eq(width, other.width)
-            && to_5digits_eq(height, other.height)
-            && to_5digits_eq(margin_left, other.margin_left)
-            && to_5digits_eq(margin_right, other.margin_right)
-            && to_5digits_eq(margin_top, other.margin_top)
-            && to_5digits_eq(margin_bottom, other.margin_bottom)
+        root.sources.extend(includes.into_sources());
+        Ok(root)
     }
 }
 
+/// A file included by another, which only adds labels and printers.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludedFile {
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    labels: HashMap<LabelIdentifier, Arc<Label>>,
+    #[serde(default)]
+    printers: HashMap<String, Arc<LabelPrinter>>,
+}
+
+/// Parse one file, as TOML or YAML by extension and JSON otherwise.
+async fn read_single_file<T: DeserializeOwned>(
+    path: &Path,
+) -> anyhow::Result<T> {
+    let data = tokio::fs::read_to_string(path).await?;
+    let value = zpl::config::parse(&data, path)?;
+    serde_json::from_value(value).map_err(|err| {
+        anyhow::anyhow!("Invalid configuration {}: {err}", path.display())
+    })
+}
+
 impl LabelVirtualization {
     pub fn is_connnected(&self) -> bool {
         matches!(
@@ -144,26 +600,73 @@ impl LabelVirtualization {
     }
 }
 
-#[test]
-fn validate_dimensions() {
-    let lhs = LabelDimensions {
-        width: 51.,
-        height: 51.,
-        margin_left: 1.,
-        margin_right: 1.,
-        margin_top: 1.,
-        margin_bottom: 1.,
-    };
-
-    assert!(lhs.approx_cmp(&lhs), "Does not equal itself?");
-
-    assert!(
-        !LabelDimensions { width: 50., ..lhs }.approx_cmp(&lhs),
-        "Does equal another"
-    );
-
-    assert!(
-        !LabelDimensions { height: 50., ..lhs }.approx_cmp(&lhs),
-        "Does equal another"
-    );
+#[tokio::test]
+async fn include_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let site = r#"{
+        "printers": {
+            "site": { "label": "51mm", "addr": "127.0.0.1:9100" }
+        }
+    }"#;
+
+    std::fs::write(dir.path().join("site.json"), site).unwrap();
+    std::fs::write(
+        dir.path().join("server.json"),
+        r#"{
+            "include": ["site.json"],
+            "labels": {
+                "51mm": {
+                    "dimensions": {
+                        "width": 51.0,
+                        "height": 51.0,
+                        "margin_left": 1.0,
+                        "margin_right": 1.0,
+                        "margin_top": 1.0,
+                        "margin_bottom": 1.0
+                    }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let cfg = Configuration::from_file(&dir.path().join("server.json"))
+        .await
+        .unwrap();
+    assert!(cfg.printers.contains_key("site"));
+    assert!(cfg.include.is_empty());
+
+    std::fs::write(
+        dir.path().join("conflict.json"),
+        r#"{ "include": ["site.json", "other.json"] }"#,
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("other.json"), site).unwrap();
+
+    let error = Configuration::from_file(&dir.path().join("conflict.json"))
+        .await
+        .err()
+        .expect("Duplicate printer accepted");
+    assert!(error.to_string().contains("`site`"), "{error}");
+}
+
+#[tokio::test]
+async fn included_files_only_add_labels_and_printers() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("server.json"),
+        r#"{ "include": ["site.toml"] }"#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("site.toml"),
+        "job_log = \"/var/log/zpl/jobs.jsonl\"\n",
+    )
+    .unwrap();
+
+    let error = Configuration::from_file(&dir.path().join("server.json"))
+        .await
+        .err()
+        .expect("Setting of an included file accepted");
+    assert!(error.to_string().contains("job_log"), "{error}");
 }
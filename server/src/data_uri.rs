@@ -56,7 +56,7 @@ impl<'lt> Deserialize<'lt> for DataUri {
                 Ok(data) => Arc::<[u8]>::from(data),
                 Err(b64err) => {
                     let unexpected = Unexpected::Str(data_part);
-                    log::warn!("Invalid client Base 64 {b64err}");
+                    tracing::warn!("Invalid client Base 64 {b64err}");
                     return Err(D::Error::invalid_value(
                         unexpected,
                         &"a base64 encoded string",
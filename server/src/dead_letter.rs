@@ -0,0 +1,149 @@
+//! Jobs that failed to print, kept to be looked at and printed again.
+//!
+//! A job lands here when its printer could not print it, with the reason and the job log record
+//! pointing at its artifacts. From here it is either requeued to its printer as it was, or
+//! discarded. Only the most recent failures of each printer are kept, in memory.
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use serde::Serialize;
+
+use crate::{
+    history::{JobRecord, JobResult},
+    job::{JobOptions, PrintJob},
+};
+
+/// How many failed jobs to keep per printer.
+const RETAINED: usize = 100;
+
+#[derive(Default)]
+pub struct DeadLetters {
+    next: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, DeadLetter>>,
+}
+
+#[derive(Clone)]
+pub struct DeadLetter {
+    pub job: PrintJob,
+    pub options: JobOptions,
+    pub record: JobRecord,
+}
+
+#[derive(Serialize)]
+pub struct DeadLetterReport {
+    id: u64,
+    reason: String,
+    /// The job log entry, with the digests of the stored payload and commands.
+    record: JobRecord,
+}
+
+impl DeadLetters {
+    /// Keep a failed job, forgetting the oldest of its printer.
+    pub fn push(&self, letter: DeadLetter) -> u64 {
+        let id = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        let printer = letter.record.printer.clone();
+        let mut jobs = self.jobs.lock().unwrap();
+
+        jobs.insert(id, letter);
+
+        let of_printer: Vec<u64> = jobs
+            .iter()
+            .filter(|(_, letter)| letter.record.printer == printer)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in of_printer
+            .iter()
+            .take(of_printer.len().saturating_sub(RETAINED))
+        {
+            jobs.remove(id);
+        }
+
+        id
+    }
+
+    /// The failed jobs of a printer, oldest first.
+    pub fn list(&self, printer: &str) -> Vec<DeadLetterReport> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, letter)| letter.record.printer == printer)
+            .map(|(id, letter)| DeadLetterReport {
+                id: *id,
+                reason: match &letter.record.result {
+                    JobResult::Failed { reason } => reason.clone(),
+                    JobResult::Pending
+                    | JobResult::Printed
+                    | JobResult::Suspended { .. } => String::new(),
+                },
+                record: letter.record.clone(),
+            })
+            .collect()
+    }
+
+    pub fn get(&self, printer: &str, id: u64) -> Option<DeadLetter> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .filter(|letter| letter.record.printer == printer)
+            .cloned()
+    }
+
+    pub fn remove(&self, printer: &str, id: u64) -> Option<DeadLetter> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.get(&id)?.record.printer != printer {
+            return None;
+        }
+
+        jobs.remove(&id)
+    }
+}
+
+#[test]
+fn retains_recent_failures_per_printer() {
+    let letter = |printer: &str| DeadLetter {
+        job: PrintJob::Zpl {
+            code: "^XA^XZ".to_string(),
+        },
+        options: JobOptions::default(),
+        record: JobRecord {
+            timestamp_unix: 0,
+            printer: printer.to_string(),
+            label: "label".to_string(),
+            payload_sha256: String::new(),
+            template: None,
+            payload_bytes: 0,
+            zpl_sha256: None,
+            raster_sha256: None,
+            requester: None,
+            copies: 1,
+            result: JobResult::Failed {
+                reason: "Connection reset".to_string(),
+            },
+            job_id: None,
+            effective: None,
+        },
+    };
+
+    let letters = DeadLetters::default();
+    let first = letters.push(letter("a"));
+    let other = letters.push(letter("b"));
+
+    for _ in 0..RETAINED {
+        letters.push(letter("a"));
+    }
+
+    assert!(letters.get("a", first).is_none());
+    assert!(letters.get("a", other).is_none());
+    assert_eq!(letters.list("a").len(), RETAINED);
+    assert_eq!(letters.list("b")[0].reason, "Connection reset");
+    assert!(letters.remove("b", other).is_some());
+    assert!(letters.list("b").is_empty());
+}
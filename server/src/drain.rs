@@ -0,0 +1,92 @@
+//! Printers taken out of service for maintenance, without losing the jobs already queued.
+//!
+//! A draining printer refuses new jobs but prints those it accepted before, such that it can be
+//! updated or serviced once its queue ran empty. Kept across reloads, like the media.
+use serde::Serialize;
+
+use std::{collections::HashMap, sync::Mutex};
+
+/// How long clients are asked to wait before submitting again, unless the drain names a time.
+pub const RETRY_AFTER_SECS: u64 = 300;
+
+#[derive(Default)]
+pub struct Draining {
+    printers: Mutex<HashMap<String, Drain>>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+pub struct Drain {
+    /// When the drain was started, as a Unix time.
+    pub since: u64,
+    /// Jobs waiting in the queue when the drain was started.
+    pub queued_at_start: usize,
+    /// Seconds clients are asked to wait before submitting again.
+    pub retry_after: u64,
+}
+
+/// How far a printer got with its queue, for the operator to know when to start.
+#[derive(Serialize)]
+pub struct DrainProgress {
+    pub draining: Option<Drain>,
+    /// Jobs waiting in the queue.
+    pub queued: usize,
+    /// Whether a job is being printed.
+    pub printing: bool,
+    /// Whether the printer is draining and done with all jobs it accepted.
+    pub drained: bool,
+}
+
+impl Draining {
+    /// Stop accepting jobs for a printer, false if it is draining already.
+    pub fn start(&self, printer: &str, drain: Drain) -> bool {
+        let mut printers = self.printers.lock().unwrap();
+        if printers.contains_key(printer) {
+            return false;
+        }
+
+        printers.insert(printer.to_string(), drain);
+        true
+    }
+
+    /// Accept jobs again, returning the drain ended if there was one.
+    pub fn resume(&self, printer: &str) -> Option<Drain> {
+        self.printers.lock().unwrap().remove(printer)
+    }
+
+    pub fn get(&self, printer: &str) -> Option<Drain> {
+        self.printers.lock().unwrap().get(printer).copied()
+    }
+}
+
+impl DrainProgress {
+    pub fn new(draining: Option<Drain>, queued: usize, printing: bool) -> Self {
+        DrainProgress {
+            draining,
+            queued,
+            printing,
+            drained: draining.is_some() && queued == 0 && !printing,
+        }
+    }
+}
+
+#[test]
+fn drain_and_resume() {
+    let draining = Draining::default();
+    let drain = Drain {
+        since: 100,
+        queued_at_start: 2,
+        retry_after: RETRY_AFTER_SECS,
+    };
+
+    assert!(draining.start("shelf", drain));
+    assert!(!draining.start("shelf", drain));
+    assert!(draining.get("other").is_none());
+
+    let progress = DrainProgress::new(draining.get("shelf"), 1, true);
+    assert!(!progress.drained);
+    let progress = DrainProgress::new(draining.get("shelf"), 0, false);
+    assert!(progress.drained);
+
+    assert_eq!(draining.resume("shelf").map(|drain| drain.since), Some(100));
+    assert!(!DrainProgress::new(draining.get("shelf"), 0, false).drained);
+}
@@ -0,0 +1,108 @@
+//! What happens to jobs and printers, as it happens, for clients that would rather be told than
+//! poll.
+//!
+//! Events are kept only until every subscriber has seen them, or until too many are pending for
+//! a slow subscriber, which then misses some.
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::intake::JobState;
+
+/// Events pending for the slowest subscriber before it misses some.
+const CAPACITY: usize = 256;
+
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A job tracked by id went on to another state.
+    Job {
+        printer: String,
+        id: u64,
+        #[serde(flatten)]
+        state: JobState,
+    },
+    Printer {
+        printer: String,
+        #[serde(flatten)]
+        state: PrinterState,
+    },
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum PrinterState {
+    /// Reached again, after the server started or the printer was down.
+    Up,
+    Down {
+        reason: String,
+    },
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Events {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl Events {
+    pub fn publish(&self, event: Event) {
+        // Without subscribers the event is of no interest to anyone.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+impl Event {
+    pub fn printer(&self) -> &str {
+        match self {
+            Event::Job { printer, .. } | Event::Printer { printer, .. } => {
+                printer
+            }
+        }
+    }
+}
+
+#[test]
+fn event_shapes() {
+    let event = Event::Job {
+        printer: "a".to_string(),
+        id: 3,
+        state: JobState::Queued { queue_position: 2 },
+    };
+    assert_eq!(
+        serde_json::to_value(event).unwrap(),
+        serde_json::json!({
+            "type": "job",
+            "printer": "a",
+            "id": 3,
+            "state": "queued",
+            "queue_position": 2,
+        })
+    );
+
+    let event = Event::Printer {
+        printer: "a".to_string(),
+        state: PrinterState::Down {
+            reason: "timed out".to_string(),
+        },
+    };
+    assert_eq!(
+        serde_json::to_value(event).unwrap(),
+        serde_json::json!({
+            "type": "printer",
+            "printer": "a",
+            "state": "down",
+            "reason": "timed out",
+        })
+    );
+}
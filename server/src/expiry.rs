@@ -0,0 +1,144 @@
+//! Dates for labels of perishable goods, computed by the server such that all clients agree.
+//!
+//! Jobs name a shelf life instead of dates, which are filled into placeholders of their content:
+//! `{{product}}`, `{{shelf_life_days}}`, `{{printed}}` and `{{expiry}}`.
+use serde::{Deserialize, Serialize};
+use zpl::command::ClockTime;
+
+/// Offsets from UTC span from -12 to +14 hours.
+const UTC_OFFSETS: std::ops::RangeInclusive<i32> = -720..=840;
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiExpiry {
+    #[serde(default)]
+    pub product: Option<String>,
+    /// Days from the day of printing to the day of expiry.
+    pub shelf_life_days: u32,
+    /// How dates are written, with `%Y`, `%y`, `%m`, `%d` and `%%`. `%Y-%m-%d` by default.
+    #[serde(default)]
+    pub date_format: Option<String>,
+    /// Minutes ahead of UTC where the label is printed, instead of the server's site.
+    #[serde(default)]
+    pub utc_offset: Option<i32>,
+}
+
+impl ApiExpiry {
+    /// The placeholders and their values, for a job printed at a Unix time.
+    pub fn values(
+        &self,
+        now: i64,
+        site_utc_offset: i32,
+    ) -> anyhow::Result<Vec<(&'static str, String)>> {
+        let offset = self.utc_offset.unwrap_or(site_utc_offset);
+        if !UTC_OFFSETS.contains(&offset) {
+            anyhow::bail!("UTC offset of {offset} minutes out of range");
+        }
+
+        let format = self.date_format.as_deref().unwrap_or("%Y-%m-%d");
+        let expires = now + i64::from(self.shelf_life_days) * 86_400;
+
+        Ok(vec![
+            ("product", self.product.clone().unwrap_or_default()),
+            ("shelf_life_days", self.shelf_life_days.to_string()),
+            (
+                "printed",
+                format_date(&ClockTime::from_unix(now, offset), format)?,
+            ),
+            (
+                "expiry",
+                format_date(&ClockTime::from_unix(expires, offset), format)?,
+            ),
+        ])
+    }
+}
+
+/// Write the date of a time in a format of `%` placeholders.
+fn format_date(time: &ClockTime, format: &str) -> anyhow::Result<String> {
+    let mut out = String::new();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", time.year)),
+            Some('y') => out.push_str(&format!("{:02}", time.year % 100)),
+            Some('m') => out.push_str(&format!("{:02}", time.month)),
+            Some('d') => out.push_str(&format!("{:02}", time.day)),
+            Some('%') => out.push('%'),
+            Some(other) => anyhow::bail!("Unknown date placeholder %{other}"),
+            None => anyhow::bail!("Date format ends in %"),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Replace each `{{name}}` by its value, as written by an encoding for the content.
+pub fn fill(
+    code: &mut String,
+    values: &[(&str, String)],
+    encode: fn(&str) -> anyhow::Result<String>,
+) -> anyhow::Result<()> {
+    for (name, value) in values {
+        let placeholder = format!("{{{{{name}}}}}");
+        if code.contains(&placeholder) {
+            *code = code.replace(&placeholder, &encode(value)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Escape a value for the text of an SVG document.
+pub fn escape_xml(value: &str) -> anyhow::Result<String> {
+    Ok(value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;"))
+}
+
+/// Refuse values which would be read as commands in field data.
+pub fn check_zpl(value: &str) -> anyhow::Result<String> {
+    if value.contains(['^', '~']) {
+        anyhow::bail!("Value {value:?} contains ^ or ~");
+    }
+
+    Ok(value.to_string())
+}
+
+#[test]
+fn expiry_in_site_time() {
+    let expiry = ApiExpiry {
+        product: Some("Salad & Dip".to_string()),
+        shelf_life_days: 3,
+        date_format: Some("%d.%m.%y".to_string()),
+        utc_offset: None,
+    };
+
+    // 2024-02-28 23:30 UTC, already the 29th an hour ahead.
+    let values = expiry.values(1_709_163_000, 60).unwrap();
+    let mut code =
+        "<text>{{product}}: {{printed}} to {{expiry}}</text>".to_string();
+    fill(&mut code, &values, escape_xml).unwrap();
+    assert_eq!(code, "<text>Salad &amp; Dip: 29.02.24 to 03.03.24</text>");
+
+    let mut code = "^FD{{expiry}}^FS".to_string();
+    let values = expiry.values(1_709_163_000, 0).unwrap();
+    fill(&mut code, &values, check_zpl).unwrap();
+    assert_eq!(code, "^FD02.03.24^FS");
+
+    let caret = ApiExpiry {
+        product: Some("^XZ".to_string()),
+        ..expiry
+    };
+    let values = caret.values(0, 0).unwrap();
+    let mut code = "^FD{{product}}^FS".to_string();
+    assert!(fill(&mut code, &values, check_zpl).is_err());
+}
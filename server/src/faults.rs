@@ -0,0 +1,272 @@
+//! Failures injected into virtual printers, to rehearse how the server handles them.
+//!
+//! Only built with the `fault-injection` feature. The faults of a printer are set through an
+//! administrative endpoint and apply to every job it takes, until they are cleared. Virtual
+//! printers fail their simulated jobs, the connections to printers with a device pass through
+//! [`FaultyConnection`] which fails connecting, resets while sending labels and raises error flags
+//! in the host status the printer reports.
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use zpl::device::intercept::Interceptor;
+
+/// Start and end of each line of a response.
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+
+#[derive(Default)]
+pub struct Faults {
+    plan: Mutex<FaultPlan>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct FaultPlan {
+    /// Fail this many of the next attempts to connect to a printer with a device.
+    pub connect_failures: u32,
+    /// Fail this many of the next jobs as if the connection dropped while sending.
+    pub drop_connection: u32,
+    /// Delay sending each job, in milliseconds.
+    pub slow_write_ms: u64,
+    /// Fail each job after sending, as if the status response was cut short.
+    pub partial_status: bool,
+    /// Report a condition of the printer that fails all jobs and self-tests.
+    pub error_state: Option<ErrorState>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorState {
+    PaperOut,
+    RibbonOut,
+    HeadOpen,
+    Paused,
+}
+
+impl Faults {
+    pub fn plan(&self) -> FaultPlan {
+        self.plan.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, plan: FaultPlan) {
+        *self.plan.lock().unwrap() = plan;
+    }
+
+    /// Pass a connection to a printer with a device through these faults.
+    pub fn interceptor(self: &Arc<Self>) -> Arc<dyn Interceptor> {
+        Arc::new(FaultyConnection {
+            faults: self.clone(),
+            cursor: Mutex::default(),
+        })
+    }
+
+    /// Fail an attempt to connect, while failures are left.
+    pub fn connect(&self) -> io::Result<()> {
+        let mut plan = self.plan.lock().unwrap();
+        if plan.connect_failures == 0 {
+            return Ok(());
+        }
+
+        plan.connect_failures -= 1;
+        Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+    }
+
+    /// Fail or delay a job about to be sent.
+    pub async fn before_send(&self) -> anyhow::Result<()> {
+        let drop = self.take_drop();
+        let plan = self.plan();
+
+        self.check_state()?;
+
+        if drop {
+            return Err(io::Error::from(io::ErrorKind::ConnectionReset).into());
+        }
+
+        tokio::time::sleep(Duration::from_millis(plan.slow_write_ms)).await;
+        Ok(())
+    }
+
+    /// Whether to drop the connection of the job being sent, counting it.
+    fn take_drop(&self) -> bool {
+        let mut plan = self.plan.lock().unwrap();
+        let drop = plan.drop_connection > 0;
+        plan.drop_connection = plan.drop_connection.saturating_sub(1);
+        drop
+    }
+
+    /// Fail a job that was sent, as if its status could not be read.
+    pub fn after_send(&self) -> anyhow::Result<()> {
+        if self.plan.lock().unwrap().partial_status {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Host status response cut short",
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Fail while an error condition is set.
+    pub fn check_state(&self) -> anyhow::Result<()> {
+        let Some(state) = self.plan.lock().unwrap().error_state else {
+            return Ok(());
+        };
+
+        anyhow::bail!(match state {
+            ErrorState::PaperOut => "Printer reports paper out",
+            ErrorState::RibbonOut => "Printer reports ribbon out",
+            ErrorState::HeadOpen => "Printer reports head open",
+            ErrorState::Paused => "Printer is paused",
+        })
+    }
+}
+
+/// The traffic of one connection to a printer with a device, failed as planned.
+pub struct FaultyConnection {
+    faults: Arc<Faults>,
+    cursor: Mutex<Cursor>,
+}
+
+/// Where the connection is within the host status the printer reports.
+#[derive(Default)]
+struct Cursor {
+    /// Lines of the host status read so far, while one is expected.
+    status_line: Option<usize>,
+    field: usize,
+    /// Whether a label was sent since the last host status.
+    label_sent: bool,
+}
+
+impl FaultyConnection {
+    /// The flags of the host status to raise for a condition, by line and field.
+    fn flags(state: ErrorState) -> &'static [(usize, usize)] {
+        // Printers pause on all conditions.
+        match state {
+            ErrorState::PaperOut => &[(0, 1), (0, 2)],
+            ErrorState::Paused => &[(0, 2)],
+            ErrorState::HeadOpen => &[(0, 2), (1, 2)],
+            // Only counts in thermal transfer mode.
+            ErrorState::RibbonOut => &[(0, 2), (1, 3), (1, 4)],
+        }
+    }
+}
+
+impl Interceptor for FaultyConnection {
+    fn connect(&self) -> io::Result<()> {
+        self.faults.connect()
+    }
+
+    fn sent(&self, bytes: &[u8]) -> io::Result<()> {
+        let contains = |command: &[u8]| {
+            bytes.windows(command.len()).any(|window| window == command)
+        };
+
+        let mut cursor = self.cursor.lock().unwrap();
+        if contains(b"^XA") {
+            cursor.label_sent = true;
+            if self.faults.take_drop() {
+                return Err(io::Error::from(io::ErrorKind::ConnectionReset));
+            }
+        }
+
+        if contains(b"~HS") {
+            cursor.status_line = Some(0);
+            cursor.field = 0;
+        }
+
+        Ok(())
+    }
+
+    fn received(&self, bytes: &mut [u8]) -> io::Result<()> {
+        let mut cursor = self.cursor.lock().unwrap();
+        if cursor.status_line.is_none() {
+            return Ok(());
+        }
+
+        let plan = self.faults.plan();
+        if plan.partial_status && std::mem::take(&mut cursor.label_sent) {
+            cursor.status_line = None;
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Host status response cut short",
+            ));
+        }
+
+        let flags = plan.error_state.map_or(&[][..], Self::flags);
+        for byte in bytes {
+            let Some(line) = cursor.status_line else {
+                break;
+            };
+
+            match *byte {
+                STX => cursor.field = 0,
+                ETX if line == 2 => cursor.status_line = None,
+                ETX => cursor.status_line = Some(line + 1),
+                b',' => cursor.field += 1,
+                b'0' if flags.contains(&(line, cursor.field)) => *byte = b'1',
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn drops_the_next_jobs() {
+    let faults = Faults::default();
+    faults.set(FaultPlan {
+        drop_connection: 2,
+        ..FaultPlan::default()
+    });
+
+    assert!(faults.before_send().await.is_err());
+    assert!(faults.before_send().await.is_err());
+    assert!(faults.before_send().await.is_ok());
+
+    faults.set(FaultPlan {
+        error_state: Some(ErrorState::HeadOpen),
+        ..FaultPlan::default()
+    });
+    assert!(faults.before_send().await.is_err());
+    assert!(faults.after_send().is_ok());
+}
+
+#[test]
+fn device_connections() {
+    let faults = Arc::new(Faults::default());
+    faults.set(FaultPlan {
+        connect_failures: 1,
+        drop_connection: 1,
+        error_state: Some(ErrorState::HeadOpen),
+        ..FaultPlan::default()
+    });
+    let connection = faults.interceptor();
+
+    assert!(connection.connect().is_err());
+    assert!(connection.connect().is_ok());
+
+    assert!(connection.sent(b"^XA^FO0,0^XZ").is_err());
+    assert!(connection.sent(b"^XA^FO0,0^XZ").is_ok());
+
+    // Flags of other responses stay as they are.
+    let mut identification = *b"\x02ZD420,V84.20.18Z,0,0\x03";
+    connection.received(&mut identification).unwrap();
+    assert_eq!(&identification, b"\x02ZD420,V84.20.18Z,0,0\x03");
+
+    connection.sent(b"~HS").unwrap();
+    let mut status = b"\x02030,0,0,1245,000,0,0,0,000,0,0,0\x03\r\n\x02000,0,0,0,0,2,4,0,00000000,1,000\x03\r\n\x021234,0\x03\r\n".to_vec();
+    // Read in pieces, as they arrive.
+    let (first, second) = status.split_at_mut(20);
+    connection.received(first).unwrap();
+    connection.received(second).unwrap();
+    assert_eq!(
+        status,
+        b"\x02030,0,1,1245,000,0,0,0,000,0,0,0\x03\r\n\x02000,0,1,0,0,2,4,0,00000000,1,000\x03\r\n\x021234,0\x03\r\n"
+    );
+}
@@ -0,0 +1,154 @@
+//! Firmware updates delivered over the connection the server manages anyway.
+//!
+//! An update is first staged, with its checksum verified against the one the administrator
+//! expects. Only a separate confirmation queues it for the printer, which then receives it in
+//! between jobs. Progress is kept for the administrator to follow.
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde::Serialize;
+
+use crate::artifacts;
+
+/// Staged updates by their id.
+#[derive(Default)]
+pub struct Updates {
+    next: AtomicU64,
+    staged: Mutex<HashMap<u64, Arc<FirmwareUpdate>>>,
+}
+
+pub struct FirmwareUpdate {
+    pub id: u64,
+    pub printer: String,
+    pub data: Arc<[u8]>,
+    sha256: String,
+    sent: AtomicU64,
+    state: Mutex<FirmwareState>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum FirmwareState {
+    /// Verified, waiting for confirmation.
+    Staged,
+    /// Confirmed, waiting for the printer to finish its current job.
+    Queued,
+    Sending,
+    /// Completely sent, the printer applies it and restarts on its own.
+    Sent,
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Serialize)]
+pub struct FirmwareReport {
+    id: u64,
+    printer: String,
+    sha256: String,
+    total_bytes: u64,
+    sent_bytes: u64,
+    #[serde(flatten)]
+    state: FirmwareState,
+}
+
+impl Updates {
+    /// Keep an update for a printer, if it matches the expected SHA-256.
+    pub fn stage(
+        &self,
+        printer: &str,
+        data: Arc<[u8]>,
+        expected_sha256: &str,
+    ) -> Result<Arc<FirmwareUpdate>, String> {
+        let sha256 = artifacts::digest(&data).0;
+        if !sha256.eq_ignore_ascii_case(expected_sha256.trim()) {
+            return Err(format!(
+                "Checksum mismatch, the uploaded file has SHA-256 {sha256}"
+            ));
+        }
+
+        let update = Arc::new(FirmwareUpdate {
+            id: self.next.fetch_add(1, Ordering::Relaxed) + 1,
+            printer: printer.to_string(),
+            data,
+            sha256,
+            sent: AtomicU64::new(0),
+            state: Mutex::new(FirmwareState::Staged),
+        });
+
+        let mut staged = self.staged.lock().unwrap();
+        // Only the latest update of a printer is kept, they are large.
+        staged
+            .retain(|_, other| other.printer != printer || !other.is_pending());
+        staged.insert(update.id, update.clone());
+
+        Ok(update)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<FirmwareUpdate>> {
+        self.staged.lock().unwrap().get(&id).cloned()
+    }
+}
+
+impl FirmwareUpdate {
+    /// Move from staged to queued, exactly once.
+    pub fn confirm(&self) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            FirmwareState::Staged => {
+                *state = FirmwareState::Queued;
+                Ok(())
+            }
+            _ => Err("The update was already confirmed".to_string()),
+        }
+    }
+
+    pub fn set_state(&self, state: FirmwareState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    pub fn set_sent(&self, sent: usize) {
+        self.sent.store(sent as u64, Ordering::Relaxed);
+    }
+
+    fn is_pending(&self) -> bool {
+        matches!(
+            *self.state.lock().unwrap(),
+            FirmwareState::Staged | FirmwareState::Queued
+        )
+    }
+
+    pub fn report(&self) -> FirmwareReport {
+        FirmwareReport {
+            id: self.id,
+            printer: self.printer.clone(),
+            sha256: self.sha256.clone(),
+            total_bytes: self.data.len() as u64,
+            sent_bytes: self.sent.load(Ordering::Relaxed),
+            state: self.state.lock().unwrap().clone(),
+        }
+    }
+}
+
+#[test]
+fn stage_and_confirm() {
+    let updates = Updates::default();
+    let data: Arc<[u8]> = b"firmware".as_slice().into();
+    let sha256 = artifacts::digest(&data).0;
+
+    assert!(updates.stage("a", data.clone(), "00").is_err());
+
+    let first = updates.stage("a", data.clone(), &sha256).unwrap();
+    let second = updates.stage("a", data, &sha256.to_uppercase()).unwrap();
+    // The first one was never confirmed, the second replaces it.
+    assert!(updates.get(first.id).is_none());
+
+    second.confirm().unwrap();
+    assert!(second.confirm().is_err());
+    assert_eq!(updates.get(second.id).unwrap().report().total_bytes, 8);
+}
@@ -0,0 +1,262 @@
+//! The accounting log of jobs handed to printers.
+//!
+//! Every job accepted into a queue ends up as one line of JSON in an append-only file, recording
+//! what was printed where, on whose request, and whether it succeeded.
+use serde::{Deserialize, Serialize};
+
+use std::{path::PathBuf, time::SystemTime};
+
+use tokio::{
+    io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _},
+    sync::Mutex,
+};
+
+use crate::{artifacts, job::PrintApi};
+use zpl::{
+    command::{CharacterSet, PostPrintAction},
+    label::{CoordinateTransform, Orientation, QrRendering},
+    util::image::ImageCompression,
+};
+
+pub struct JobLog {
+    path: PathBuf,
+    /// Serializes appends, such that lines of concurrent jobs do not interleave.
+    lock: Mutex<()>,
+    /// How many bytes to read at once, going back from the end of the log.
+    chunk: u64,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct JobRecord {
+    pub timestamp_unix: u64,
+    pub printer: String,
+    pub label: String,
+    /// Hex encoded SHA-256 of the submitted content, its artifact if stored.
+    pub payload_sha256: String,
+    /// The template the job was made from, as named by the client.
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub payload_bytes: u64,
+    /// The artifact of the commands sent to the printer, if stored.
+    #[serde(default)]
+    pub zpl_sha256: Option<String>,
+    /// The artifact of the label as rendered, a PNG, if stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raster_sha256: Option<String>,
+    /// The peer address of the client that submitted the job.
+    pub requester: Option<String>,
+    pub copies: u32,
+    pub result: JobResult,
+    /// The id of a job accepted asynchronously, to report its outcome by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
+    /// The settings the job was printed with, unless it was raw commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective: Option<EffectiveOptions>,
+}
+
+/// The settings of a printed job, after the defaults of its printer and stock were merged with
+/// the overrides of the job.
+///
+/// Settings left to the printer, such as darkness and speed without an override, are `None`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EffectiveOptions {
+    pub dpmm: u32,
+    pub copies: u32,
+    pub darkness: Option<usize>,
+    pub speed: Option<usize>,
+    pub compression: ImageCompression,
+    /// The home offset of the printer's calibration, in dots.
+    pub home_x: Option<i32>,
+    #[serde(default)]
+    pub home_y: Option<i32>,
+    pub post_print: Option<PostPrintAction>,
+    #[serde(default)]
+    pub orientation: Orientation,
+    pub transform: CoordinateTransform,
+    pub qr: QrRendering,
+    #[serde(default)]
+    pub charset: CharacterSet,
+    /// The factor SVG content was shrunk by to fit, if the job asked to shrink it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shrink: Option<f32>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobResult {
+    /// Not yet known, never written to the log.
+    Pending,
+    Printed,
+    Failed {
+        reason: String,
+    },
+    /// Sent, but the printer stopped with labels left, which it prints once seen to.
+    Suspended {
+        reason: String,
+    },
+}
+
+impl JobLog {
+    pub fn new(path: PathBuf) -> Self {
+        JobLog {
+            path,
+            lock: Mutex::new(()),
+            chunk: 64 * 1024,
+        }
+    }
+
+    pub async fn append(&self, record: &JobRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+
+    /// The most recent records of a printer, oldest first, after skipping the `skip` most
+    /// recent ones.
+    ///
+    /// The log is read backwards from its end, only as far as needed for the records asked for.
+    pub async fn history(
+        &self,
+        printer: &str,
+        skip: usize,
+        limit: usize,
+    ) -> std::io::Result<Vec<JobRecord>> {
+        let wanted = skip.saturating_add(limit);
+        // Newest first, until reversed at the end.
+        let mut records = vec![];
+
+        let _guard = self.lock.lock().await;
+        let mut file = match tokio::fs::File::open(&self.path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(vec![]);
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut end = file.metadata().await?.len();
+        // The start of the line the last chunk began in the middle of.
+        let mut partial = vec![];
+        while end > 0 && records.len() < wanted {
+            let start = end.saturating_sub(self.chunk);
+            let mut data = vec![0; (end - start) as usize];
+            file.seek(std::io::SeekFrom::Start(start)).await?;
+            file.read_exact(&mut data).await?;
+            data.append(&mut partial);
+            end = start;
+
+            // Only whole lines, unless this is the start of the log.
+            let first = match data.iter().position(|&byte| byte == b'\n') {
+                _ if start == 0 => 0,
+                Some(newline) => newline + 1,
+                None => {
+                    partial = data;
+                    continue;
+                }
+            };
+
+            let lines = data[first..]
+                .split(|&byte| byte == b'\n')
+                .filter(|line| !line.is_empty());
+            for line in lines.rev() {
+                let record: JobRecord = match serde_json::from_slice(line) {
+                    Ok(record) => record,
+                    Err(error) => {
+                        tracing::warn!(
+                            "Skipping malformed job log line: {error}"
+                        );
+                        continue;
+                    }
+                };
+
+                if record.printer == printer {
+                    records.push(record);
+                    if records.len() == wanted {
+                        break;
+                    }
+                }
+            }
+
+            data.truncate(first);
+            partial = data;
+        }
+
+        records.drain(..skip.min(records.len()));
+        records.reverse();
+        Ok(records)
+    }
+}
+
+impl JobRecord {
+    pub fn new(
+        printer: &str,
+        label: &str,
+        payload: &PrintApi,
+        requester: Option<String>,
+    ) -> Self {
+        JobRecord {
+            timestamp_unix: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            printer: printer.to_string(),
+            label: label.to_string(),
+            payload_sha256: artifacts::digest(payload.payload()).0,
+            template: payload.template.clone(),
+            payload_bytes: payload.payload().len() as u64,
+            zpl_sha256: None,
+            raster_sha256: None,
+            requester,
+            copies: 1,
+            result: JobResult::Pending,
+            job_id: None,
+            effective: None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn history_filters_and_pages() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = JobLog {
+        // Small enough for records to span chunks.
+        chunk: 50,
+        ..JobLog::new(dir.path().join("jobs.jsonl"))
+    };
+    assert!(log.history("left", 0, 10).await.unwrap().is_empty());
+
+    let payload: PrintApi =
+        serde_json::from_str(r#"{"zpl":{"code":"^XA^XZ"}}"#).unwrap();
+    for copies in 1..=7 {
+        for printer in ["left", "right"] {
+            let record = JobRecord {
+                copies,
+                ..JobRecord::new(printer, "label", &payload, None)
+            };
+            log.append(&record).await.unwrap();
+        }
+    }
+
+    let copies = |records: Vec<JobRecord>| {
+        assert!(records.iter().all(|record| record.printer == "left"));
+        records
+            .iter()
+            .map(|record| record.copies)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(copies(log.history("left", 0, 3).await.unwrap()), [5, 6, 7]);
+    assert_eq!(copies(log.history("left", 3, 3).await.unwrap()), [2, 3, 4]);
+    assert_eq!(copies(log.history("left", 6, 3).await.unwrap()), [1]);
+    assert_eq!(copies(log.history("left", 0, 100).await.unwrap()).len(), 7);
+    assert!(log.history("nowhere", 0, 10).await.unwrap().is_empty());
+}
@@ -0,0 +1,163 @@
+//! Submissions remembered for a while, such that a job sent twice is printed once.
+//!
+//! A client names each job by an `Idempotency-Key` header, or the server takes the digest of the
+//! content if configured to. A repeat within the window is answered with the response to the
+//! first submission, naming the same job, or refused while the first is still being handled.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::http::StatusCode;
+
+/// How many submissions to remember at most, the oldest forgotten first.
+const RETAINED: usize = 10_000;
+
+#[derive(Default)]
+pub struct Submissions {
+    /// By printer and key.
+    entries: Mutex<HashMap<(String, String), Entry>>,
+}
+
+struct Entry {
+    at: Instant,
+    /// The response to the submission, once it was accepted.
+    response: Option<(StatusCode, serde_json::Value)>,
+}
+
+pub enum Claim<'a> {
+    /// Not seen within the window, go ahead and report with [`Pending::complete`].
+    New(Pending<'a>),
+    /// The response to the earlier submission.
+    Repeat(StatusCode, serde_json::Value),
+    /// The earlier submission is still being handled.
+    InProgress,
+}
+
+impl Submissions {
+    /// Look up a submission, remembering it as in progress if it is new.
+    pub fn claim(
+        &self,
+        printer: &str,
+        key: &str,
+        window: Duration,
+    ) -> Claim<'_> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| now.duration_since(entry.at) < window);
+
+        let id = (printer.to_string(), key.to_string());
+        if let Some(entry) = entries.get(&id) {
+            return match &entry.response {
+                Some((status, body)) => Claim::Repeat(*status, body.clone()),
+                None => Claim::InProgress,
+            };
+        }
+
+        if entries.len() >= RETAINED {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.at)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            id.clone(),
+            Entry {
+                at: now,
+                response: None,
+            },
+        );
+        Claim::New(Pending {
+            submissions: self,
+            id: Some(id),
+        })
+    }
+}
+
+/// A submission claimed as in progress.
+///
+/// Forgotten when dropped without being completed, such as when the client hung up while it was
+/// being handled, so that it is not refused as in progress until the window passes.
+pub struct Pending<'a> {
+    submissions: &'a Submissions,
+    /// Taken once completed.
+    id: Option<(String, String)>,
+}
+
+impl Pending<'_> {
+    /// Remember the response to the submission, or forget it if it was refused such that the
+    /// client may try again.
+    pub fn complete(
+        mut self,
+        response: Option<(StatusCode, serde_json::Value)>,
+    ) {
+        let Some(id) = self.id.take() else {
+            return;
+        };
+        let mut entries = self.submissions.entries.lock().unwrap();
+
+        match response {
+            Some(response) => {
+                if let Some(entry) = entries.get_mut(&id) {
+                    entry.response = Some(response);
+                }
+            }
+            None => {
+                entries.remove(&id);
+            }
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.submissions.entries.lock().unwrap().remove(&id);
+        }
+    }
+}
+
+#[test]
+fn repeated_submissions() {
+    let submissions = Submissions::default();
+    let window = Duration::from_secs(60);
+    let accepted = serde_json::json!({ "id": 1 });
+
+    let Claim::New(a) = submissions.claim("a", "k", window) else {
+        panic!("expected a new submission");
+    };
+    assert!(matches!(
+        submissions.claim("a", "k", window),
+        Claim::InProgress
+    ));
+    // Keys are those of one printer.
+    let Claim::New(b) = submissions.claim("b", "k", window) else {
+        panic!("expected a new submission");
+    };
+
+    a.complete(Some((StatusCode::OK, accepted.clone())));
+    match submissions.claim("a", "k", window) {
+        Claim::Repeat(status, body) => {
+            assert_eq!((status, body), (StatusCode::OK, accepted))
+        }
+        _ => panic!("expected the first response"),
+    }
+
+    // Refused submissions may be retried, as may those abandoned while in progress.
+    b.complete(None);
+    let abandoned = submissions.claim("b", "k", window);
+    assert!(matches!(abandoned, Claim::New(_)));
+    drop(abandoned);
+    assert!(matches!(submissions.claim("b", "k", window), Claim::New(_)));
+
+    // All are forgotten after the window.
+    assert!(matches!(
+        submissions.claim("a", "k", Duration::ZERO),
+        Claim::New(_)
+    ));
+}
@@ -0,0 +1,220 @@
+//! Jobs accepted before their content was validated, and what became of them.
+//!
+//! Clients asking for an asynchronous response get an id right after the cheap checks of their
+//! job, and look up here whether it was queued, rejected or printed. Jobs queued while the client
+//! waits get an id as well, with the response. Only the most recent jobs are kept, in memory.
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde::Serialize;
+
+use crate::{
+    events::{Event, Events},
+    history::{EffectiveOptions, JobResult},
+    validation::ValidationError,
+};
+
+/// How many jobs to remember.
+const RETAINED: usize = 1000;
+
+#[derive(Default)]
+pub struct Intake {
+    next: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, JobStatus>>,
+    /// Where changes of state are published.
+    events: Events,
+}
+
+/// Reports how a job is coming along while it is printed, if it is tracked by id.
+#[derive(Clone)]
+pub struct Progress {
+    intake: Arc<Intake>,
+    id: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct JobStatus {
+    pub printer: String,
+    #[serde(flatten)]
+    pub state: JobState,
+    /// The settings the job was printed with, once it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective: Option<EffectiveOptions>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for or undergoing validation of the content.
+    Validating,
+    /// Valid and waiting for the printer, behind the jobs before it in the queue.
+    Queued {
+        queue_position: usize,
+    },
+    /// Taken up by the printer, its content being rendered.
+    Rendering,
+    /// Rendered and being sent to the printer.
+    Sending,
+    Printed,
+    /// The content or options were found invalid, the job was never queued.
+    Rejected {
+        reason: String,
+        /// What was found invalid, for clients to point at.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<ValidationError>,
+    },
+    /// Printing failed.
+    Failed {
+        reason: String,
+    },
+    /// The printer holds labels of the job, printing them once it is seen to.
+    Suspended {
+        reason: String,
+    },
+}
+
+impl Intake {
+    pub fn new(events: Events) -> Self {
+        Intake {
+            next: AtomicU64::default(),
+            jobs: Mutex::default(),
+            events,
+        }
+    }
+
+    /// Start tracking a job, forgetting the oldest ones.
+    pub fn register(&self, printer: &str) -> u64 {
+        let id = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut jobs = self.jobs.lock().unwrap();
+
+        let job = JobStatus {
+            printer: printer.to_string(),
+            state: JobState::Validating,
+            effective: None,
+        };
+        self.publish(id, &job);
+        jobs.insert(id, job);
+
+        while jobs.len() > RETAINED {
+            jobs.pop_first();
+        }
+
+        id
+    }
+
+    /// Note that the job passed validation, unless it is already done printing.
+    pub fn queued(&self, id: u64, queue_position: usize) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            if let JobState::Validating = job.state {
+                job.state = JobState::Queued { queue_position };
+                self.publish(id, job);
+            }
+        }
+    }
+
+    pub fn set(&self, id: u64, state: JobState) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.state = state;
+            self.publish(id, job);
+        }
+    }
+
+    /// Record the outcome of printing a job, with the settings it was printed with.
+    pub fn finish(
+        &self,
+        id: u64,
+        result: &JobResult,
+        effective: Option<EffectiveOptions>,
+    ) {
+        let state = match result {
+            JobResult::Pending => return,
+            JobResult::Printed => JobState::Printed,
+            JobResult::Failed { reason } => JobState::Failed {
+                reason: reason.clone(),
+            },
+            JobResult::Suspended { reason } => JobState::Suspended {
+                reason: reason.clone(),
+            },
+        };
+
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.state = state;
+            job.effective = effective;
+            self.publish(id, job);
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobStatus> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    /// The jobs of a printer not yet printed, rejected or failed, oldest first.
+    pub fn pending(&self, printer: &str) -> Vec<(u64, JobStatus)> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, job)| job.printer == printer && job.state.is_pending())
+            .map(|(id, job)| (*id, job.clone()))
+            .collect()
+    }
+
+    fn publish(&self, id: u64, job: &JobStatus) {
+        self.events.publish(Event::Job {
+            printer: job.printer.clone(),
+            id,
+            state: job.state.clone(),
+        });
+    }
+}
+
+impl JobState {
+    pub fn is_pending(&self) -> bool {
+        matches!(
+            self,
+            JobState::Validating
+                | JobState::Queued { .. }
+                | JobState::Rendering
+                | JobState::Sending
+        )
+    }
+}
+
+impl Progress {
+    pub fn new(intake: Arc<Intake>, id: Option<u64>) -> Self {
+        Progress { intake, id }
+    }
+
+    pub fn set(&self, state: JobState) {
+        if let Some(id) = self.id {
+            self.intake.set(id, state);
+        }
+    }
+}
+
+#[test]
+fn retains_recent_jobs() {
+    let intake = Intake::default();
+    let first = intake.register("a");
+
+    for _ in 0..RETAINED {
+        intake.register("b");
+    }
+
+    assert!(intake.get(first).is_none());
+
+    let last = intake.register("c");
+    let queued = intake.register("c");
+    intake.queued(queued, 1);
+    intake.finish(last, &JobResult::Printed, None);
+    assert!(matches!(intake.get(last).unwrap().state, JobState::Printed));
+
+    let pending = intake.pending("c");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].0, queued);
+}
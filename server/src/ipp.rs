@@ -0,0 +1,228 @@
+//! A minimal IPP destination for each printer, such that system print dialogs can use them.
+//!
+//! Only what clients need to submit raster documents is understood: printing, validating and
+//! querying printer attributes. Documents go through the same intake as the JSON API. See RFC 8010
+//! for the encoding and RFC 8011 for the semantics.
+
+/// Attribute group delimiters.
+pub mod group {
+    pub const OPERATION: u8 = 0x01;
+    pub const JOB: u8 = 0x02;
+    pub const END: u8 = 0x03;
+    pub const PRINTER: u8 = 0x04;
+}
+
+/// Value tags of attributes.
+pub mod tag {
+    pub const INTEGER: u8 = 0x21;
+    pub const BOOLEAN: u8 = 0x22;
+    pub const ENUM: u8 = 0x23;
+    pub const TEXT: u8 = 0x41;
+    pub const NAME: u8 = 0x42;
+    pub const KEYWORD: u8 = 0x44;
+    pub const URI: u8 = 0x45;
+    pub const CHARSET: u8 = 0x47;
+    pub const LANGUAGE: u8 = 0x48;
+    pub const MIME_TYPE: u8 = 0x49;
+}
+
+pub mod operation {
+    pub const PRINT_JOB: u16 = 0x0002;
+    pub const VALIDATE_JOB: u16 = 0x0004;
+    pub const GET_JOBS: u16 = 0x000A;
+    pub const GET_PRINTER_ATTRIBUTES: u16 = 0x000B;
+}
+
+pub mod status {
+    pub const OK: u16 = 0x0000;
+    pub const BAD_REQUEST: u16 = 0x0400;
+    pub const NOT_FOUND: u16 = 0x0406;
+    pub const DOCUMENT_FORMAT_NOT_SUPPORTED: u16 = 0x040A;
+    pub const OPERATION_NOT_SUPPORTED: u16 = 0x0501;
+    pub const NOT_ACCEPTING_JOBS: u16 = 0x0506;
+    pub const BUSY: u16 = 0x0507;
+}
+
+pub struct Request {
+    pub operation: u16,
+    pub request_id: u32,
+    pub attributes: Vec<Attribute>,
+    /// Everything after the attributes, the document of a print job.
+    pub document: Vec<u8>,
+}
+
+pub struct Attribute {
+    pub group: u8,
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+pub struct Response {
+    status: u16,
+    request_id: u32,
+    data: Vec<u8>,
+}
+
+impl Request {
+    pub fn parse(body: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader(body);
+        let _version = reader.take(2)?;
+        let operation = reader.u16()?;
+        let request_id =
+            u32::from_be_bytes(reader.take(4)?.try_into().unwrap());
+
+        let mut attributes: Vec<Attribute> = vec![];
+        let mut current = None;
+
+        loop {
+            let tag = reader.take(1)?[0];
+
+            if tag == group::END {
+                break;
+            }
+
+            // Delimiters start a new group.
+            if tag <= 0x0F {
+                current = Some(tag);
+                continue;
+            }
+
+            let group = current.ok_or("attribute outside of a group")?;
+            let name_len = reader.u16()? as usize;
+            let name = String::from_utf8_lossy(reader.take(name_len)?);
+            let value_len = reader.u16()? as usize;
+            let value = reader.take(value_len)?.to_vec();
+
+            // An empty name continues the values of the previous attribute.
+            let name = match (name.is_empty(), attributes.last()) {
+                (true, Some(last)) => last.name.clone(),
+                _ => name.into_owned(),
+            };
+
+            attributes.push(Attribute { group, name, value });
+        }
+
+        Ok(Request {
+            operation,
+            request_id,
+            attributes,
+            document: reader.0.to_vec(),
+        })
+    }
+
+    /// The first value of an operation attribute, as text.
+    pub fn operation_attribute(&self, name: &str) -> Option<String> {
+        self.attributes
+            .iter()
+            .find(|attr| attr.group == group::OPERATION && attr.name == name)
+            .map(|attr| String::from_utf8_lossy(&attr.value).into_owned())
+    }
+}
+
+impl Response {
+    /// Start a response, with the operation attributes every response carries.
+    pub fn new(status: u16, request_id: u32) -> Self {
+        let mut response = Response {
+            status,
+            request_id,
+            data: vec![],
+        };
+
+        response.group(group::OPERATION);
+        response.text(tag::CHARSET, "attributes-charset", "utf-8");
+        response.text(tag::LANGUAGE, "attributes-natural-language", "en");
+        response
+    }
+
+    pub fn with_message(status: u16, request_id: u32, message: &str) -> Self {
+        let mut response = Self::new(status, request_id);
+        response.text(tag::TEXT, "status-message", message);
+        response
+    }
+
+    pub fn group(&mut self, group: u8) {
+        self.data.push(group);
+    }
+
+    pub fn value(&mut self, tag: u8, name: &str, value: &[u8]) {
+        self.data.push(tag);
+        self.data
+            .extend_from_slice(&(name.len() as u16).to_be_bytes());
+        self.data.extend_from_slice(name.as_bytes());
+        self.data
+            .extend_from_slice(&(value.len() as u16).to_be_bytes());
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn text(&mut self, tag: u8, name: &str, value: &str) {
+        self.value(tag, name, value.as_bytes());
+    }
+
+    /// An attribute with several values of the same tag.
+    pub fn texts(&mut self, tag: u8, name: &str, values: &[&str]) {
+        for (idx, value) in values.iter().enumerate() {
+            let name = if idx == 0 { name } else { "" };
+            self.text(tag, name, value);
+        }
+    }
+
+    pub fn integer(&mut self, tag: u8, name: &str, value: i32) {
+        self.value(tag, name, &value.to_be_bytes());
+    }
+
+    pub fn encode(mut self) -> Vec<u8> {
+        let mut out = vec![2, 0];
+        out.extend_from_slice(&self.status.to_be_bytes());
+        out.extend_from_slice(&self.request_id.to_be_bytes());
+        self.data.push(group::END);
+        out.append(&mut self.data);
+        out
+    }
+}
+
+struct Reader<'data>(&'data [u8]);
+
+impl<'data> Reader<'data> {
+    fn take(&mut self, len: usize) -> Result<&'data [u8], String> {
+        if self.0.len() < len {
+            return Err("truncated request".to_string());
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+}
+
+#[test]
+fn parse_print_job() {
+    // Requests share the layout of responses, with the operation in place of the status.
+    let mut request = Response::new(operation::PRINT_JOB, 7);
+    request.text(tag::MIME_TYPE, "document-format", "image/png");
+    request.texts(tag::KEYWORD, "requested-attributes", &["a", "b"]);
+    let mut body = request.encode();
+    body.extend_from_slice(b"\x89PNG");
+
+    let request = Request::parse(&body).unwrap();
+    assert_eq!(request.operation, operation::PRINT_JOB);
+    assert_eq!(request.request_id, 7);
+    assert_eq!(
+        request.operation_attribute("document-format").as_deref(),
+        Some("image/png")
+    );
+    assert_eq!(
+        request
+            .attributes
+            .iter()
+            .filter(|attr| attr.name == "requested-attributes")
+            .count(),
+        2
+    );
+    assert_eq!(request.document, b"\x89PNG");
+
+    assert!(Request::parse(&body[..12]).is_err());
+}
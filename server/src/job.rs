@@ -1,15 +1,34 @@
-use std::sync::OnceLock;
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex},
+};
 
-use log::error;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 use zpl::{
-    command::HostIdentification,
-    label::{Label, LabelContent, Unit},
+    command::{BackfeedSequence, HostIdentification, PostPrintAction},
+    label::{Emphasis, Fit, Label, LabelContent, PrintOptions},
+    length::{Length, LengthUnit},
     resvg::{usvg, usvg::fontdb},
+    util::{
+        image::ImageCompression,
+        transform::{self, Transform},
+    },
 };
 
-use crate::{configuration::LabelDimensions, data_uri::DataUri};
+use crate::{
+    configuration::{
+        self, FontConfiguration, LabelDimensions, PassthroughLimits,
+        PrintLimits, SvgResourcePolicy,
+    },
+    data_uri::DataUri,
+    expiry::{self, ApiExpiry},
+    validation::{self, ValidationError},
+    zones,
+};
 
 #[derive(Deserialize)]
 pub struct PrintApi {
@@ -19,10 +38,132 @@ pub struct PrintApi {
     /// dimensions as given. This lets us mitigate a 'race condition' where the printer is
     /// physically reconfigured and reloaded without it being given a new name.
     pub dimensions: Option<LabelDimensions>,
+    /// Which way round to print the label.
+    #[serde(default)]
+    pub orientation: ApiOrientation,
+    /// Shrink SVG content running past the edges of the document, such as a long name, until it
+    /// fits. The factor applied is reported with the effective options.
+    #[serde(default)]
+    pub shrink_to_fit: bool,
+    /// The template this job was made from, to group statistics by.
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Printer settings to use for this job only.
+    #[serde(default)]
+    pub options: PrintApiOptions,
+    /// Print the content darker, without raising the darkness of the whole label.
+    #[serde(default)]
+    pub emphasis: Option<ApiEmphasis>,
+    /// The date and time, stamped by the printer when it prints the label.
+    #[serde(default)]
+    pub clock: Option<ApiClockField>,
+    /// The unit of the dimensions and positions in this job, instead of the server's.
+    #[serde(default)]
+    pub units: Option<LengthUnit>,
+    /// The day of printing and of expiry, filled into placeholders of SVG or ZPL content.
+    #[serde(default)]
+    pub expiry: Option<ApiExpiry>,
+    /// Jobs of high priority are printed before all waiting jobs of normal priority.
+    #[serde(default)]
+    pub priority: Priority,
+    /// Hold the job until this time, in RFC 3339 such as `2024-05-01T06:00:00+02:00`.
+    #[serde(default)]
+    pub not_before: Option<String>,
     #[serde(flatten)]
     pub kind: PrintApiKind,
 }
 
+/// Which lane of the printer's queue a job waits in.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    #[default]
+    Normal,
+    /// Such as an urgent reprint, ahead of a large batch.
+    High,
+}
+
+/// Overrides of the printer settings, validated against the printer's limits.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrintApiOptions {
+    pub darkness: Option<u32>,
+    /// Print speed in inches per second.
+    pub speed: Option<u32>,
+    pub post_print: Option<PostPrint>,
+    pub backfeed: Option<Backfeed>,
+    /// How to encode rasterized content, instead of the printer's default.
+    pub compression: Option<ImageCompression>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PostPrint {
+    Cut,
+    TearOff,
+    PeelOff,
+    RewindBatch,
+    Applicator,
+}
+
+impl From<PostPrint> for PostPrintAction {
+    fn from(action: PostPrint) -> Self {
+        match action {
+            PostPrint::Cut => PostPrintAction::Cut,
+            PostPrint::TearOff => PostPrintAction::TearOff,
+            PostPrint::PeelOff => PostPrintAction::PeelOff,
+            PostPrint::RewindBatch => PostPrintAction::RewindBatch,
+            PostPrint::Applicator => PostPrintAction::Applicator,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backfeed {
+    AfterPrinting,
+    BeforePrinting,
+    Default,
+    Off,
+    /// Multiples of 10, from 10 to 90.
+    Percent(u8),
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiEmphasis {
+    DoubleStrike,
+    Thicken { dots: u32 },
+}
+
+/// Which way round a label is printed, the method of mirroring left to the printer's
+/// configuration.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiOrientation {
+    /// Print the label mirrored, e.g. to apply it behind a transparent surface.
+    #[serde(default)]
+    pub mirrored: bool,
+    /// Print the label rotated by 180 degrees, e.g. to come out head first when applied.
+    #[serde(default)]
+    pub flipped: bool,
+}
+
+/// Text the printer fills with the time from its clock.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiClockField {
+    /// Placeholders such as `%Y-%m-%d %H:%M` in the printer's notation, see `^FC`.
+    pub format: String,
+    /// Position of the upper left corner on the label, in the unit of the job.
+    pub x: f32,
+    pub y: f32,
+    /// Height of the characters, in the unit of the job.
+    pub height: f32,
+}
+
 #[derive(Deserialize)]
 #[non_exhaustive]
 pub enum PrintApiKind {
@@ -32,113 +173,747 @@ pub enum PrintApiKind {
     #[serde(rename = "image")]
     #[non_exhaustive]
     Image { data: DataUri },
+    /// A page of a PDF document, rasterized like an image.
+    #[serde(rename = "pdf")]
+    #[non_exhaustive]
+    Pdf {
+        data: DataUri,
+        /// The page to print, counted from 1. The first by default.
+        #[serde(default)]
+        #[cfg_attr(not(feature = "pdf"), allow(dead_code))]
+        page: Option<u32>,
+    },
+    /// Commands sent to the printer as they are, for clients that already have ZPL.
+    #[serde(rename = "zpl")]
+    #[non_exhaustive]
+    Zpl { code: String },
 }
 
 /// The representation after ingestion by the API. We try to avoid IO, in particular fallible IO,
 /// after that representation has been reached. This reduces the number of late errors that must
 /// wait on a device to process the job to be noticed.
 #[non_exhaustive]
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
 pub enum PrintJob {
-    Svg { tree: usvg::Tree },
-    Image { image: image::DynamicImage },
+    Svg {
+        tree: usvg::Tree,
+        /// A hash of the document, to reuse its raster for later jobs.
+        key: Option<u64>,
+    },
+    Image {
+        image: image::DynamicImage,
+    },
+    /// Raw commands, these bypass rendering and all label settings.
+    Zpl {
+        code: String,
+    },
 }
 
+/// What the server understood of a job, for clients to compare with what they sent.
+#[derive(Serialize)]
+pub struct NormalizedJob<'a> {
+    pub content: NormalizedContent,
+    pub orientation: ApiOrientation,
+    pub shrink_to_fit: bool,
+    pub template: Option<&'a str>,
+    pub options: &'a PrintApiOptions,
+    pub emphasis: Option<ApiEmphasis>,
+    pub clock: Option<&'a ApiClockField>,
+    pub expiry: Option<&'a ApiExpiry>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NormalizedContent {
+    /// The size of the document, in its own units.
+    Svg {
+        width: f32,
+        height: f32,
+    },
+    /// A decoded image, or the rasterized page of a PDF document.
+    Image {
+        mime: String,
+        width: u32,
+        height: u32,
+        color: String,
+    },
+    Zpl {
+        bytes: usize,
+    },
+}
+
+/// Settings chosen by the client for one job, independent of its content.
+#[derive(Clone, Default)]
+pub struct JobOptions {
+    pub orientation: ApiOrientation,
+    pub shrink_to_fit: bool,
+    pub overrides: PrintApiOptions,
+    pub emphasis: Option<ApiEmphasis>,
+    pub clock: Option<ApiClockField>,
+    pub priority: Priority,
+}
+
+/// Options by the font directories of printers, besides those of the deployment.
+static SVG_OPTIONS: LazyLock<Mutex<HashMap<Vec<PathBuf>, SvgOptions>>> =
+    LazyLock::new(Default::default);
+
+type SvgOptions = Arc<usvg::Options<'static>>;
+
+/// The fonts of the deployment.
+static FONTS: Mutex<FontConfiguration> = Mutex::new(FontConfiguration {
+    system: true,
+    directories: Vec::new(),
+});
+
+/// Where images referenced by documents may be loaded from.
+static SVG_RESOURCES: Mutex<SvgResourcePolicy> =
+    Mutex::new(SvgResourcePolicy::DataOnly);
+
 impl PrintApi {
-    pub fn validate_as_job(&self) -> anyhow::Result<PrintJob> {
+    /// The submitted content, as it was received.
+    pub fn payload(&self) -> &[u8] {
+        match &self.kind {
+            PrintApiKind::Svg { code } => code.as_bytes(),
+            PrintApiKind::Image { data } => &data.data,
+            PrintApiKind::Pdf { data, .. } => &data.data,
+            PrintApiKind::Zpl { code } => code.as_bytes(),
+        }
+    }
+
+    /// Convert all lengths to mm, from the unit of the job or else the given one.
+    pub fn convert_to_millimetres(&mut self, units: LengthUnit) {
+        let units = self.units.take().unwrap_or(units);
+
+        if let Some(dimensions) = &mut self.dimensions {
+            *dimensions = dimensions.in_millimetres(units);
+        }
+
+        if let Some(clock) = &mut self.clock {
+            clock.x = units.to_mm(clock.x);
+            clock.y = units.to_mm(clock.y);
+            clock.height = units.to_mm(clock.height);
+        }
+    }
+
+    /// Fill the dates of an expiry label, printed at a Unix time, into the content.
+    pub fn fill_expiry(
+        &mut self,
+        now: i64,
+        site_utc_offset: i32,
+    ) -> anyhow::Result<()> {
+        let Some(expiry) = &self.expiry else {
+            return Ok(());
+        };
+
+        let values = expiry.values(now, site_utc_offset)?;
+        match &mut self.kind {
+            PrintApiKind::Svg { code } => {
+                expiry::fill(code, &values, expiry::escape_xml)
+            }
+            PrintApiKind::Zpl { code } => {
+                expiry::fill(code, &values, expiry::check_zpl)
+            }
+            _ => anyhow::bail!(
+                "Expiry dates can only be filled into SVG or ZPL content"
+            ),
+        }
+    }
+
+    pub fn job_options(&self) -> JobOptions {
+        JobOptions {
+            orientation: self.orientation,
+            shrink_to_fit: self.shrink_to_fit,
+            overrides: self.options.clone(),
+            emphasis: self.emphasis,
+            clock: self.clock.clone(),
+            priority: self.priority,
+        }
+    }
+
+    /// Describe the job validated from this request.
+    pub fn normalize<'a>(&'a self, job: &PrintJob) -> NormalizedJob<'a> {
+        let content = match job {
+            PrintJob::Svg { tree, .. } => NormalizedContent::Svg {
+                width: tree.size().width(),
+                height: tree.size().height(),
+            },
+            PrintJob::Image { image } => NormalizedContent::Image {
+                mime: match &self.kind {
+                    PrintApiKind::Image { data }
+                    | PrintApiKind::Pdf { data, .. } => data.mime.clone(),
+                    _ => String::new(),
+                },
+                width: image.width(),
+                height: image.height(),
+                color: format!("{:?}", image.color()),
+            },
+            PrintJob::Zpl { code } => {
+                NormalizedContent::Zpl { bytes: code.len() }
+            }
+        };
+
+        NormalizedJob {
+            content,
+            orientation: self.orientation,
+            shrink_to_fit: self.shrink_to_fit,
+            template: self.template.as_deref(),
+            options: &self.options,
+            emphasis: self.emphasis,
+            clock: self.clock.as_ref(),
+            expiry: self.expiry.as_ref(),
+        }
+    }
+
+    /// Decode the content, setting text in the fonts of the deployment and these directories.
+    pub fn validate_as_job(
+        &self,
+        fonts: &[PathBuf],
+    ) -> Result<PrintJob, ValidationError> {
         Ok(match &self.kind {
             PrintApiKind::Svg { code } => {
-                let tree = usvg::Tree::from_str(&code, Self::svg_options())?;
-                PrintJob::Svg { tree }
+                let options = Self::printer_svg_options(fonts);
+                let tree = usvg::Tree::from_str(code, &options)
+                    .map_err(|error| ValidationError::svg(&error))?;
+                // Rasters differ between printers of different fonts.
+                let mut hasher = std::hash::DefaultHasher::new();
+                (code, fonts).hash(&mut hasher);
+                PrintJob::Svg {
+                    tree,
+                    key: Some(hasher.finish()),
+                }
             }
             PrintApiKind::Image { data: uri } => {
-                let data = std::io::Cursor::new(uri.data.clone());
-                let image = {
-                    let mut reader = image::io::Reader::new(data);
+                let image = decode_image(&uri.data, &uri.mime)?;
 
-                    let format_hint = match uri.mime.as_str() {
-                        "image/png" | "application/png" => {
-                            Some(image::ImageFormat::Png)
-                        }
-                        "image/jpg" | "image/jpeg" => {
-                            Some(image::ImageFormat::Jpeg)
+                PrintJob::Image { image }
+            }
+            #[cfg(feature = "pdf")]
+            PrintApiKind::Pdf { data, page } => {
+                let image =
+                    crate::pdf::rasterize(&data.data, page.unwrap_or(1))
+                        .map_err(ValidationError::content)?;
+                PrintJob::Image { image }
+            }
+            #[cfg(not(feature = "pdf"))]
+            PrintApiKind::Pdf { .. } => {
+                return Err(ValidationError::UnsupportedMime {
+                    mime: "application/pdf".to_string(),
+                    supported: validation::IMAGE_TYPES,
+                })
+            }
+            PrintApiKind::Zpl { code } => PrintJob::Zpl { code: code.clone() },
+        })
+    }
+
+    /// Check raw ZPL against the limits of the printer, other content passes.
+    pub fn validate_passthrough(
+        &self,
+        limits: &PassthroughLimits,
+    ) -> Result<(), String> {
+        let PrintApiKind::Zpl { code } = &self.kind else {
+            return Ok(());
+        };
+
+        if self.orientation.mirrored
+            || self.orientation.flipped
+            || self.emphasis.is_some()
+            || self.clock.is_some()
+            || self.options.darkness.is_some()
+            || self.options.speed.is_some()
+            || self.options.post_print.is_some()
+            || self.options.backfeed.is_some()
+            || self.options.compression.is_some()
+        {
+            return Err("Raw ZPL is sent as is, without options".to_string());
+        }
+
+        if code.len() > limits.max_bytes {
+            return Err(format!(
+                "Raw ZPL of {} bytes exceeds the limit of {} bytes",
+                code.len(),
+                limits.max_bytes
+            ));
+        }
+
+        let upper = code.to_ascii_uppercase();
+
+        for command in ["^CC", "~CC", "^CT", "~CT"] {
+            if upper.contains(command) {
+                return Err(format!(
+                    "Raw ZPL must not change command prefixes ({command})"
+                ));
+            }
+        }
+
+        for command in &limits.forbidden {
+            if upper.contains(&command.to_ascii_uppercase()) {
+                return Err(format!("Raw ZPL must not use {command}"));
+            }
+        }
+
+        // Every label format is closed before the next one starts.
+        let mut formats = 0;
+        let mut open = false;
+        for (idx, _) in upper.match_indices('^') {
+            match upper.get(idx + 1..idx + 3) {
+                Some("XA") if open => {
+                    return Err("Unterminated ^XA before ^XA".to_string())
+                }
+                Some("XA") => open = true,
+                Some("XZ") if !open => {
+                    return Err("^XZ without a preceding ^XA".to_string())
+                }
+                Some("XZ") => {
+                    open = false;
+                    formats += 1;
+                }
+                _ => {}
+            }
+        }
+
+        if open {
+            return Err("Unterminated ^XA at the end".to_string());
+        }
+
+        if formats == 0 {
+            return Err("Raw ZPL contains no label format".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Get SVG parsing and rendering options for usvg / resvg, with the fonts of the deployment.
+    ///
+    /// The options are shared between labels, and loaded afresh after a reset such as when the
+    /// configured fonts change.
+    pub fn svg_options() -> Arc<usvg::Options<'static>> {
+        Self::printer_svg_options(&[])
+    }
+
+    /// Options with the fonts of a printer, loaded from its directories besides those of the
+    /// deployment.
+    pub fn printer_svg_options(
+        directories: &[PathBuf],
+    ) -> Arc<usvg::Options<'static>> {
+        let mut options = SVG_OPTIONS.lock().unwrap();
+        if let Some(options) = options.get(directories) {
+            return options.clone();
+        }
+
+        let loaded = Self::load_svg_options(directories);
+        options.insert(directories.to_vec(), loaded.clone());
+        loaded
+    }
+
+    fn load_svg_options(
+        directories: &[PathBuf],
+    ) -> Arc<usvg::Options<'static>> {
+        let fonts = FONTS.lock().unwrap().clone();
+        let mut db = fontdb::Database::new();
+        if fonts.system {
+            db.load_system_fonts();
+        }
+
+        for directory in fonts.directories.iter().chain(directories) {
+            db.load_fonts_dir(directory);
+        }
+
+        let policy = SVG_RESOURCES.lock().unwrap().clone();
+        let resources_dir = match &policy {
+            SvgResourcePolicy::DataOnly => None,
+            SvgResourcePolicy::Directory(path) => path.canonicalize().ok(),
+        };
+
+        Arc::new(usvg::Options {
+            fontdb: db.into(),
+            resources_dir: resources_dir.clone(),
+            image_href_resolver: usvg::ImageHrefResolver {
+                resolve_data: usvg::ImageHrefResolver::default_data_resolver(),
+                resolve_string: Box::new(move |href, options| {
+                    let Some(directory) = &resources_dir else {
+                        warn!("Skipping image {href:?}, only data URIs are allowed");
+                        return None;
+                    };
+
+                    // Canonical, such that neither `..` nor links lead out of the directory.
+                    match directory.join(href).canonicalize() {
+                        Ok(path) if path.starts_with(directory) => {
+                            (usvg::ImageHrefResolver::default_string_resolver())(
+                                path.to_str()?,
+                                options,
+                            )
                         }
-                        other => {
-                            error!("Unknown image format {other}");
+                        _ => {
+                            warn!("Skipping image {href:?}, not within {directory:?}");
                             None
                         }
-                    };
-
-                    if let Some(format) = format_hint {
-                        reader.set_format(format);
                     }
+                }),
+            },
+            ..Default::default()
+        })
+    }
 
-                    reader.decode()?
-                };
+    /// Forget the shared options, such that fonts are loaded afresh for the next job.
+    pub fn reset_svg_options() {
+        SVG_OPTIONS.lock().unwrap().clear();
+    }
 
-                PrintJob::Image { image }
+    /// Load the fonts of the deployment afresh, returning the number of faces.
+    ///
+    /// Those of printers are loaded for their next job. Jobs already parsed keep the fonts they
+    /// were parsed with.
+    pub fn reload_fonts() -> usize {
+        let options = Self::load_svg_options(&[]);
+        let faces = options.fontdb.len();
+        let mut cache = SVG_OPTIONS.lock().unwrap();
+        cache.clear();
+        cache.insert(vec![], options);
+        faces
+    }
+
+    /// Load images referenced by documents according to this policy from now on.
+    pub fn set_svg_resources(policy: &SvgResourcePolicy) {
+        let mut current = SVG_RESOURCES.lock().unwrap();
+        if *current != *policy {
+            *current = policy.clone();
+            drop(current);
+            Self::reset_svg_options();
+        }
+    }
+
+    /// Set text in these fonts from now on.
+    pub fn set_fonts(fonts: &FontConfiguration) {
+        let mut current = FONTS.lock().unwrap();
+        if *current != *fonts {
+            *current = fonts.clone();
+            drop(current);
+            Self::reset_svg_options();
+        }
+    }
+}
+
+/// The most dots content may be thickened by, beyond which it only blurs.
+pub const MAX_THICKEN_DOTS: u32 = 3;
+
+impl ApiEmphasis {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ApiEmphasis::Thicken { dots } if *dots > MAX_THICKEN_DOTS => {
+                Err(format!(
+                    "Thickening by {dots} dots exceeds the limit of {MAX_THICKEN_DOTS}"
+                ))
             }
-        })
+            _ => Ok(()),
+        }
     }
+}
 
-    /// Get SVG parsing and rendering options for usvg / resvg.
-    ///
-    /// Keep in mind this is one choice. It's not clear if this should be a static and if not,
-    /// which object should keep the authoritative version and how to refresh them. But in
-    /// particular using the system font database introduces a hard host-dependency that is
-    /// implicit. For stronger reproducibility / fingerprint resistance (okay maybe that concern is
-    /// hardly realistic) it would be better to have an explicit list shared by the host
-    /// environment or at least allowing it to override. And then if we combine that with
-    /// hot-reloading we get fully dynamic state that we nevertheless want to share between labels
-    /// being printed.
-    fn svg_options() -> &'static usvg::Options<'static> {
-        static ONCE: OnceLock<usvg::Options> = OnceLock::new();
-        ONCE.get_or_init(|| {
-            let mut db = fontdb::Database::new();
-            db.load_system_fonts();
+impl From<ApiEmphasis> for Emphasis {
+    fn from(emphasis: ApiEmphasis) -> Self {
+        match emphasis {
+            ApiEmphasis::DoubleStrike => Emphasis::DoubleStrike,
+            ApiEmphasis::Thicken { dots } => Emphasis::Thicken { dots },
+        }
+    }
+}
 
-            usvg::Options {
-                fontdb: db.into(),
-                ..Default::default()
+impl PrintApiOptions {
+    /// Check that the overrides stay within what the printer permits.
+    pub fn validate(&self, limits: &PrintLimits) -> Result<(), String> {
+        fn within(
+            name: &str,
+            value: Option<u32>,
+            (min, max): (u32, u32),
+        ) -> Result<(), String> {
+            match value {
+                Some(value) if value < min || value > max => Err(format!(
+                    "{name} {value} outside of the permitted {min} to {max}"
+                )),
+                _ => Ok(()),
             }
-        })
+        }
+
+        within("Darkness", self.darkness, limits.darkness)?;
+        within("Speed", self.speed, limits.speed)?;
+
+        if self.post_print == Some(PostPrint::Cut) && !limits.cutter {
+            return Err("The printer has no cutter".to_string());
+        }
+
+        if let Some(Backfeed::Percent(p)) = self.backfeed {
+            if !(10..=90).contains(&p) {
+                return Err(format!("Backfeed {p}% outside of 10% to 90%"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(&self, options: &mut PrintOptions) {
+        options.darkness = self.darkness.map(|d| d as usize);
+        options.speed = self.speed.map(|s| s as usize);
+        if let Some(action) = self.post_print {
+            options.post_print = Some(action.into());
+        }
+        options.backfeed = self.backfeed.map(|backfeed| match backfeed {
+            Backfeed::AfterPrinting => BackfeedSequence::AfterPrinting,
+            Backfeed::BeforePrinting => BackfeedSequence::BeforePrinting,
+            Backfeed::Default => BackfeedSequence::Default,
+            Backfeed::Off => BackfeedSequence::Off,
+            Backfeed::Percent(p) => BackfeedSequence::Percent(p),
+        });
+        if let Some(compression) = self.compression {
+            options.compression = compression;
+        }
+    }
+}
+
+/// The image formats decoded, by the MIME types clients name them with.
+const IMAGE_FORMATS: &[(image::ImageFormat, &[&str])] = &[
+    (image::ImageFormat::Png, &["image/png", "application/png"]),
+    (image::ImageFormat::Jpeg, &["image/jpeg", "image/jpg"]),
+    (image::ImageFormat::Gif, &["image/gif"]),
+    (image::ImageFormat::Bmp, &["image/bmp", "image/x-ms-bmp"]),
+    (image::ImageFormat::Tiff, &["image/tiff"]),
+    (image::ImageFormat::WebP, &["image/webp"]),
+];
+
+/// Decode an image by its content, the MIME type only counts for formats without a signature.
+fn decode_image(
+    data: &[u8],
+    mime: &str,
+) -> Result<image::DynamicImage, ValidationError> {
+    let supported = |format| {
+        IMAGE_FORMATS
+            .iter()
+            .any(|(supported, _)| *supported == format)
+    };
+    let named = IMAGE_FORMATS
+        .iter()
+        .find(|(_, mimes)| mimes.contains(&mime))
+        .map(|(format, _)| *format);
+
+    let format = match image::guess_format(data) {
+        Ok(format) if supported(format) => format,
+        Ok(format) => {
+            return Err(ValidationError::UnsupportedMime {
+                mime: format.to_mime_type().to_string(),
+                supported: validation::IMAGE_TYPES,
+            })
+        }
+        Err(_) => match named {
+            Some(format) => format,
+            None => {
+                return Err(ValidationError::UnsupportedMime {
+                    mime: mime.to_string(),
+                    supported: validation::IMAGE_TYPES,
+                })
+            }
+        },
+    };
+
+    if named.is_some_and(|named| named != format) {
+        warn!("Image sent as {mime} is {}", format.to_mime_type());
     }
+
+    image::load_from_memory_with_format(data, format).map_err(|error| {
+        ValidationError::UndecodableImage {
+            message: format!(
+                "Failed to decode the {} image: {error}",
+                format.to_mime_type()
+            ),
+            supported: validation::IMAGE_TYPES,
+        }
+    })
 }
 
 impl PrintJob {
+    /// Place the content on a label of the stock, with its exclusion zones and the
+    /// transforms of the printer applied.
     pub fn into_label(
         self,
-        dim: &LabelDimensions,
+        stock: &configuration::Label,
         host: &HostIdentification,
-    ) -> Label {
+        options: &JobOptions,
+        transforms: &[Transform],
+    ) -> anyhow::Result<Label> {
+        let dim = &stock.dimensions;
         let cwidth = (dim.width - dim.margin_left - dim.margin_right).max(0.0);
         let cheight =
             (dim.height - dim.margin_top - dim.margin_bottom).max(0.0);
 
-        let width = dim.width as u32;
-        let height = dim.height as u32;
-        let mut label = Label::new(width, height, host.dpmm);
+        let mut label = Label::new(dim.width, dim.height, host.dpmm);
 
         match self {
-            PrintJob::Svg { tree } => {
+            PrintJob::Svg { tree, key } => {
                 label.content.push(LabelContent::SvgTree {
                     tree,
-                    x: Unit::Millimetres(dim.margin_left),
-                    y: Unit::Millimetres(dim.margin_top),
-                    w: Unit::Millimetres(cwidth),
-                    h: Unit::Millimetres(cheight),
+                    key,
+                    x: Length::mm(dim.margin_left),
+                    y: Length::mm(dim.margin_top),
+                    w: Length::mm(cwidth),
+                    h: Length::mm(cheight),
+                    shrink: options.shrink_to_fit,
                 });
             }
             PrintJob::Image { image } => {
                 label.content.push(LabelContent::Image {
                     img: image,
-                    x: Unit::Millimetres(dim.margin_left),
-                    y: Unit::Millimetres(dim.margin_top),
-                    w: Unit::Millimetres(cwidth),
-                    h: Unit::Millimetres(cheight),
+                    x: Length::mm(dim.margin_left),
+                    y: Length::mm(dim.margin_top),
+                    w: Length::mm(cwidth),
+                    h: Length::mm(cheight),
+                    fit: Fit::Cover,
                 });
             }
+            // Nothing we can render, the commands are sent separately.
+            PrintJob::Zpl { .. } => {}
+        }
+
+        if let Some(emphasis) = options.emphasis {
+            label.content = label
+                .content
+                .into_iter()
+                .map(|content| content.emphasized(emphasis.into()))
+                .collect();
+        }
+
+        // Rendered by the printer, kept out of zones like all other content.
+        if let Some(clock) = &options.clock {
+            label.content.push(LabelContent::ClockField {
+                format: clock.format.clone(),
+                x: Length::mm(clock.x),
+                y: Length::mm(clock.y),
+                height: Length::mm(clock.height),
+            });
+        }
+
+        zones::mask(&mut label, &stock.exclusion_zones)?;
+
+        if !transforms.is_empty() {
+            let dpmm = label.dpmm;
+            label.flatten(|image| transform::apply(image, transforms, dpmm))?;
         }
 
-        label
+        Ok(label)
+    }
+}
+
+#[test]
+fn passthrough_validation() {
+    let job = |code: &str| PrintApi {
+        dimensions: None,
+        orientation: Default::default(),
+        shrink_to_fit: false,
+        template: None,
+        options: Default::default(),
+        emphasis: None,
+        clock: None,
+        units: None,
+        expiry: None,
+        priority: Default::default(),
+        not_before: None,
+        kind: PrintApiKind::Zpl {
+            code: code.to_string(),
+        },
+    };
+
+    let limits = PassthroughLimits::default();
+    let valid = job("~SD20^XA^FO10,10^FDHello^FS^XZ\n^xa^xz");
+    assert!(valid.validate_passthrough(&limits).is_ok());
+
+    for invalid in [
+        "^XA^FDHello^FS",
+        "^XZ^XA^XZ",
+        "^XA^XA^XZ",
+        "~SD20",
+        "^XA^JUF^XZ",
+        "^XA~jr^XZ",
+        "^XA^CC+^XZ",
+    ] {
+        assert!(job(invalid).validate_passthrough(&limits).is_err());
+    }
+
+    let mut mirrored = job("^XA^XZ");
+    mirrored.orientation.mirrored = true;
+    assert!(mirrored.validate_passthrough(&limits).is_err());
+
+    let small = PassthroughLimits {
+        max_bytes: 4,
+        ..PassthroughLimits::default()
+    };
+    assert!(job("^XA^XZ").validate_passthrough(&small).is_err());
+}
+
+#[test]
+fn thickening_bounded() {
+    let thicken = |dots| ApiEmphasis::Thicken { dots }.validate();
+    assert!(thicken(MAX_THICKEN_DOTS).is_ok());
+    assert!(thicken(MAX_THICKEN_DOTS + 1).is_err());
+    assert!(ApiEmphasis::DoubleStrike.validate().is_ok());
+}
+
+#[test]
+fn images_by_content() {
+    let mut gif = std::io::Cursor::new(vec![]);
+    image::DynamicImage::new_luma8(2, 3)
+        .write_to(&mut gif, image::ImageFormat::Gif)
+        .unwrap();
+
+    // Named wrongly, but recognized by its signature.
+    let Ok(image) = decode_image(gif.get_ref(), "image/png") else {
+        panic!("GIF not decoded");
+    };
+    assert_eq!((image.width(), image.height()), (2, 3));
+
+    let unknown = decode_image(b"not an image", "text/plain");
+    assert!(matches!(
+        unknown,
+        Err(ValidationError::UnsupportedMime { mime, .. }) if mime == "text/plain"
+    ));
+
+    let truncated = decode_image(&gif.get_ref()[..20], "image/gif");
+    assert!(matches!(
+        truncated,
+        Err(ValidationError::UndecodableImage { .. })
+    ));
+}
+
+#[test]
+fn svg_files_not_loaded() {
+    use base64::Engine as _;
+
+    let mut png = std::io::Cursor::new(vec![]);
+    image::DynamicImage::new_luma8(2, 2)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let file = directory.path().join("secret.png");
+    std::fs::write(&file, png.get_ref()).unwrap();
+
+    let embedded =
+        base64::engine::general_purpose::STANDARD.encode(png.get_ref());
+    let code = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2">
+            <image href="{}" width="2" height="2"/>
+            <image href="data:image/png;base64,{embedded}" x="2" width="2" height="2"/>
+        </svg>"#,
+        file.display()
+    );
+
+    let tree = usvg::Tree::from_str(&code, &PrintApi::svg_options()).unwrap();
+    fn images(group: &usvg::Group) -> usize {
+        group
+            .children()
+            .iter()
+            .map(|node| match node {
+                usvg::Node::Image(_) => 1,
+                usvg::Node::Group(group) => images(group),
+                _ => 0,
+            })
+            .sum()
     }
+    assert_eq!(images(tree.root()), 1);
 }
@@ -0,0 +1,210 @@
+//! Sets up logging, keeping the most recent log lines in memory in addition to the usual output.
+//!
+//! Lets a support bundle include what happened before a problem was noticed, without requiring
+//! the logs to have been captured elsewhere.
+//!
+//! Jobs are logged within a `job` span naming their printer and, if they are tracked, their id.
+//! Within it, the phases of printing have spans of their own: `validate`, `render`, `send` and
+//! `confirm`, the last recording the outcome.
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    sync::{Mutex, OnceLock},
+};
+
+use tracing::{
+    field::{self, Field},
+    span, Event, Span, Subscriber,
+};
+use tracing_log::NormalizeEvent as _;
+use tracing_subscriber::{
+    filter::LevelFilter,
+    layer::{Context, SubscriberExt as _},
+    registry::LookupSpan,
+    util::SubscriberInitExt as _,
+    EnvFilter, Layer,
+};
+
+/// How many lines to keep.
+const CAPACITY: usize = 2000;
+
+static RECENT: OnceLock<Mutex<VecDeque<String>>> = OnceLock::new();
+
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One object per line, with the spans an event happened in.
+    Json,
+}
+
+/// Keeps info messages and above, whatever is written out.
+struct Capture;
+
+/// The fields of a span, as written in the kept lines.
+struct SpanFields(String);
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    text: String,
+}
+
+/// Install the logger filtered by `RUST_LOG`, also keeping info messages and above.
+///
+/// Messages of the `log` crate, such as those of the library, are logged alike.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::ERROR.into())
+        .from_env_lossy();
+
+    let output = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let output = match format {
+        LogFormat::Text => output.boxed(),
+        LogFormat::Json => output.json().with_span_list(true).boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(output.with_filter(filter))
+        .with(Capture.with_filter(LevelFilter::INFO))
+        .try_init()
+        .expect("the logger is installed only once");
+}
+
+/// The span of the events of a job, naming its printer and its id if it is tracked.
+pub fn job_span(printer: &str, id: Option<u64>) -> Span {
+    let span = tracing::info_span!("job", printer, id = field::Empty);
+    if let Some(id) = id {
+        span.record("id", id);
+    }
+
+    span
+}
+
+/// The kept lines, oldest first.
+pub fn recent() -> Vec<String> {
+    let Some(lines) = RECENT.get() else {
+        return vec![];
+    };
+
+    lines.lock().unwrap().iter().cloned().collect()
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+    fn on_new_span(
+        &self,
+        attrs: &span::Attributes<'_>,
+        id: &span::Id,
+        ctx: Context<'_, S>,
+    ) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.text));
+        }
+    }
+
+    fn on_record(
+        &self,
+        id: &span::Id,
+        values: &span::Record<'_>,
+        ctx: Context<'_, S>,
+    ) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(text)) = extensions.get_mut::<SpanFields>() {
+            let mut fields = Fields {
+                text: std::mem::take(text),
+                ..Fields::default()
+            };
+            values.record(&mut fields);
+            *text = fields.text;
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let mut line =
+            format!("{timestamp} {} {}: ", metadata.level(), metadata.target());
+
+        for span in ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            line.push_str(span.name());
+            if let Some(SpanFields(text)) =
+                span.extensions().get::<SpanFields>()
+            {
+                if !text.is_empty() {
+                    let _ = write!(line, "{{{text}}}");
+                }
+            }
+            line.push_str(": ");
+        }
+
+        line.push_str(&fields.message);
+        if !fields.text.is_empty() {
+            let _ = write!(line, " {}", fields.text);
+        }
+
+        let mut lines = RECENT.get_or_init(Mutex::default).lock().unwrap();
+        if lines.len() == CAPACITY {
+            lines.pop_front();
+        }
+
+        lines.push_back(line);
+    }
+}
+
+impl field::Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            // Where a message of the `log` crate came from, already in its metadata.
+            name if name.starts_with("log.") => {}
+            name => {
+                if !self.text.is_empty() {
+                    self.text.push(' ');
+                }
+                let _ = write!(self.text, "{name}={value:?}");
+            }
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{value}"));
+    }
+}
+
+#[test]
+fn kept_lines() {
+    let subscriber = tracing_subscriber::registry().with(Capture);
+    tracing::subscriber::with_default(subscriber, || {
+        let _job = job_span("gx430t", Some(3)).entered();
+        let _render = tracing::info_span!("render").entered();
+        tracing::info!(dots = 64, "Rendered");
+    });
+
+    // Named by the module of whichever binary it was logged in.
+    let line = recent().pop().unwrap();
+    let (_, module) = line.split_once(" INFO ").unwrap();
+    assert!(
+        module.ends_with(
+            "::logs: job{printer=gx430t id=3}: render: Rendered dots=64"
+        ),
+        "{line}"
+    );
+}
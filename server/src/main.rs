@@ -1,21 +1,67 @@
+mod advertise;
 mod app;
+mod artifacts;
 mod configuration;
 mod data_uri;
+mod dead_letter;
+mod drain;
+mod events;
+mod expiry;
+#[cfg(feature = "fault-injection")]
+mod faults;
+mod firmware;
+mod history;
+mod idempotency;
+mod intake;
+mod ipp;
 mod job;
+mod logs;
+mod media;
+mod metrics;
+mod notify;
+#[cfg(feature = "pdf")]
+mod pdf;
 mod physical_printer;
+mod pull;
+mod pull_api;
+mod render;
+mod schedule;
 mod spa;
+mod statistics;
+mod support;
+mod validation;
+mod watchdog;
+mod watcher;
+mod ws;
+mod zones;
 
 use crate::app::App;
+use crate::validation::ValidationError;
 
 use axum::{
-    extract::{Path, State},
-    routing::{get, post},
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, Path, Query, State},
+    http::{
+        header::{
+            AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, HOST,
+            RETRY_AFTER,
+        },
+        HeaderMap, StatusCode,
+    },
+    response::IntoResponse,
+    routing::{delete, get, post},
     Json, Router,
 };
 use clap::Parser;
 
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
-use tokio::{sync::RwLock, task::JoinSet};
+use serde::Deserialize;
+
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio::{
+    sync::{watch, RwLock},
+    task::JoinSet,
+};
+use tracing::Instrument as _;
 
 pub struct ShutdownToken;
 
@@ -28,6 +74,26 @@ struct PrintResources {
     configuration: PathBuf,
     active_printer: JoinSet<()>,
     printer: HashMap<String, PrintQueue>,
+    services: physical_printer::Services,
+    /// The configuration files to watch for changes, if enabled.
+    watched: watch::Sender<Option<Vec<PathBuf>>>,
+    /// The port the API is served on, to advertise.
+    port: u16,
+    advertisement: Option<advertise::Advertisement>,
+    /// Whether printers are exposed as IPP destinations.
+    ipp: bool,
+    /// The largest document accepted via IPP, in bytes.
+    ipp_max_document: usize,
+    watchdog: Option<configuration::WatchdogConfiguration>,
+    idempotency: configuration::IdempotencyConfiguration,
+    /// Required from callers of administrative endpoints, which are disabled without it.
+    admin_token: Option<String>,
+    /// Firmware updates staged for or delivered to printers, kept across reloads.
+    firmware: Arc<firmware::Updates>,
+    /// The unit of lengths shown, and of those in jobs which do not name one.
+    units: zpl::length::LengthUnit,
+    /// Minutes ahead of UTC of the site, for dates filled into jobs.
+    utc_offset: i32,
 }
 
 struct PrintQueue {
@@ -36,16 +102,27 @@ struct PrintQueue {
 }
 
 async fn reload(State(state): State<Server>) -> String {
-    let configuration = match {
+    let configuration = {
         let state = state.inner.read().await;
         configuration::Configuration::from_file(&state.configuration).await
-    } {
+    };
+
+    let mut configuration = match configuration {
         Ok(cfg) => cfg,
         Err(error) => {
             return error.to_string();
         }
     };
 
+    // Fail before tearing down the current printers.
+    let notifier = match configuration.notifications.take() {
+        Some(cfg) => match notify::Notifier::new(cfg) {
+            Ok(notifier) => Some(Arc::new(notifier)),
+            Err(error) => return error.to_string(),
+        },
+        None => None,
+    };
+
     let mut state = state.inner.write().await;
 
     // Drop all standing connections.
@@ -54,6 +131,56 @@ async fn reload(State(state): State<Server>) -> String {
     // should be dropped by themselves? Maybe we should just shove them into the background.
     while let Some(_next) = state.active_printer.join_next().await {}
 
+    let watched = configuration.watch.then(|| configuration.sources.clone());
+    state.watched.send_replace(watched);
+
+    state.services.history = configuration
+        .job_log
+        .clone()
+        .map(|path| Arc::new(history::JobLog::new(path)));
+
+    state.services.notifier = notifier;
+
+    let rendering = std::mem::take(&mut configuration.rendering);
+    state.services.limiter = Arc::new(render::RenderLimiter::new(rendering));
+
+    state.services.artifacts = configuration.artifacts.as_ref().map(|cfg| {
+        Arc::new(artifacts::ArtifactStore::new(
+            cfg.path.clone(),
+            cfg.retention,
+        ))
+    });
+
+    state.ipp = configuration.ipp;
+    state.ipp_max_document =
+        configuration.ipp_max_document.unwrap_or(IPP_MAX_DOCUMENT);
+    state.watchdog = configuration.watchdog.clone();
+    state.idempotency = configuration.idempotency.clone();
+    state.admin_token = configuration.admin_token.clone();
+    state.units = configuration.units;
+    state.utc_offset = configuration.utc_offset;
+    state
+        .services
+        .statistics
+        .set_templates(configuration.templates.clone());
+    job::PrintApi::set_fonts(&configuration.fonts());
+    job::PrintApi::set_svg_resources(&configuration.svg_resources);
+
+    // Replace rather than update, such that printers no longer configured disappear.
+    state.advertisement = None;
+    if let Some(advertise) = &configuration.advertise {
+        match advertise::Advertisement::register(
+            advertise,
+            &configuration,
+            state.port,
+        ) {
+            Ok(advertisement) => state.advertisement = Some(advertisement),
+            Err(error) => {
+                tracing::warn!("Failed to advertise via mDNS: {error}")
+            }
+        }
+    }
+
     for (name, printer) in &configuration.printers {
         let Some(printer) = physical_printer::LabelPrinter::new(
             &configuration,
@@ -63,7 +190,10 @@ async fn reload(State(state): State<Server>) -> String {
         };
 
         let (driver, con) = physical_printer::Driver::new(&printer);
-        let printer = physical_printer::PhysicalPrinter::new(printer);
+        let printer = physical_printer::PhysicalPrinter::new(
+            printer,
+            state.services.clone(),
+        );
 
         let con = con.with_name(name.clone());
         state.active_printer.spawn(printer.clone().drive(con));
@@ -75,40 +205,1104 @@ async fn reload(State(state): State<Server>) -> String {
     "Success".to_string()
 }
 
+/// Load fonts afresh from the configured directories, keeping printers connected.
+async fn reload_fonts(State(state): State<Server>) -> String {
+    let fonts = match configuration::Configuration::from_file(
+        &state.inner.read().await.configuration,
+    )
+    .await
+    {
+        Ok(cfg) => cfg.fonts(),
+        Err(error) => return error.to_string(),
+    };
+
+    job::PrintApi::set_fonts(&fonts);
+    let faces = tokio::task::spawn_blocking(job::PrintApi::reload_fonts).await;
+
+    // Rasters of documents set in the fonts replaced are stale.
+    if let Some(cache) = &state.inner.read().await.services.limiter.cache {
+        cache.clear();
+    }
+
+    match faces {
+        Ok(faces) => format!("Loaded {faces} font faces"),
+        Err(error) => error.to_string(),
+    }
+}
+
+#[derive(Deserialize)]
+struct PrintQuery {
+    /// Render the job and return its commands instead of printing it.
+    #[serde(default)]
+    dry_run: bool,
+    /// With a dry run, also return the label as it would come out.
+    #[serde(default)]
+    preview: bool,
+}
+
 async fn push_job(
     State(state): State<Server>,
     Path(printer): Path<String>,
-    Json(payload): Json<job::PrintApi>,
-) -> String {
+    Query(query): Query<PrintQuery>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(mut payload): Json<job::PrintApi>,
+) -> axum::response::Response {
+    let now = physical_printer::unix_now() as i64;
+    let not_before = match payload.not_before.as_deref() {
+        Some(time) => match schedule::parse_rfc3339(time) {
+            Ok(time) => Some(time),
+            Err(error) => {
+                return (StatusCode::BAD_REQUEST, error).into_response()
+            }
+        },
+        None => None,
+    };
+
+    let (submissions, window, key) = {
+        let inner = state.inner.read().await;
+        payload.convert_to_millimetres(inner.units);
+        // Dates are those of the day the job is printed.
+        let printed = not_before.unwrap_or(now).max(now);
+        if let Err(error) = payload.fill_expiry(printed, inner.utc_offset) {
+            return (StatusCode::BAD_REQUEST, error.to_string())
+                .into_response();
+        }
+
+        let key = idempotency_key(&headers).or_else(|| {
+            inner.idempotency.hash_payload.then(|| {
+                format!("sha256:{}", artifacts::digest(payload.payload()).0)
+            })
+        });
+        (
+            inner.services.submissions.clone(),
+            inner.idempotency.window,
+            key,
+        )
+    };
+
+    if query.dry_run {
+        return dry_run(state, printer, payload, query.preview)
+            .await
+            .into_response();
+    }
+
+    let Some(key) = key else {
+        return match submit_job(
+            state, printer, peer, &headers, payload, not_before,
+        )
+        .await
+        {
+            Ok((status, body)) => (status, Json(body)).into_response(),
+            Err(response) => response,
+        };
+    };
+
+    let pending = match submissions.claim(&printer, &key, window) {
+        idempotency::Claim::New(pending) => pending,
+        idempotency::Claim::Repeat(status, body) => {
+            tracing::info!(
+                "Repeated submission for {printer} not printed again"
+            );
+            return (status, Json(body)).into_response();
+        }
+        idempotency::Claim::InProgress => {
+            return (
+                StatusCode::CONFLICT,
+                "The same job is being submitted already",
+            )
+                .into_response()
+        }
+    };
+
+    // Forgotten if the client hangs up before the job is submitted.
+    let submitted =
+        submit_job(state, printer, peer, &headers, payload, not_before).await;
+    // Refused jobs are forgotten, such that they may be submitted again.
+    pending.complete(submitted.as_ref().ok().cloned());
+
+    match submitted {
+        Ok((status, body)) => (status, Json(body)).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// The key a client named its job by, to submit it again without printing it twice.
+fn idempotency_key(headers: &HeaderMap) -> Option<String> {
+    let key = headers.get("idempotency-key")?.to_str().ok()?.trim();
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+/// Hold, queue or accept a job for validation, as the client asked.
+async fn submit_job(
+    state: Server,
+    printer: String,
+    peer: SocketAddr,
+    headers: &HeaderMap,
+    payload: job::PrintApi,
+    not_before: Option<i64>,
+) -> Result<(StatusCode, serde_json::Value), axum::response::Response> {
+    if let Some(refusal) = refuse_if_draining(&state, &printer).await {
+        return Err(refusal);
+    }
+
+    let now = physical_printer::unix_now() as i64;
+    if let Some(due) = not_before.filter(|&due| due > now) {
+        let inner = state.inner.read().await;
+        let requester = Some(peer.to_string());
+        return match schedule_job(&inner, &printer, &payload, requester, due)
+            .await
+        {
+            Ok(id) => Ok((
+                StatusCode::ACCEPTED,
+                serde_json::json!({
+                    "scheduled": id,
+                    "not_before": schedule::format_rfc3339(due),
+                }),
+            )),
+            Err(err) => Err(err.into_response()),
+        };
+    }
+
+    if prefers_async(headers) {
+        return intake_job(state, printer, peer, payload)
+            .await
+            .map(|(status, Json(body))| (status, body));
+    }
+
     let inner = state.inner.read().await;
-    log::info!("New job asked");
+    tracing::info!("New job asked");
 
-    let Some(queue) = inner.printer.get(&printer) else {
-        return "No such printer".to_string();
+    // Tracked like asynchronous jobs, such that repeated submissions name the same one.
+    let intake = inner.services.intake.clone();
+    let id = intake.register(&printer);
+    let requester = Some(peer.to_string());
+    match queue_job(&inner, &printer, &payload, requester, Some(id)).await {
+        Ok(position) => {
+            intake.queued(id, position);
+            Ok((
+                StatusCode::OK,
+                serde_json::json!({
+                    "status": "ok",
+                    "id": id,
+                    "queue_position": position,
+                }),
+            ))
+        }
+        Err(err) => {
+            intake.set(
+                id,
+                intake::JobState::Rejected {
+                    reason: err.to_string(),
+                    detail: err.validation(),
+                },
+            );
+            Err(err.into_response())
+        }
+    }
+}
+
+/// Run a job through validation and rendering as if printing it, on the same configuration.
+async fn dry_run(
+    state: Server,
+    printer: String,
+    payload: job::PrintApi,
+    preview: bool,
+) -> Result<Json<physical_printer::DryRun>, axum::response::Response> {
+    let printer = {
+        let inner = state.inner.read().await;
+        match inner.printer.get(&printer) {
+            Some(queue) => queue.printer.clone(),
+            None => {
+                return Err(
+                    (StatusCode::NOT_FOUND, "No such printer").into_response()
+                )
+            }
+        }
+    };
+
+    let job = printer
+        .verify_label(&payload)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    printer
+        .dry_run(job, &payload.job_options(), preview)
+        .map(Json)
+        .map_err(|error| {
+            (StatusCode::UNPROCESSABLE_ENTITY, error.to_string())
+                .into_response()
+        })
+}
+
+/// Turn away jobs for a draining printer, asking clients to come back after the maintenance.
+async fn refuse_if_draining(
+    state: &Server,
+    printer: &str,
+) -> Option<axum::response::Response> {
+    let drain = state.inner.read().await.services.drain.get(printer)?;
+
+    Some(
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, drain.retry_after.to_string())],
+            format!("{printer} is drained for maintenance"),
+        )
+            .into_response(),
+    )
+}
+
+/// Whether the client asked not to wait for its job to be validated (RFC 7240).
+fn prefers_async(headers: &HeaderMap) -> bool {
+    headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|preference| {
+            preference.trim().eq_ignore_ascii_case("respond-async")
+        })
+}
+
+/// Accept a job after the cheap checks, validating its content in the background.
+async fn intake_job(
+    state: Server,
+    printer: String,
+    peer: SocketAddr,
+    payload: job::PrintApi,
+) -> Result<(StatusCode, Json<serde_json::Value>), axum::response::Response> {
+    let (intake, limiter) = {
+        let inner = state.inner.read().await;
+        let Some(queue) = inner.printer.get(&printer) else {
+            return Err(
+                (StatusCode::NOT_FOUND, "No such printer").into_response()
+            );
+        };
+
+        queue
+            .printer
+            .check_request(&payload)
+            .map_err(IntoResponse::into_response)?;
+
+        // Still checked once queued, but most clients are better told right away.
+        if queue.driver.is_full(payload.priority) {
+            return Err(QueueError::Full.into_response());
+        }
+
+        let services = &inner.services;
+        (services.intake.clone(), services.limiter.clone())
+    };
+
+    let id = intake.register(&printer);
+    tracing::info!("New job {id} accepted for validation");
+
+    tokio::spawn(async move {
+        let _permit = limiter.acquire_validation(&payload).await;
+        let inner = state.inner.read().await;
+        let requester = Some(peer.to_string());
+
+        match queue_job(&inner, &printer, &payload, requester, Some(id)).await {
+            Ok(position) => intake.queued(id, position),
+            Err(error) => intake.set(
+                id,
+                intake::JobState::Rejected {
+                    reason: error.to_string(),
+                    detail: error.validation(),
+                },
+            ),
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "id": id,
+            "status": format!("/api/v1/jobs/{id}"),
+        })),
+    ))
+}
+
+/// What became of a job accepted asynchronously.
+async fn job_status(
+    State(state): State<Server>,
+    Path(id): Path<u64>,
+) -> Result<Json<intake::JobStatus>, StatusCode> {
+    let intake = state.inner.read().await.services.intake.clone();
+    intake.get(id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Render a job as it would come out of a printer, without printing it.
+async fn preview(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Json(mut payload): Json<job::PrintApi>,
+) -> Result<impl IntoResponse, axum::response::Response> {
+    let (printer, store) = {
+        let inner = state.inner.read().await;
+        payload.convert_to_millimetres(inner.units);
+        payload
+            .fill_expiry(physical_printer::unix_now() as i64, inner.utc_offset)
+            .map_err(|error| {
+                (StatusCode::BAD_REQUEST, error.to_string()).into_response()
+            })?;
+        match inner.printer.get(&printer) {
+            Some(queue) => {
+                (queue.printer.clone(), inner.services.artifacts.clone())
+            }
+            None => {
+                return Err(
+                    (StatusCode::NOT_FOUND, "No such printer").into_response()
+                )
+            }
+        }
+    };
+
+    let job = printer
+        .verify_label(&payload)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let Some(png) = printer.preview(job, &payload.job_options()) else {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The job can not be previewed",
+        )
+            .into_response());
+    };
+
+    // Stored to be fetched again by its digest, which doubles as its tag.
+    let tag = match store {
+        Some(store) => match store.put(&png).await {
+            Ok(id) => id,
+            Err(error) => {
+                tracing::warn!("Failed to store the preview: {error}");
+                artifacts::digest(&png)
+            }
+        },
+        None => artifacts::digest(&png),
+    };
+
+    Ok((
+        [
+            (CONTENT_TYPE, "image/png".to_string()),
+            (ETAG, format!("\"{}\"", tag.0)),
+        ],
+        png,
+    ))
+}
+
+/// Echo what the server understood of a job, without printing it.
+async fn normalize(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Query(query): Query<UnitsQuery>,
+    Json(mut payload): Json<job::PrintApi>,
+) -> Result<Json<serde_json::Value>, axum::response::Response> {
+    let (printer, units) = {
+        let inner = state.inner.read().await;
+        payload.convert_to_millimetres(inner.units);
+        payload
+            .fill_expiry(physical_printer::unix_now() as i64, inner.utc_offset)
+            .map_err(|error| {
+                (StatusCode::BAD_REQUEST, error.to_string()).into_response()
+            })?;
+        match inner.printer.get(&printer) {
+            Some(queue) => {
+                (queue.printer.clone(), query.units.unwrap_or(inner.units))
+            }
+            None => {
+                return Err(
+                    (StatusCode::NOT_FOUND, "No such printer").into_response()
+                )
+            }
+        }
+    };
+
+    let job = printer
+        .verify_label(&payload)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    Ok(Json(serde_json::json!({
+        "job": payload.normalize(&job),
+        "label": printer.label_geometry(units),
+    })))
+}
+
+/// Verify a job and hand it to the queue of a printer.
+async fn queue_job(
+    inner: &PrintResources,
+    printer: &str,
+    payload: &job::PrintApi,
+    requester: Option<String>,
+    job_id: Option<u64>,
+) -> Result<usize, QueueError> {
+    let Some(queue) = inner.printer.get(printer) else {
+        return Err(QueueError::NoPrinter);
+    };
+
+    let span = logs::job_span(printer, job_id);
+    let (job, record) = prepare_job(inner, queue, printer, payload, requester)
+        .instrument(tracing::info_span!(parent: &span, "validate"))
+        .await?;
+    let record = history::JobRecord { job_id, ..record };
+
+    match queue
+        .driver
+        .send_job(job, payload.job_options(), record.clone())
+        .await
+    {
+        Ok(position) => Ok(position),
+        Err(err) => {
+            if let Some(history) = &inner.services.history {
+                let record = history::JobRecord {
+                    result: history::JobResult::Failed {
+                        reason: err.to_string(),
+                    },
+                    ..record
+                };
+
+                if let Err(error) = history.append(&record).await {
+                    tracing::warn!("Failed to append to the job log: {error}");
+                }
+            }
+
+            Err(match err {
+                physical_printer::SendError::Full => QueueError::Full,
+                physical_printer::SendError::Closed => {
+                    QueueError::Failed(err.to_string())
+                }
+            })
+        }
+    }
+}
+
+/// Verify a job and build its record, storing its payload.
+async fn prepare_job(
+    inner: &PrintResources,
+    queue: &PrintQueue,
+    printer: &str,
+    payload: &job::PrintApi,
+    requester: Option<String>,
+) -> Result<(job::PrintJob, history::JobRecord), QueueError> {
+    tracing::info!("Job to be verified");
+    let job = queue
+        .printer
+        .verify_label(payload)
+        .await
+        .map_err(QueueError::Invalid)?;
+
+    tracing::info!("Job to be sent to the printer");
+    if let Some(store) = &inner.services.artifacts {
+        if let Err(error) = store.put(payload.payload()).await {
+            tracing::warn!("Failed to store the job payload: {error}");
+        }
+    }
+
+    let record = history::JobRecord::new(
+        printer,
+        &queue.printer.label().0,
+        payload,
+        requester,
+    );
+
+    Ok((job, record))
+}
+
+/// Verify a job and hold it until it is due, see [`schedule`].
+async fn schedule_job(
+    inner: &PrintResources,
+    printer: &str,
+    payload: &job::PrintApi,
+    requester: Option<String>,
+    due: i64,
+) -> Result<u64, QueueError> {
+    let Some(queue) = inner.printer.get(printer) else {
+        return Err(QueueError::NoPrinter);
     };
 
-    log::info!("Job to be verified");
-    let job = match queue.printer.verify_label(&payload).await {
-        Ok(job) => job,
-        Err(err) => return err,
+    let span = logs::job_span(printer, None);
+    let (job, record) = prepare_job(inner, queue, printer, payload, requester)
+        .instrument(tracing::info_span!(parent: &span, "validate"))
+        .await?;
+    let id = inner.services.schedule.push(schedule::ScheduledJob {
+        due,
+        job,
+        options: payload.job_options(),
+        record,
+    });
+
+    tracing::info!(
+        "Job {id} for {printer} scheduled for {}",
+        schedule::format_rfc3339(due)
+    );
+    Ok(id)
+}
+
+/// How long clients are asked to wait before submitting to a full queue again, about the time
+/// a printer takes for a few labels.
+const QUEUE_FULL_RETRY_AFTER_SECS: u64 = 5;
+
+/// Why a job did not make it into the queue of a printer.
+enum QueueError {
+    NoPrinter,
+    Invalid(ValidationError),
+    /// The queue is full, the job may be submitted again shortly.
+    Full,
+    /// The queue did not take the job, such as when the printer stopped.
+    Failed(String),
+}
+
+impl QueueError {
+    fn validation(&self) -> Option<ValidationError> {
+        match self {
+            QueueError::Invalid(error) => Some(error.clone()),
+            QueueError::NoPrinter
+            | QueueError::Full
+            | QueueError::Failed(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueError::NoPrinter => f.write_str("No such printer"),
+            QueueError::Invalid(error) => error.fmt(f),
+            QueueError::Full => f.write_str("The queue of the printer is full"),
+            QueueError::Failed(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl IntoResponse for QueueError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            QueueError::NoPrinter => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            QueueError::Invalid(error) => error.into_response(),
+            QueueError::Full => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, QUEUE_FULL_RETRY_AFTER_SECS.to_string())],
+                self.to_string(),
+            )
+                .into_response(),
+            QueueError::Failed(reason) => {
+                (StatusCode::SERVICE_UNAVAILABLE, reason).into_response()
+            }
+        }
+    }
+}
+
+/// The largest document accepted via IPP unless configured otherwise, in bytes.
+const IPP_MAX_DOCUMENT: usize = 2 << 20;
+
+/// Serve the IPP destination of a printer, see [`ipp`].
+async fn ipp_printer(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: axum::body::Body,
+) -> Result<impl IntoResponse, StatusCode> {
+    let limit = {
+        let inner = state.inner.read().await;
+        if !inner.ipp {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        inner.ipp_max_document
+    };
+
+    // Not holding the state lock while a client uploads.
+    let body = axum::body::to_bytes(body, limit)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    let inner = state.inner.read().await;
+
+    let request =
+        ipp::Request::parse(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let host = headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    let uri = format!("ipp://{host}/ipp/{printer}");
+
+    let response =
+        ipp_respond(&inner, &printer, &uri, request, peer.to_string()).await;
+    Ok(([(CONTENT_TYPE, "application/ipp")], response.encode()))
+}
+
+async fn ipp_respond(
+    inner: &PrintResources,
+    printer: &str,
+    uri: &str,
+    request: ipp::Request,
+    requester: String,
+) -> ipp::Response {
+    use ipp::{operation, status, tag};
+
+    // Raster formats the image intake decodes.
+    const FORMATS: &[&str] = &[
+        "image/png",
+        "image/jpeg",
+        "image/gif",
+        "image/bmp",
+        "image/tiff",
+        "image/webp",
+        #[cfg(feature = "pdf")]
+        "application/pdf",
+        "application/octet-stream",
+    ];
+
+    let id = request.request_id;
+    let Some(queue) = inner.printer.get(printer) else {
+        return ipp::Response::with_message(
+            status::NOT_FOUND,
+            id,
+            "No such printer",
+        );
+    };
+
+    let format = request
+        .operation_attribute("document-format")
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+
+    match request.operation {
+        operation::GET_PRINTER_ATTRIBUTES => {
+            let up = queue.printer.metrics().up;
+            let name = queue.printer.display_name().unwrap_or(printer);
+
+            let mut response = ipp::Response::new(status::OK, id);
+            response.group(ipp::group::PRINTER);
+            response.text(tag::URI, "printer-uri-supported", uri);
+            response.text(tag::KEYWORD, "uri-security-supported", "none");
+            response.text(tag::KEYWORD, "uri-authentication-supported", "none");
+            response.text(tag::NAME, "printer-name", printer);
+            response.text(tag::TEXT, "printer-info", name);
+            // Idle or stopped.
+            response.integer(
+                tag::ENUM,
+                "printer-state",
+                if up { 3 } else { 5 },
+            );
+            response.text(tag::KEYWORD, "printer-state-reasons", "none");
+            let accepting = inner.services.drain.get(printer).is_none();
+            response.value(
+                tag::BOOLEAN,
+                "printer-is-accepting-jobs",
+                &[accepting.into()],
+            );
+            response.integer(
+                tag::INTEGER,
+                "queued-job-count",
+                queue.driver.queued_jobs() as i32,
+            );
+            response.texts(
+                tag::KEYWORD,
+                "ipp-versions-supported",
+                &["1.1", "2.0"],
+            );
+            for (idx, op) in [
+                operation::PRINT_JOB,
+                operation::VALIDATE_JOB,
+                operation::GET_JOBS,
+                operation::GET_PRINTER_ATTRIBUTES,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let name = if idx == 0 { "operations-supported" } else { "" };
+                response.integer(tag::ENUM, name, op.into());
+            }
+            response.text(tag::CHARSET, "charset-configured", "utf-8");
+            response.text(tag::CHARSET, "charset-supported", "utf-8");
+            response.text(tag::LANGUAGE, "natural-language-configured", "en");
+            response.text(
+                tag::LANGUAGE,
+                "generated-natural-language-supported",
+                "en",
+            );
+            response.text(
+                tag::MIME_TYPE,
+                "document-format-default",
+                "image/png",
+            );
+            response.texts(
+                tag::MIME_TYPE,
+                "document-format-supported",
+                FORMATS,
+            );
+            response.text(
+                tag::KEYWORD,
+                "pdl-override-supported",
+                "not-attempted",
+            );
+            response.text(tag::KEYWORD, "compression-supported", "none");
+            response
+        }
+        operation::GET_JOBS => {
+            let mut response = ipp::Response::new(status::OK, id);
+            for (job_id, job) in inner.services.intake.pending(printer) {
+                let job_id = ipp_job_id(job_id);
+                response.group(ipp::group::JOB);
+                response.integer(tag::INTEGER, "job-id", job_id);
+                response.text(tag::URI, "job-uri", &format!("{uri}/{job_id}"));
+                // Pending, or processing once taken up by the printer.
+                let state = match job.state {
+                    intake::JobState::Rendering | intake::JobState::Sending => {
+                        5
+                    }
+                    _ => 3,
+                };
+                response.integer(tag::ENUM, "job-state", state);
+                response.text(tag::KEYWORD, "job-state-reasons", "none");
+            }
+            response
+        }
+        operation::VALIDATE_JOB | operation::PRINT_JOB
+            if !FORMATS.contains(&format.as_str()) =>
+        {
+            ipp::Response::with_message(
+                status::DOCUMENT_FORMAT_NOT_SUPPORTED,
+                id,
+                &format!("Unsupported document format {format}"),
+            )
+        }
+        operation::VALIDATE_JOB => ipp::Response::new(status::OK, id),
+        operation::PRINT_JOB if inner.services.drain.get(printer).is_some() => {
+            ipp::Response::with_message(
+                status::NOT_ACCEPTING_JOBS,
+                id,
+                "The printer is drained for maintenance",
+            )
+        }
+        operation::PRINT_JOB => {
+            let payload = job::PrintApi {
+                dimensions: None,
+                orientation: Default::default(),
+                shrink_to_fit: false,
+                template: None,
+                options: Default::default(),
+                emphasis: None,
+                clock: None,
+                units: None,
+                expiry: None,
+                priority: Default::default(),
+                not_before: None,
+                kind: if format == "application/pdf" {
+                    job::PrintApiKind::Pdf {
+                        data: data_uri::DataUri {
+                            mime: format,
+                            data: request.document.into(),
+                        },
+                        page: None,
+                    }
+                } else {
+                    job::PrintApiKind::Image {
+                        data: data_uri::DataUri {
+                            mime: format,
+                            data: request.document.into(),
+                        },
+                    }
+                },
+            };
+
+            // Tracked like other jobs, such that they are listed until printed.
+            let intake = &inner.services.intake;
+            let job = intake.register(printer);
+            let queued =
+                queue_job(inner, printer, &payload, Some(requester), Some(job))
+                    .await;
+            if let Err(err) = &queued {
+                intake.set(
+                    job,
+                    intake::JobState::Rejected {
+                        reason: err.to_string(),
+                        detail: err.validation(),
+                    },
+                );
+            }
+
+            match queued {
+                Ok(position) => {
+                    intake.queued(job, position);
+                    let job_id = ipp_job_id(job);
+                    let mut response = ipp::Response::new(status::OK, id);
+                    response.group(ipp::group::JOB);
+                    response.integer(tag::INTEGER, "job-id", job_id);
+                    response.text(
+                        tag::URI,
+                        "job-uri",
+                        &format!("{uri}/{job_id}"),
+                    );
+                    // Pending.
+                    response.integer(tag::ENUM, "job-state", 3);
+                    response.text(tag::KEYWORD, "job-state-reasons", "none");
+                    response
+                }
+                Err(err) => {
+                    let code = match err {
+                        QueueError::Full | QueueError::Failed(_) => {
+                            status::BUSY
+                        }
+                        _ => status::BAD_REQUEST,
+                    };
+
+                    ipp::Response::with_message(code, id, &err.to_string())
+                }
+            }
+        }
+        _ => ipp::Response::with_message(
+            status::OPERATION_NOT_SUPPORTED,
+            id,
+            "Operation not supported",
+        ),
+    }
+}
+
+/// The IPP identifier of a job, which has to be a positive 32 bit integer.
+fn ipp_job_id(id: u64) -> i32 {
+    ((id.max(1) - 1) % i32::MAX as u64) as i32 + 1
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    limit: Option<usize>,
+    /// How many of the most recent records to skip, for paging further back.
+    offset: Option<usize>,
+}
+
+async fn history(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<history::JobRecord>>, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+
+    if !inner.printer.contains_key(&printer) {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    }
+
+    let Some(history) = &inner.services.history else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "No job log configured".to_string(),
+        ));
+    };
+
+    let limit = query.limit.unwrap_or(100);
+    let offset = query.offset.unwrap_or(0);
+    match history.history(&printer, offset, limit).await {
+        Ok(records) => Ok(Json(records)),
+        Err(error) => {
+            Err((StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))
+        }
+    }
+}
+
+/// Jobs of a printer that failed to print, oldest first.
+async fn dead_letters(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<Json<Vec<dead_letter::DeadLetterReport>>, StatusCode> {
+    let inner = state.inner.read().await;
+
+    if !inner.printer.contains_key(&printer) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(inner.services.dead_letters.list(&printer)))
+}
+
+/// Queue a failed job to its printer again, as it was submitted.
+async fn requeue_dead_letter(
+    State(state): State<Server>,
+    Path((printer, id)): Path<(String, u64)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    let not_found =
+        || (StatusCode::NOT_FOUND, "No such failed job".to_string());
+
+    let queue = inner.printer.get(&printer).ok_or_else(not_found)?;
+    if inner.services.drain.get(&printer).is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("{printer} is drained for maintenance"),
+        ));
+    }
+
+    let dead_letters = &inner.services.dead_letters;
+    let letter = dead_letters.get(&printer, id).ok_or_else(not_found)?;
+
+    let record = history::JobRecord {
+        timestamp_unix: std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs()),
+        zpl_sha256: None,
+        raster_sha256: None,
+        result: history::JobResult::Pending,
+        job_id: None,
+        effective: None,
+        ..letter.record
+    };
+
+    queue
+        .driver
+        .send_job(letter.job, letter.options, record)
+        .await
+        .map_err(|error| {
+            (StatusCode::SERVICE_UNAVAILABLE, error.to_string())
+        })?;
+
+    // Only once queued, such that a full queue does not lose the job.
+    dead_letters.remove(&printer, id);
+    tracing::info!("Failed job {id} of {printer} queued again");
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Print the stored commands of an earlier job again, as named by its job log record.
+async fn reprint(
+    State(state): State<Server>,
+    Path((printer, id)): Path<(String, String)>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    let inner = state.inner.read().await;
+    if let Err(status) = check_admin(&inner, &headers) {
+        return status.into_response();
+    }
+
+    let Some(store) = &inner.services.artifacts else {
+        return (StatusCode::NOT_FOUND, "No artifact store configured")
+            .into_response();
+    };
+
+    let code = match store.get(&artifacts::ArtifactId(id)).await {
+        Ok(Some(data)) => match String::from_utf8(data) {
+            Ok(code) => code,
+            Err(_) => {
+                return (StatusCode::UNPROCESSABLE_ENTITY, "Not ZPL commands")
+                    .into_response()
+            }
+        },
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "No such artifact").into_response()
+        }
+        Err(error) => {
+            tracing::warn!("Failed to read artifact: {error}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let payload = job::PrintApi {
+        dimensions: None,
+        orientation: Default::default(),
+        shrink_to_fit: false,
+        template: None,
+        options: Default::default(),
+        emphasis: None,
+        clock: None,
+        units: None,
+        expiry: None,
+        priority: Default::default(),
+        not_before: None,
+        kind: job::PrintApiKind::Zpl { code },
     };
 
-    log::info!("Job to be sent to the printer");
-    match queue.driver.send_job(job).await {
-        Ok(()) => "ok".to_string(),
-        Err(err) => err.to_string(),
+    let requester = Some(peer.to_string());
+    match queue_job(&inner, &printer, &payload, requester, None).await {
+        Ok(position) => {
+            tracing::info!("Stored job reprinted on {printer}");
+            let body = serde_json::json!({
+                "status": "ok",
+                "queue_position": position,
+            });
+            (StatusCode::OK, Json(body)).into_response()
+        }
+        Err(error) => error.into_response(),
+    }
+}
+
+/// Jobs of a printer held until a later time, the earliest first.
+async fn scheduled_jobs(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<Json<Vec<schedule::ScheduledReport>>, StatusCode> {
+    let inner = state.inner.read().await;
+
+    if !inner.printer.contains_key(&printer) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(inner.services.schedule.list(&printer)))
+}
+
+async fn cancel_scheduled_job(
+    State(state): State<Server>,
+    Path((printer, id)): Path<(String, u64)>,
+) -> StatusCode {
+    let inner = state.inner.read().await;
+
+    match inner.services.schedule.remove(&printer, id) {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn discard_dead_letter(
+    State(state): State<Server>,
+    Path((printer, id)): Path<(String, u64)>,
+) -> StatusCode {
+    let inner = state.inner.read().await;
+
+    match inner.services.dead_letters.remove(&printer, id) {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
     }
 }
 
-async fn status(State(state): State<Server>) -> String {
+#[derive(Deserialize)]
+struct UnitsQuery {
+    /// Show lengths in this unit instead of the server's.
+    units: Option<zpl::length::LengthUnit>,
+}
+
+/// The dimensions and resolution of a printer's labels, to size content by.
+async fn label_geometry(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Query(query): Query<UnitsQuery>,
+) -> Result<Json<physical_printer::LabelGeometry>, StatusCode> {
+    let inner = state.inner.read().await;
+    let queue = inner.printer.get(&printer).ok_or(StatusCode::NOT_FOUND)?;
+    let units = query.units.unwrap_or(inner.units);
+    Ok(Json(queue.printer.label_geometry(units)))
+}
+
+async fn status(
+    State(state): State<Server>,
+    Query(query): Query<UnitsQuery>,
+) -> String {
     let inner = state.inner.read().await;
+    let units = query.units.unwrap_or(inner.units);
 
     let map: HashMap<String, serde_json::Value> = inner
         .printer
         .iter()
         .map(|(name, queue)| {
-            let description = serde_json::to_value(queue.printer.status())
-                .unwrap_or_default();
+            let mut description =
+                serde_json::to_value(queue.printer.status(units))
+                    .unwrap_or_default();
+            if let Some(fields) = description.as_object_mut() {
+                fields.insert(
+                    "queue".to_string(),
+                    serde_json::json!({
+                        "depth": queue.driver.queued_jobs(),
+                        "capacity": queue.driver.queue_capacity(),
+                    }),
+                );
+            }
             (name.clone(), description)
         })
         .collect();
@@ -116,35 +1310,1086 @@ async fn status(State(state): State<Server>) -> String {
     serde_json::to_string(&map).unwrap()
 }
 
-#[tokio::main(flavor = "multi_thread")]
-async fn main() {
-    env_logger::init();
+async fn artifact(
+    State(state): State<Server>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let store = {
+        let inner = state.inner.read().await;
+        check_admin(&inner, &headers)?;
+        inner.services.artifacts.clone()
+    };
+    let store = store.ok_or(StatusCode::NOT_FOUND)?;
 
-    let config = App::parse();
-    let state = Server::new(config.configuration.into());
+    match store.get(&artifacts::ArtifactId(id)).await {
+        Ok(Some(data)) => {
+            Ok(([(CONTENT_TYPE, "application/octet-stream")], data))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(error) => {
+            tracing::warn!("Failed to read artifact: {error}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn template_report(
+    State(state): State<Server>,
+) -> Json<Vec<statistics::TemplateReport>> {
+    let statistics = state.inner.read().await.services.statistics.clone();
+    Json(statistics.report())
+}
+
+async fn coverage_report(
+    State(state): State<Server>,
+) -> Json<Vec<statistics::CoverageReport>> {
+    let coverage = state.inner.read().await.services.coverage.clone();
+    Json(coverage.report())
+}
+
+async fn prometheus_metrics(State(state): State<Server>) -> impl IntoResponse {
+    let inner = state.inner.read().await;
+
+    let mut printers: Vec<_> = inner
+        .printer
+        .iter()
+        .map(|(name, queue)| metrics::PrinterSample {
+            name: name.clone(),
+            metrics: queue.printer.metrics(),
+            queued: queue.driver.queued_jobs(),
+        })
+        .collect();
+    printers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let templates = inner.services.statistics.report();
+    let cache = inner
+        .services
+        .limiter
+        .cache
+        .as_ref()
+        .map(|c| c.statistics());
+    let text = metrics::encode(&printers, &templates, cache);
+
+    ([(CONTENT_TYPE, metrics::CONTENT_TYPE)], text)
+}
+
+#[derive(Deserialize)]
+struct SupportQuery {
+    #[serde(default)]
+    consent: bool,
+}
+
+/// Collect the configuration, logs, job history and printer state into one archive.
+async fn support_bundle(
+    State(state): State<Server>,
+    Query(query): Query<SupportQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    // Number of job log records to include per printer.
+    const HISTORY_EXCERPT: usize = 50;
+
+    if !query.consent {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "A support bundle contains the configuration with secrets redacted, recent logs, \
+             job history, printer status and the commands last sent to each printer. Repeat \
+             the request with `?consent=true` to create it."
+                .to_string(),
+        ));
+    }
+
+    let internal = |error: std::io::Error| {
+        (StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    };
+
+    let inner = state.inner.read().await;
+    let mut bundle = support::SupportBundle::new();
+
+    match configuration::Configuration::from_file(&inner.configuration).await {
+        Ok(configuration) => {
+            let mut value =
+                serde_json::to_value(&configuration).unwrap_or_default();
+            support::redact(&mut value);
+            bundle.add_json("configuration.json", &value)
+        }
+        Err(error) => {
+            bundle.add_text("configuration.error.txt", &error.to_string())
+        }
+    }
+    .map_err(internal)?;
+
+    bundle
+        .add_text("log.txt", &logs::recent().join("\n"))
+        .map_err(internal)?;
+
+    let mut printers = serde_json::Map::new();
+    for (name, queue) in &inner.printer {
+        let printer = &queue.printer;
+        printers.insert(
+            name.clone(),
+            serde_json::json!({
+                "status": printer.status(inner.units),
+                "metrics": printer.metrics(),
+                "queued": queue.driver.queued_jobs(),
+            }),
+        );
+
+        if let Some(commands) = printer.last_commands() {
+            bundle
+                .add_text(&format!("transcripts/{name}.zpl"), &commands)
+                .map_err(internal)?;
+        }
+
+        if let Some(history) = &inner.services.history {
+            let records = history
+                .history(name, 0, HISTORY_EXCERPT)
+                .await
+                .map_err(internal)?;
+            bundle
+                .add_json(&format!("history/{name}.json"), &records)
+                .map_err(internal)?;
+        }
+    }
+
+    bundle
+        .add_json("printers.json", &printers)
+        .map_err(internal)?;
+    bundle
+        .add_json("templates.json", &inner.services.statistics.report())
+        .map_err(internal)?;
+    bundle
+        .add_json("coverage.json", &inner.services.coverage.report())
+        .map_err(internal)?;
+
+    let archive = bundle.finish().map_err(internal)?;
+    Ok((
+        [
+            (CONTENT_TYPE, "application/gzip"),
+            (
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"zpl-support.tar.gz\"",
+            ),
+        ],
+        archive,
+    ))
+}
+
+/// Periodically remove expired artifacts, with the store of the current configuration.
+async fn collect_artifacts(state: Server) {
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(3600));
+
+    loop {
+        interval.tick().await;
+
+        let store = state.inner.read().await.services.artifacts.clone();
+        let Some(store) = store else {
+            continue;
+        };
+
+        match store.collect_garbage().await {
+            Ok(removed) => {
+                tracing::info!("Removed {removed} expired artifacts")
+            }
+            Err(error) => {
+                tracing::warn!("Failed to collect artifacts: {error}")
+            }
+        }
+    }
+}
+
+/// Long-poll for the next job of a printer served by an agent.
+async fn agent_poll(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Query(query): Query<pull_api::PollQuery>,
+    headers: HeaderMap,
+) -> Result<Json<pull_api::PulledJob>, StatusCode> {
+    // Keep clients well below common proxy timeouts.
+    const POLL_WAIT: std::time::Duration = std::time::Duration::from_secs(20);
+
+    let printer = {
+        let inner = state.inner.read().await;
+        agent_printer(&inner, &printer, &headers)?
+    };
+
+    printer.mark_seen();
+    let queue = printer.pull_queue().ok_or(StatusCode::NOT_FOUND)?;
+
+    if let Some(dpmm) = query.dpmm {
+        queue.set_dpmm(dpmm);
+    }
+
+    // Do not hold the state lock while waiting, a reload must not block on agents.
+    match queue.poll(POLL_WAIT).await {
+        Some(job) => Ok(Json(job)),
+        None => Err(StatusCode::NO_CONTENT),
+    }
+}
+
+/// Receive the result of a job printed by an agent.
+async fn agent_report(
+    State(state): State<Server>,
+    Path((printer, id)): Path<(String, u64)>,
+    headers: HeaderMap,
+    Json(result): Json<pull_api::PullResult>,
+) -> StatusCode {
+    let printer = {
+        let inner = state.inner.read().await;
+        match agent_printer(&inner, &printer, &headers) {
+            Ok(printer) => printer,
+            Err(status) => return status,
+        }
+    };
+
+    printer.mark_seen();
+    match printer.pull_queue() {
+        Some(queue) if queue.report(id, result) => StatusCode::NO_CONTENT,
+        // Timed out, or reported twice.
+        Some(_) => StatusCode::GONE,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+fn agent_printer(
+    inner: &PrintResources,
+    printer: &str,
+    headers: &HeaderMap,
+) -> Result<physical_printer::PhysicalPrinter, StatusCode> {
+    let queue = inner.printer.get(printer).ok_or(StatusCode::NOT_FOUND)?;
+
+    if queue.printer.pull_queue().is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if !queue.printer.accepts_agent(token) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(queue.printer.clone())
+}
+
+/// Stop a printer after its current label, e.g. when a batch runs away.
+async fn pause(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    device_control(state, &printer, zpl::command::ZplCommand::Pause).await
+}
+
+async fn resume(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    device_control(state, &printer, zpl::command::ZplCommand::Resume).await
+}
+
+/// Drop everything in the printer's buffer, including the rest of a batch.
+///
+/// Jobs still queued on the server are dropped as well.
+async fn cancel(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    device_control(state, &printer, zpl::command::ZplCommand::CancelAll).await
+}
+
+async fn device_control(
+    state: Server,
+    printer: &str,
+    command: zpl::command::ZplCommand,
+) -> Result<StatusCode, (StatusCode, String)> {
+    send_control(&state, printer, command).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Have the printer's task send a device control on its connection, ahead of any job.
+async fn send_control(
+    state: &Server,
+    printer: &str,
+    command: zpl::command::ZplCommand,
+) -> Result<(), (StatusCode, String)> {
+    let reply = {
+        let inner = state.inner.read().await;
+        let Some(queue) = inner.printer.get(printer) else {
+            return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+        };
+
+        if !queue.printer.is_physical() {
+            return Err((
+                StatusCode::CONFLICT,
+                "Only physical printers can be controlled".to_string(),
+            ));
+        }
+
+        queue.driver.control(command).map_err(|error| {
+            (StatusCode::SERVICE_UNAVAILABLE, error.to_string())
+        })?
+    };
+
+    tokio::time::timeout(std::time::Duration::from_secs(5), reply)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                "The printer did not take the command in time".to_string(),
+            )
+        })?
+        .map_err(|_| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "printer stopped".to_string(),
+            )
+        })?
+        .map_err(|error| (StatusCode::BAD_GATEWAY, error.to_string()))
+}
+
+/// The roll loaded into a printer, and whether it is being changed.
+async fn media_state(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<Json<media::MediaState>, StatusCode> {
+    let inner = state.inner.read().await;
+    if !inner.printer.contains_key(&printer) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(inner.services.media.state(&printer)))
+}
+
+/// A physical printer to change the roll of, with the tracking of its media.
+async fn roll_change_printer(
+    state: &Server,
+    printer: &str,
+) -> Result<
+    (physical_printer::PhysicalPrinter, Arc<media::MediaTracking>),
+    (StatusCode, String),
+> {
+    let inner = state.inner.read().await;
+    let Some(queue) = inner.printer.get(printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    if !queue.printer.is_physical() {
+        return Err((
+            StatusCode::CONFLICT,
+            "Only physical printers have rolls to change".to_string(),
+        ));
+    }
+
+    Ok((queue.printer.clone(), inner.services.media.clone()))
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DrainRequest {
+    /// Seconds clients are asked to wait before submitting again.
+    #[serde(default)]
+    retry_after: Option<u64>,
+}
+
+/// Stop accepting jobs for a printer while it prints those queued, ahead of maintenance.
+async fn start_drain(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    request: Option<Json<DrainRequest>>,
+) -> Result<Json<drain::DrainProgress>, (StatusCode, String)> {
+    let Json(request) = request.unwrap_or_default();
+    let inner = state.inner.read().await;
+    let Some(queue) = inner.printer.get(&printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    let drain = drain::Drain {
+        since: physical_printer::unix_now(),
+        queued_at_start: queue.driver.queued_jobs(),
+        retry_after: request.retry_after.unwrap_or(drain::RETRY_AFTER_SECS),
+    };
+    if !inner.services.drain.start(&printer, drain) {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("{printer} is already draining"),
+        ));
+    }
+
+    tracing::info!("Draining {printer}, {} jobs queued", drain.queued_at_start);
+    Ok(Json(drain_progress(&inner, &printer, queue)))
+}
+
+/// How far a draining printer got with the jobs it accepted.
+async fn drain_state(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<Json<drain::DrainProgress>, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    let Some(queue) = inner.printer.get(&printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    Ok(Json(drain_progress(&inner, &printer, queue)))
+}
+
+/// Accept jobs for a drained printer again.
+async fn resume_drained(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<Json<drain::DrainProgress>, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    let Some(queue) = inner.printer.get(&printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    if inner.services.drain.resume(&printer).is_none() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("{printer} is not draining"),
+        ));
+    }
+
+    tracing::info!("Accepting jobs for {printer} again");
+    Ok(Json(drain_progress(&inner, &printer, queue)))
+}
+
+fn drain_progress(
+    inner: &PrintResources,
+    printer: &str,
+    queue: &PrintQueue,
+) -> drain::DrainProgress {
+    drain::DrainProgress::new(
+        inner.services.drain.get(printer),
+        queue.driver.queued_jobs(),
+        queue.printer.is_printing(),
+    )
+}
+
+/// Pause a printer for its roll to be changed, answered by what the operator is to do.
+async fn start_roll_change(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let (_, media) = roll_change_printer(&state, &printer).await?;
+
+    if !media.start_change(&printer, physical_printer::unix_now()) {
+        return Err((
+            StatusCode::CONFLICT,
+            "A roll change is already in progress".to_string(),
+        ));
+    }
+
+    let paused =
+        send_control(&state, &printer, zpl::command::ZplCommand::Pause).await;
+    if let Err(error) = paused {
+        media.abort_change(&printer);
+        return Err(error);
+    }
+
+    tracing::info!("Roll change of {printer} started");
+    Ok(Json(serde_json::json!({
+        "steps": [
+            "Open the printer and remove the empty roll and its liner.",
+            "Load the new roll and thread it under the sensors and guides.",
+            "Close the print head.",
+            "Confirm the new roll, the printer calibrates and feeds a test label.",
+        ],
+        "done": format!("/api/v1/printer/{printer}/roll-change/done"),
+        "abort": format!("/api/v1/printer/{printer}/roll-change/abort"),
+    })))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RollChangeDone {
+    /// The stock loaded, e.g. its article number.
+    #[serde(default)]
+    stock: Option<String>,
+    /// Labels on the new roll, to estimate when it runs out.
+    #[serde(default)]
+    labels: Option<u64>,
+    /// Only feed a label, for the same stock as before.
+    #[serde(default)]
+    skip_calibration: bool,
+}
+
+/// Resume a printer with its new roll, calibrating to it and feeding a test label.
+async fn finish_roll_change(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Json(done): Json<RollChangeDone>,
+) -> Result<Json<media::Roll>, (StatusCode, String)> {
+    let (device, media) = roll_change_printer(&state, &printer).await?;
+
+    device
+        .finish_roll_change(!done.skip_calibration)
+        .await
+        .map_err(|error| (StatusCode::BAD_GATEWAY, error.to_string()))?;
+
+    let roll = media.finish_change(
+        &printer,
+        physical_printer::unix_now(),
+        done.stock,
+        done.labels,
+    );
+
+    tracing::info!("Roll change of {printer} finished");
+    Ok(Json(roll))
+}
+
+/// Resume a printer without recording a new roll.
+async fn abort_roll_change(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let (_, media) = roll_change_printer(&state, &printer).await?;
+
+    send_control(&state, &printer, zpl::command::ZplCommand::Resume).await?;
+
+    media.abort_change(&printer);
+    tracing::info!("Roll change of {printer} aborted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct CalibrateQuery {
+    /// Only feed a blank label.
+    #[serde(default)]
+    feed_only: bool,
+    /// Also set what the printer does on power up and on closing the head.
+    on_power_up: Option<zpl::command::MediaFeed>,
+}
+
+/// Calibrate the media sensors of a printer, e.g. after changing the roll.
+async fn calibrate(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Query(query): Query<CalibrateQuery>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    let Some(queue) = inner.printer.get(&printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    if !queue.printer.is_physical() {
+        return Err((
+            StatusCode::CONFLICT,
+            "Only physical printers can be calibrated".to_string(),
+        ));
+    }
+
+    let commands = zpl::command::CommandSequence::calibration(
+        query.feed_only,
+        query.on_power_up,
+    );
+
+    queue.driver.send_maintenance(commands).map_err(|error| {
+        (StatusCode::SERVICE_UNAVAILABLE, error.to_string())
+    })?;
+
+    tracing::info!("Calibration of {printer} queued");
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+struct ClockQuery {
+    /// Minutes ahead of UTC of the printer's time zone.
+    #[serde(default)]
+    utc_offset: i32,
+}
+
+/// Set a printer's clock to the server's time, once the jobs before are printed.
+async fn set_clock(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Query(query): Query<ClockQuery>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    let Some(queue) = inner.printer.get(&printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    if !queue.printer.is_physical() {
+        return Err((
+            StatusCode::CONFLICT,
+            "Only physical printers have a clock".to_string(),
+        ));
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let time = zpl::command::ClockTime::from_unix(now as i64, query.utc_offset);
+
+    queue
+        .driver
+        .send_maintenance(zpl::command::CommandSequence::set_clock(time))
+        .map_err(|error| {
+            (StatusCode::SERVICE_UNAVAILABLE, error.to_string())
+        })?;
+
+    tracing::info!("Setting the clock of {printer} queued");
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+struct DiagnoseQuery {
+    /// Print a head test pattern, once the jobs before it are printed.
+    #[serde(default)]
+    test_label: bool,
+}
+
+/// Report the state of a printer's head, optionally printing a test pattern as well.
+/// Read the network settings of a printer, or the ones known last if it does not answer.
+async fn network(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<Json<physical_printer::NetworkReadout>, (StatusCode, String)> {
+    let printer = {
+        let inner = state.inner.read().await;
+        let Some(queue) = inner.printer.get(&printer) else {
+            return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+        };
+
+        if !queue.printer.is_physical() {
+            return Err((
+                StatusCode::CONFLICT,
+                "Only physical printers have network settings".to_string(),
+            ));
+        }
+
+        queue.printer.clone()
+    };
+
+    Ok(Json(printer.network_settings().await))
+}
+
+/// The bytes last exchanged with a printer, in order, those outside printable ASCII escaped.
+async fn tapped(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    check_admin(&inner, &headers).map_err(|status| (status, String::new()))?;
+
+    let Some(queue) = inner.printer.get(&printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    let Some(chunks) = queue.printer.tapped() else {
+        return Err((
+            StatusCode::CONFLICT,
+            "The bytes exchanged with this printer are not kept".to_string(),
+        ));
+    };
+
+    let chunks: Vec<_> = chunks
+        .iter()
+        .map(|chunk| {
+            serde_json::json!({
+                "direction": chunk.direction,
+                "data": chunk.bytes.escape_ascii().to_string(),
+            })
+        })
+        .collect();
+    Ok(Json(chunks.into()))
+}
+
+async fn diagnose(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Query(query): Query<DiagnoseQuery>,
+) -> Result<Json<zpl::command::HeadDiagnostic>, (StatusCode, String)> {
+    let printer = {
+        let inner = state.inner.read().await;
+        let Some(queue) = inner.printer.get(&printer) else {
+            return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+        };
+
+        if !queue.printer.is_physical() {
+            return Err((
+                StatusCode::CONFLICT,
+                "Only physical printers can be diagnosed".to_string(),
+            ));
+        }
+
+        if query.test_label {
+            let Some(commands) = queue.printer.head_test() else {
+                return Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "The printer was not reached yet".to_string(),
+                ));
+            };
+
+            queue.driver.send_maintenance(commands).map_err(|error| {
+                (StatusCode::SERVICE_UNAVAILABLE, error.to_string())
+            })?;
+            tracing::info!("Head test of {printer} queued");
+        }
+
+        queue.printer.clone()
+    };
+
+    match printer.head_diagnostic().await {
+        Ok(report) => Ok(Json(report)),
+        Err(error) => Err((StatusCode::BAD_GATEWAY, error.to_string())),
+    }
+}
+
+/// Connect to a printer now and report its status, or why it could not be reached.
+async fn probe(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+) -> Result<Json<zpl::command::HostStatus>, (StatusCode, String)> {
+    let printer = {
+        let inner = state.inner.read().await;
+        let Some(queue) = inner.printer.get(&printer) else {
+            return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+        };
+
+        if !queue.printer.is_physical() {
+            return Err((
+                StatusCode::CONFLICT,
+                "Only physical printers can be probed".to_string(),
+            ));
+        }
+
+        queue.printer.clone()
+    };
+
+    match printer.probe().await {
+        Ok(status) => Ok(Json(status)),
+        Err(error) => Err((StatusCode::BAD_GATEWAY, error.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct FirmwareQuery {
+    /// The checksum the file is expected to have, as hex.
+    sha256: String,
+}
+
+/// Stage a firmware update for a printer, delivered only once confirmed.
+async fn upload_firmware(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    Query(query): Query<FirmwareQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    check_admin(&inner, &headers).map_err(|status| (status, String::new()))?;
+
+    let Some(queue) = inner.printer.get(&printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    if !queue.printer.is_physical() {
+        return Err((
+            StatusCode::CONFLICT,
+            "Firmware can only be sent to physical printers".to_string(),
+        ));
+    }
+
+    let update = inner
+        .firmware
+        .stage(&printer, body.to_vec().into(), &query.sha256)
+        .map_err(|error| (StatusCode::BAD_REQUEST, error))?;
+
+    tracing::info!(
+        "Firmware update {} of {} bytes staged for {printer}",
+        update.id,
+        update.data.len()
+    );
+
+    Ok(Json(serde_json::json!({
+        "id": update.id,
+        "bytes": update.data.len(),
+        "confirm": format!("/api/v1/admin/firmware/{}/confirm", update.id),
+    })))
+}
+
+/// Queue a staged firmware update for its printer.
+async fn confirm_firmware(
+    State(state): State<Server>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Result<Json<firmware::FirmwareReport>, (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    check_admin(&inner, &headers).map_err(|status| (status, String::new()))?;
+
+    let Some(update) = inner.firmware.get(id) else {
+        return Err((StatusCode::NOT_FOUND, "No such update".to_string()));
+    };
+
+    let Some(queue) = inner.printer.get(&update.printer) else {
+        return Err((StatusCode::NOT_FOUND, "No such printer".to_string()));
+    };
+
+    update
+        .confirm()
+        .map_err(|error| (StatusCode::CONFLICT, error))?;
+
+    if let Err(error) = queue.driver.send_firmware(update.clone()) {
+        update.set_state(firmware::FirmwareState::Failed {
+            reason: error.to_string(),
+        });
+        return Err((StatusCode::SERVICE_UNAVAILABLE, error.to_string()));
+    }
+
+    tracing::warn!("Firmware update {id} confirmed for {}", update.printer);
+    Ok(Json(update.report()))
+}
+
+/// The progress of a firmware update.
+async fn firmware_status(
+    State(state): State<Server>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Result<Json<firmware::FirmwareReport>, StatusCode> {
+    let inner = state.inner.read().await;
+    check_admin(&inner, &headers)?;
+
+    let update = inner.firmware.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(update.report()))
+}
+
+/// The failures currently injected into a printer.
+#[cfg(feature = "fault-injection")]
+async fn printer_faults(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<faults::FaultPlan>, StatusCode> {
+    let inner = state.inner.read().await;
+    check_admin(&inner, &headers)?;
+
+    let faults = injectable(&inner, &printer)?;
+    Ok(Json(faults.plan()))
+}
+
+/// Replace the failures injected into a printer.
+#[cfg(feature = "fault-injection")]
+async fn inject_faults(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    headers: HeaderMap,
+    Json(plan): Json<faults::FaultPlan>,
+) -> StatusCode {
+    let inner = state.inner.read().await;
+    if let Err(status) = check_admin(&inner, &headers) {
+        return status;
+    }
+
+    match injectable(&inner, &printer) {
+        Ok(faults) => {
+            tracing::warn!("Injecting faults into {printer}");
+            faults.set(plan);
+            StatusCode::NO_CONTENT
+        }
+        Err(status) => status,
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+async fn clear_faults(
+    State(state): State<Server>,
+    Path(printer): Path<String>,
+    headers: HeaderMap,
+) -> StatusCode {
+    let inner = state.inner.read().await;
+    if let Err(status) = check_admin(&inner, &headers) {
+        return status;
+    }
+
+    match injectable(&inner, &printer) {
+        Ok(faults) => {
+            faults.set(Default::default());
+            StatusCode::NO_CONTENT
+        }
+        Err(status) => status,
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+fn injectable<'a>(
+    inner: &'a PrintResources,
+    printer: &str,
+) -> Result<&'a faults::Faults, StatusCode> {
+    let queue = inner.printer.get(printer).ok_or(StatusCode::NOT_FOUND)?;
+    queue.printer.faults().ok_or(StatusCode::CONFLICT)
+}
+
+/// [`check_admin`] for handlers answering with a message.
+async fn require_admin(
+    state: &Server,
+    headers: &HeaderMap,
+) -> Result<(), (StatusCode, String)> {
+    let inner = state.inner.read().await;
+    check_admin(&inner, headers).map_err(|status| (status, String::new()))
+}
+
+/// Administrative endpoints do not exist unless a token is configured.
+fn check_admin(
+    inner: &PrintResources,
+    headers: &HeaderMap,
+) -> Result<(), StatusCode> {
+    let Some(expected) = &inner.admin_token else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match token {
+        Some(token) if same_secret(token, expected) => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Compare secrets in time independent of where they differ, telling only whether their lengths do.
+fn same_secret(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |differences, (a, b)| differences | (a ^ b))
+            == 0
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let config = App::parse();
+    logs::init(config.log_format);
+    let listener = tokio::net::TcpListener::bind(config.listen).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let state = Server::new(config.configuration.into(), port);
 
     assert_eq!(reload(State(state.clone())).await, "Success");
+    tokio::spawn(collect_artifacts(state.clone()));
+    tokio::spawn(watchdog::run(state.clone()));
+    tokio::spawn(schedule::run(state.clone()));
+
+    let mut watched = state.inner.read().await.watched.subscribe();
+    watched.mark_changed();
+    tokio::spawn(watcher::watch_configuration(state.clone(), watched));
 
     let app = Router::new()
         .route("/", get(spa::frontpage))
         .route("/index.html", get(spa::frontpage))
         .route("/static/style.css", get(spa::static_style_css))
+        .route("/metrics", get(prometheus_metrics))
         .route("/api/v1/info", get(status))
         .route("/api/v1/reload", post(reload))
+        .route("/api/v1/reload-fonts", post(reload_fonts))
         .route("/api/v1/print/:printer", post(push_job))
-        .with_state(state);
+        .route("/api/v1/jobs/:id", get(job_status))
+        .route("/api/v1/ws", get(ws::upgrade))
+        .route("/api/v1/preview/:printer", post(preview))
+        .route("/api/v1/normalize/:printer", post(normalize))
+        .route("/api/v1/printer/:printer/history", get(history))
+        .route("/api/v1/printer/:printer/reprint/:id", post(reprint))
+        .route("/api/v1/printer/:printer/dead-letter", get(dead_letters))
+        .route(
+            "/api/v1/printer/:printer/dead-letter/:id",
+            delete(discard_dead_letter),
+        )
+        .route(
+            "/api/v1/printer/:printer/dead-letter/:id/requeue",
+            post(requeue_dead_letter),
+        )
+        .route("/api/v1/printer/:printer/scheduled", get(scheduled_jobs))
+        .route(
+            "/api/v1/printer/:printer/scheduled/:id",
+            delete(cancel_scheduled_job),
+        )
+        .route("/api/v1/printer/:printer/label", get(label_geometry))
+        .route("/api/v1/printer/:printer/calibrate", post(calibrate))
+        .route("/api/v1/printer/:printer/clock", post(set_clock))
+        .route("/api/v1/printer/:printer/diagnose", post(diagnose))
+        .route("/api/v1/printer/:printer/network", get(network))
+        .route("/api/v1/printer/:printer/probe", post(probe))
+        .route("/api/v1/printer/:printer/tap", get(tapped))
+        .route("/api/v1/printer/:printer/pause", post(pause))
+        .route("/api/v1/printer/:printer/resume", post(resume))
+        .route("/api/v1/printer/:printer/cancel", post(cancel))
+        .route("/api/v1/printer/:printer/media", get(media_state))
+        .route(
+            "/api/v1/printer/:printer/drain",
+            get(drain_state).post(start_drain),
+        )
+        .route(
+            "/api/v1/printer/:printer/drain/resume",
+            post(resume_drained),
+        )
+        .route(
+            "/api/v1/printer/:printer/roll-change",
+            post(start_roll_change),
+        )
+        .route(
+            "/api/v1/printer/:printer/roll-change/done",
+            post(finish_roll_change),
+        )
+        .route(
+            "/api/v1/printer/:printer/roll-change/abort",
+            post(abort_roll_change),
+        )
+        .route("/api/v1/artifacts/:id", get(artifact))
+        .route("/api/v1/reports/templates", get(template_report))
+        .route("/api/v1/reports/coverage", get(coverage_report))
+        .route("/api/v1/support-bundle", get(support_bundle))
+        .route("/ipp/:printer", post(ipp_printer))
+        .route("/api/v1/agent/:printer/job", get(agent_poll))
+        .route("/api/v1/agent/:printer/job/:id", post(agent_report))
+        .route(
+            "/api/v1/admin/printer/:printer/firmware",
+            post(upload_firmware).layer(DefaultBodyLimit::max(256 << 20)),
+        )
+        .route("/api/v1/admin/firmware/:id", get(firmware_status))
+        .route("/api/v1/admin/firmware/:id/confirm", post(confirm_firmware));
 
-    let listener = tokio::net::TcpListener::bind(config.listen).await.unwrap();
-    axum::serve(listener, app).await.unwrap()
+    #[cfg(feature = "fault-injection")]
+    let app = app.route(
+        "/api/v1/admin/printer/:printer/faults",
+        get(printer_faults).put(inject_faults).delete(clear_faults),
+    );
+
+    let app = app.with_state(state);
+
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap()
 }
 
 impl Server {
-    pub fn new(configuration: PathBuf) -> Self {
+    pub fn new(configuration: PathBuf, port: u16) -> Self {
         Server {
             inner: Arc::new(RwLock::new(PrintResources {
                 configuration,
                 active_printer: Default::default(),
                 printer: Default::default(),
+                services: Default::default(),
+                watched: watch::Sender::new(None),
+                port,
+                advertisement: None,
+                ipp: false,
+                ipp_max_document: IPP_MAX_DOCUMENT,
+                watchdog: None,
+                idempotency: Default::default(),
+                admin_token: None,
+                units: zpl::length::LengthUnit::default(),
+                utc_offset: 0,
+                firmware: Default::default(),
             })),
         }
     }
@@ -0,0 +1,124 @@
+//! The rolls of label stock loaded into each printer, as changed by operators.
+//!
+//! A roll change pauses the printer until the operator confirms the new roll is loaded, which
+//! records the roll and counts the labels printed from it. Kept in memory, like the statistics.
+use serde::Serialize;
+
+use std::{collections::HashMap, sync::Mutex};
+
+#[derive(Default)]
+pub struct MediaTracking {
+    printers: Mutex<HashMap<String, MediaState>>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct MediaState {
+    /// When the roll change in progress was started, as a Unix time.
+    pub changing_since: Option<u64>,
+    /// The roll loaded by the last finished change, if any.
+    pub roll: Option<Roll>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Roll {
+    /// When the roll was loaded, as a Unix time.
+    pub loaded_at: u64,
+    /// The stock as named by the operator, e.g. its article number.
+    pub stock: Option<String>,
+    /// Labels on the roll when loaded, if known.
+    pub labels: Option<u64>,
+    /// Labels printed from the roll since.
+    pub printed: u64,
+    /// Labels estimated to be left, if the labels on the roll are known.
+    pub remaining: Option<u64>,
+}
+
+impl MediaTracking {
+    /// Note the start of a roll change, false if one is already in progress.
+    pub fn start_change(&self, printer: &str, now: u64) -> bool {
+        let mut printers = self.printers.lock().unwrap();
+        let state = printers.entry(printer.to_string()).or_default();
+
+        if state.changing_since.is_some() {
+            return false;
+        }
+
+        state.changing_since = Some(now);
+        true
+    }
+
+    /// Forget a roll change in progress, keeping the roll loaded before.
+    pub fn abort_change(&self, printer: &str) {
+        if let Some(state) = self.printers.lock().unwrap().get_mut(printer) {
+            state.changing_since = None;
+        }
+    }
+
+    /// Record a new roll, whether or not the change was started here.
+    pub fn finish_change(
+        &self,
+        printer: &str,
+        now: u64,
+        stock: Option<String>,
+        labels: Option<u64>,
+    ) -> Roll {
+        let roll = Roll {
+            loaded_at: now,
+            stock,
+            labels,
+            printed: 0,
+            remaining: labels,
+        };
+
+        let mut printers = self.printers.lock().unwrap();
+        let state = printers.entry(printer.to_string()).or_default();
+        state.changing_since = None;
+        state.roll = Some(roll.clone());
+        roll
+    }
+
+    /// Count labels printed from the current roll, if one was recorded.
+    pub fn record_printed(&self, printer: &str, copies: u64) {
+        let mut printers = self.printers.lock().unwrap();
+        let Some(roll) = printers
+            .get_mut(printer)
+            .and_then(|state| state.roll.as_mut())
+        else {
+            return;
+        };
+
+        roll.printed += copies;
+        roll.remaining = roll
+            .labels
+            .map(|labels| labels.saturating_sub(roll.printed));
+    }
+
+    pub fn state(&self, printer: &str) -> MediaState {
+        self.printers
+            .lock()
+            .unwrap()
+            .get(printer)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[test]
+fn roll_change_counts_labels() {
+    let media = MediaTracking::default();
+    media.record_printed("shelf", 5);
+    assert!(media.state("shelf").roll.is_none());
+
+    assert!(media.start_change("shelf", 100));
+    assert!(!media.start_change("shelf", 110));
+    assert_eq!(media.state("shelf").changing_since, Some(100));
+
+    media.finish_change("shelf", 120, Some("57x32".to_string()), Some(1000));
+    media.record_printed("shelf", 3);
+    media.record_printed("shelf", 2);
+
+    let state = media.state("shelf");
+    assert!(state.changing_since.is_none());
+    let roll = state.roll.unwrap();
+    assert_eq!((roll.printed, roll.remaining), (5, Some(995)));
+}
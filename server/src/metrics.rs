@@ -0,0 +1,238 @@
+//! Counters of all printers and templates, in the Prometheus text format.
+//!
+//! Meant to be scraped by existing monitoring, to alert on printers going offline or queues not
+//! draining. Templates are labelled by their configured name only, jobs of any other are counted
+//! as [`AD_HOC`](crate::statistics::AD_HOC), such that clients can not add series.
+use std::fmt::Write as _;
+
+use zpl::util::cache::CacheStatistics;
+
+use crate::{
+    physical_printer::PrinterMetrics,
+    statistics::{TemplateReport, RENDER_BUCKETS},
+};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The metrics of one printer, with the jobs waiting in its queue.
+pub struct PrinterSample {
+    pub name: String,
+    pub metrics: PrinterMetrics,
+    pub queued: usize,
+}
+
+/// A metric of each printer: its name, type, description and value.
+type PrinterMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&PrinterSample) -> u64,
+);
+
+pub fn encode(
+    printers: &[PrinterSample],
+    templates: &[TemplateReport],
+    cache: Option<CacheStatistics>,
+) -> String {
+    let mut out = String::new();
+
+    let per_printer: [PrinterMetric; 7] = [
+        (
+            "zpl_printer_up",
+            "gauge",
+            "Whether the printer is reachable.",
+            |p| p.metrics.up as u64,
+        ),
+        (
+            "zpl_printer_jobs_queued",
+            "gauge",
+            "Jobs accepted but not yet taken up by the printer.",
+            |p| p.queued as u64,
+        ),
+        (
+            "zpl_printer_jobs_printed_total",
+            "counter",
+            "Jobs handed to the printer successfully.",
+            |p| p.metrics.jobs_printed,
+        ),
+        (
+            "zpl_printer_job_failures_total",
+            "counter",
+            "Jobs that failed to render or print.",
+            |p| p.metrics.jobs_failed,
+        ),
+        (
+            "zpl_printer_sent_bytes_total",
+            "counter",
+            "Bytes of commands sent for printed jobs.",
+            |p| p.metrics.bytes_sent,
+        ),
+        (
+            "zpl_printer_connection_retries_total",
+            "counter",
+            "Connection attempts after the first one.",
+            |p| p.metrics.connection_retries,
+        ),
+        (
+            "zpl_printer_status_age_seconds",
+            "gauge",
+            "Time since the status of the printer was last updated.",
+            |p| p.metrics.status_age_seconds,
+        ),
+    ];
+
+    for (name, kind, help, value) in per_printer {
+        header(&mut out, name, kind, help);
+        for printer in printers {
+            let label = escape(&printer.name);
+            let _ = writeln!(
+                out,
+                "{name}{{printer=\"{label}\"}} {}",
+                value(printer)
+            );
+        }
+    }
+
+    let name = "zpl_template_render_seconds";
+    header(&mut out, name, "histogram", "Time spent rendering jobs.");
+    for report in templates {
+        let label = escape(&report.template);
+        let stats = &report.stats;
+
+        for (bound, count) in
+            RENDER_BUCKETS.iter().zip(&stats.render_seconds_buckets)
+        {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{template=\"{label}\",le=\"{bound}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{template=\"{label}\",le=\"+Inf\"}} {}",
+            stats.jobs
+        );
+        let _ = writeln!(
+            out,
+            "{name}_sum{{template=\"{label}\"}} {}",
+            stats.render_seconds_total
+        );
+        let _ = writeln!(
+            out,
+            "{name}_count{{template=\"{label}\"}} {}",
+            stats.jobs
+        );
+    }
+
+    let name = "zpl_template_payload_bytes_total";
+    header(&mut out, name, "counter", "Bytes of submitted content.");
+    for report in templates {
+        let label = escape(&report.template);
+        let _ = writeln!(
+            out,
+            "{name}{{template=\"{label}\"}} {}",
+            report.stats.payload_bytes_total
+        );
+    }
+
+    let name = "zpl_template_coverage_percent";
+    header(
+        &mut out,
+        name,
+        "gauge",
+        "Mean share of dots printed black on labels rendered from content.",
+    );
+    for report in templates
+        .iter()
+        .filter(|report| report.stats.covered_jobs > 0)
+    {
+        let label = escape(&report.template);
+        let _ = writeln!(
+            out,
+            "{name}{{template=\"{label}\"}} {}",
+            report.coverage_percent_mean
+        );
+    }
+
+    if let Some(cache) = cache {
+        let cached: [(&str, &str, &str, u64); 3] = [
+            (
+                "zpl_raster_cache_entries",
+                "gauge",
+                "Rasterized content items kept for reuse.",
+                cache.entries as u64,
+            ),
+            (
+                "zpl_raster_cache_hits_total",
+                "counter",
+                "Content items taken from the cache instead of rendered.",
+                cache.hits,
+            ),
+            (
+                "zpl_raster_cache_misses_total",
+                "counter",
+                "Content items rendered as they were not in the cache.",
+                cache.misses,
+            ),
+        ];
+
+        for (name, kind, help, value) in cached {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{name} {value}");
+        }
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escape a label value as required by the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn encode_printer() {
+    let printers = [PrinterSample {
+        name: "shelf \"A\"".to_string(),
+        metrics: PrinterMetrics {
+            up: true,
+            jobs_printed: 3,
+            jobs_failed: 1,
+            bytes_sent: 1024,
+            connection_retries: 0,
+            status_age_seconds: 2,
+        },
+        queued: 2,
+    }];
+
+    use crate::statistics::{TemplateStatistics, AD_HOC};
+
+    let templates = TemplateStatistics::default();
+    templates.set_templates(["badge".to_string()].into());
+    let render = std::time::Duration::from_millis(30);
+    templates.record(Some("badge"), render, 100, None);
+    templates.record(Some("sha256:0123"), render, 100, None);
+    templates.record(None, render, 100, None);
+
+    let text = encode(&printers, &templates.report(), None);
+    assert!(text.contains("zpl_printer_up{printer=\"shelf \\\"A\\\"\"} 1\n"));
+    assert!(text.contains(
+        "zpl_template_payload_bytes_total{template=\"badge\"} 100\n"
+    ));
+    assert!(text.contains(&format!(
+        "zpl_template_payload_bytes_total{{template=\"{AD_HOC}\"}} 200\n"
+    )));
+    assert!(!text.contains("sha256"));
+    assert!(text
+        .contains("zpl_printer_jobs_queued{printer=\"shelf \\\"A\\\"\"} 2\n"));
+    assert!(text.contains("# TYPE zpl_template_render_seconds histogram\n"));
+}
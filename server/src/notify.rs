@@ -0,0 +1,165 @@
+//! E-mail notifications about failed jobs and printers going down.
+//!
+//! Meant for sites without a monitoring stack of their own. Each printer names its recipients,
+//! either directly or through groups defined in the notification configuration.
+use lettre::{
+    message::{
+        header::ContentType, Attachment, Mailbox, MultiPart, SinglePart,
+    },
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport as _, Message, Tokio1Executor,
+};
+
+use crate::{
+    configuration::{LabelPrinter, NotificationConfiguration, SmtpSecurity},
+    history::JobRecord,
+};
+
+pub struct Notifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    config: NotificationConfiguration,
+}
+
+impl Notifier {
+    pub fn new(config: NotificationConfiguration) -> anyhow::Result<Self> {
+        let smtp = &config.smtp;
+        let mut transport = match smtp.security {
+            SmtpSecurity::Tls => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(
+                    &smtp.host,
+                )?
+            }
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                    &smtp.host,
+                )
+            }
+        };
+
+        if let Some(port) = smtp.port {
+            transport = transport.port(port);
+        }
+
+        if let (Some(user), Some(password)) = (&smtp.username, &smtp.password) {
+            transport = transport
+                .credentials(Credentials::new(user.clone(), password.clone()));
+        }
+
+        Ok(Notifier {
+            transport: transport.build(),
+            from: config.from.parse()?,
+            config,
+        })
+    }
+
+    pub async fn job_failed(
+        &self,
+        printer: &LabelPrinter,
+        record: &JobRecord,
+        error: &str,
+        preview: Option<Vec<u8>>,
+    ) {
+        let subject = format!("Print job failed on {}", record.printer);
+        let body = format!(
+            "A job for printer {} could not be printed.\n\n\
+             Error: {error}\n\
+             Label: {}\n\
+             Template: {}\n\
+             Payload SHA-256: {}\n\
+             Requester: {}\n",
+            record.printer,
+            record.label,
+            record.template.as_deref().unwrap_or("-"),
+            record.payload_sha256,
+            record.requester.as_deref().unwrap_or("-"),
+        );
+
+        self.send(printer, &subject, body, preview).await
+    }
+
+    pub async fn printer_down(
+        &self,
+        name: &str,
+        printer: &LabelPrinter,
+        error: &str,
+    ) {
+        let subject = format!("Printer {name} is down");
+        let body = format!(
+            "Lost the connection to printer {name} at {}.\n\nError: {error}\n",
+            printer.addr
+        );
+
+        self.send(printer, &subject, body, None).await
+    }
+
+    /// The recipients of notifications about a printer.
+    fn recipients(&self, printer: &LabelPrinter) -> Vec<Mailbox> {
+        let entries = if printer.notify.is_empty() {
+            &self.config.recipients
+        } else {
+            &printer.notify
+        };
+
+        entries
+            .iter()
+            .flat_map(|entry| match self.config.groups.get(entry) {
+                Some(group) => group.clone(),
+                None => vec![entry.clone()],
+            })
+            .filter_map(|address| match address.parse() {
+                Ok(mailbox) => Some(mailbox),
+                Err(error) => {
+                    tracing::warn!("Invalid recipient {address}: {error}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    async fn send(
+        &self,
+        printer: &LabelPrinter,
+        subject: &str,
+        body: String,
+        preview: Option<Vec<u8>>,
+    ) {
+        let recipients = self.recipients(printer);
+        if recipients.is_empty() {
+            return;
+        }
+
+        let mut builder =
+            Message::builder().from(self.from.clone()).subject(subject);
+
+        for to in recipients {
+            builder = builder.to(to);
+        }
+
+        let text = SinglePart::plain(body);
+        let message = match preview {
+            Some(png) => builder.multipart(
+                MultiPart::mixed().singlepart(text).singlepart(
+                    Attachment::new("preview.png".to_string())
+                        .body(png, ContentType::parse("image/png").unwrap()),
+                ),
+            ),
+            None => builder.singlepart(text),
+        };
+
+        let message = match message {
+            Ok(message) => message,
+            Err(error) => {
+                tracing::warn!("Failed to compose notification: {error}");
+                return;
+            }
+        };
+
+        if let Err(error) = self.transport.send(message).await {
+            tracing::warn!("Failed to send notification: {error}");
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Rasterize pages of PDF documents, such that they print through the image path.
+//!
+//! Rendering is pure Rust, the page is drawn on white at a fixed resolution and then scaled to the
+//! label like any other image.
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use hayro::{
+    hayro_interpret::InterpreterSettings, hayro_syntax::Pdf, vello_cpu,
+    PixmapSettings, RenderCache, RenderSettings,
+};
+
+/// The resolution pages are rendered at, that of common 12 dots/mm printers.
+const DPI: f32 = 300.0;
+
+/// The most pixels a page may render to, well above any label at [`DPI`].
+const MAX_PIXELS: f32 = 16_000_000.0;
+
+/// Render one page, counted from 1.
+pub fn rasterize(
+    data: &[u8],
+    page: u32,
+) -> anyhow::Result<image::DynamicImage> {
+    let pdf = Pdf::new(Arc::new(data.to_vec()))
+        .map_err(|err| anyhow!("Invalid PDF document: {err:?}"))?;
+    let pages = pdf.pages();
+
+    let Some(selected) =
+        page.checked_sub(1).and_then(|idx| pages.get(idx as usize))
+    else {
+        bail!("Page {page} not in the document of {} pages", pages.len());
+    };
+
+    let scale = DPI / 72.0;
+    // Check the size before rendering, the pixmap is allocated in full.
+    let (width, height) = selected.render_dimensions();
+    let (width, height) = (width * scale, height * scale);
+    if !(width >= 1.0 && height >= 1.0 && width * height <= MAX_PIXELS) {
+        bail!(
+            "Page {page} of {width:.0} by {height:.0} pixels exceeds the limit of {MAX_PIXELS} pixels"
+        );
+    }
+
+    let pixmap = hayro::render(
+        selected,
+        &RenderCache::new(),
+        &InterpreterSettings::default(),
+        &RenderSettings::default(),
+        &PixmapSettings {
+            x_scale: scale,
+            y_scale: scale,
+            bg_color: vello_cpu::color::palette::css::WHITE,
+        },
+    );
+
+    let (width, height) = (pixmap.width() as u32, pixmap.height() as u32);
+    // The background is opaque, so premultiplied alpha is the same as straight alpha.
+    let rgba = pixmap
+        .take_rgba8(vello_cpu::peniko::ImageAlphaType::AlphaPremultiplied);
+    let image = image::RgbaImage::from_raw(width, height, rgba)
+        .ok_or_else(|| anyhow!("Page {page} rendered to an invalid image"))?;
+
+    Ok(image.into())
+}
+
+#[test]
+fn rasterize_page() {
+    // A 2 by 1 inch page with a black square at its bottom left.
+    const DOCUMENT: &str = "\
+        %PDF-1.4\n\
+        1 0 obj\n\
+        << /Type /Catalog /Pages 2 0 R >>\n\
+        endobj\n\
+        2 0 obj\n\
+        << /Type /Pages /Kids [3 0 R] /Count 1 >>\n\
+        endobj\n\
+        3 0 obj\n\
+        << /Type /Page /Parent 2 0 R /MediaBox [0 0 144 72] /Contents 4 0 R >>\n\
+        endobj\n\
+        4 0 obj\n\
+        << /Length 25 >>\n\
+        stream\n\
+        0 0 0 rg 10 10 50 50 re f\n\
+        endstream\n\
+        endobj\n\
+        xref\n\
+        0 5\n\
+        0000000000 65535 f \n\
+        0000000009 00000 n \n\
+        0000000058 00000 n \n\
+        0000000115 00000 n \n\
+        0000000201 00000 n \n\
+        trailer\n\
+        << /Size 5 /Root 1 0 R >>\n\
+        startxref\n\
+        276\n\
+        %%EOF\n\
+    ";
+
+    let image = rasterize(DOCUMENT.as_bytes(), 1).unwrap().into_luma8();
+    assert_eq!(image.dimensions(), (600, 300));
+    assert_eq!(image.get_pixel(100, 250).0, [0]);
+    assert_eq!(image.get_pixel(500, 50).0, [255]);
+
+    assert!(rasterize(DOCUMENT.as_bytes(), 2).is_err());
+    assert!(rasterize(DOCUMENT.as_bytes(), 0).is_err());
+
+    let huge = DOCUMENT.replace("[0 0 144 72]", "[0 0 14400 7200]");
+    let error = rasterize(huge.as_bytes(), 1).unwrap_err();
+    assert!(error.to_string().contains("exceeds"));
+}
+
+#[test]
+fn preview_page() {
+    // 10 by 5 mm, black on the left half.
+    let label = image::GrayImage::from_fn(80, 40, |x, _| {
+        image::Luma([if x < 40 { 0 } else { 255 }])
+    });
+
+    let document = zpl::util::image::pdf_page(&label, 8);
+    let image = rasterize(&document, 1).unwrap().into_luma8();
+    assert_eq!(image.dimensions(), (118, 59));
+    assert!(image.get_pixel(20, 30).0[0] < 64);
+    assert!(image.get_pixel(100, 30).0[0] > 192);
+}
@@ -1,30 +1,52 @@
-use crate::{configuration, job, ShutdownToken};
-use zpl::label::{PrintCalibration, PrintOptions, Unit};
+use crate::{
+    artifacts, configuration, dead_letter, drain, events, firmware, history,
+    idempotency, intake, job, logs, media, notify, pull, render, schedule,
+    statistics, validation::ValidationError, zones, ShutdownToken,
+};
+
+#[cfg(feature = "fault-injection")]
+use crate::faults;
+use zpl::{
+    label::{Mirroring, Orientation, PrintOptions, QrRendering, RenderOptions},
+    length::{Length, LengthUnit},
+    quirks::Quirks,
+};
 
-use log::{debug, error, info, warn};
+use tracing::{debug, error, info, warn, Instrument as _};
 
+use base64::prelude::*;
 use serde::Serialize;
 
 use std::{
+    collections::VecDeque,
+    future::Future,
     io::Write as _,
-    path::PathBuf,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
     sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
         Arc,
     },
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
 
 use tokio::{
-    sync::{mpsc, oneshot},
+    sync::{mpsc, oneshot, Notify},
     task::JoinSet,
 };
 
 use zpl::{
-    command::{HostIdentification, HostStatus},
-    device::ZplPrinter,
+    command::{
+        CommandSequence, HeadDiagnostic, HostIdentification, HostStatus,
+        NetworkSettings, PostPrintAction, ResponseSpec, Separator, ZplCommand,
+    },
+    device::{tap, PrintError, PrintedOutcome, ZplPrinter},
 };
 
+/// How often printers configured by name are looked up again while connected.
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct LabelPrinter {
     config: Arc<configuration::LabelPrinter>,
     label: Arc<configuration::Label>,
@@ -38,6 +60,54 @@ pub struct LabelPrinter {
 pub struct PhysicalPrinter {
     target: Arc<LabelPrinter>,
     status: Arc<PrinterStatus>,
+    services: Services,
+    pull: Arc<pull::PullQueue>,
+    /// The bytes last exchanged with the printer, if configured.
+    tap: Option<Arc<tap::RingBuffer>>,
+    /// Asks the drive loop to check its connection, or to connect, right away.
+    reconnect: Arc<Notify>,
+    #[cfg(feature = "fault-injection")]
+    faults: Arc<faults::Faults>,
+}
+
+/// Server-wide facilities shared by all printers.
+#[derive(Clone)]
+pub struct Services {
+    pub history: Option<Arc<history::JobLog>>,
+    pub artifacts: Option<Arc<artifacts::ArtifactStore>>,
+    pub statistics: Arc<statistics::TemplateStatistics>,
+    pub coverage: Arc<statistics::CoverageStatistics>,
+    pub notifier: Option<Arc<notify::Notifier>>,
+    pub limiter: Arc<render::RenderLimiter>,
+    pub intake: Arc<intake::Intake>,
+    pub dead_letters: Arc<dead_letter::DeadLetters>,
+    pub media: Arc<media::MediaTracking>,
+    pub drain: Arc<drain::Draining>,
+    pub schedule: Arc<schedule::Schedule>,
+    pub submissions: Arc<idempotency::Submissions>,
+    pub events: events::Events,
+}
+
+impl Default for Services {
+    fn default() -> Self {
+        let events = events::Events::default();
+
+        Services {
+            history: None,
+            artifacts: None,
+            statistics: Default::default(),
+            coverage: Default::default(),
+            notifier: None,
+            limiter: Default::default(),
+            intake: Arc::new(intake::Intake::new(events.clone())),
+            dead_letters: Default::default(),
+            media: Default::default(),
+            drain: Default::default(),
+            schedule: Default::default(),
+            submissions: Default::default(),
+            events,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -48,19 +118,160 @@ struct PrinterStatus {
     // an accurate picture of reliability to clients.
     is_up: AtomicBool,
     updated_at: AtomicU64,
+    /// Whether the last connection attempt succeeded and the connection did not break since.
+    reachable: AtomicBool,
+    jobs_printed: AtomicU64,
+    jobs_failed: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// Connection attempts after the first one.
+    connection_retries: AtomicU64,
+    /// The commands of the last printed job, for support bundles.
+    last_commands: std::sync::Mutex<Option<String>>,
+    /// Consecutive failed self-tests of the watchdog.
+    failed_self_tests: AtomicU32,
+    /// Whether the watchdog gave up on the printer, until a self-test succeeds again.
+    flagged: AtomicBool,
+    /// The resolution reported by the device on the last connection, 0 before.
+    dpmm: AtomicU32,
+    /// The network settings last read, with the time they were read at.
+    network: std::sync::Mutex<Option<(u64, NetworkSettings)>>,
+    /// Whether a job was taken from the queue and is not done yet.
+    printing: AtomicBool,
+}
+
+/// A snapshot of the counters of a printer, for monitoring.
+#[derive(Serialize)]
+pub struct PrinterMetrics {
+    pub up: bool,
+    pub jobs_printed: u64,
+    pub jobs_failed: u64,
+    pub bytes_sent: u64,
+    pub connection_retries: u64,
+    pub status_age_seconds: u64,
+}
+
+/// The network settings of a printer, read now or, if it is not reachable, last.
+#[derive(Serialize)]
+pub struct NetworkReadout {
+    /// Whether the printer answered just now.
+    pub reachable: bool,
+    pub error: Option<String>,
+    /// When the settings were read, in seconds since the epoch.
+    pub read_at: Option<u64>,
+    pub settings: Option<NetworkSettings>,
 }
 
 #[derive(Serialize)]
 pub struct StatusInformation {
     display_name: Option<String>,
     printer_label: PrinterInformation,
+    /// The unit of the label dimensions.
+    units: LengthUnit,
     is_up: bool,
     updated_at_unix: u64,
+    /// The printer failed repeated self-tests and likely needs attention.
+    flagged: bool,
+}
+
+/// What a job would be printed with, see [`PhysicalPrinter::dry_run`].
+#[derive(Serialize)]
+pub struct DryRun {
+    /// The commands exactly as they would be sent.
+    pub zpl: String,
+    /// The same commands one per line, for reading.
+    pub annotated: String,
+    pub bytes: usize,
+    /// The resolution rendered for.
+    pub dpmm: u32,
+    /// The settings the job would be printed with, unless it is raw commands.
+    pub effective: Option<history::EffectiveOptions>,
+    /// The label as it would come out, as a Base64 encoded PNG, if asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+}
+
+/// What clients need to know to draw content to fit a printer's labels.
+#[derive(Serialize)]
+pub struct LabelGeometry {
+    dimensions: PrinterInformation,
+    units: LengthUnit,
+    /// Dots per mm, unknown until a connected printer is first reached.
+    dpmm: Option<u32>,
+    /// Where content is placed on the label, in dots.
+    printable: Option<PrintableArea>,
+}
+
+#[derive(Serialize)]
+pub struct PrintableArea {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
 }
 
 type ConnectionHandled = anyhow::Result<Option<ActiveConnection>>;
 
-struct PrinterInformation(Arc<LabelPrinter>);
+/// A job handed to the printer, with the connection that may be reused.
+struct Printed {
+    con: Option<ActiveConnection>,
+    /// The commands sent for the job, byte for byte.
+    zpl: String,
+    /// Time spent turning the job into commands.
+    render_time: Duration,
+    /// The share of the label printed black, unknown for raw commands.
+    coverage: Option<f64>,
+    /// The settings rendered with, none for raw commands.
+    effective: Option<history::EffectiveOptions>,
+}
+
+/// Why a job was not printed.
+enum JobError {
+    /// The job could not be turned into commands, which leaves the printer as it was.
+    Render {
+        error: anyhow::Error,
+        con: Option<Box<ActiveConnection>>,
+    },
+    /// Sending the job to the printer, or printing it, failed.
+    Printer(anyhow::Error),
+    /// The printer stopped with labels of the job left, which it prints once seen to. The
+    /// connection stays usable.
+    Suspended {
+        error: anyhow::Error,
+        con: Option<Box<ActiveConnection>>,
+    },
+    /// The labels were neither confirmed printed nor did the printer fail, as when waiting for
+    /// them timed out with the printer still holding them, or they were cancelled on the printer.
+    /// Sending the job again could print them twice, and nobody has to see to the printer.
+    Unconfirmed {
+        error: anyhow::Error,
+        held: bool,
+        con: Option<Box<ActiveConnection>>,
+    },
+}
+
+impl From<anyhow::Error> for JobError {
+    fn from(error: anyhow::Error) -> Self {
+        JobError::Printer(error)
+    }
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::Render { error, .. }
+            | JobError::Printer(error)
+            | JobError::Suspended { error, .. }
+            | JobError::Unconfirmed { error, .. } => {
+                std::fmt::Display::fmt(error, f)
+            }
+        }
+    }
+}
+
+type PendingLabel =
+    Pin<Box<dyn Future<Output = Result<Printed, JobError>> + Send>>;
+
+struct PrinterInformation(Arc<LabelPrinter>, LengthUnit);
 
 impl Serialize for PrinterInformation {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -68,30 +279,96 @@ impl Serialize for PrinterInformation {
         S: serde::Serializer,
     {
         // Only the dimensions are public information.
-        self.0.label.dimensions.serialize(serializer)
+        self.0
+            .label
+            .dimensions
+            .in_units(self.1)
+            .serialize(serializer)
+    }
+}
+
+/// Why a task was not queued for a printer.
+#[derive(Debug)]
+pub enum SendError {
+    /// All places of the queue are taken, the task may be sent again later.
+    Full,
+    /// The printer's task ended, such as on shutdown.
+    Closed,
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Full => f.write_str("queue full"),
+            SendError::Closed => f.write_str("printer stopped"),
+        }
+    }
+}
+
+impl<T> From<mpsc::error::TrySendError<T>> for SendError {
+    fn from(error: mpsc::error::TrySendError<T>) -> Self {
+        match error {
+            mpsc::error::TrySendError::Full(_) => SendError::Full,
+            mpsc::error::TrySendError::Closed(_) => SendError::Closed,
+        }
     }
 }
 
 pub struct Driver {
     message: mpsc::Sender<Task>,
+    /// Jobs of high priority, taken before any waiting in `message`.
+    urgent: mpsc::Sender<Task>,
+    self_test: mpsc::Sender<SelfTestReply>,
     end: Option<oneshot::Sender<ShutdownToken>>,
 }
 
 /// Counter part to driver, the physical printer side.
 pub struct Connector {
     message: mpsc::Receiver<Task>,
+    urgent: mpsc::Receiver<Task>,
+    /// Self-tests of the watchdog, served even while jobs wait.
+    self_test: mpsc::Receiver<SelfTestReply>,
     end: oneshot::Receiver<ShutdownToken>,
     name: String,
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum Task {
-    Job { print_job: job::PrintJob },
+    Job {
+        print_job: job::PrintJob,
+        options: job::JobOptions,
+        record: history::JobRecord,
+    },
+    Firmware {
+        update: Arc<firmware::FirmwareUpdate>,
+    },
+    /// Commands for the printer itself rather than a label, such as calibration.
+    Maintenance { commands: CommandSequence },
+    /// Pause, resume or cancel, sent even while a job is printed, see [`Driver::control`].
+    Control {
+        command: ZplCommand,
+        reply: oneshot::Sender<anyhow::Result<()>>,
+    },
 }
 
+type SelfTestReply = oneshot::Sender<anyhow::Result<()>>;
+
 struct ActiveConnection {
     target: Arc<LabelPrinter>,
     printer: ZplPrinter,
+    /// The address the connection was opened to, for noticing the printer moved.
+    addr: SocketAddr,
     device_status: HostStatus,
+    /// Device controls sent in between the requests of the job holding the connection.
+    interjections: mpsc::Sender<ZplCommand>,
+}
+
+struct PullParameter {
+    target: Arc<LabelPrinter>,
+    pull: Arc<pull::PullQueue>,
+    dpmm: Option<u32>,
+    /// How long to wait for the agent to fetch the job and report its result.
+    timeout: std::time::Duration,
 }
 
 struct SimulationParameter {
@@ -99,6 +376,10 @@ struct SimulationParameter {
     dpmm: Option<u32>,
     target: Arc<LabelPrinter>,
     persist: Option<PathBuf>,
+    /// Pictures of the label written next to the persisted ZPL.
+    previews: Vec<configuration::PreviewFormat>,
+    #[cfg(feature = "fault-injection")]
+    faults: Arc<faults::Faults>,
 }
 
 impl LabelPrinter {
@@ -115,40 +396,460 @@ impl LabelPrinter {
 }
 
 impl PhysicalPrinter {
-    pub fn new(label: LabelPrinter) -> Self {
+    pub fn new(label: LabelPrinter, services: Services) -> Self {
         PhysicalPrinter {
+            tap: label
+                .config
+                .tap
+                .map(|bytes| Arc::new(tap::RingBuffer::new(bytes))),
             target: Arc::new(label),
             status: Arc::default(),
+            services,
+            pull: Arc::default(),
+            reconnect: Arc::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: Arc::default(),
+        }
+    }
+
+    /// The failures injected into this printer, unless an agent prints for it.
+    ///
+    /// Virtual printers fail their simulated jobs, the connections to others fail at the device.
+    #[cfg(feature = "fault-injection")]
+    pub fn faults(&self) -> Option<&faults::Faults> {
+        match self.target.config.virtualization {
+            configuration::LabelVirtualization::Pulled { .. } => None,
+            _ => Some(&self.faults),
+        }
+    }
+
+    /// The jobs waiting for an agent, if this printer is served by one.
+    pub fn pull_queue(&self) -> Option<&pull::PullQueue> {
+        match self.target.config.virtualization {
+            configuration::LabelVirtualization::Pulled { .. } => {
+                Some(&self.pull)
+            }
+            _ => None,
         }
     }
 
+    /// Whether the server holds a connection of its own, needed for anything besides jobs.
+    pub fn is_physical(&self) -> bool {
+        matches!(
+            self.target.config.virtualization,
+            configuration::LabelVirtualization::Physical
+        )
+    }
+
+    /// Resume after a new roll was loaded, calibrating to it and feeding a label to check.
+    ///
+    /// Uses a connection of its own, as the printer is paused while jobs may wait on the other.
+    pub async fn finish_roll_change(
+        &self,
+        calibrate: bool,
+    ) -> anyhow::Result<()> {
+        let mut printer =
+            tokio::time::timeout(Duration::from_secs(1), self.open()).await??;
+
+        printer.resume().await?;
+        if calibrate {
+            printer.calibrate().await?;
+        }
+        printer.feed_label().await?;
+
+        Ok(())
+    }
+
+    /// The bytes last exchanged with the printer, on all connections, if they are kept.
+    pub fn tapped(&self) -> Option<Vec<tap::Chunk>> {
+        Some(self.tap.as_ref()?.chunks())
+    }
+
+    fn tap(&self) -> Option<Arc<dyn tap::Tap>> {
+        Some(self.tap.clone()? as Arc<dyn tap::Tap>)
+    }
+
+    /// Open a connection of its own to the printer, wherever its name resolves to now.
+    async fn open(&self) -> std::io::Result<ZplPrinter> {
+        let addr = self.target.config.addr.resolve().await?;
+        #[cfg(feature = "fault-injection")]
+        self.faults.connect()?;
+        let mut printer = ZplPrinter::with_address(addr).await?;
+        #[cfg(feature = "fault-injection")]
+        printer.set_interceptor(Some(self.faults.interceptor()));
+        printer.set_tap(self.tap());
+        printer.set_retry_policy(self.target.config.retry.clone());
+        printer.set_reconnect_host(self.target.config.addr.to_string());
+        Ok(printer)
+    }
+
+    /// Ask the printer for its head diagnostic, on a connection of its own.
+    pub async fn head_diagnostic(&self) -> anyhow::Result<HeadDiagnostic> {
+        let request = async {
+            let mut printer = self.open().await?;
+            printer.request_head_diagnostic().await
+        };
+
+        Ok(tokio::time::timeout(Duration::from_secs(5), request).await??)
+    }
+
+    /// Ask the printer for its status right away, on a connection of its own.
+    ///
+    /// The status shown for the printer is refreshed by the answer, or marked down without one.
+    /// Either way the connection for jobs is checked right away, or opened again without waiting
+    /// out the delay after failed attempts.
+    pub async fn probe(&self) -> anyhow::Result<HostStatus> {
+        let request = async {
+            let mut printer = self.open().await?;
+            printer.request_device_status().await.cloned()
+        };
+        let status = tokio::time::timeout(Duration::from_secs(5), request)
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| Ok(result?));
+        self.reconnect.notify_one();
+        self.status.updated_at.store(unix_now(), Ordering::Relaxed);
+
+        let status = match status {
+            Ok(status) => status,
+            Err(error) => {
+                if self.status.reachable.swap(false, Ordering::Relaxed) {
+                    self.notify_down(
+                        &format!("@{}", self.target.config.addr),
+                        &error,
+                    );
+                }
+                return Err(error);
+            }
+        };
+
+        let dpmm = status.identification.dpmm;
+        self.status.dpmm.store(dpmm, Ordering::Relaxed);
+        self.status.is_up.store(true, Ordering::Relaxed);
+        Ok(status)
+    }
+
+    /// Ask the printer for its network settings, on a connection of its own.
+    ///
+    /// When it does not answer, the settings read last still tell whether it was losing its
+    /// wireless signal before.
+    pub async fn network_settings(&self) -> NetworkReadout {
+        let request = async {
+            let mut printer = self.open().await?;
+            printer.request_network_settings().await
+        };
+
+        let result = tokio::time::timeout(Duration::from_secs(5), request)
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| Ok(result?));
+
+        let mut last = self.status.network.lock().unwrap();
+        let error = match result {
+            Ok(settings) => {
+                *last = Some((unix_now(), settings));
+                None
+            }
+            Err(error) => Some(error.to_string()),
+        };
+
+        NetworkReadout {
+            reachable: error.is_none(),
+            error,
+            read_at: last.as_ref().map(|(at, _)| *at),
+            settings: last.as_ref().map(|(_, settings)| settings.clone()),
+        }
+    }
+
+    /// A head test pattern covering the printer's labels, once its resolution is known.
+    pub fn head_test(&self) -> Option<CommandSequence> {
+        let dim = &self.target.label.dimensions;
+        let dpmm = self.dpmm()? as f32;
+
+        Some(CommandSequence::head_test(
+            (dim.width * dpmm) as u32,
+            (dim.height * dpmm) as u32,
+        ))
+    }
+
+    /// Check the bearer token presented by an agent.
+    pub fn accepts_agent(&self, token: Option<&str>) -> bool {
+        match &self.target.config.virtualization {
+            configuration::LabelVirtualization::Pulled {
+                token: Some(expected),
+                ..
+            } => token == Some(expected.as_str()),
+            configuration::LabelVirtualization::Pulled {
+                token: None, ..
+            } => true,
+            _ => false,
+        }
+    }
+
+    /// Note that an agent is in contact on behalf of this printer.
+    pub fn mark_seen(&self) {
+        self.status.is_up.store(true, Ordering::Relaxed);
+        self.status.updated_at.store(unix_now(), Ordering::Relaxed);
+    }
+
+    /// How to refer to this printer for the user, if configured.
+    pub fn display_name(&self) -> Option<&str> {
+        self.target.config.display_name.as_deref()
+    }
+
+    /// The label type configured for this printer.
+    pub fn label(&self) -> &configuration::LabelIdentifier {
+        &self.target.config.label
+    }
+
+    /// Whether a job was taken from the queue and is being rendered or printed.
+    pub fn is_printing(&self) -> bool {
+        self.status.printing.load(Ordering::Relaxed)
+    }
+
+    pub fn metrics(&self) -> PrinterMetrics {
+        let status = &self.status;
+        let up = match self.target.config.virtualization {
+            configuration::LabelVirtualization::Pulled { .. } => {
+                status.is_up.load(Ordering::Relaxed)
+            }
+            configuration::LabelVirtualization::ZplOnly { .. } => true,
+            _ => status.reachable.load(Ordering::Relaxed),
+        };
+
+        PrinterMetrics {
+            up,
+            jobs_printed: status.jobs_printed.load(Ordering::Relaxed),
+            jobs_failed: status.jobs_failed.load(Ordering::Relaxed),
+            bytes_sent: status.bytes_sent.load(Ordering::Relaxed),
+            connection_retries: status
+                .connection_retries
+                .load(Ordering::Relaxed),
+            status_age_seconds: unix_now()
+                .saturating_sub(status.updated_at.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// The commands sent for the last printed job, if any.
+    pub fn last_commands(&self) -> Option<String> {
+        self.status.last_commands.lock().unwrap().clone()
+    }
+
     /// Get the serializable public status information for this printer.
-    pub fn status(&self) -> StatusInformation {
+    pub fn status(&self, units: LengthUnit) -> StatusInformation {
         StatusInformation {
             is_up: self.status.is_up.load(Ordering::Relaxed),
             display_name: self.target.config.display_name.clone(),
-            printer_label: PrinterInformation(self.target.clone()),
+            printer_label: PrinterInformation(self.target.clone(), units),
+            units,
             updated_at_unix: self.status.updated_at.load(Ordering::Relaxed),
+            flagged: self.status.flagged.load(Ordering::Relaxed),
         }
     }
 
-    pub async fn verify_label(
+    /// The resolution jobs are rendered at, as far as known yet.
+    pub fn dpmm(&self) -> Option<u32> {
+        let detected = match self.status.dpmm.load(Ordering::Relaxed) {
+            0 => None,
+            dpmm => Some(dpmm),
+        };
+
+        match &self.target.config.virtualization {
+            configuration::LabelVirtualization::Physical => detected,
+            configuration::LabelVirtualization::DropJobs { .. } => {
+                Some(detected.unwrap_or(8))
+            }
+            configuration::LabelVirtualization::ZplOnly { dpmm, .. } => {
+                Some(dpmm.unwrap_or(8))
+            }
+            configuration::LabelVirtualization::Pulled { dpmm, .. } => {
+                Some(self.pull.dpmm().or(*dpmm).unwrap_or(8))
+            }
+        }
+    }
+
+    /// The geometry, with the label dimensions in the given unit.
+    pub fn label_geometry(&self, units: LengthUnit) -> LabelGeometry {
+        let dim = &self.target.label.dimensions;
+        let dpmm = self.dpmm();
+
+        // As content is placed by `job::PrintJob::into_label`.
+        let printable = dpmm.map(|dpmm| {
+            let dots = |mm: f32| Length::mm(mm).to_dots(dpmm);
+            PrintableArea {
+                x: dots(dim.margin_left),
+                y: dots(dim.margin_top),
+                width: dots(dim.width - dim.margin_left - dim.margin_right),
+                height: dots(dim.height - dim.margin_top - dim.margin_bottom),
+            }
+        });
+
+        LabelGeometry {
+            dimensions: PrinterInformation(self.target.clone(), units),
+            units,
+            dpmm,
+            printable,
+        }
+    }
+
+    /// Count the outcome of a self-test, flagging the printer after `threshold` failures in a row.
+    pub fn record_self_test(
+        &self,
+        name: &str,
+        result: &anyhow::Result<()>,
+        threshold: u32,
+    ) {
+        let error = match result {
+            Ok(()) => {
+                self.status.failed_self_tests.store(0, Ordering::Relaxed);
+                if self.status.flagged.swap(false, Ordering::Relaxed) {
+                    info!("[{name}]: Self-test passed again");
+                }
+
+                return;
+            }
+            Err(error) => error,
+        };
+
+        let failed = self
+            .status
+            .failed_self_tests
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        warn!("[{name}]: Self-test failed ({failed} in a row): {error}");
+
+        if failed >= threshold.max(1)
+            && !self.status.flagged.swap(true, Ordering::Relaxed)
+        {
+            error!("[{name}]: Flagged after {failed} failed self-tests");
+            self.notify_down(
+                name,
+                &format!("{failed} self-tests failed in a row, last: {error}"),
+            );
+        }
+    }
+
+    /// The checks of a job that do not look into its content, cheap enough for any request.
+    pub fn check_request(
         &self,
         payload: &job::PrintApi,
-    ) -> Result<job::PrintJob, String> {
+    ) -> Result<(), ValidationError> {
         if let Some(dimensions) = &payload.dimensions {
             if !dimensions.approx_cmp(&self.target.label.dimensions) {
-                return Err(
-                    "Dimension mismatch, check physical label configuration"
-                        .to_string(),
-                );
+                return Err(ValidationError::DimensionMismatch {
+                    expected: self.target.label.dimensions.clone(),
+                    got: dimensions.clone(),
+                });
             }
         };
 
-        match tokio::task::block_in_place(|| payload.validate_as_job()) {
-            Ok(job) => Ok(job),
-            Err(error) => return Err(error.to_string()),
+        payload
+            .options
+            .validate(&self.target.config.limits)
+            .map_err(|message| ValidationError::Options { message })?;
+        if let Some(emphasis) = &payload.emphasis {
+            emphasis
+                .validate()
+                .map_err(|message| ValidationError::Options { message })?;
+        }
+        payload
+            .validate_passthrough(&self.target.config.passthrough)
+            .map_err(|message| ValidationError::Passthrough { message })
+    }
+
+    pub async fn verify_label(
+        &self,
+        payload: &job::PrintApi,
+    ) -> Result<job::PrintJob, ValidationError> {
+        self.check_request(payload)?;
+
+        let fonts = &self.target.config.font_directories;
+        let job =
+            tokio::task::block_in_place(|| payload.validate_as_job(fonts))?;
+
+        let stock = &self.target.label;
+        let options = payload.job_options();
+        let label = tokio::task::block_in_place(|| {
+            job.clone().into_label(
+                stock,
+                &preview_host(),
+                &options,
+                &self.target.config.transforms,
+            )
+        })
+        .map_err(ValidationError::content)?;
+
+        let findings = tokio::task::block_in_place(|| {
+            zones::check(&label, &stock.exclusion_zones).map_err(|error| {
+                ValidationError::Placement {
+                    message: error.to_string(),
+                }
+            })?;
+            let svg = job::PrintApi::printer_svg_options(fonts);
+            Ok::<_, ValidationError>(zpl::lint::check(&label, &svg))
+        })?;
+
+        for finding in findings {
+            if finding.severity == zpl::lint::Severity::Error {
+                return Err(ValidationError::Lint {
+                    message: finding.message,
+                });
+            }
+
+            warn!("Job content: {}", finding.message);
         }
+
+        Ok(job)
+    }
+
+    /// Render a job into the commands it would be printed with, without queueing it.
+    ///
+    /// Rendered for the resolution last detected, the firmware is not known outside of a
+    /// connection and content is encoded as configured.
+    pub fn dry_run(
+        &self,
+        job: job::PrintJob,
+        options: &job::JobOptions,
+        preview: bool,
+    ) -> anyhow::Result<DryRun> {
+        let identification = HostIdentification {
+            dpmm: self.dpmm().unwrap_or(preview_host().dpmm),
+            ..HostIdentification::default()
+        };
+
+        let preview = preview
+            .then(|| self.preview(job.clone(), options))
+            .flatten()
+            .map(|png| BASE64_STANDARD.encode(png));
+
+        let (seq, _, effective) = tokio::task::block_in_place(|| {
+            render_for_device(
+                &self.target,
+                job,
+                options,
+                &identification,
+                self.services.limiter.cache.clone(),
+            )
+        })?;
+
+        let zpl = seq.encoded(Separator::None);
+        Ok(DryRun {
+            bytes: zpl.len(),
+            annotated: seq.encoded(Separator::Newline),
+            zpl,
+            dpmm: identification.dpmm,
+            effective,
+            preview,
+        })
+    }
+
+    /// Render a job into a PNG as it would come out, with what is pre-printed on the stock.
+    pub fn preview(
+        &self,
+        job: job::PrintJob,
+        options: &job::JobOptions,
+    ) -> Option<Vec<u8>> {
+        tokio::task::block_in_place(|| job_preview(job, options, &self.target))
     }
 
     pub async fn drive(self, con: Connector) {
@@ -156,17 +857,27 @@ impl PhysicalPrinter {
             JoinSet::new();
         let mut con = con;
         let mut active: Option<ActiveConnection> = None;
+        let mut first_attempt = true;
+        let mut self_tests: Vec<SelfTestReply> = vec![];
+        // Taken from the urgent lane while busy, in search of device controls.
+        let mut held: VecDeque<Task> = VecDeque::new();
+        let mut interjections: Option<mpsc::Sender<ZplCommand>> = None;
 
         // To avoid barraging the printer / network with connection attempts, we ensure a minimum
-        // amount of time is between each one. Note that these refer to the connection attempt
-        // itself, meaning if a connection is running for a longtime and then drops the first
-        // attempt is made immediately. This then restarts the delay.
+        // amount of time is between each one, growing with each failure as the retry policy of
+        // the printer asks. Note that these refer to the connection attempt itself, meaning if a
+        // connection is running for a longtime and then drops the first attempt is made
+        // immediately. This then restarts the delay.
         //
-        // Note: the first tick is immediate meaning we do not wait with the initial connection.
-        let retry_fail = std::time::Duration::from_millis(1_000);
-        let mut interval_reconnect = tokio::time::interval(retry_fail);
-        interval_reconnect
-            .set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // Once the policy has no attempts left, the printer is tried again every `max` of its
+        // backoff, for as long as it takes.
+        //
+        // Note: we do not wait with the initial connection.
+        let backoff = self.target.config.retry.backoff.clone();
+        let max_attempts = self.target.config.retry.max_attempts.max(1);
+        let mut failures = 0;
+        let mut last_attempt = tokio::time::Instant::now();
+        let mut next_attempt = last_attempt;
 
         let keepalive_interval = std::time::Duration::from_millis(1_000);
         let mut interval_keepalive = tokio::time::interval(keepalive_interval);
@@ -175,34 +886,60 @@ impl PhysicalPrinter {
 
         let connection_timeout = std::time::Duration::from_millis(1_000);
 
+        // Printers configured by name may be moved by DHCP or DNS while we hold a connection
+        // to their old address, which then only breaks once the address is handed out again.
+        let mut interval_resolve = tokio::time::interval(RESOLVE_INTERVAL);
+        interval_resolve
+            .set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let resolve_names = self.target.config.addr.is_name();
+
         loop {
             if label_being_printed.is_empty()
                 && active.is_none()
                 && self.target.config.virtualization.is_connnected()
             {
-                interval_reconnect.tick().await;
+                // A probe of the printer asks not to wait any longer.
+                tokio::select! {
+                    _ = tokio::time::sleep_until(next_attempt) => {}
+                    _ = self.reconnect.notified() => {}
+                }
+                last_attempt = tokio::time::Instant::now();
+                next_attempt = last_attempt + backoff.delay(1);
+
+                if !std::mem::take(&mut first_attempt) {
+                    self.status
+                        .connection_retries
+                        .fetch_add(1, Ordering::Relaxed);
+                }
 
                 info!(
                     "[{}]: Connecting to printer at {}",
                     con.name, self.target.config.addr
                 );
 
-                info!(
-                    "[{}]: Next reconnection attempt in {:?}",
-                    con.name, retry_fail
-                );
-
                 let label = self.target.clone();
                 let name = con.name.clone();
+                let tap = self.tap();
+                #[cfg(feature = "fault-injection")]
+                let faults = self.faults.clone();
 
                 label_being_printed.spawn(async move {
+                    let addr = label.config.addr.resolve().await?;
+                    #[cfg(feature = "fault-injection")]
+                    faults.connect()?;
                     let mut printer = tokio::time::timeout(
                         connection_timeout,
-                        ZplPrinter::with_address(label.config.addr),
+                        ZplPrinter::with_address(addr),
                     )
                     .await??;
-
-                    debug!("[{}]: Connection opened", name);
+                    #[cfg(feature = "fault-injection")]
+                    printer.set_interceptor(Some(faults.interceptor()));
+                    printer.set_tap(tap);
+                    printer.set_retry_policy(label.config.retry.clone());
+                    printer.set_reconnect_host(label.config.addr.to_string());
+                    let interjections = printer.interjections();
+
+                    debug!("[{}]: Connection opened to {}", name, addr);
                     let device_status = printer.request_device_status().await?;
                     info!("[{}]: Device status up", name);
 
@@ -210,7 +947,9 @@ impl PhysicalPrinter {
 
                     Ok(Some(ActiveConnection {
                         printer,
+                        addr,
                         device_status,
+                        interjections,
                         target: label,
                     }))
                 });
@@ -223,24 +962,92 @@ impl PhysicalPrinter {
                 // being or not.
                 _ = interval_keepalive.tick(), if active.is_some() => {
                     if let Some(ready) = &mut active {
-                        if let Err(error) = ready.verify().await {
-                            warn!("[{}]: Connection broken {}", con.name, error);
-                            let _ = active.take();
+                        match ready.verify().await {
+                            Err(error) => {
+                                warn!("[{}]: Connection broken {}", con.name, error);
+                                let _ = active.take();
+
+                                // Notify only once when it goes down.
+                                if self.status.reachable.swap(false, Ordering::Relaxed) {
+                                    self.notify_down(&con.name, &error);
+                                }
+                            }
+                            // Up again after a failed probe.
+                            Ok(()) => self.notify_up(&con.name),
                         }
                     }
                 }
+                // A probe of the printer, failed or not, has the connection checked now.
+                _ = self.reconnect.notified(), if active.is_some() => {
+                    interval_keepalive.reset_immediately();
+                }
+                _ = interval_resolve.tick(), if resolve_names && active.is_some() => {
+                    let moved = match self.target.config.addr.resolve().await {
+                        Ok(addr) => active
+                            .as_ref()
+                            .map(|ready| ready.addr)
+                            .filter(|&old| old != addr)
+                            .map(|old| (old, addr)),
+                        Err(error) => {
+                            warn!(
+                                "[{}]: Could not resolve {}: {}",
+                                con.name, self.target.config.addr, error
+                            );
+                            None
+                        }
+                    };
+
+                    if let Some((old, new)) = moved {
+                        info!(
+                            "[{}]: {} moved from {} to {}, reconnecting",
+                            con.name, self.target.config.addr, old, new
+                        );
+                        let _ = active.take();
+                    }
+                }
                 success = label_being_printed.join_next(), if is_connection_busy => {
+                    self.status.printing.store(false, Ordering::Relaxed);
+                    let outcome = match &success {
+                        Some(Ok(Err(err))) => Err(err.to_string()),
+                        Some(Err(err)) => Err(err.to_string()),
+                        _ => Ok(()),
+                    };
+
+                    for reply in self_tests.drain(..) {
+                        let _ = reply.send(outcome.clone().map_err(anyhow::Error::msg));
+                    }
+
                     match success {
                         Some(Ok(Ok(ready))) => {
-                            if ready.is_some() {
+                            if let Some(ready) = &ready {
                                 info!("[{}]: Ready for next label in a few", con.name);
+                                let dpmm = ready.device_status.identification.dpmm;
+                                self.status.dpmm.store(dpmm, Ordering::Relaxed);
                                 interval_keepalive.reset();
+                                failures = 0;
+                                self.notify_up(&con.name);
                             }
 
+                            interjections = ready.as_ref().map(|ready| ready.interjections.clone());
                             active = ready;
                         },
                         Some(Ok(Err(err))) => {
                             warn!("[{}]: {:?}", con.name, err);
+
+                            failures += 1;
+                            let delay = match failures < max_attempts {
+                                true => backoff.delay(failures),
+                                false => backoff.max,
+                            };
+                            next_attempt = last_attempt + delay;
+                            info!(
+                                "[{}]: Next reconnection attempt in {:?}",
+                                con.name, delay
+                            );
+
+                            if self.status.reachable.swap(false, Ordering::Relaxed) {
+                                self.notify_down(&con.name, &err);
+                            }
                         }
                         Some(Err(err)) => {
                             error!("[{}]: {:?}", con.name,  err);
@@ -258,16 +1065,49 @@ impl PhysicalPrinter {
 
                     break;
                 },
-                // Back-Pressure: only accept message while not printing. Could also do a buffer
-                // but the channel already is a buffer itself. That only makes sense if we want to
-                // do a re-ordering that the channel's sequential semantics does not permit.
-                job = con.message.recv(), if !is_connection_busy => {
-                    let Some(Task::Job{ print_job }) = job else {
+                // While printing, only the urgent lane is read, so device controls reach the
+                // printer ahead of the job under way. Other urgent tasks read meanwhile are held
+                // back and run first once it is done. Back-pressure: with one task held, nothing
+                // more is read until the printer is idle, the message lane stays the buffer.
+                job = next_task(&mut held, &mut con.urgent, &mut con.message, is_connection_busy),
+                    if !is_connection_busy || held.is_empty() =>
+                {
+                    match job {
+                        // Device controls go ahead of the job under way.
+                        Some(Task::Control { command, reply }) => {
+                            let sent = match is_connection_busy {
+                                true => interject(interjections.as_ref(), command.clone()),
+                                false => control(&mut active, command.clone()).await,
+                            };
+                            if sent.is_ok() && matches!(command, ZplCommand::CancelAll) {
+                                self.cancel_queued(&mut con, &mut held);
+                            }
+                            let _ = reply.send(sent);
+                        }
+                        // Others wait for their turn.
+                        Some(task) if is_connection_busy => held.push_back(task),
+                        Some(Task::Job { print_job, options, record }) => {
+                            self.create_job(print_job, options, record, active.take(), &mut label_being_printed);
+                        }
+                        Some(Task::Firmware { update }) => {
+                            self.update_firmware(&con.name, update, active.take(), &mut label_being_printed);
+                        }
+                        Some(Task::Maintenance { commands }) => {
+                            self.maintain(&con.name, commands, active.take(), &mut label_being_printed);
+                        }
+                        // The driver is gone, and with it the end of the loop.
+                        None if is_connection_busy => {}
                         // Reached end of job queue.
-                        break;
-                    };
-
-                    self.create_job(print_job, active.take(), &mut label_being_printed);
+                        None => break,
+                    }
+                }
+                Some(reply) = con.self_test.recv() => {
+                    if is_connection_busy {
+                        // Answered by the outcome of the job or connection attempt under way.
+                        self_tests.push(reply);
+                    } else {
+                        let _ = reply.send(self.self_test(&mut active).await);
+                    }
                 }
             );
 
@@ -275,13 +1115,117 @@ impl PhysicalPrinter {
         }
     }
 
+    /// Drop the jobs waiting after a cancel, keeping all other tasks in order.
+    fn cancel_queued(&self, con: &mut Connector, held: &mut VecDeque<Task>) {
+        let waiting = std::mem::take(held)
+            .into_iter()
+            .chain(std::iter::from_fn(|| con.urgent.try_recv().ok()))
+            .chain(std::iter::from_fn(|| con.message.try_recv().ok()))
+            .collect::<Vec<_>>();
+
+        for task in waiting {
+            let Task::Job { mut record, .. } = task else {
+                held.push_back(task);
+                continue;
+            };
+
+            info!("[{}]: Job dropped, cancelled on the printer", con.name);
+            record.result = history::JobResult::Failed {
+                reason: "Cancelled on the printer".to_string(),
+            };
+            if let Some(id) = record.job_id {
+                self.services.intake.finish(id, &record.result, None);
+            }
+            if let Some(history) = self.services.history.clone() {
+                tokio::spawn(async move {
+                    if let Err(error) = history.append(&record).await {
+                        warn!("Failed to append to the job log: {error}");
+                    }
+                });
+            }
+        }
+    }
+
+    /// Send maintenance commands in place of the next job.
+    fn maintain(
+        &self,
+        name: &str,
+        commands: CommandSequence,
+        con: Option<ActiveConnection>,
+        label_being_printed: &mut JoinSet<ConnectionHandled>,
+    ) {
+        let Some(mut con) = con else {
+            warn!("[{}]: Maintenance skipped, not connected", name);
+            return;
+        };
+
+        info!("[{}]: Sending maintenance commands", name);
+        label_being_printed.spawn(async move {
+            con.printer.send(commands).await?;
+            Ok(Some(con))
+        });
+    }
+
+    /// Deliver a firmware update in place of the next job.
+    ///
+    /// The printer restarts to apply it, so the connection is given up afterwards and re-opened
+    /// by the usual retries.
+    fn update_firmware(
+        &self,
+        name: &str,
+        update: Arc<firmware::FirmwareUpdate>,
+        con: Option<ActiveConnection>,
+        label_being_printed: &mut JoinSet<ConnectionHandled>,
+    ) {
+        let Some(mut con) = con else {
+            update.set_state(firmware::FirmwareState::Failed {
+                reason: "Not connected to a physical printer".to_string(),
+            });
+            return;
+        };
+
+        info!("[{}]: Sending firmware update {}", name, update.id);
+        update.set_state(firmware::FirmwareState::Sending);
+        let name = name.to_string();
+
+        label_being_printed.spawn(async move {
+            let sent = con
+                .printer
+                .send_firmware(&update.data, |sent| update.set_sent(sent))
+                .await;
+
+            match sent {
+                Ok(()) => {
+                    info!("[{}]: Firmware update {} sent", name, update.id);
+                    update.set_state(firmware::FirmwareState::Sent);
+                    Ok(None)
+                }
+                Err(error) => {
+                    update.set_state(firmware::FirmwareState::Failed {
+                        reason: error.to_string(),
+                    });
+                    Err(error.into())
+                }
+            }
+        });
+    }
+
     fn create_job(
         &self,
         print_job: job::PrintJob,
+        options: job::JobOptions,
+        mut record: history::JobRecord,
         con: Option<ActiveConnection>,
         label_being_printed: &mut JoinSet<ConnectionHandled>,
     ) {
-        match &self.target.config.virtualization {
+        // Keep the content to show what failed and to print it again.
+        let failed_job = (print_job.clone(), options.clone());
+        let target = self.target.clone();
+
+        let limiter = self.services.limiter.clone();
+        let progress =
+            intake::Progress::new(self.services.intake.clone(), record.job_id);
+        let printing: PendingLabel = match &self.target.config.virtualization {
             configuration::LabelVirtualization::DropJobs {
                 wait_time,
                 persist,
@@ -291,39 +1235,291 @@ impl PhysicalPrinter {
                     dpmm: None,
                     target: self.target.clone(),
                     persist: persist.clone(),
+                    previews: vec![],
+                    #[cfg(feature = "fault-injection")]
+                    faults: self.faults.clone(),
                 };
 
-                label_being_printed
-                    .spawn(simulation_label(con, print_job, simulation));
+                Box::pin(simulation_label(
+                    con,
+                    print_job,
+                    options,
+                    simulation,
+                    limiter.clone(),
+                    progress,
+                ))
             }
             configuration::LabelVirtualization::ZplOnly {
                 dpmm,
                 persist,
                 wait_time,
+                previews,
             } => {
                 let simulation = SimulationParameter {
                     wait_time: *wait_time,
                     dpmm: *dpmm,
                     target: self.target.clone(),
                     persist: persist.clone(),
+                    previews: previews.clone(),
+                    #[cfg(feature = "fault-injection")]
+                    faults: self.faults.clone(),
                 };
 
-                label_being_printed
-                    .spawn(simulation_label(con, print_job, simulation));
+                Box::pin(simulation_label(
+                    con,
+                    print_job,
+                    options,
+                    simulation,
+                    limiter.clone(),
+                    progress,
+                ))
+            }
+            configuration::LabelVirtualization::Pulled {
+                dpmm,
+                timeout,
+                ..
+            } => {
+                let pull = PullParameter {
+                    target: self.target.clone(),
+                    pull: self.pull.clone(),
+                    dpmm: *dpmm,
+                    timeout: *timeout,
+                };
+
+                Box::pin(pull_label(
+                    print_job, options, pull, limiter, progress,
+                ))
             }
             configuration::LabelVirtualization::Physical => {
                 let active = con
                     .expect("Pyshical connection re-spawned or still active");
-                label_being_printed.spawn(print_label(active, print_job));
+                Box::pin(print_label(
+                    active, print_job, options, limiter, progress,
+                ))
             }
+        };
+
+        let Services {
+            history,
+            artifacts,
+            statistics,
+            coverage: coverage_statistics,
+            intake,
+            notifier,
+            dead_letters,
+            media,
+            ..
+        } = self.services.clone();
+        let status = self.status.clone();
+
+        let span = logs::job_span(&record.printer, record.job_id);
+        self.status.printing.store(true, Ordering::Relaxed);
+        label_being_printed.spawn(async move {
+            let printed = printing.await;
+            let confirm = async move {
+            let handled = match printed {
+                Ok(Printed {
+                    con,
+                    zpl,
+                    render_time,
+                    coverage,
+                    effective,
+                }) => {
+                    record.effective = effective;
+                    statistics.record(
+                        record.template.as_deref(),
+                        render_time,
+                        record.payload_bytes,
+                        coverage,
+                    );
+
+                    if let Some(coverage) = coverage {
+                        coverage_statistics.record(
+                            &record.printer,
+                            coverage,
+                            record.copies.into(),
+                            record.timestamp_unix,
+                        );
+                    }
+
+                    media.record_printed(&record.printer, record.copies.into());
+                    status.jobs_printed.fetch_add(1, Ordering::Relaxed);
+                    status
+                        .bytes_sent
+                        .fetch_add(zpl.len() as u64, Ordering::Relaxed);
+                    *status.last_commands.lock().unwrap() = Some(zpl.clone());
+
+                    if let Some(store) = &artifacts {
+                        match store.put(zpl.as_bytes()).await {
+                            Ok(id) => record.zpl_sha256 = Some(id.0),
+                            Err(error) => {
+                                warn!("Failed to store printed ZPL: {error}")
+                            }
+                        }
+                    }
+
+                    Ok(con)
+                }
+                Err(error) => {
+                    if !matches!(
+                        error,
+                        JobError::Suspended { .. } | JobError::Unconfirmed { .. }
+                    ) {
+                        status.jobs_failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(error)
+                }
+            };
+
+            // Kept as the raster of the job, and shown with its failure.
+            let printer_failed = matches!(
+                handled,
+                Err(JobError::Printer(_) | JobError::Suspended { .. })
+            );
+            let preview = if artifacts.is_some()
+                || (printer_failed && notifier.is_some())
+            {
+                let (job, options) = failed_job.clone();
+                let target = target.clone();
+                tokio::task::spawn_blocking(move || {
+                    job_preview(job, &options, &target)
+                })
+                .await
+                .ok()
+                .flatten()
+            } else {
+                None
+            };
+
+            if let (Some(store), Some(png)) = (&artifacts, &preview) {
+                match store.put(png).await {
+                    Ok(id) => record.raster_sha256 = Some(id.0),
+                    Err(error) => warn!("Failed to store the raster: {error}"),
+                }
+            }
+
+            // Jobs that could not be rendered are the client's to fix, and tell nothing about the
+            // printer. Nor does the job wait for the mail to be sent.
+            if let (Err(error), true, Some(notifier)) =
+                (&handled, printer_failed, notifier)
+            {
+                let error = error.to_string();
+                let (target, record) = (target.clone(), record.clone());
+                tokio::spawn(async move {
+                    notifier
+                        .job_failed(&target.config, &record, &error, preview)
+                        .await
+                });
+            }
+
+            record.result = match &handled {
+                Ok(_) => history::JobResult::Printed,
+                Err(
+                    JobError::Suspended { error, .. }
+                    | JobError::Unconfirmed {
+                        error, held: true, ..
+                    },
+                ) => history::JobResult::Suspended {
+                    reason: error.to_string(),
+                },
+                Err(error) => history::JobResult::Failed {
+                    reason: error.to_string(),
+                },
+            };
+
+            // Printing suspended or unconfirmed jobs again would print their labels twice.
+            if let Err(JobError::Render { .. } | JobError::Printer(_)) = &handled {
+                let (job, options) = failed_job;
+                let id = dead_letters.push(dead_letter::DeadLetter {
+                    job,
+                    options,
+                    record: record.clone(),
+                });
+                info!("[{}]: Failed job kept as {id}", record.printer);
+            }
+
+            if let Some(id) = record.job_id {
+                intake.finish(id, &record.result, record.effective.clone());
+            }
+
+            if let Some(history) = history {
+                if let Err(error) = history.append(&record).await {
+                    warn!("Failed to append to the job log: {error}");
+                }
+            }
+
+            // The connection is as good as before a job that never reached the printer.
+            match handled {
+                Ok(con) => Ok(con),
+                Err(
+                    JobError::Render { con, .. }
+                    | JobError::Suspended { con, .. }
+                    | JobError::Unconfirmed { con, .. },
+                ) => Ok(con.map(|con| *con)),
+                Err(JobError::Printer(error)) => Err(error),
+            }
+            };
+
+            confirm.instrument(tracing::info_span!("confirm")).await
+        }
+        .instrument(span));
+    }
+
+    async fn self_test(
+        &self,
+        active: &mut Option<ActiveConnection>,
+    ) -> anyhow::Result<()> {
+        if !self.target.config.virtualization.is_connnected() {
+            #[cfg(feature = "fault-injection")]
+            self.faults.check_state()?;
+
+            return Ok(());
+        }
+
+        match active {
+            Some(ready) => ready.verify().await,
+            None => anyhow::bail!("Not connected"),
+        }
+    }
+
+    /// Mark the printer reachable, publishing it once when it was not.
+    fn notify_up(&self, name: &str) {
+        if !self.status.reachable.swap(true, Ordering::Relaxed) {
+            self.services.events.publish(events::Event::Printer {
+                printer: name.to_string(),
+                state: events::PrinterState::Up,
+            });
         }
     }
 
+    fn notify_down(&self, name: &str, error: &dyn std::fmt::Display) {
+        self.services.events.publish(events::Event::Printer {
+            printer: name.to_string(),
+            state: events::PrinterState::Down {
+                reason: error.to_string(),
+            },
+        });
+
+        let Some(notifier) = self.services.notifier.clone() else {
+            return;
+        };
+
+        let target = self.target.clone();
+        let name = name.to_string();
+        let error = error.to_string();
+
+        tokio::spawn(async move {
+            notifier.printer_down(&name, &target.config, &error).await
+        });
+    }
+
     fn set_up_status(&self, connection: Option<&ActiveConnection>) {
-        let seconds = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+        if self.pull_queue().is_some() {
+            // Only the agent can tell, see `mark_seen`.
+            return;
+        }
+
+        let seconds = unix_now();
 
         self.status
             .is_up
@@ -333,48 +1529,455 @@ impl PhysicalPrinter {
     }
 }
 
-async fn print_label(
-    mut con: ActiveConnection,
+/// Render a job into a PNG, for humans to see what was to be printed.
+fn job_preview(
     job: job::PrintJob,
-) -> ConnectionHandled {
-    let label = tokio::task::block_in_place(|| {
-        job.into_label(
-            &con.target.label.dimensions,
-            &con.device_status.identification,
-        )
-    });
+    options: &job::JobOptions,
+    target: &LabelPrinter,
+) -> Option<Vec<u8>> {
+    let host = preview_host();
+    if let job::PrintJob::Zpl { code } = &job {
+        let image = passthrough_preview(code, &target.label, host.dpmm)?;
+        let mut png = std::io::Cursor::new(vec![]);
+        image.write_to(&mut png, image::ImageFormat::Png).ok()?;
+        return Some(png.into_inner());
+    }
+
+    let image = match job
+        .into_label(&target.label, &host, options, &target.config.transforms)
+        .and_then(|label| label.preview())
+    {
+        Ok(image) => image,
+        Err(error) => {
+            warn!("Failed to render preview: {error}");
+            return None;
+        }
+    };
 
-    let options = {
-        let mut options = PrintOptions::default();
+    let mut image = image.into_luma8();
+    zones::underlay(&mut image, &target.label.exclusion_zones, host.dpmm);
 
-        options.copies = 1;
-        if let Some(cfg) = &con.target.config.calibration {
-            options.calibration = Some(PrintCalibration {
-                home_x: Unit::Millimetres(cfg.home_x),
-            });
+    let mut png = std::io::Cursor::new(vec![]);
+    image.write_to(&mut png, image::ImageFormat::Png).ok()?;
+    Some(png.into_inner())
+}
+
+/// Draw raw ZPL as far as it is made of graphics, on labels of the configured size unless the
+/// commands set theirs. Only the first label drawn is shown.
+fn passthrough_preview(
+    code: &str,
+    label: &configuration::Label,
+    dpmm: u32,
+) -> Option<image::GrayImage> {
+    let size = (
+        Length::mm(label.dimensions.width).to_dots(dpmm),
+        Length::mm(label.dimensions.height).to_dots(dpmm),
+    );
+
+    let mut image = match zpl::render::zpl_rasterizer::rasterize(code, size) {
+        Ok(pages) => pages.into_iter().next()?,
+        Err(error) => {
+            warn!("Failed to render preview: {error}");
+            return None;
+        }
+    };
+
+    zones::underlay(&mut image, &label.exclusion_zones, dpmm);
+    Some(image)
+}
+
+/// Write a picture of a simulated label, for people to review.
+fn write_preview(
+    path: &Path,
+    image: &image::GrayImage,
+    dpmm: u32,
+    format: configuration::PreviewFormat,
+) -> anyhow::Result<()> {
+    match format {
+        configuration::PreviewFormat::Png => image.save(path)?,
+        configuration::PreviewFormat::Pdf => {
+            std::fs::write(path, zpl::util::image::pdf_page(image, dpmm))?
         }
+    }
 
-        options
+    info!("Persisted preview into {}", path.display());
+    Ok(())
+}
+
+/// Only for viewing and checking, the resolution of the device does not matter.
+fn preview_host() -> HostIdentification {
+    HostIdentification {
+        dpmm: 8,
+        ..HostIdentification::default()
+    }
+}
+
+/// Raw ZPL is sent as submitted, without any of the label settings.
+fn passthrough(code: String) -> CommandSequence {
+    CommandSequence(vec![ZplCommand::Raw {
+        command: code,
+        response: ResponseSpec::None,
+    }])
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Combine the printer configuration with the choices made for a job.
+fn print_options(
+    target: &LabelPrinter,
+    job_options: &job::JobOptions,
+) -> PrintOptions {
+    let mut options = PrintOptions {
+        copies: 1,
+        compression: target.config.image_compression,
+        transform: target.config.coordinates,
+        charset: target.config.character_set,
+        svg: Some(job::PrintApi::printer_svg_options(
+            &target.config.font_directories,
+        )),
+        ..PrintOptions::default()
+    };
+
+    options.calibration = target
+        .config
+        .calibration
+        .as_ref()
+        .map(|cfg| cfg.to_options());
+    options.stock = target.label.stock.to_options();
+
+    options.post_print = target.label.post_print.map(Into::into);
+    job_options.overrides.apply(&mut options);
+
+    let orientation = job_options.orientation;
+    options.orientation = Orientation {
+        mirrored: orientation.mirrored.then_some(
+            match target.config.mirroring {
+                configuration::MirrorMethod::Native => Mirroring::Native,
+                configuration::MirrorMethod::Raster => Mirroring::Raster,
+            },
+        ),
+        flipped: orientation.flipped,
     };
 
-    let seq = label.print(&options).await?;
+    options
+}
+
+/// The settings of a job after merging the printer's defaults and the job's overrides.
+fn effective_options(
+    options: &PrintOptions,
+    dpmm: u32,
+) -> history::EffectiveOptions {
+    history::EffectiveOptions {
+        dpmm,
+        copies: options.copies,
+        darkness: options.darkness,
+        speed: options.speed,
+        compression: options.compression,
+        home_x: options
+            .calibration
+            .as_ref()
+            .map(|calibration| calibration.home_x.to_signed_dots(dpmm)),
+        home_y: options
+            .calibration
+            .as_ref()
+            .map(|calibration| calibration.home_y.to_signed_dots(dpmm)),
+        post_print: options.post_print.clone(),
+        orientation: options.orientation,
+        transform: options.transform,
+        qr: options.qr,
+        charset: options.charset,
+        shrink: None,
+    }
+}
+
+/// The factor SVG content of the label was shrunk by to fit, if its job asked for it.
+fn shrink_factor(
+    label: &zpl::label::Label,
+    options: &PrintOptions,
+) -> Option<f32> {
+    let render = RenderOptions {
+        svg: options.svg.clone(),
+        ..RenderOptions::default()
+    };
+
+    label
+        .content
+        .iter()
+        .find_map(|content| content.shrink_factor(&render))
+}
+
+/// How to put QR codes on the printer's labels, as configured or for the defects of its model.
+fn qr_rendering(
+    target: &LabelPrinter,
+    identification: &HostIdentification,
+) -> QrRendering {
+    target
+        .config
+        .qr_codes
+        .unwrap_or_else(|| Quirks::of(identification).qr_rendering())
+}
+
+type Rendered = (
+    CommandSequence,
+    Option<f64>,
+    Option<history::EffectiveOptions>,
+);
+
+/// Turn a job into the commands for a printer, with what its firmware supports.
+fn render_for_device(
+    target: &LabelPrinter,
+    job: job::PrintJob,
+    job_options: &job::JobOptions,
+    identification: &HostIdentification,
+    cache: Option<Arc<zpl::util::cache::RasterCache>>,
+) -> anyhow::Result<Rendered> {
+    if let job::PrintJob::Zpl { code } = job {
+        return Ok((passthrough(code), None, None));
+    }
+
+    let mut options = print_options(target, job_options);
+    options.cache = cache;
+    options.compression = options.compression.supported_by(identification);
+    options.charset = options.charset.supported_by(identification);
+    options.qr = qr_rendering(target, identification);
+    let mut effective = effective_options(&options, identification.dpmm);
+
+    let label = job.into_label(
+        &target.label,
+        identification,
+        job_options,
+        &target.config.transforms,
+    )?;
+    effective.shrink = shrink_factor(&label, &options);
+
+    let seq = label.print(&options)?;
+    let coverage = label.coverage(&seq);
+    Ok((seq, Some(coverage), Some(effective)))
+}
+
+async fn print_label(
+    mut con: ActiveConnection,
+    job: job::PrintJob,
+    job_options: job::JobOptions,
+    limiter: Arc<render::RenderLimiter>,
+    progress: intake::Progress,
+) -> Result<Printed, JobError> {
+    let permit = limiter.acquire(&job).await;
+    progress.set(intake::JobState::Rendering);
+    let started = Instant::now();
+    let (seq, coverage, effective) = {
+        let _render = tracing::info_span!("render").entered();
+        let rendered = tokio::task::block_in_place(|| {
+            render_for_device(
+                &con.target,
+                job,
+                &job_options,
+                &con.device_status.identification,
+                limiter.cache.clone(),
+            )
+        });
+
+        match rendered {
+            Ok(rendered) => rendered,
+            Err(error) => {
+                let con = Some(Box::new(con));
+                return Err(JobError::Render { error, con });
+            }
+        }
+    };
+    let render_time = started.elapsed();
+    drop(permit);
     // tokio::fs::write("/tmp/zpl-debug", seq.to_string()).await?;
-    con.printer.send(seq).await?;
+    let zpl = seq.encoded(Separator::None);
+    progress.set(intake::JobState::Sending);
+    if let Err(mut error) = send_label(&mut con, seq, &job_options).await {
+        if let JobError::Suspended { con: held, .. }
+        | JobError::Unconfirmed { con: held, .. } = &mut error
+        {
+            *held = Some(Box::new(con));
+        }
+        return Err(error);
+    }
 
     // No change in connection state, free to reuse it.
-    Ok(Some(con))
+    Ok(Printed {
+        con: Some(con),
+        zpl,
+        render_time,
+        coverage,
+        effective,
+    })
+}
+
+/// Send the commands of a job, and wait for its labels to be printed if configured.
+///
+/// A printer stopping with labels left, paused or for a condition such as running out of media,
+/// suspends the job rather than failing it, as does timing out or a cancel on the printer leave it
+/// unconfirmed, the connection to be filled in by the caller.
+async fn send_label(
+    con: &mut ActiveConnection,
+    seq: CommandSequence,
+    job_options: &job::JobOptions,
+) -> Result<(), JobError> {
+    con.printer
+        .send(seq)
+        .instrument(tracing::info_span!("send"))
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    if let Some(wait) = &con.target.config.wait_for_printed {
+        let action = job_options
+            .overrides
+            .post_print
+            .or(con.target.label.post_print)
+            // As labels are printed without one.
+            .map_or(PostPrintAction::Cut, Into::into);
+        let suspended = |error| JobError::Suspended { error, con: None };
+        let outcome = con
+            .printer
+            .wait_for_printed(&action, wait, |remaining| {
+                debug!("{remaining} labels left to print")
+            })
+            .await
+            .map_err(|error| match error {
+                PrintError::Io(error) => JobError::Printer(error.into()),
+                error => suspended(error.into()),
+            })?;
+
+        match outcome {
+            PrintedOutcome::Completed => {}
+            PrintedOutcome::TimedOut { remaining } => {
+                return Err(JobError::Unconfirmed {
+                    error: anyhow::anyhow!(
+                        "Not printed within {:?}, {remaining} labels left",
+                        wait.timeout
+                    ),
+                    held: true,
+                    con: None,
+                })
+            }
+            PrintedOutcome::PrinterPaused => {
+                return Err(suspended(anyhow::anyhow!(
+                    "The printer was paused with labels left, which print once it is resumed"
+                )))
+            }
+            PrintedOutcome::Cancelled => {
+                return Err(JobError::Unconfirmed {
+                    error: anyhow::anyhow!("Cancelled on the printer with labels left"),
+                    held: false,
+                    con: None,
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn pull_label(
+    job: job::PrintJob,
+    job_options: job::JobOptions,
+    pull: PullParameter,
+    limiter: Arc<render::RenderLimiter>,
+    progress: intake::Progress,
+) -> Result<Printed, JobError> {
+    let PullParameter {
+        target,
+        pull,
+        dpmm,
+        timeout,
+    } = pull;
+
+    let dpmm = if let Some(dpmm) = pull.dpmm().or(dpmm) {
+        dpmm
+    } else {
+        warn!("No dpmm configured, nor reported by the agent. Using 8 dpmm");
+        8
+    };
+
+    let host = HostIdentification {
+        dpmm,
+        ..HostIdentification::default()
+    };
+
+    let permit = limiter.acquire(&job).await;
+    progress.set(intake::JobState::Rendering);
+    let started = Instant::now();
+    let (seq, coverage, effective) = {
+        let _render = tracing::info_span!("render").entered();
+        match job {
+            job::PrintJob::Zpl { code } => (passthrough(code), None, None),
+            job => {
+                let mut options = print_options(&target, &job_options);
+                options.cache = limiter.cache.clone();
+                options.qr = qr_rendering(&target, &host);
+                let effective = effective_options(&options, dpmm);
+
+                let rendered = tokio::task::block_in_place(|| {
+                    let label = job.into_label(
+                        &target.label,
+                        &host,
+                        &job_options,
+                        &target.config.transforms,
+                    )?;
+
+                    let seq = label.print(&options)?;
+                    let coverage = label.coverage(&seq);
+                    Ok::<_, anyhow::Error>((
+                        seq,
+                        Some(coverage),
+                        Some(effective),
+                    ))
+                });
+
+                match rendered {
+                    Ok(rendered) => rendered,
+                    Err(error) => {
+                        return Err(JobError::Render { error, con: None })
+                    }
+                }
+            }
+        }
+    };
+    let render_time = started.elapsed();
+    drop(permit);
+    let zpl = seq.encoded(Separator::None);
+    progress.set(intake::JobState::Sending);
+    pull.submit(zpl.clone(), timeout)
+        .instrument(tracing::info_span!("send"))
+        .await?;
+
+    // There is no connection of our own to keep.
+    Ok(Printed {
+        con: None,
+        zpl,
+        render_time,
+        coverage,
+        effective,
+    })
 }
 
 async fn simulation_label(
     con: Option<ActiveConnection>,
     job: job::PrintJob,
+    job_options: job::JobOptions,
     sim: SimulationParameter,
-) -> ConnectionHandled {
+    limiter: Arc<render::RenderLimiter>,
+    progress: intake::Progress,
+) -> Result<Printed, JobError> {
     let SimulationParameter {
         dpmm,
         mut persist,
         target,
         wait_time,
+        previews,
+        #[cfg(feature = "fault-injection")]
+        faults,
     } = sim;
 
     // Start the time for our operation, do not depend on conversion itself.
@@ -395,48 +1998,168 @@ async fn simulation_label(
         host
     };
 
-    let label = tokio::task::block_in_place(|| {
-        job.into_label(&target.label.dimensions, &identification)
-    });
+    let permit = limiter.acquire(&job).await;
+    progress.set(intake::JobState::Rendering);
+    let started = Instant::now();
+    let wants_preview = persist.is_some() && !previews.is_empty();
+    let mut preview = None;
+    let (commands, coverage, effective) = {
+        let _render = tracing::info_span!("render").entered();
+        match job {
+            job::PrintJob::Zpl { code } => {
+                if wants_preview {
+                    preview = passthrough_preview(
+                        &code,
+                        &target.label,
+                        identification.dpmm,
+                    )
+                    .map(|image| (image, identification.dpmm));
+                }
 
-    let commands = label.render().await?;
-    // Loop once but also can break..
-    while let Some(target) = persist.take() {
-        let into = match tempfile::Builder::new()
-            .prefix(&format!("label-{}-", {
-                std::time::SystemTime::now()
-                    .duration_since(std::time::SystemTime::UNIX_EPOCH)
-                    .map_or(0, |duration| duration.as_secs())
-            }))
-            .suffix(".zpl")
-            .tempfile_in(&target)
-        {
-            Ok(file) => file,
-            Err(error) => {
+                (passthrough(code), None, None)
+            }
+            job => {
+                let mut options = print_options(&target, &job_options);
+                options.qr = qr_rendering(&target, &identification);
+                let mut effective =
+                    effective_options(&options, identification.dpmm);
+
+                let render = RenderOptions {
+                    mirror: false,
+                    compression: options.compression,
+                    bounds: Default::default(),
+                    deterministic: false,
+                    svg: options.svg.clone(),
+                    transform: target.config.coordinates,
+                    cache: limiter.cache.clone(),
+                    qr: options.qr,
+                    charset: options.charset,
+                };
+
+                let rendered = tokio::task::block_in_place(|| {
+                    let label = job.into_label(
+                        &target.label,
+                        &identification,
+                        &job_options,
+                        &target.config.transforms,
+                    )?;
+                    effective.shrink = shrink_factor(&label, &options);
+
+                    // Without a device the output should still reflect the requested mirroring,
+                    // as the printer is configured to produce it.
+                    let (mut commands, mirror) = label.render_mirrored(
+                        &render,
+                        options.orientation.mirrored,
+                    )?;
+                    if mirror == Some(Mirroring::Native) {
+                        commands.0.insert(0, ZplCommand::SetMirrored(true));
+                    }
+                    let coverage = label.coverage(&commands);
+
+                    if wants_preview {
+                        preview = match label.preview() {
+                            Ok(image) => {
+                                let mut image =
+                                    match options.orientation.mirrored {
+                                        Some(_) => image.fliph().into_luma8(),
+                                        None => image.into_luma8(),
+                                    };
+                                zones::underlay(
+                                    &mut image,
+                                    &target.label.exclusion_zones,
+                                    label.dpmm,
+                                );
+                                Some((image, label.dpmm))
+                            }
+                            Err(error) => {
+                                warn!("Failed to render preview: {error}");
+                                None
+                            }
+                        };
+                    }
+
+                    Ok::<_, anyhow::Error>((
+                        commands,
+                        Some(coverage),
+                        Some(effective),
+                    ))
+                });
+
+                match rendered {
+                    Ok(rendered) => rendered,
+                    Err(error) => {
+                        let con = con.map(Box::new);
+                        return Err(JobError::Render { error, con });
+                    }
+                }
+            }
+        }
+    };
+    let render_time = started.elapsed();
+    drop(permit);
+
+    #[cfg(feature = "fault-injection")]
+    faults.before_send().await?;
+    progress.set(intake::JobState::Sending);
+    tracing::info_span!("send").in_scope(|| {
+        // Loop once but also can break..
+        while let Some(target) = persist.take() {
+            let into = match tempfile::Builder::new()
+                .prefix(&format!("label-{}-", {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                        .map_or(0, |duration| duration.as_secs())
+                }))
+                .suffix(".zpl")
+                .tempfile_in(&target)
+            {
+                Ok(file) => file,
+                Err(error) => {
+                    warn!("Failed to dump ZPL even through requested: {error}");
+                    break;
+                }
+            };
+
+            info!("Persisting ZPL into {}", into.path().display());
+
+            if let Err(error) = write!(&into, "{}", commands) {
                 warn!("Failed to dump ZPL even through requested: {error}");
                 break;
             }
-        };
 
-        info!("Persisting ZPL into {}", into.path().display());
+            let path = into.path().to_owned();
+            if let Err(error) = into.persist(&path) {
+                warn!("Failed to persist ZPL file: {error}");
+                break;
+            }
 
-        if let Err(error) = write!(&into, "{}", commands) {
-            warn!("Failed to dump ZPL even through requested: {error}");
-            break;
-        }
+            info!("Persisted ZPL into {}", path.display());
 
-        let path = into.path().to_owned();
-        if let Err(error) = into.persist(&path) {
-            warn!("Failed to persist ZPL file: {error}");
-            break;
+            if let Some((image, dpmm)) = &preview {
+                for format in &previews {
+                    let path = path.with_extension(format.extension());
+                    if let Err(error) =
+                        write_preview(&path, image, *dpmm, *format)
+                    {
+                        warn!("Failed to persist preview: {error}");
+                    }
+                }
+            }
         }
-
-        info!("Persisted ZPL into {}", path.display());
-    }
+    });
 
     target_time.await;
 
-    Ok(con)
+    #[cfg(feature = "fault-injection")]
+    faults.after_send()?;
+
+    Ok(Printed {
+        con,
+        zpl: commands.encoded(Separator::None),
+        render_time,
+        coverage,
+        effective,
+    })
 }
 
 impl Driver {
@@ -445,15 +2168,21 @@ impl Driver {
         const BOUND: usize = 8;
 
         let (msg_send, msg_recv) = mpsc::channel(BOUND);
+        let (urgent_send, urgent_recv) = mpsc::channel(BOUND);
+        let (test_send, test_recv) = mpsc::channel(1);
         let (end_send, end_recv) = oneshot::channel();
 
         let driver = Driver {
             message: msg_send,
+            urgent: urgent_send,
+            self_test: test_send,
             end: Some(end_send),
         };
 
         let con = Connector {
             message: msg_recv,
+            urgent: urgent_recv,
+            self_test: test_recv,
             end: end_recv,
             name: format!("@{}", target.config.addr),
         };
@@ -461,13 +2190,89 @@ impl Driver {
         (driver, con)
     }
 
+    /// Queue a job in the lane of its priority, returning its place in the queue counted from 1.
     pub async fn send_job(
         &self,
         print_job: job::PrintJob,
-    ) -> Result<(), &'static str> {
-        match self.message.try_send(Task::Job { print_job }) {
-            Ok(_) => Ok(()),
-            Err(_) => Err("failed to queue"),
+        options: job::JobOptions,
+        record: history::JobRecord,
+    ) -> Result<usize, SendError> {
+        let priority = options.priority;
+        let task = Task::Job {
+            print_job,
+            options,
+            record,
+        };
+
+        self.lane(priority).try_send(task)?;
+        let position = match priority {
+            job::Priority::High => queued(&self.urgent),
+            job::Priority::Normal => self.queued_jobs(),
+        };
+        Ok(position.max(1))
+    }
+
+    /// Queue commands such as calibration, sent once the jobs before them are printed.
+    pub fn send_maintenance(
+        &self,
+        commands: CommandSequence,
+    ) -> Result<(), SendError> {
+        Ok(self.message.try_send(Task::Maintenance { commands })?)
+    }
+
+    /// Queue a confirmed firmware update, delivered once the jobs before it are printed.
+    pub fn send_firmware(
+        &self,
+        update: Arc<firmware::FirmwareUpdate>,
+    ) -> Result<(), SendError> {
+        Ok(self.message.try_send(Task::Firmware { update })?)
+    }
+
+    /// Pause, resume or cancel on the printer, ahead of all jobs waiting.
+    ///
+    /// Sent on the connection of the jobs, in between the requests of a job being printed. A
+    /// cancel also drops the jobs waiting on the server.
+    pub fn control(
+        &self,
+        command: ZplCommand,
+    ) -> Result<oneshot::Receiver<anyhow::Result<()>>, SendError> {
+        let (reply, result) = oneshot::channel();
+        self.urgent.try_send(Task::Control { command, reply })?;
+        Ok(result)
+    }
+
+    /// Request a self-test, answered by the status of the connection or by the outcome of the job
+    /// currently being printed.
+    pub fn self_test(
+        &self,
+    ) -> Result<oneshot::Receiver<anyhow::Result<()>>, &'static str> {
+        let (reply, result) = oneshot::channel();
+
+        match self.self_test.try_send(reply) {
+            Ok(_) => Ok(result),
+            Err(_) => Err("previous self-test still pending"),
+        }
+    }
+
+    /// Jobs accepted but not yet taken up by the printer, in both lanes.
+    pub fn queued_jobs(&self) -> usize {
+        queued(&self.message) + queued(&self.urgent)
+    }
+
+    /// The most jobs accepted before the printer takes them up, in both lanes.
+    pub fn queue_capacity(&self) -> usize {
+        self.message.max_capacity() + self.urgent.max_capacity()
+    }
+
+    /// Whether the lane of a priority takes no more jobs.
+    pub fn is_full(&self, priority: job::Priority) -> bool {
+        self.lane(priority).capacity() == 0
+    }
+
+    fn lane(&self, priority: job::Priority) -> &mpsc::Sender<Task> {
+        match priority {
+            job::Priority::Normal => &self.message,
+            job::Priority::High => &self.urgent,
         }
     }
 
@@ -478,6 +2283,78 @@ impl Driver {
     }
 }
 
+fn queued(lane: &mpsc::Sender<Task>) -> usize {
+    lane.max_capacity() - lane.capacity()
+}
+
+/// The next task, those held back first, then urgent ones.
+///
+/// Only urgent ones while busy, for the device controls among them.
+async fn next_task(
+    held: &mut VecDeque<Task>,
+    urgent: &mut mpsc::Receiver<Task>,
+    message: &mut mpsc::Receiver<Task>,
+    busy: bool,
+) -> Option<Task> {
+    if busy {
+        return urgent.recv().await;
+    }
+
+    if let Some(task) = held.pop_front() {
+        return Some(task);
+    }
+
+    tokio::select! {
+        biased;
+        Some(task) = urgent.recv() => Some(task),
+        task = message.recv() => task,
+    }
+}
+
+/// Send a device control on the idle connection.
+async fn control(
+    active: &mut Option<ActiveConnection>,
+    command: ZplCommand,
+) -> anyhow::Result<()> {
+    let Some(ready) = active else {
+        anyhow::bail!("Not connected to the printer");
+    };
+
+    match command {
+        ZplCommand::Pause => ready.printer.pause().await?,
+        ZplCommand::Resume => ready.printer.resume().await?,
+        ZplCommand::CancelAll => ready.printer.cancel_all().await?,
+        _ => anyhow::bail!("Not a device control command"),
+    }
+
+    Ok(())
+}
+
+/// Have the job holding the connection send a device control in between its requests.
+fn interject(
+    interjections: Option<&mpsc::Sender<ZplCommand>>,
+    command: ZplCommand,
+) -> anyhow::Result<()> {
+    if !matches!(
+        command,
+        ZplCommand::Pause | ZplCommand::Resume | ZplCommand::CancelAll
+    ) {
+        anyhow::bail!("Not a device control command");
+    }
+
+    interjections
+        .ok_or_else(|| anyhow::anyhow!("Not connected to the printer"))?
+        .try_send(command)
+        .map_err(|error| match error {
+            mpsc::error::TrySendError::Full(_) => {
+                anyhow::anyhow!("Other device controls are still waiting")
+            }
+            mpsc::error::TrySendError::Closed(_) => {
+                anyhow::anyhow!("Not connected to the printer")
+            }
+        })
+}
+
 impl ActiveConnection {
     pub async fn verify(&mut self) -> anyhow::Result<()> {
         self.printer
@@ -507,3 +2384,88 @@ impl Drop for Driver {
         self.shutdown();
     }
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn job_printed_once_offline_printer_comes_up() {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    // Nothing listens on the address yet.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let cfg: configuration::Configuration = serde_json::from_value(serde_json::json!({
+        "labels": { "51mm": { "dimensions": {
+            "width": 51.0, "height": 51.0, "margin_left": 1.0, "margin_right": 1.0,
+            "margin_top": 1.0, "margin_bottom": 1.0
+        } } },
+        "printers": { "offline": { "label": "51mm", "addr": addr.to_string() } },
+    }))
+    .unwrap();
+    let printer = cfg.printers["offline"].clone();
+    let printer = LabelPrinter::new(&cfg, printer).unwrap();
+
+    let services = Services::default();
+    let intake = services.intake.clone();
+    let (driver, con) = Driver::new(&printer);
+    let printer = PhysicalPrinter::new(printer, services);
+    tokio::spawn(printer.drive(con));
+
+    // Out of connection attempts by the time the job arrives.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let payload: job::PrintApi = serde_json::from_value(
+        serde_json::json!({ "zpl": { "code": "^XA^XZ" } }),
+    )
+    .unwrap();
+    let mut record = history::JobRecord::new("offline", "51mm", &payload, None);
+    let id = intake.register("offline");
+    record.job_id = Some(id);
+    let job = job::PrintJob::Zpl {
+        code: "^XA^XZ".to_string(),
+    };
+    driver
+        .send_job(job, job::JobOptions::default(), record)
+        .await
+        .unwrap();
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap_or(0);
+            if n == 0 {
+                return;
+            }
+
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+            let answers = [
+                ("~HS", "\x02030,0,0,0800,000,0,0,0,000,0,0,0\x03\r\n\x02000,0,0,0,0,2,4,0,00000000,1,000\x03\r\n\x021234,0\x03\r\n"),
+                ("~HM", "\x021024,780,780\x03\r\n"),
+                ("~HI", "\x02ZD420-300dpi,V84.20.18Z,12,8176KB\x03\r\n"),
+            ];
+            for (command, answer) in answers {
+                for _ in request.matches(command) {
+                    socket.write_all(answer.as_bytes()).await.unwrap();
+                }
+            }
+        }
+    });
+
+    let done = async {
+        loop {
+            let state = intake.get(id).unwrap().state;
+            if matches!(
+                state,
+                intake::JobState::Printed | intake::JobState::Failed { .. }
+            ) {
+                return state;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    let state = tokio::time::timeout(Duration::from_secs(30), done)
+        .await
+        .unwrap();
+    assert!(matches!(state, intake::JobState::Printed));
+}
@@ -0,0 +1,103 @@
+//! Jobs waiting for an on-site agent to fetch them.
+//!
+//! For printers that the server can not reach, e.g. behind NAT, the driver does not connect out.
+//! Instead it offers each rendered job here and an agent next to the printer polls for it,
+//! prints it, and reports the result back.
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{oneshot, Notify};
+
+use crate::pull_api::{PullResult, PulledJob};
+
+#[derive(Default)]
+pub struct PullQueue {
+    next_id: AtomicU64,
+    /// The resolution last reported by the agent, zero if unknown.
+    dpmm: AtomicU32,
+    offered: Mutex<Option<Offered>>,
+    in_flight: Mutex<HashMap<u64, oneshot::Sender<PullResult>>>,
+    available: Notify,
+}
+
+struct Offered {
+    job: PulledJob,
+    done: oneshot::Sender<PullResult>,
+}
+
+impl PullQueue {
+    /// Offer a job to the agent and wait for it to report the result.
+    pub async fn submit(
+        &self,
+        zpl: String,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (done, result) = oneshot::channel();
+
+        let job = PulledJob { id, zpl };
+        *self.offered.lock().unwrap() = Some(Offered { job, done });
+        self.available.notify_one();
+
+        let result = tokio::time::timeout(timeout, result).await;
+
+        // Whatever happened, the job is no longer anyone's to print.
+        self.offered.lock().unwrap().take();
+        self.in_flight.lock().unwrap().remove(&id);
+
+        match result {
+            Ok(Ok(PullResult::Printed)) => Ok(()),
+            Ok(Ok(PullResult::Failed { reason })) => {
+                anyhow::bail!("Agent failed to print: {reason}")
+            }
+            Ok(Err(_)) => anyhow::bail!("Job abandoned"),
+            Err(_) => anyhow::bail!("No result from the agent in {timeout:?}"),
+        }
+    }
+
+    /// Wait for a job to become available, handing it to the caller.
+    pub async fn poll(&self, wait: Duration) -> Option<PulledJob> {
+        let deadline = tokio::time::Instant::now() + wait;
+
+        loop {
+            let notified = self.available.notified();
+
+            if let Some(Offered { job, done }) =
+                self.offered.lock().unwrap().take()
+            {
+                self.in_flight.lock().unwrap().insert(job.id, done);
+                return Some(job);
+            }
+
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    /// Finish a job previously handed out. Returns if the job was still awaited.
+    pub fn report(&self, id: u64, result: PullResult) -> bool {
+        let Some(done) = self.in_flight.lock().unwrap().remove(&id) else {
+            return false;
+        };
+
+        done.send(result).is_ok()
+    }
+
+    pub fn set_dpmm(&self, dpmm: u32) {
+        self.dpmm.store(dpmm, Ordering::Relaxed);
+    }
+
+    pub fn dpmm(&self) -> Option<u32> {
+        match self.dpmm.load(Ordering::Relaxed) {
+            0 => None,
+            dpmm => Some(dpmm),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! Messages exchanged between the server and on-site agents pulling jobs.
+use serde::{Deserialize, Serialize};
+
+/// A rendered job, handed to an agent for printing.
+#[derive(Deserialize, Serialize)]
+pub struct PulledJob {
+    pub id: u64,
+    pub zpl: String,
+}
+
+/// The outcome of a pulled job, as reported back by the agent.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullResult {
+    Printed,
+    Failed { reason: String },
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct PollQuery {
+    /// The resolution the agent discovered from its printer.
+    pub dpmm: Option<u32>,
+}
@@ -0,0 +1,137 @@
+//! Bounds the work spent on rendering jobs across all printers.
+//!
+//! Jobs differ widely in their cost, so each kind takes a configurable weight out of a shared
+//! capacity instead of counting as one. Heavy jobs then can not starve the cheap ones.
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use zpl::util::cache::RasterCache;
+
+use crate::{
+    configuration::{RenderConfiguration, RenderWeights},
+    job::{PrintApi, PrintApiKind, PrintJob},
+};
+
+pub struct RenderLimiter {
+    /// Replaced as a whole on [`RenderLimiter::reset`], permits leaked on the old one stay there.
+    permits: Mutex<Arc<Semaphore>>,
+    capacity: u32,
+    weights: RenderWeights,
+    /// Rasterized content shared by the jobs of all printers.
+    pub cache: Option<Arc<RasterCache>>,
+}
+
+impl RenderLimiter {
+    pub fn new(config: RenderConfiguration) -> Self {
+        let RenderConfiguration {
+            capacity,
+            weights,
+            cache,
+        } = config;
+        let capacity = capacity.max(1);
+
+        RenderLimiter {
+            permits: Mutex::new(Arc::new(Semaphore::new(capacity as usize))),
+            capacity,
+            weights,
+            cache: (cache > 0).then(|| Arc::new(RasterCache::new(cache))),
+        }
+    }
+
+    /// Wait until the job may be rendered, which it may as long as the permit is held.
+    pub async fn acquire(&self, job: &PrintJob) -> OwnedSemaphorePermit {
+        self.acquire_many(self.weight(job)).await
+    }
+
+    /// Wait until submitted content may be validated, parsing it is rendering work of its own.
+    pub async fn acquire_validation(
+        &self,
+        payload: &PrintApi,
+    ) -> OwnedSemaphorePermit {
+        let weight = match payload.kind {
+            PrintApiKind::Svg { .. } => self.weights.svg,
+            PrintApiKind::Image { .. } | PrintApiKind::Pdf { .. } => {
+                self.weights.image
+            }
+            PrintApiKind::Zpl { .. } => 1,
+        };
+
+        self.acquire_many(weight.clamp(1, self.capacity)).await
+    }
+
+    /// Start afresh with the full capacity and an empty cache, for rendering that got stuck.
+    ///
+    /// Renders still holding permits finish against the previous capacity.
+    pub fn reset(&self) {
+        *self.permits.lock().unwrap() =
+            Arc::new(Semaphore::new(self.capacity as usize));
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    async fn acquire_many(&self, permits: u32) -> OwnedSemaphorePermit {
+        let semaphore = self.permits.lock().unwrap().clone();
+        semaphore
+            .acquire_many_owned(permits)
+            .await
+            .expect("the render semaphore is never closed")
+    }
+
+    fn weight(&self, job: &PrintJob) -> u32 {
+        let weight = match job {
+            PrintJob::Svg { .. } => self.weights.svg,
+            PrintJob::Image { .. } => self.weights.image,
+            // Sent as is, without rendering.
+            PrintJob::Zpl { .. } => 1,
+        };
+
+        weight.clamp(1, self.capacity)
+    }
+}
+
+impl Default for RenderLimiter {
+    fn default() -> Self {
+        RenderLimiter::new(RenderConfiguration::default())
+    }
+}
+
+#[tokio::test]
+async fn weighted_permits() {
+    let limiter = RenderLimiter::new(RenderConfiguration {
+        capacity: 4,
+        weights: RenderWeights { svg: 8, image: 1 },
+        cache: 0,
+    });
+
+    let image = PrintJob::Image {
+        image: image::DynamicImage::new_luma8(1, 1),
+    };
+    let svg = PrintJob::Svg {
+        tree: zpl::resvg::usvg::Tree::from_str(
+            "<svg xmlns='http://www.w3.org/2000/svg' width='1' height='1'/>",
+            &Default::default(),
+        )
+        .unwrap(),
+        key: None,
+    };
+
+    let first = limiter.acquire(&image).await;
+    assert_eq!(limiter.weight(&svg), 4);
+
+    let blocked = tokio::time::timeout(
+        std::time::Duration::from_millis(10),
+        limiter.acquire(&svg),
+    );
+    assert!(blocked.await.is_err());
+
+    drop(first);
+    let svg = limiter.acquire(&svg).await;
+    let available = || limiter.permits.lock().unwrap().available_permits();
+    assert_eq!(available(), 0);
+
+    // A permit that leaked no longer counts after a reset.
+    std::mem::forget(svg);
+    limiter.reset();
+    assert_eq!(available(), 4);
+}
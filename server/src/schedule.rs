@@ -0,0 +1,284 @@
+//! Jobs held back until a time named by the client, such as a batch submitted during the day to
+//! be printed when the next shift starts.
+//!
+//! Jobs are validated when submitted and handed to their printer once due, as soon as it is up,
+//! not drained and has room in its queue. They are kept in memory only, a restart loses them.
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use zpl::command::ClockTime;
+
+use crate::{
+    history::{JobRecord, JobResult},
+    job::{JobOptions, PrintJob},
+    physical_printer, Server,
+};
+
+/// How often to look for jobs that became due.
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub struct Schedule {
+    next: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, ScheduledJob>>,
+}
+
+pub struct ScheduledJob {
+    /// The Unix time before which the job is not printed.
+    pub due: i64,
+    pub job: PrintJob,
+    pub options: JobOptions,
+    pub record: JobRecord,
+}
+
+#[derive(Serialize)]
+pub struct ScheduledReport {
+    id: u64,
+    not_before: String,
+    record: JobRecord,
+}
+
+impl Schedule {
+    pub fn push(&self, job: ScheduledJob) -> u64 {
+        let id = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        self.jobs.lock().unwrap().insert(id, job);
+        id
+    }
+
+    /// The jobs waiting for a printer, by the time they are due.
+    pub fn list(&self, printer: &str) -> Vec<ScheduledReport> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut reports: Vec<_> = jobs
+            .iter()
+            .filter(|(_, job)| job.record.printer == printer)
+            .map(|(id, job)| (job.due, *id, job))
+            .collect();
+        reports.sort_by_key(|(due, id, _)| (*due, *id));
+
+        reports
+            .into_iter()
+            .map(|(due, id, job)| ScheduledReport {
+                id,
+                not_before: format_rfc3339(due),
+                record: job.record.clone(),
+            })
+            .collect()
+    }
+
+    pub fn remove(&self, printer: &str, id: u64) -> Option<ScheduledJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.get(&id)?.record.printer != printer {
+            return None;
+        }
+
+        jobs.remove(&id)
+    }
+
+    /// Take the jobs due at a time that can be printed now, the earliest first.
+    fn take_due(
+        &self,
+        now: i64,
+        ready: impl Fn(&ScheduledJob) -> bool,
+    ) -> Vec<(u64, ScheduledJob)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut due: Vec<(i64, u64)> = jobs
+            .iter()
+            .filter(|(_, job)| job.due <= now && ready(job))
+            .map(|(id, job)| (job.due, *id))
+            .collect();
+        due.sort();
+
+        due.into_iter()
+            .filter_map(|(_, id)| Some((id, jobs.remove(&id)?)))
+            .collect()
+    }
+
+    /// Keep a job taken but not printed, such as when its printer's queue filled up meanwhile.
+    fn put_back(&self, id: u64, job: ScheduledJob) {
+        self.jobs.lock().unwrap().insert(id, job);
+    }
+}
+
+/// Hand jobs to their printers as they become due.
+pub async fn run(state: Server) {
+    let mut interval = tokio::time::interval(TICK);
+
+    loop {
+        interval.tick().await;
+
+        let inner = state.inner.read().await;
+        let schedule = inner.services.schedule.clone();
+        let now = physical_printer::unix_now() as i64;
+
+        let due = schedule.take_due(now, |job| {
+            let printer = job.record.printer.as_str();
+            match inner.printer.get(printer) {
+                Some(queue) => {
+                    queue.printer.metrics().up
+                        && inner.services.drain.get(printer).is_none()
+                        && !queue.driver.is_full(job.options.priority)
+                }
+                // Taken to be dropped.
+                None => true,
+            }
+        });
+
+        for (id, scheduled) in due {
+            let ScheduledJob {
+                due,
+                job,
+                options,
+                record,
+            } = scheduled;
+
+            let Some(queue) = inner.printer.get(&record.printer) else {
+                tracing::warn!(
+                    "Scheduled job {id} dropped, {} no longer exists",
+                    record.printer
+                );
+
+                if let Some(history) = &inner.services.history {
+                    let record = JobRecord {
+                        result: JobResult::Failed {
+                            reason: "No such printer".to_string(),
+                        },
+                        ..record
+                    };
+                    if let Err(error) = history.append(&record).await {
+                        tracing::warn!(
+                            "Failed to append to the job log: {error}"
+                        );
+                    }
+                }
+                continue;
+            };
+
+            let kept = (job.clone(), options.clone(), record.clone());
+            match queue.driver.send_job(job, options, record).await {
+                Ok(_) => tracing::info!("Scheduled job {id} queued"),
+                Err(error) => {
+                    tracing::info!("Scheduled job {id} held back: {error}");
+                    let (job, options, record) = kept;
+                    schedule.put_back(
+                        id,
+                        ScheduledJob {
+                            due,
+                            job,
+                            options,
+                            record,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The Unix time of a timestamp such as `2024-05-01T06:00:00+02:00`, after RFC 3339.
+///
+/// Fractions of seconds are ignored.
+pub fn parse_rfc3339(text: &str) -> Result<i64, String> {
+    let invalid = || format!("Invalid RFC 3339 timestamp {text:?}");
+    let number = |range: std::ops::Range<usize>| -> Result<i64, String> {
+        let digits = text.get(range).ok_or_else(invalid)?;
+        match digits.bytes().all(|b| b.is_ascii_digit()) {
+            true => digits.parse().map_err(|_| invalid()),
+            false => Err(invalid()),
+        }
+    };
+
+    let bytes = text.as_bytes();
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if bytes.len() < 20
+        || separators.iter().any(|&(at, byte)| bytes[at] != byte)
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+    {
+        return Err(invalid());
+    }
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) =
+        (number(11..13)?, number(14..16)?, number(17..19)?);
+
+    let mut rest = &text[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        rest = fraction.trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return Err(invalid()),
+            };
+            let part = |range| {
+                rest.get(range).and_then(|p: &str| p.parse::<i64>().ok())
+            };
+            let hours = part(1..3).ok_or_else(invalid)?;
+            let minutes = part(4..6).ok_or_else(invalid)?;
+            sign * (hours * 60 + minutes)
+        }
+        _ => return Err(invalid()),
+    };
+
+    if !(1..=12).contains(&month) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+
+    // Days since 1970-01-01, after Howard Hinnant's `days_from_civil`.
+    let shifted = if month <= 2 { year - 1 } else { year };
+    let era = shifted.div_euclid(400);
+    let year_of_era = shifted - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era =
+        year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    // Days beyond the end of the month roll over into the next, refuse those.
+    if ClockTime::from_unix(days * 86_400, 0).day as i64 != day {
+        return Err(invalid());
+    }
+
+    Ok(
+        days * 86_400 + hour * 3600 + minute * 60 + second.min(59)
+            - offset * 60,
+    )
+}
+
+/// A Unix time in UTC, as RFC 3339.
+pub fn format_rfc3339(unix: i64) -> String {
+    let time = ClockTime::from_unix(unix, 0);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year, time.month, time.day, time.hour, time.minute, time.second
+    )
+}
+
+#[test]
+fn rfc3339_timestamps() {
+    assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Ok(0));
+    assert_eq!(
+        parse_rfc3339("2024-02-29T08:30:00.250+02:00"),
+        Ok(1_709_188_200)
+    );
+    assert_eq!(format_rfc3339(1_709_188_200), "2024-02-29T06:30:00Z");
+
+    for invalid in [
+        "2023-02-29T00:00:00Z",
+        "2024-13-01T00:00:00Z",
+        "2024-01-01T00:00:00",
+        "2024-01-01 00:00:00+0200",
+        "2024-1-01T00:00:00Z",
+    ] {
+        assert!(parse_rfc3339(invalid).is_err(), "{invalid}");
+    }
+}
@@ -48,6 +48,6 @@ impl HotResource {
         }
 
         let content = std::fs::read_to_string(self.from_path).unwrap();
-        return (headers, Cow::Owned(content));
+        (headers, Cow::Owned(content))
     }
 }
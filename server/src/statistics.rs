@@ -0,0 +1,242 @@
+//! Render cost of jobs, grouped by the template they were made from.
+//!
+//! Jobs name their template explicitly. Only configured templates are told apart, all other jobs
+//! are counted together as [`AD_HOC`], such that clients can not grow the statistics without
+//! bound. This points at the templates which dominate rendering latency.
+//!
+//! The black dot coverage of labels is also kept by printer and day, to estimate how much ribbon
+//! thermal transfer printers use.
+use serde::Serialize;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Mutex,
+    time::Duration,
+};
+
+use zpl::command::ClockTime;
+
+/// Upper bounds of the render time histogram, in seconds.
+pub const RENDER_BUCKETS: [f64; 9] =
+    [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The group of jobs made from no configured template.
+pub const AD_HOC: &str = "ad hoc";
+
+#[derive(Default)]
+pub struct TemplateStatistics {
+    /// The configured templates, to group by.
+    known: Mutex<HashSet<String>>,
+    templates: Mutex<HashMap<String, TemplateStats>>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct TemplateStats {
+    pub jobs: u64,
+    pub render_seconds_total: f64,
+    pub render_seconds_max: f64,
+    /// Count of renders not slower than each of [`RENDER_BUCKETS`].
+    pub render_seconds_buckets: [u64; RENDER_BUCKETS.len()],
+    pub payload_bytes_total: u64,
+    pub payload_bytes_max: u64,
+    /// Labels of known coverage, rendered from content rather than sent as raw commands.
+    pub covered_jobs: u64,
+    pub coverage_percent_total: f64,
+    pub coverage_percent_max: f64,
+}
+
+#[derive(Serialize)]
+pub struct TemplateReport {
+    pub template: String,
+    #[serde(flatten)]
+    pub stats: TemplateStats,
+    pub render_seconds_mean: f64,
+    pub coverage_percent_mean: f64,
+}
+
+/// How many days of coverage to keep.
+const RETAINED_DAYS: usize = 92;
+
+#[derive(Default)]
+pub struct CoverageStatistics {
+    /// By day in UTC, as `YYYY-MM-DD`, and printer.
+    days: Mutex<BTreeMap<(String, String), CoverageStats>>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct CoverageStats {
+    pub labels: u64,
+    pub coverage_percent_total: f64,
+    pub coverage_percent_max: f64,
+}
+
+#[derive(Serialize)]
+pub struct CoverageReport {
+    pub day: String,
+    pub printer: String,
+    #[serde(flatten)]
+    pub stats: CoverageStats,
+    pub coverage_percent_mean: f64,
+}
+
+impl TemplateStatistics {
+    /// Tell apart the jobs of these templates from now on, keeping those recorded so far.
+    pub fn set_templates(&self, templates: HashSet<String>) {
+        *self.known.lock().unwrap() = templates;
+    }
+
+    pub fn record(
+        &self,
+        template: Option<&str>,
+        render: Duration,
+        payload_bytes: u64,
+        coverage: Option<f64>,
+    ) {
+        let template = template
+            .filter(|template| self.known.lock().unwrap().contains(*template))
+            .unwrap_or(AD_HOC);
+
+        let seconds = render.as_secs_f64();
+        let mut templates = self.templates.lock().unwrap();
+        let stats = templates.entry(template.to_string()).or_default();
+
+        stats.jobs += 1;
+        stats.render_seconds_total += seconds;
+        stats.render_seconds_max = stats.render_seconds_max.max(seconds);
+        stats.payload_bytes_total += payload_bytes;
+        stats.payload_bytes_max = stats.payload_bytes_max.max(payload_bytes);
+
+        if let Some(coverage) = coverage {
+            let percent = coverage * 100.0;
+            stats.covered_jobs += 1;
+            stats.coverage_percent_total += percent;
+            stats.coverage_percent_max =
+                stats.coverage_percent_max.max(percent);
+        }
+
+        for (bound, count) in
+            RENDER_BUCKETS.iter().zip(&mut stats.render_seconds_buckets)
+        {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+    }
+
+    /// All templates, the slowest by maximum render time first.
+    pub fn report(&self) -> Vec<TemplateReport> {
+        let templates = self.templates.lock().unwrap();
+        let mut report: Vec<_> = templates
+            .iter()
+            .map(|(template, stats)| TemplateReport {
+                template: template.clone(),
+                render_seconds_mean: stats.render_seconds_total
+                    / stats.jobs.max(1) as f64,
+                coverage_percent_mean: stats.coverage_percent_total
+                    / stats.covered_jobs.max(1) as f64,
+                stats: stats.clone(),
+            })
+            .collect();
+
+        report.sort_by(|a, b| {
+            b.stats
+                .render_seconds_max
+                .total_cmp(&a.stats.render_seconds_max)
+        });
+
+        report
+    }
+}
+
+impl CoverageStatistics {
+    /// Count copies of a label printed at a Unix time, forgetting the oldest days.
+    pub fn record(
+        &self,
+        printer: &str,
+        coverage: f64,
+        copies: u64,
+        unix_time: u64,
+    ) {
+        let time = ClockTime::from_unix(unix_time as i64, 0);
+        let day = format!("{:04}-{:02}-{:02}", time.year, time.month, time.day);
+        let percent = coverage * 100.0;
+
+        let mut days = self.days.lock().unwrap();
+        let stats = days.entry((day, printer.to_string())).or_default();
+        stats.labels += copies;
+        stats.coverage_percent_total += percent * copies as f64;
+        stats.coverage_percent_max = stats.coverage_percent_max.max(percent);
+
+        let mut recorded: Vec<String> =
+            days.keys().map(|(day, _)| day.clone()).collect();
+        recorded.dedup();
+        if let Some(cutoff) = recorded
+            .len()
+            .checked_sub(RETAINED_DAYS)
+            .map(|n| &recorded[n])
+        {
+            days.retain(|(day, _), _| day >= cutoff);
+        }
+    }
+
+    /// All printers and days, the oldest first.
+    pub fn report(&self) -> Vec<CoverageReport> {
+        self.days
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((day, printer), stats)| CoverageReport {
+                day: day.clone(),
+                printer: printer.clone(),
+                stats: stats.clone(),
+                coverage_percent_mean: stats.coverage_percent_total
+                    / stats.labels.max(1) as f64,
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn only_configured_templates_apart() {
+    let statistics = TemplateStatistics::default();
+    statistics.set_templates(HashSet::from(["badge".to_string()]));
+
+    let render = Duration::from_millis(20);
+    statistics.record(Some("badge"), render, 10, None);
+    statistics.record(Some("badge"), render, 30, Some(0.5));
+    statistics.record(Some("sha256:0123"), render, 5, None);
+    statistics.record(None, render, 5, None);
+
+    let mut report = statistics.report();
+    report.sort_by(|a, b| a.template.cmp(&b.template));
+    assert_eq!(report.len(), 2);
+    assert_eq!(
+        (report[0].template.as_str(), report[0].stats.jobs),
+        (AD_HOC, 2)
+    );
+    assert_eq!(report[1].template, "badge");
+    assert_eq!(report[1].stats.payload_bytes_max, 30);
+    assert_eq!(report[1].stats.covered_jobs, 1);
+}
+
+#[test]
+fn coverage_by_printer_and_day() {
+    const DAY: u64 = 86400;
+
+    let coverage = CoverageStatistics::default();
+    coverage.record("a", 0.25, 2, 0);
+    coverage.record("a", 0.5, 1, 3600);
+    coverage.record("b", 0.1, 1, DAY);
+
+    let report = coverage.report();
+    assert_eq!(report[0].day, "1970-01-01");
+    assert_eq!(report[0].stats.labels, 3);
+    assert!((report[0].coverage_percent_mean - 100.0 / 3.0).abs() < 1e-9);
+    assert_eq!(report[0].stats.coverage_percent_max, 50.0);
+    assert_eq!(report[1].printer, "b");
+
+    for day in 2..RETAINED_DAYS as u64 + 2 {
+        coverage.record("a", 0.1, 1, day * DAY);
+    }
+    assert_eq!(coverage.report()[0].day, "1970-01-03");
+}
@@ -0,0 +1,101 @@
+//! Support bundles, an archive of everything needed to diagnose a printing problem.
+//!
+//! The bundle holds the configuration with secrets redacted, recent log lines, excerpts of the
+//! job log, the state of each printer and the commands last sent to it. It is only ever created
+//! on explicit request, for the operator to pass on.
+use serde::Serialize;
+
+use flate2::{write::GzEncoder, Compression};
+
+/// Parts of the names of settings holding secrets, such as `smtp_password` or `api_key`.
+const SECRET_PATTERNS: [&str; 4] = ["secret", "password", "token", "key"];
+
+pub struct SupportBundle {
+    archive: tar::Builder<GzEncoder<Vec<u8>>>,
+    mtime: u64,
+}
+
+impl SupportBundle {
+    pub fn new() -> Self {
+        let encoder = GzEncoder::new(vec![], Compression::default());
+
+        SupportBundle {
+            archive: tar::Builder::new(encoder),
+            mtime: std::time::SystemTime::now()
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+        }
+    }
+
+    pub fn add_text(&mut self, path: &str, text: &str) -> std::io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(text.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+
+        self.archive.append_data(&mut header, path, text.as_bytes())
+    }
+
+    pub fn add_json(
+        &mut self,
+        path: &str,
+        value: &impl Serialize,
+    ) -> std::io::Result<()> {
+        let text = serde_json::to_string_pretty(value)?;
+        self.add_text(path, &text)
+    }
+
+    /// The gzip compressed tar archive.
+    pub fn finish(self) -> std::io::Result<Vec<u8>> {
+        self.archive.into_inner()?.finish()
+    }
+}
+
+/// Replace the values of all secret keys, at any depth.
+pub fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                let secret =
+                    SECRET_PATTERNS.iter().any(|pattern| key.contains(pattern));
+
+                // Nested settings are redacted by their own names, a printer may be named anything.
+                if secret && !value.is_null() && !value.is_object() {
+                    *value = "<redacted>".into();
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[test]
+fn redacts_secrets() {
+    let mut config = serde_json::json!({
+        "notifications": { "smtp": { "host": "mail", "password": "hunter2" } },
+        "printers": { "a": { "virtualization": { "pulled": { "token": "t" } } } },
+        "unset": { "password": null },
+        "webhook": { "signing_key": "k", "Bearer_Token": "b" },
+        "printers_by_key": { "monkey": { "addr": "10.0.0.1:9100" } },
+    });
+
+    redact(&mut config);
+    assert_eq!(config["notifications"]["smtp"]["password"], "<redacted>");
+    assert_eq!(config["notifications"]["smtp"]["host"], "mail");
+    assert_eq!(
+        config["printers"]["a"]["virtualization"]["pulled"]["token"],
+        "<redacted>"
+    );
+    assert!(config["unset"]["password"].is_null());
+    assert_eq!(config["webhook"]["signing_key"], "<redacted>");
+    assert_eq!(config["webhook"]["Bearer_Token"], "<redacted>");
+    assert_eq!(config["printers_by_key"]["monkey"]["addr"], "10.0.0.1:9100");
+
+    let mut bundle = SupportBundle::new();
+    bundle.add_json("configuration.json", &config).unwrap();
+    assert!(!bundle.finish().unwrap().is_empty());
+}
@@ -0,0 +1,133 @@
+//! Why a job was refused, in enough detail for client UIs to point at the problem.
+//!
+//! Responses carry the error as JSON, tagged by its `kind`, along with a `message` for people.
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use zpl::resvg::usvg;
+
+use crate::configuration::LabelDimensions;
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationError {
+    /// The job was drawn for other labels than the printer has, in mm.
+    DimensionMismatch {
+        expected: LabelDimensions,
+        got: LabelDimensions,
+    },
+    /// The SVG document could not be parsed, at a position where known.
+    Svg {
+        message: String,
+        line: Option<u32>,
+        column: Option<u32>,
+    },
+    /// The document is of a type that can not be printed.
+    UnsupportedMime {
+        mime: String,
+        supported: &'static [&'static str],
+    },
+    /// The image could not be decoded, such as when it is truncated.
+    UndecodableImage {
+        message: String,
+        supported: &'static [&'static str],
+    },
+    /// The document could not be decoded, such as a corrupt PDF.
+    Content { message: String },
+    /// An option is beyond what the printer permits.
+    Options { message: String },
+    /// Raw ZPL that is not sent as it is.
+    Passthrough { message: String },
+    /// The content is misplaced on the label, such as over a pre-printed zone.
+    Placement { message: String },
+    /// The content would not print as intended, see [`zpl::lint`].
+    Lint { message: String },
+}
+
+/// The image types decoded, along with PDF documents if built with them.
+pub const IMAGE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/bmp",
+    "image/tiff",
+    "image/webp",
+];
+
+impl ValidationError {
+    pub fn svg(error: &usvg::Error) -> Self {
+        let position = match error {
+            usvg::Error::ParsingFailed(error) => Some(error.pos()),
+            _ => None,
+        };
+
+        ValidationError::Svg {
+            message: error.to_string(),
+            line: position.map(|position| position.row),
+            column: position.map(|position| position.col),
+        }
+    }
+
+    pub fn content(error: impl std::fmt::Display) -> Self {
+        ValidationError::Content {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::DimensionMismatch { .. } => {
+                write!(
+                    f,
+                    "Dimension mismatch, check physical label configuration"
+                )
+            }
+            // The message of the parser names the position already.
+            ValidationError::Svg { message, .. } => f.write_str(message),
+            ValidationError::UnsupportedMime { mime, supported } => write!(
+                f,
+                "Documents of type {mime} can not be printed, only {}",
+                supported.join(", ")
+            ),
+            ValidationError::UndecodableImage { message, supported } => {
+                write!(f, "{message}, supported are {}", supported.join(", "))
+            }
+            ValidationError::Content { message }
+            | ValidationError::Options { message }
+            | ValidationError::Passthrough { message }
+            | ValidationError::Placement { message }
+            | ValidationError::Lint { message } => f.write_str(message),
+        }
+    }
+}
+
+impl IntoResponse for ValidationError {
+    fn into_response(self) -> Response {
+        let mut body = serde_json::to_value(&self).unwrap_or_default();
+        if let Some(fields) = body.as_object_mut() {
+            fields.insert("message".to_string(), self.to_string().into());
+        }
+
+        (StatusCode::BAD_REQUEST, Json(body)).into_response()
+    }
+}
+
+#[test]
+fn svg_error_position() {
+    let error = usvg::Tree::from_str("<svg>\n<g></svg>", &Default::default())
+        .err()
+        .unwrap();
+    let error = ValidationError::svg(&error);
+
+    let body = serde_json::to_value(&error).unwrap();
+    assert_eq!(body["kind"], "svg");
+    assert_eq!(
+        (body["line"].clone(), body["column"].clone()),
+        (2.into(), 4.into())
+    );
+}
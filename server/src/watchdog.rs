@@ -0,0 +1,149 @@
+//! Recover unattended deployments from failures that otherwise need a restart.
+//!
+//! Periodically renders a small label without printing it, through the same limiter and font
+//! database as real jobs, and has each printer query its device status. Rendering that fails
+//! repeatedly, e.g. on a wedged font database or leaked render permits, is restarted by loading
+//! fonts afresh and resetting the render limiter. Printers failing repeatedly are flagged.
+use std::{sync::Arc, time::Duration};
+
+use tokio::task::JoinSet;
+
+use zpl::{command::HostIdentification, label::RenderOptions, resvg::usvg};
+
+use crate::{
+    configuration::{self, LabelDimensions},
+    job::{JobOptions, PrintApi, PrintJob},
+    render::RenderLimiter,
+    Server,
+};
+
+/// How often to look whether the watchdog was enabled by a reload.
+const IDLE: Duration = Duration::from_secs(60);
+
+/// Uses text, such that the font database takes part.
+const SELF_TEST_SVG: &str = "<svg xmlns='http://www.w3.org/2000/svg' \
+    width='20' height='10'><text x='1' y='8' font-size='6'>OK</text></svg>";
+
+pub async fn run(state: Server) {
+    let mut render_failures = 0;
+
+    loop {
+        let config = state.inner.read().await.watchdog.clone();
+        let Some(config) = config else {
+            tokio::time::sleep(IDLE).await;
+            continue;
+        };
+
+        tokio::time::sleep(config.interval).await;
+
+        let limiter = state.inner.read().await.services.limiter.clone();
+        let rendered = tokio::time::timeout(
+            config.timeout,
+            tokio::spawn(render_self_test(limiter)),
+        )
+        .await;
+
+        match rendered {
+            Ok(Ok(Ok(()))) => render_failures = 0,
+            Ok(Ok(Err(error))) => {
+                render_failures += 1;
+                tracing::warn!("Render self-test failed: {error}");
+            }
+            Ok(Err(error)) => {
+                render_failures += 1;
+                tracing::warn!("Render self-test panicked: {error}");
+            }
+            Err(_) => {
+                render_failures += 1;
+                tracing::warn!("Render self-test timed out");
+            }
+        }
+
+        if render_failures >= config.failures.max(1) {
+            tracing::error!(
+                "Rendering failed {render_failures} self-tests in a row, restarting"
+            );
+
+            render_failures = 0;
+            let faces = tokio::task::block_in_place(PrintApi::reload_fonts);
+            state.inner.read().await.services.limiter.reset();
+            tracing::info!("Render state reset, {faces} font faces loaded");
+            continue;
+        }
+
+        check_printers(&state, config.timeout, config.failures).await;
+    }
+}
+
+/// Render a label as for a printer, without sending it anywhere.
+async fn render_self_test(limiter: Arc<RenderLimiter>) -> anyhow::Result<()> {
+    let tree = tokio::task::block_in_place(|| {
+        usvg::Tree::from_str(SELF_TEST_SVG, &PrintApi::svg_options())
+    })?;
+
+    let job = PrintJob::Svg { tree, key: None };
+    let _permit = limiter.acquire(&job).await;
+
+    let stock = configuration::Label {
+        dimensions: LabelDimensions {
+            width: 20.0,
+            height: 10.0,
+            margin_left: 0.0,
+            margin_right: 0.0,
+            margin_top: 0.0,
+            margin_bottom: 0.0,
+        },
+        exclusion_zones: vec![],
+        stock: Default::default(),
+        post_print: None,
+    };
+
+    let host = HostIdentification {
+        dpmm: 8,
+        ..HostIdentification::default()
+    };
+
+    tokio::task::block_in_place(|| {
+        let label =
+            job.into_label(&stock, &host, &JobOptions::default(), &[])?;
+        label.render_with(&RenderOptions::default())?;
+        Ok(())
+    })
+}
+
+/// Have each printer query its status, or report on the job it is printing.
+async fn check_printers(state: &Server, timeout: Duration, threshold: u32) {
+    let mut checks = JoinSet::new();
+
+    {
+        let state = state.inner.read().await;
+        for (name, queue) in &state.printer {
+            let printer = queue.printer.clone();
+            let name = name.clone();
+            let reply = queue.driver.self_test();
+
+            checks.spawn(async move {
+                let result = match reply {
+                    Ok(reply) => match tokio::time::timeout(timeout, reply)
+                        .await
+                    {
+                        Ok(Ok(result)) => result,
+                        Ok(Err(_)) => Err(anyhow::anyhow!("Printer stopped")),
+                        Err(_) => Err(anyhow::anyhow!("Timed out")),
+                    },
+                    Err(error) => Err(anyhow::anyhow!(error)),
+                };
+
+                printer.record_self_test(&name, &result, threshold);
+            });
+        }
+    }
+
+    while checks.join_next().await.is_some() {}
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn render_without_printer() {
+    let limiter = Arc::new(RenderLimiter::default());
+    render_self_test(limiter).await.unwrap();
+}
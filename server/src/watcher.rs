@@ -0,0 +1,96 @@
+//! Reload the configuration when its files change.
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+
+use axum::extract::State;
+use notify::{RecursiveMode, Watcher as _};
+use tokio::sync::{mpsc, watch};
+
+use crate::Server;
+
+/// Editors tend to write files in several steps, wait for them to settle.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watch the files of the active configuration, as published on each reload.
+///
+/// `files` is `None` while watching is disabled by the configuration.
+pub async fn watch_configuration(
+    state: Server,
+    mut files: watch::Receiver<Option<Vec<PathBuf>>>,
+) {
+    let (events, mut event) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(
+        move |result: notify::Result<notify::Event>| {
+            let _ = events.send(result);
+        },
+    ) {
+        Ok(watcher) => watcher,
+        Err(error) => {
+            tracing::error!("Can not watch the configuration: {error}");
+            return;
+        }
+    };
+
+    let mut sources = HashSet::new();
+    let mut directories = HashSet::new();
+
+    loop {
+        tokio::select! {
+            changed = files.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+
+                sources = files
+                    .borrow_and_update()
+                    .clone()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect::<HashSet<_>>();
+
+                // Watch directories, files are often replaced rather than written.
+                let wanted: HashSet<PathBuf> = sources
+                    .iter()
+                    .filter_map(|path| path.parent().map(PathBuf::from))
+                    .collect();
+
+                for dir in directories.difference(&wanted) {
+                    let _ = watcher.unwatch(dir);
+                }
+
+                for dir in wanted.difference(&directories) {
+                    if let Err(error) =
+                        watcher.watch(dir, RecursiveMode::NonRecursive)
+                    {
+                        tracing::warn!("Can not watch {}: {error}", dir.display());
+                    }
+                }
+
+                directories = wanted;
+            }
+            Some(result) = event.recv() => {
+                let is_relevant = match result {
+                    Ok(event) => {
+                        !event.kind.is_access()
+                            && event.paths.iter().any(|p| sources.contains(p))
+                    }
+                    Err(error) => {
+                        tracing::warn!("Configuration watch failed: {error}");
+                        false
+                    }
+                };
+
+                if !is_relevant {
+                    continue;
+                }
+
+                while let Ok(Some(_)) =
+                    tokio::time::timeout(DEBOUNCE, event.recv()).await
+                {}
+
+                tracing::info!("Configuration changed, reloading");
+                let result = crate::reload(State(state.clone())).await;
+                tracing::info!("Reload: {result}");
+            }
+        }
+    }
+}
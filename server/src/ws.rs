@@ -0,0 +1,263 @@
+//! Interactive sessions over a WebSocket, for kiosks and other frontends that show how their jobs
+//! come along as it happens.
+//!
+//! Clients send JSON messages tagged by `type`:
+//!
+//! - `{"type": "subscribe", "printers": ["gx430t"]}` to receive the events of these printers, of
+//!   all printers if the list is empty. A new subscription replaces the previous one.
+//! - `{"type": "print", "printer": "gx430t", "tag": "1", "job": {...}}` to submit a job, as to
+//!   `/api/v1/print/:printer`. The tag is returned with the answer, to tell submissions apart.
+//!
+//! A submission is answered by `accepted` with the id of the job, or by `rejected`. The events of
+//! jobs submitted in the session are sent whatever the subscription, each naming its printer, as
+//! the job is validated, queued, rendered, sent and printed.
+use std::{collections::HashSet, net::SocketAddr};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, State,
+    },
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{events::Event, intake::JobState, job, physical_printer, Server};
+
+/// The longest error text of a refused job relayed to the client.
+const REASON_LIMIT: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    Subscribe {
+        printers: Vec<String>,
+    },
+    Print {
+        printer: String,
+        #[serde(default)]
+        tag: Option<String>,
+        job: Box<job::PrintApi>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Subscribed {
+        printers: Vec<String>,
+    },
+    Accepted {
+        printer: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        id: u64,
+    },
+    Rejected {
+        printer: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        /// The HTTP status the job would have been refused with.
+        status: u16,
+        reason: String,
+    },
+    /// A message that was not understood, or events missed by a slow client.
+    Error {
+        reason: String,
+    },
+}
+
+/// What a session is told about.
+#[derive(Default)]
+struct Session {
+    /// The printers subscribed to, all of them if empty, `None` before subscribing.
+    printers: Option<HashSet<String>>,
+    /// Jobs submitted in the session and not yet done.
+    jobs: HashSet<u64>,
+}
+
+pub async fn upgrade(
+    State(state): State<Server>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    socket: WebSocketUpgrade,
+) -> impl IntoResponse {
+    socket.on_upgrade(move |socket| session(state, peer, socket))
+}
+
+async fn session(state: Server, peer: SocketAddr, mut socket: WebSocket) {
+    let mut events = state.inner.read().await.services.events.subscribe();
+    let mut session = Session::default();
+    tracing::info!("WebSocket session of {peer} opened");
+
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    handle(&state, peer, &mut session, &text).await
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                // Pings are answered by axum, binary messages not used.
+                Some(Ok(_)) => continue,
+                Some(Err(error)) => {
+                    tracing::debug!("WebSocket session of {peer} broke: {error}");
+                    break;
+                }
+            },
+            event = events.recv() => match event {
+                Ok(event) => match session.relay(event) {
+                    Some(event) => serde_json::to_string(&event),
+                    None => continue,
+                },
+                Err(RecvError::Lagged(missed)) => serde_json::to_string(&Reply::Error {
+                    reason: format!("{missed} events missed"),
+                }),
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        let Ok(reply) = reply else {
+            continue;
+        };
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+
+    tracing::info!("WebSocket session of {peer} closed");
+}
+
+async fn handle(
+    state: &Server,
+    peer: SocketAddr,
+    session: &mut Session,
+    text: &str,
+) -> serde_json::Result<String> {
+    let request = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(error) => {
+            return serde_json::to_string(&Reply::Error {
+                reason: error.to_string(),
+            })
+        }
+    };
+
+    let reply = match request {
+        Request::Subscribe { printers } => {
+            session.printers = Some(printers.iter().cloned().collect());
+            Reply::Subscribed { printers }
+        }
+        Request::Print { printer, tag, job } => {
+            match submit(state, peer, &printer, *job).await {
+                Ok(id) => {
+                    session.jobs.insert(id);
+                    Reply::Accepted { printer, tag, id }
+                }
+                Err((status, reason)) => Reply::Rejected {
+                    printer,
+                    tag,
+                    status,
+                    reason,
+                },
+            }
+        }
+    };
+
+    serde_json::to_string(&reply)
+}
+
+/// Accept a job for validation in the background, as asynchronous HTTP requests are.
+async fn submit(
+    state: &Server,
+    peer: SocketAddr,
+    printer: &str,
+    mut payload: job::PrintApi,
+) -> Result<u64, (u16, String)> {
+    if payload.not_before.is_some() {
+        return Err((400, "Jobs are scheduled over HTTP only".to_string()));
+    }
+
+    {
+        let inner = state.inner.read().await;
+        payload.convert_to_millimetres(inner.units);
+        let now = physical_printer::unix_now() as i64;
+        if let Err(error) = payload.fill_expiry(now, inner.utc_offset) {
+            return Err((400, error.to_string()));
+        }
+    }
+
+    let refused = match crate::refuse_if_draining(state, printer).await {
+        Some(refusal) => refusal,
+        None => {
+            match crate::intake_job(
+                state.clone(),
+                printer.to_string(),
+                peer,
+                payload,
+            )
+            .await
+            {
+                Ok((_, body)) => {
+                    return body["id"]
+                        .as_u64()
+                        .ok_or((500, "Job accepted without an id".to_string()))
+                }
+                Err(response) => response,
+            }
+        }
+    };
+
+    let status = refused.status().as_u16();
+    let reason = axum::body::to_bytes(refused.into_body(), REASON_LIMIT).await;
+    let reason = reason
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_default();
+    Err((status, reason))
+}
+
+impl Session {
+    /// The event to send, if the session is interested in it.
+    fn relay(&mut self, event: Event) -> Option<Event> {
+        if let Event::Job { id, state, .. } = &event {
+            if self.jobs.contains(id) {
+                if matches!(
+                    state,
+                    JobState::Printed
+                        | JobState::Failed { .. }
+                        | JobState::Suspended { .. }
+                        | JobState::Rejected { .. }
+                ) {
+                    self.jobs.remove(id);
+                }
+                return Some(event);
+            }
+        }
+
+        let printers = self.printers.as_ref()?;
+        let wanted = printers.is_empty() || printers.contains(event.printer());
+        wanted.then_some(event)
+    }
+}
+
+#[test]
+fn relayed_events() {
+    let job = |printer: &str, id, state| Event::Job {
+        printer: printer.to_string(),
+        id,
+        state,
+    };
+
+    let mut session = Session::default();
+    session.jobs.insert(1);
+    assert!(session.relay(job("a", 2, JobState::Printed)).is_none());
+    assert!(session.relay(job("a", 1, JobState::Sending)).is_some());
+    assert!(session.relay(job("a", 1, JobState::Printed)).is_some());
+    assert!(session.jobs.is_empty());
+
+    session.printers = Some(HashSet::new());
+    assert!(session.relay(job("a", 2, JobState::Printed)).is_some());
+
+    session.printers = Some(HashSet::from(["b".to_string()]));
+    assert!(session.relay(job("a", 2, JobState::Printed)).is_none());
+    assert!(session.relay(job("b", 2, JobState::Printed)).is_some());
+}
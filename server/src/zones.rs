@@ -0,0 +1,236 @@
+//! Keep jobs off the pre-printed regions of label stock.
+//!
+//! Zones either reject jobs with content inside them, checked on a render when the job is
+//! submitted, or are blanked when the job is rendered for the printer. Content the printer renders
+//! itself can not be blanked, so it is rejected in any zone by its estimated bounding box. Previews
+//! draw what is pre-printed below the content, to show the label as it comes out.
+use std::ops::Range;
+
+use image::{imageops::FilterType, GrayImage, Luma};
+use tracing::warn;
+use zpl::label::{BoundingBox, Label};
+
+use crate::configuration::{ExclusionPolicy, ExclusionZone};
+
+/// Pixels darker than this are printed.
+const PRINTED: u8 = 128;
+
+/// Drawn in zones without a picture of what is pre-printed.
+const SHADE: u8 = 200;
+
+/// Refuse content in any zone with the rejecting policy.
+pub fn check(label: &Label, zones: &[ExclusionZone]) -> anyhow::Result<()> {
+    let rejecting = zones
+        .iter()
+        .filter(|zone| zone.policy == ExclusionPolicy::Reject)
+        .collect::<Vec<_>>();
+
+    if rejecting.is_empty() {
+        return Ok(());
+    }
+
+    check_native(label, rejecting.iter().copied())?;
+
+    let image = label.preview()?.into_luma8();
+
+    for zone in rejecting {
+        let (xs, ys) = area(zone, label.dpmm, &image);
+        let printed = ys
+            .into_iter()
+            .any(|y| xs.clone().any(|x| image.get_pixel(x, y).0[0] < PRINTED));
+
+        if printed {
+            anyhow::bail!(
+                "Content overlaps the pre-printed zone at {}, {} mm",
+                zone.x,
+                zone.y
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Blank all zones with the masking policy.
+///
+/// The rasterized content is replaced by a single picture of the label. Content the printer
+/// renders itself is refused in these zones, it can not be blanked.
+pub fn mask(label: &mut Label, zones: &[ExclusionZone]) -> anyhow::Result<()> {
+    let masking = zones
+        .iter()
+        .filter(|zone| zone.policy == ExclusionPolicy::Mask);
+
+    if masking.clone().next().is_none() {
+        return Ok(());
+    }
+
+    check_native(label, masking)?;
+
+    let dpmm = label.dpmm;
+    label.flatten(|mut image| {
+        for zone in zones {
+            if zone.policy != ExclusionPolicy::Mask {
+                continue;
+            }
+
+            let (xs, ys) = area(zone, dpmm, &image);
+            for y in ys {
+                for x in xs.clone() {
+                    image.put_pixel(x, y, Luma([255]));
+                }
+            }
+        }
+
+        Ok(image)
+    })
+}
+
+/// Refuse content the printer renders itself if its bounding box reaches into any of the zones.
+fn check_native<'a>(
+    label: &Label,
+    zones: impl Iterator<Item = &'a ExclusionZone> + Clone,
+) -> anyhow::Result<()> {
+    let dots = |mm: f32| (mm * label.dpmm as f32).max(0.0);
+
+    for (item, area) in label.bounding_boxes().iter().enumerate() {
+        if !label.content[item].is_native() {
+            continue;
+        }
+
+        for zone in zones.clone() {
+            let x = dots(zone.x).floor() as u32;
+            let y = dots(zone.y).floor() as u32;
+            let zone_area = BoundingBox {
+                x,
+                y,
+                width: (dots(zone.x + zone.width).ceil() as u32)
+                    .saturating_sub(x),
+                height: (dots(zone.y + zone.height).ceil() as u32)
+                    .saturating_sub(y),
+            };
+
+            if zone_area.intersects(area) {
+                anyhow::bail!(
+                    "Item {item}, rendered by the printer, overlaps the pre-printed zone at {}, {} mm",
+                    zone.x,
+                    zone.y
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Draw what is pre-printed in each zone below a picture of the label.
+pub fn underlay(canvas: &mut GrayImage, zones: &[ExclusionZone], dpmm: u32) {
+    for zone in zones {
+        let (xs, ys) = area(zone, dpmm, canvas);
+        if xs.is_empty() || ys.is_empty() {
+            continue;
+        }
+
+        let picture =
+            zone.underlay
+                .as_ref()
+                .and_then(|path| match image::open(path) {
+                    Ok(picture) => Some(
+                        picture
+                            .resize_exact(
+                                xs.len() as u32,
+                                ys.len() as u32,
+                                FilterType::Triangle,
+                            )
+                            .into_luma8(),
+                    ),
+                    Err(error) => {
+                        warn!(
+                            "Failed to load underlay {}: {error}",
+                            path.display()
+                        );
+                        None
+                    }
+                });
+
+        for y in ys.clone() {
+            for x in xs.clone() {
+                let pre = picture.as_ref().map_or(SHADE, |picture| {
+                    picture.get_pixel(x - xs.start, y - ys.start).0[0]
+                });
+
+                let pixel = canvas.get_pixel_mut(x, y);
+                pixel.0[0] = pixel.0[0].min(pre);
+            }
+        }
+    }
+}
+
+/// The pixels covered by a zone, within the image.
+fn area(
+    zone: &ExclusionZone,
+    dpmm: u32,
+    image: &GrayImage,
+) -> (Range<u32>, Range<u32>) {
+    let dots = |mm: f32| (mm * dpmm as f32).max(0.0);
+    let (width, height) = image.dimensions();
+
+    let x0 = (dots(zone.x).floor() as u32).min(width);
+    let y0 = (dots(zone.y).floor() as u32).min(height);
+    let x1 = (dots(zone.x + zone.width).ceil() as u32).clamp(x0, width);
+    let y1 = (dots(zone.y + zone.height).ceil() as u32).clamp(y0, height);
+
+    (x0..x1, y0..y1)
+}
+
+#[test]
+fn reject_and_mask() {
+    let mut label = Label::new(10.0, 10.0, 8);
+    label.content.push(zpl::label::LabelContent::Image {
+        img: GrayImage::from_pixel(40, 80, Luma([0])).into(),
+        x: zpl::length::Length::dots(0),
+        y: zpl::length::Length::dots(0),
+        w: zpl::length::Length::dots(40),
+        h: zpl::length::Length::dots(80),
+        fit: zpl::label::Fit::Cover,
+    });
+
+    let zone = |x: f32, policy: ExclusionPolicy| ExclusionZone {
+        x,
+        y: 2.0,
+        width: 2.0,
+        height: 2.0,
+        policy,
+        underlay: None,
+    };
+
+    // Content covers the left half only.
+    assert!(check(&label, &[zone(1.0, ExclusionPolicy::Reject)]).is_err());
+    assert!(check(&label, &[zone(6.0, ExclusionPolicy::Reject)]).is_ok());
+
+    mask(&mut label, &[zone(1.0, ExclusionPolicy::Mask)]).unwrap();
+    let image = label.preview().unwrap().into_luma8();
+    assert_eq!(image.get_pixel(12, 20).0, [255]);
+    assert_eq!(image.get_pixel(4, 20).0, [0]);
+    assert!(check(&label, &[zone(1.0, ExclusionPolicy::Reject)]).is_ok());
+
+    // The printer numbers labels from 6 mm down and across.
+    label.content.push(zpl::label::LabelContent::SerialNumber {
+        start: 1,
+        increment: 1,
+        pad: 3,
+        x: zpl::length::Length::dots(48),
+        y: zpl::length::Length::dots(48),
+        height: zpl::length::Length::dots(8),
+    });
+    let corner = |policy| ExclusionZone {
+        y: 6.0,
+        ..zone(6.0, policy)
+    };
+
+    assert!(check(&label, &[zone(6.0, ExclusionPolicy::Reject)]).is_ok());
+    assert!(check(&label, &[corner(ExclusionPolicy::Reject)]).is_err());
+    assert!(mask(&mut label, &[corner(ExclusionPolicy::Mask)]).is_err());
+
+    mask(&mut label, &[zone(6.0, ExclusionPolicy::Mask)]).unwrap();
+    assert!(label.content[1].is_native());
+}
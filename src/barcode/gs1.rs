@@ -0,0 +1,477 @@
+//! GS1 element strings, as carried by GS1-128 barcodes and GS1 DataMatrix codes.
+//!
+//! An element string is written as application identifiers in parentheses, each followed by its
+//! data, such as `(01)09501101530003(10)ABC123`. Each element is checked against the format GS1
+//! assigns its identifier, check digits are verified or appended, and a FNC1 separates elements
+//! of variable length from the next, such that scanners can tell where they end.
+use std::{fmt, str::FromStr};
+
+use quick_error::quick_error;
+
+use crate::command::{CommandSequence, FieldData, ZplCommand};
+
+quick_error! {
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum Error {
+        /// The text is not made of identifiers in parentheses followed by their data.
+        Syntax(message: String) {
+            display("{}", message)
+        }
+        UnknownIdentifier(ai: String) {
+            display("Application identifier ({}) is not known", ai)
+        }
+        Length(ai: String, length: usize) {
+            display("Data of ({}) can not be {} characters long", ai, length)
+        }
+        Character(ai: String, c: char) {
+            display("Data of ({}) can not contain {:?}", ai, c)
+        }
+        CheckDigit(ai: String, expected: char) {
+            display("Check digit of ({}) should be {}", ai, expected)
+        }
+        Date(ai: String) {
+            display("Data of ({}) is not a date as YYMMDD", ai)
+        }
+    }
+}
+
+/// The format of the data of an application identifier.
+#[derive(Clone, Copy)]
+struct Format {
+    numeric: bool,
+    min: usize,
+    max: usize,
+    /// Whether the last digit is a check digit over the others.
+    check_digit: bool,
+    date: bool,
+}
+
+impl Format {
+    const fn fixed(length: usize) -> Self {
+        Format {
+            numeric: true,
+            min: length,
+            max: length,
+            check_digit: false,
+            date: false,
+        }
+    }
+
+    const fn numeric(max: usize) -> Self {
+        Format {
+            min: 1,
+            ..Format::fixed(max)
+        }
+    }
+
+    const fn text(max: usize) -> Self {
+        Format {
+            numeric: false,
+            ..Format::numeric(max)
+        }
+    }
+
+    const fn check_digit(self) -> Self {
+        Format {
+            check_digit: true,
+            ..self
+        }
+    }
+
+    const fn date(self) -> Self {
+        Format { date: true, ..self }
+    }
+}
+
+/// The format of the commonly used application identifiers, as in the GS1 General
+/// Specifications.
+fn format(ai: &str) -> Option<Format> {
+    let digit = |index: usize| ai.as_bytes().get(index).map(|b| b - b'0');
+
+    Some(match ai {
+        "00" => Format::fixed(18).check_digit(),
+        "01" | "02" => Format::fixed(14).check_digit(),
+        "10" | "21" | "22" | "254" | "420" => Format::text(20),
+        "11" | "12" | "13" | "15" | "16" | "17" => Format::fixed(6).date(),
+        "20" => Format::fixed(2),
+        "235" => Format::text(28),
+        "240" | "241" | "250" | "251" | "400" | "401" | "403" => {
+            Format::text(30)
+        }
+        "242" => Format::numeric(6),
+        "30" | "37" => Format::numeric(8),
+        "402" => Format::fixed(17).check_digit(),
+        "421" => Format::text(12),
+        "422" => Format::fixed(3),
+        "7003" => Format::fixed(10),
+        "8005" => Format::fixed(6),
+        "8020" => Format::text(25),
+        "90" => Format::text(30),
+        _ if ai.len() == 2 && ai.starts_with('9') => Format::text(90),
+        // Global location numbers, of the ship to, bill to and other parties.
+        _ if ai.len() == 3 && ai.starts_with("41") && digit(2)? <= 7 => {
+            Format::fixed(13).check_digit()
+        }
+        // Measures such as weights and lengths, the last digit placing the decimal point.
+        _ if ai.len() == 4
+            && (31..=36).contains(&(digit(0)? * 10 + digit(1)?)) =>
+        {
+            Format::fixed(6)
+        }
+        // Amounts payable, the last digit placing the decimal point.
+        _ if ai.len() == 4 && ai.starts_with("39") => match digit(2)? {
+            0 | 2 => Format::numeric(15),
+            1 | 3 => Format::numeric(18),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+/// Identifiers whose data is of a length predefined by their first two digits, which need no
+/// separator after them.
+const PREDEFINED_LENGTH: &[&str] = &[
+    "00", "01", "02", "03", "04", "11", "12", "13", "14", "15", "16", "17",
+    "18", "19", "20", "31", "32", "33", "34", "35", "36", "41",
+];
+
+/// The characters of GS1 set 82 allowed in alphanumeric data.
+fn is_allowed(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!\"%&'()*+,-./:;<=>?_".contains(c)
+}
+
+/// The GS1 check digit of the digits, weighted by 3 and 1 alternately from the right.
+///
+/// Returns `None` if the text holds other characters than digits.
+pub fn check_digit(digits: &str) -> Option<char> {
+    let mut sum = 0;
+    for (index, c) in digits.chars().rev().enumerate() {
+        let digit = c.to_digit(10)?;
+        sum += if index % 2 == 0 { 3 * digit } else { digit };
+    }
+
+    char::from_digit((10 - sum % 10) % 10, 10)
+}
+
+/// A single application identifier along with its data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Element {
+    ai: String,
+    data: String,
+}
+
+impl Element {
+    /// Check the data against the format of the identifier.
+    ///
+    /// Data which takes a check digit may leave it out, it is then appended.
+    pub fn new(ai: &str, data: &str) -> Result<Self, Error> {
+        let unknown = || Error::UnknownIdentifier(ai.to_string());
+        if !ai.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(unknown());
+        }
+        let format = format(ai).ok_or_else(unknown)?;
+
+        let allowed = |c: char| match format.numeric {
+            true => c.is_ascii_digit(),
+            false => is_allowed(c),
+        };
+        if let Some(c) = data.chars().find(|c| !allowed(*c)) {
+            return Err(Error::Character(ai.to_string(), c));
+        }
+
+        let mut data = data.to_string();
+        if format.check_digit && data.len() + 1 == format.max {
+            data.extend(check_digit(&data));
+        }
+
+        let length = data.chars().count();
+        if length < format.min || length > format.max {
+            return Err(Error::Length(ai.to_string(), length));
+        }
+
+        if format.check_digit {
+            let (digits, given) = data.split_at(length - 1);
+            let expected = check_digit(digits).unwrap_or_default();
+            if !given.starts_with(expected) {
+                return Err(Error::CheckDigit(ai.to_string(), expected));
+            }
+        }
+
+        if format.date {
+            let month = &data[2..4];
+            let day = &data[4..6];
+            if !("01"..="12").contains(&month) || day > "31" {
+                return Err(Error::Date(ai.to_string()));
+            }
+        }
+
+        Ok(Element {
+            ai: ai.to_string(),
+            data,
+        })
+    }
+
+    pub fn ai(&self) -> &str {
+        &self.ai
+    }
+
+    /// The data, including a check digit if it takes one.
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    /// Whether a FNC1 must follow the element, unless it is the last.
+    fn needs_separator(&self) -> bool {
+        !PREDEFINED_LENGTH.contains(&&self.ai[..2])
+    }
+}
+
+/// The elements of a single barcode, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElementString {
+    elements: Vec<Element>,
+}
+
+/// A piece of the encoded data, before it is written for a barcode.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Symbol {
+    Fnc1,
+    Char(char),
+}
+
+/// The subsets of Code 128 used, for text and for pairs of digits.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Subset {
+    B,
+    C,
+}
+
+impl Subset {
+    /// Write the code that starts with the subset, or switches to it from the current one.
+    fn select(self, out: &mut String, current: Option<Subset>) -> Self {
+        out.push_str(match (current, self) {
+            (None, Subset::B) => ">:",
+            (None, Subset::C) => ">;",
+            (Some(Subset::C), Subset::B) => ">6",
+            (Some(Subset::B), Subset::C) => ">5",
+            _ => "",
+        });
+        self
+    }
+}
+
+impl ElementString {
+    pub fn new(elements: Vec<Element>) -> Result<Self, Error> {
+        if elements.is_empty() {
+            return Err(Error::Syntax(
+                "No application identifiers".to_string(),
+            ));
+        }
+
+        Ok(ElementString { elements })
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    /// The commands of a GS1-128 barcode with bars of the height, in dots.
+    ///
+    /// The plain text is left out, as the printer would show the invocation codes rather than
+    /// the identifiers in parentheses. Print the [`Display`](fmt::Display) of the elements for
+    /// it instead.
+    pub fn gs1_128(&self, height: u32) -> CommandSequence {
+        CommandSequence(vec![
+            ZplCommand::FieldModeCode128 {
+                height,
+                interpretation: false,
+            },
+            ZplCommand::FieldData(FieldData::new(&self.code128_data())),
+            ZplCommand::FieldSeparator,
+        ])
+    }
+
+    /// The commands of a GS1 DataMatrix code with modules of the size, in dots.
+    pub fn data_matrix(&self, zoom: u32) -> CommandSequence {
+        CommandSequence(vec![
+            ZplCommand::FieldModeDataMatrix {
+                zoom,
+                escape: DATA_MATRIX_ESCAPE,
+            },
+            ZplCommand::FieldData(FieldData::new(&self.data_matrix_data())),
+            ZplCommand::FieldSeparator,
+        ])
+    }
+
+    /// The data, starting with a FNC1 that marks it as GS1, and with one after each element of
+    /// variable length but the last.
+    fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols = vec![Symbol::Fnc1];
+        for (index, element) in self.elements.iter().enumerate() {
+            symbols.extend(element.ai.chars().map(Symbol::Char));
+            symbols.extend(element.data.chars().map(Symbol::Char));
+            if element.needs_separator() && index + 1 < self.elements.len() {
+                symbols.push(Symbol::Fnc1);
+            }
+        }
+        symbols
+    }
+
+    /// The data for `^BC` in mode N, packing runs of digits in pairs by subset C.
+    fn code128_data(&self) -> String {
+        let symbols = self.symbols();
+
+        // The digits from the index which are encoded in pairs, if worth switching to subset C.
+        let pairs_at = |index: usize, subset: Option<Subset>| {
+            let rest = symbols.get(index..).unwrap_or_default();
+            let digits = rest
+                .iter()
+                .take_while(
+                    |s| matches!(s, Symbol::Char(c) if c.is_ascii_digit()),
+                )
+                .count();
+            let worth = digits >= 4
+                || (digits >= 2
+                    && (digits == rest.len() || subset == Some(Subset::C)));
+            if worth {
+                digits / 2 * 2
+            } else {
+                0
+            }
+        };
+
+        let mut out = String::new();
+        let mut current = None;
+        let mut index = 0;
+        while index < symbols.len() {
+            match symbols[index] {
+                Symbol::Fnc1 => {
+                    if current.is_none() {
+                        let start = match pairs_at(index + 1, None) {
+                            0 => Subset::B,
+                            _ => Subset::C,
+                        };
+                        current = Some(start.select(&mut out, current));
+                    }
+                    out.push_str(">8");
+                    index += 1;
+                }
+                Symbol::Char(c) => match pairs_at(index, current) {
+                    0 => {
+                        current = Some(Subset::B.select(&mut out, current));
+                        match c {
+                            '>' => out.push_str(">0"),
+                            c => out.push(c),
+                        }
+                        index += 1;
+                    }
+                    digits => {
+                        current = Some(Subset::C.select(&mut out, current));
+                        for symbol in &symbols[index..index + digits] {
+                            if let Symbol::Char(c) = symbol {
+                                out.push(*c);
+                            }
+                        }
+                        index += digits;
+                    }
+                },
+            }
+        }
+
+        out
+    }
+
+    /// The data for `^BX`, with FNC1 as an escape sequence.
+    fn data_matrix_data(&self) -> String {
+        self.symbols()
+            .into_iter()
+            .fold(String::new(), |mut out, symbol| {
+                match symbol {
+                    Symbol::Fnc1 => {
+                        out.push(DATA_MATRIX_ESCAPE);
+                        out.push('1');
+                    }
+                    Symbol::Char(c) => out.push(c),
+                }
+                out
+            })
+    }
+}
+
+/// Starts escape sequences in Data Matrix data, outside of the characters GS1 allows.
+const DATA_MATRIX_ESCAPE: char = '#';
+
+/// Parses identifiers in parentheses followed by their data, such as `(01)09501101530003`.
+///
+/// As identifiers are told apart by the parentheses, the data can not contain any.
+impl FromStr for ElementString {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Error> {
+        let mut elements = vec![];
+        let mut rest = text.trim();
+
+        while !rest.is_empty() {
+            let Some((ai, after)) =
+                rest.strip_prefix('(').and_then(|rest| rest.split_once(')'))
+            else {
+                return Err(Error::Syntax(format!(
+                    "Expected an application identifier in parentheses at {rest:?}"
+                )));
+            };
+
+            let end = after.find('(').unwrap_or(after.len());
+            elements.push(Element::new(ai, &after[..end])?);
+            rest = &after[end..];
+        }
+
+        ElementString::new(elements)
+    }
+}
+
+/// The text for people, with the identifiers in parentheses.
+impl fmt::Display for ElementString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for element in &self.elements {
+            write!(f, "({}){}", element.ai, element.data)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn gs1_element_strings() {
+    assert_eq!(check_digit("0950110153000"), Some('3'));
+    assert_eq!(check_digit("37610425002123456"), Some('9'));
+
+    // The check digit is appended, the batch is separated from the serial by a FNC1.
+    let elements: ElementString = "(01)0950110153000(17)260229(10)AB>12(21)42"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        elements.to_string(),
+        "(01)09501101530003(17)260229(10)AB>12(21)42"
+    );
+    assert_eq!(
+        String::from(elements.gs1_128(100)),
+        "^BCN,100,N,N,N,N\n^FD>;>801095011015300031726022910>6AB>012>8>52142\n^FS",
+    );
+    assert_eq!(
+        String::from(elements.data_matrix(5)),
+        "^BXN,5,200,,,,#\n^FD#1010950110153000317260229\
+         10AB>12#12142\n^FS",
+    );
+
+    assert_eq!(
+        "(01)09501101530004".parse::<ElementString>(),
+        Err(Error::CheckDigit("01".to_string(), '3'))
+    );
+    assert_eq!(
+        "(17)261301".parse::<ElementString>(),
+        Err(Error::Date("17".to_string()))
+    );
+    assert_eq!(
+        "(10)a b".parse::<ElementString>(),
+        Err(Error::Character("10".to_string(), ' '))
+    );
+}
@@ -0,0 +1,2 @@
+//! Barcodes whose content follows a standard beyond the symbology, checked before printing.
+pub mod gs1;
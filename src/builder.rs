@@ -0,0 +1,344 @@
+//! Fluent construction of labels and their content, checked when built.
+//!
+//! ```
+//! use zpl::builder::ImageContent;
+//! use zpl::label::{Fit, Label};
+//!
+//! let logo = image::GrayImage::new(300, 200).into();
+//! let label = Label::builder()
+//!     .size_mm(51.0, 51.0)
+//!     .dpmm(8)
+//!     .content(
+//!         ImageContent::builder(logo)
+//!             .at_mm(5.0, 5.0)
+//!             .size_mm(40.0, 40.0)
+//!             .fit(Fit::Contain),
+//!     )
+//!     .build()
+//!     .unwrap();
+//! # assert_eq!(label.content.len(), 1);
+//! ```
+use crate::label::{
+    Fit, Label, LabelContent, Margins, QrErrorCorrection, ViolationKind,
+};
+use crate::layout::Region;
+use crate::length::Length;
+
+/// A label under construction, see [`Label::builder`].
+#[derive(Default)]
+pub struct LabelBuilder {
+    width: Length,
+    height: Length,
+    dpmm: Option<u32>,
+    margins: Margins,
+    content: Vec<LabelContent>,
+    layouts: Vec<Region>,
+}
+
+impl LabelBuilder {
+    pub fn size_mm(mut self, width: f32, height: f32) -> Self {
+        self.width = Length::mm(width);
+        self.height = Length::mm(height);
+        self
+    }
+
+    pub fn size_inches(mut self, width: f32, height: f32) -> Self {
+        self.width = Length::inches(width);
+        self.height = Length::inches(height);
+        self
+    }
+
+    /// The resolution of the printer, 8 dots per mm unless given.
+    pub fn dpmm(mut self, dpmm: u32) -> Self {
+        self.dpmm = Some(dpmm);
+        self
+    }
+
+    /// Edges to keep content clear of.
+    pub fn margins(mut self, margins: Margins) -> Self {
+        self.margins = margins;
+        self
+    }
+
+    /// Add an item on top of those before.
+    pub fn content(mut self, item: impl Into<LabelContent>) -> Self {
+        self.content.push(item.into());
+        self
+    }
+
+    /// Add the items of a layout on top of all others, placed once the size of the label is
+    /// known, see [`Region`].
+    pub fn layout(mut self, region: Region) -> Self {
+        self.layouts.push(region);
+        self
+    }
+
+    /// The label, if it has a size and all content lies within its margins.
+    pub fn build(self) -> anyhow::Result<Label> {
+        let mut label = Label {
+            content: self.content,
+            width: self.width,
+            height: self.height,
+            dpmm: self.dpmm.unwrap_or(8),
+            margins: self.margins,
+        };
+
+        if label.width_dots() == 0 || label.height_dots() == 0 {
+            anyhow::bail!("Label without a size");
+        }
+
+        for region in self.layouts {
+            let content = region.place(&label);
+            label.content.extend(content);
+        }
+
+        for (item, area) in label.bounding_boxes().iter().enumerate() {
+            if area.width == 0 || area.height == 0 {
+                anyhow::bail!("Item {item} has no size");
+            }
+        }
+
+        if let Some(violation) =
+            label.validate().into_iter().find(|violation| {
+                violation.kind == ViolationKind::OutsideLabel
+                    || violation.kind == ViolationKind::InMargin
+            })
+        {
+            anyhow::bail!("{violation}");
+        }
+
+        Ok(label)
+    }
+}
+
+/// An image placed on a label, see [`ImageContent::builder`].
+pub struct ImageContent {
+    img: ::image::DynamicImage,
+    x: Length,
+    y: Length,
+    size: Option<(Length, Length)>,
+    fit: Fit,
+}
+
+impl ImageContent {
+    /// Place an image at the top left, one dot per pixel and covering its box.
+    pub fn builder(img: ::image::DynamicImage) -> Self {
+        ImageContent {
+            img,
+            x: Length::ZERO,
+            y: Length::ZERO,
+            size: None,
+            fit: Fit::Cover,
+        }
+    }
+
+    pub fn at(mut self, x: Length, y: Length) -> Self {
+        (self.x, self.y) = (x, y);
+        self
+    }
+
+    pub fn at_mm(self, x: f32, y: f32) -> Self {
+        self.at(Length::mm(x), Length::mm(y))
+    }
+
+    pub fn size(mut self, width: Length, height: Length) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    pub fn size_mm(self, width: f32, height: f32) -> Self {
+        self.size(Length::mm(width), Length::mm(height))
+    }
+
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.fit = fit;
+        self
+    }
+}
+
+impl From<ImageContent> for LabelContent {
+    fn from(image: ImageContent) -> Self {
+        let (w, h) = image.size.unwrap_or_else(|| {
+            (
+                Length::dots(image.img.width()),
+                Length::dots(image.img.height()),
+            )
+        });
+
+        LabelContent::Image {
+            img: image.img,
+            x: image.x,
+            y: image.y,
+            w,
+            h,
+            fit: image.fit,
+        }
+    }
+}
+
+/// An SVG document placed on a label, see [`SvgContent::builder`].
+pub struct SvgContent {
+    code: String,
+    x: Length,
+    y: Length,
+    w: Length,
+    h: Length,
+    shrink: bool,
+}
+
+impl SvgContent {
+    /// Place a document at the top left, to be given a size.
+    pub fn builder(code: impl Into<String>) -> Self {
+        SvgContent {
+            code: code.into(),
+            x: Length::ZERO,
+            y: Length::ZERO,
+            w: Length::ZERO,
+            h: Length::ZERO,
+            shrink: false,
+        }
+    }
+
+    pub fn at(mut self, x: Length, y: Length) -> Self {
+        (self.x, self.y) = (x, y);
+        self
+    }
+
+    pub fn at_mm(self, x: f32, y: f32) -> Self {
+        self.at(Length::mm(x), Length::mm(y))
+    }
+
+    pub fn size(mut self, width: Length, height: Length) -> Self {
+        (self.w, self.h) = (width, height);
+        self
+    }
+
+    pub fn size_mm(self, width: f32, height: f32) -> Self {
+        self.size(Length::mm(width), Length::mm(height))
+    }
+
+    /// Shrink content running past the edges of the document until it fits, such as long text.
+    pub fn shrink_to_fit(mut self) -> Self {
+        self.shrink = true;
+        self
+    }
+}
+
+impl From<SvgContent> for LabelContent {
+    fn from(svg: SvgContent) -> Self {
+        LabelContent::Svg {
+            code: svg.code,
+            x: svg.x,
+            y: svg.y,
+            w: svg.w,
+            h: svg.h,
+            shrink: svg.shrink,
+        }
+    }
+}
+
+/// A QR code drawn by the printer, see [`QrContent::builder`].
+pub struct QrContent {
+    content: String,
+    x: Length,
+    y: Length,
+    zoom: u32,
+    error_correction: QrErrorCorrection,
+    size: Option<Length>,
+}
+
+impl QrContent {
+    /// Place a code at the top left, two dots per module.
+    pub fn builder(content: impl Into<String>) -> Self {
+        QrContent {
+            content: content.into(),
+            x: Length::ZERO,
+            y: Length::ZERO,
+            zoom: 2,
+            error_correction: QrErrorCorrection::default(),
+            size: None,
+        }
+    }
+
+    pub fn at(mut self, x: Length, y: Length) -> Self {
+        (self.x, self.y) = (x, y);
+        self
+    }
+
+    pub fn at_mm(self, x: f32, y: f32) -> Self {
+        self.at(Length::mm(x), Length::mm(y))
+    }
+
+    /// Fill a square of this size, choosing the zoom and error correction, see [`qr_fit`].
+    ///
+    /// [`qr_fit`]: crate::label::qr_fit
+    pub fn size(mut self, size: Length) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn size_mm(self, size: f32) -> Self {
+        self.size(Length::mm(size))
+    }
+
+    /// Dots per module, unless filling a size.
+    pub fn zoom(mut self, zoom: u32) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    /// The level of error correction, unless filling a size.
+    pub fn error_correction(mut self, level: QrErrorCorrection) -> Self {
+        self.error_correction = level;
+        self
+    }
+}
+
+impl From<QrContent> for LabelContent {
+    fn from(qr: QrContent) -> Self {
+        LabelContent::QrCode {
+            content: qr.content,
+            x: qr.x,
+            y: qr.y,
+            zoom: qr.zoom,
+            error_correction: qr.error_correction,
+            size: qr.size,
+        }
+    }
+}
+
+#[test]
+fn content_within_bounds() {
+    let square = || {
+        ImageContent::builder(::image::GrayImage::new(40, 20).into())
+            .at_mm(1.0, 1.0)
+            .size_mm(4.0, 4.0)
+            .fit(Fit::Contain)
+    };
+
+    let label = Label::builder()
+        .size_mm(5.0, 5.0)
+        .content(square())
+        .build()
+        .unwrap();
+    assert_eq!(label.dpmm, 8);
+
+    // Centered vertically, with blank rows above and below.
+    let preview = label.preview().unwrap().into_luma8();
+    assert_eq!(preview.get_pixel(20, 9).0, [255]);
+    assert_eq!(preview.get_pixel(20, 20).0, [0]);
+
+    let error = Label::builder()
+        .size_mm(5.0, 5.0)
+        .content(square().at_mm(2.0, 1.0))
+        .build()
+        .map(|_| ())
+        .unwrap_err();
+    assert!(error.to_string().contains("extends beyond the label"));
+
+    assert!(Label::builder()
+        .size_mm(5.0, 5.0)
+        .content(SvgContent::builder("<svg/>"))
+        .build()
+        .is_err());
+}
@@ -0,0 +1,1261 @@
+//! The command line interface, printing labels and managing printers.
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context as _};
+use clap::{CommandFactory as _, Parser, Subcommand, ValueEnum};
+use core::num::NonZeroU32;
+
+use crate::config::{LabelDimensions, PrinterProfile};
+use crate::{command, device, label, length, lint, util};
+use command::CommandSequence;
+use device::ZplPrinter;
+use label::{Fit, Label, LabelContent};
+use length::Length;
+use util::image::ImageCompression;
+
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Without a subcommand, print a label.
+    #[command(flatten)]
+    print: Args,
+
+    #[arg(long, global = true, value_enum, default_value_t)]
+    output: Output,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a label.
+    Print(Args),
+    /// Query the identification and status of a printer.
+    Status { ip: SocketAddr },
+    /// Find printers answering on a network.
+    Discover {
+        /// The IPv4 network to scan, e.g. `192.168.1.0/24`.
+        network: String,
+        #[arg(long, default_value = "9100")]
+        port: u16,
+        #[arg(long, default_value = "500", help = "probe timeout in ms")]
+        timeout: u64,
+        /// How many hosts to probe at once.
+        #[arg(long, default_value = "64")]
+        concurrency: usize,
+    },
+    /// Calibrate the media sensors, e.g. after changing the roll.
+    Calibrate {
+        ip: SocketAddr,
+        /// Only feed a blank label.
+        #[arg(long)]
+        feed: bool,
+        /// Also set what the printer does on power up and on closing the head.
+        #[arg(long, value_enum)]
+        on_power_up: Option<command::MediaFeed>,
+    },
+    /// Set the printer's clock to the current time, for clock fields.
+    SetClock {
+        ip: SocketAddr,
+        /// Minutes ahead of UTC of the printer's time zone.
+        #[arg(long, default_value = "0", allow_hyphen_values = true)]
+        utc_offset: i32,
+    },
+    /// Report the state of the print head, optionally printing a test pattern first.
+    Diagnose {
+        ip: SocketAddr,
+        /// Print a pattern showing failed head elements as streaks.
+        #[arg(long)]
+        test_label: bool,
+        #[arg(long, default_value = "51", help = "test label width in mm")]
+        width: f32,
+        #[arg(long, default_value = "51", help = "test label height in mm")]
+        height: f32,
+        /// Turn communications diagnostics on or off, printing received data as hex.
+        #[arg(long, value_name = "ON")]
+        communications: Option<bool>,
+    },
+    /// Run a battery of checks against a printer and report what it handles.
+    ///
+    /// Prints a label for each graphic encoding, native barcodes and text and the post-print
+    /// actions, unless `--no-print` is given. Exits with status 1 when a check failed.
+    HwTest {
+        ip: SocketAddr,
+        /// Only query the printer.
+        #[arg(long)]
+        no_print: bool,
+        #[arg(long, default_value = "51", help = "test label width in mm")]
+        width: f32,
+        #[arg(long, default_value = "25", help = "test label height in mm")]
+        height: f32,
+        #[arg(long, default_value = "30", help = "timeout per label in s")]
+        timeout: u64,
+    },
+    /// Save the settings of a printer to a file, to restore them onto a replacement.
+    Backup {
+        ip: SocketAddr,
+        /// Where to write the settings, as JSON.
+        file: PathBuf,
+    },
+    /// Put the settings saved by `backup` onto a printer, saving them across restarts.
+    Restore {
+        ip: SocketAddr,
+        file: PathBuf,
+        /// Restore onto another model than the settings were saved from.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check a label for problems, as the server does for every job.
+    ///
+    /// Exits with status 1 when errors are found, or any warnings with `--strict`.
+    Lint(LintArgs),
+    /// Print one label per row of a table, filling in an SVG template.
+    ///
+    /// Placeholders such as `{{name}}` in the template are replaced by the column of that name.
+    Batch(BatchArgs),
+    /// Print each file put into a folder, then move it into `done/` within the folder.
+    ///
+    /// SVG documents, images and ZPL commands (`.zpl`, `.prn`) are printed. Files are taken once
+    /// their size stops changing, those that fail to print are moved into `failed/`.
+    Watch {
+        dir: PathBuf,
+        #[arg(long, default_value = "1000", help = "poll interval in ms")]
+        interval: u64,
+        #[command(flatten)]
+        print: Args,
+    },
+    /// Generate shell completions.
+    Completions { shell: clap_complete::Shell },
+}
+
+/// How results are written to standard output.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Output {
+    #[default]
+    Text,
+    /// One JSON document per command, for scripts.
+    Json,
+}
+
+#[derive(clap::Args, Clone)]
+pub struct Args {
+    /// The printer's address, 192.168.1.39:9100 unless configured.
+    ip: Option<SocketAddr>,
+
+    /// A configuration file of the server, to print on one of its printers.
+    ///
+    /// The label's size, margins and stock and the printer's address and calibration are taken
+    /// from the configuration, unless given as flags.
+    #[arg(long, requires = "printer")]
+    config: Option<PathBuf>,
+
+    /// The printer of the configuration file to print on.
+    #[arg(long, requires = "config")]
+    printer: Option<String>,
+
+    #[arg(long = "image")]
+    image: Option<PathBuf>,
+
+    #[arg(long = "svg")]
+    svg: Option<PathBuf>,
+
+    /// Read an SVG document or ZPL commands from standard input.
+    ///
+    /// ZPL, starting with `^` or `~`, is sent as it is.
+    #[arg(long, conflicts_with_all = ["image", "svg"])]
+    stdin: bool,
+
+    #[arg(
+        long = "shrink-to-fit",
+        default_value = "false",
+        help = "shrink SVG content running past the edges of the document until it fits"
+    )]
+    shrink_to_fit: bool,
+
+    #[arg(long = "copies", default_value = "1")]
+    copies: NonZeroU32,
+
+    #[arg(long = "margin", help = "xy margin in mm [default: 5]")]
+    margin: Option<u32>,
+
+    #[arg(long = "width", help = "label width in mm [default: 51]")]
+    width: Option<f32>,
+
+    #[arg(long = "height", help = "label height in mm [default: 51]")]
+    height: Option<f32>,
+
+    #[arg(
+        long = "dpmm",
+        help = "print resolution in dots per mm (overrides printer autodetection)"
+    )]
+    dpmm: Option<u32>,
+
+    #[arg(
+        long = "compression",
+        value_enum,
+        help = "encoding of the image data [default: ascii-hex]"
+    )]
+    compression: Option<ImageCompression>,
+
+    #[arg(long = "output-zpl-only", default_value = "false")]
+    output_zpl_only: bool,
+
+    #[arg(
+        long = "preview",
+        help = "write a picture of the label to this PNG file instead of printing"
+    )]
+    preview: Option<PathBuf>,
+
+    #[arg(
+        long = "debug-layout",
+        default_value = "false",
+        help = "outline the area of each item in the preview"
+    )]
+    debug_layout: bool,
+}
+
+#[derive(clap::Args)]
+pub struct LintArgs {
+    /// An SVG or image file, or a job for the server's print API as JSON.
+    file: PathBuf,
+
+    #[arg(long = "margin", default_value = "5", help = "xy margin in mm")]
+    margin: f32,
+
+    #[arg(long = "width", default_value = "51", help = "label width in mm")]
+    width: f32,
+
+    #[arg(long = "height", default_value = "51", help = "label height in mm")]
+    height: f32,
+
+    #[arg(long = "dpmm", default_value = "8")]
+    dpmm: u32,
+
+    /// Fail on warnings as well.
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(clap::Args)]
+pub struct BatchArgs {
+    /// An SVG document with placeholders.
+    #[arg(long)]
+    template: PathBuf,
+
+    /// The rows, as CSV with a header line, or as a JSON array of objects if ending in `.json`.
+    #[arg(long)]
+    data: PathBuf,
+
+    /// The labels sent ahead of the one printing.
+    #[arg(long, default_value = "2")]
+    window: u32,
+
+    /// Seconds to wait for the printer to take the next label before giving up.
+    #[arg(long, default_value = "120")]
+    stall_timeout: u64,
+
+    /// The printer and label, with `--copies` of each row.
+    #[command(flatten)]
+    print: Args,
+}
+
+impl Args {
+    /// The printer and label to print on, as configured and then changed by the flags given.
+    pub fn profile(&self) -> anyhow::Result<PrinterProfile> {
+        let mut profile = match (&self.config, &self.printer) {
+            (Some(path), Some(printer)) => PrinterProfile::load(path, printer)?,
+            _ => PrinterProfile {
+                addr: SocketAddr::from(([192, 168, 1, 39], 9100)).into(),
+                dimensions: LabelDimensions {
+                    width: 51.0,
+                    height: 51.0,
+                    margin_left: 5.0,
+                    margin_right: 5.0,
+                    margin_top: 5.0,
+                    margin_bottom: 5.0,
+                },
+                stock: Default::default(),
+                calibration: None,
+                image_compression: Default::default(),
+                coordinates: Default::default(),
+            },
+        };
+
+        let dimensions = &mut profile.dimensions;
+        if let Some(margin) = self.margin {
+            let margin = margin as f32;
+            dimensions.margin_left = margin;
+            dimensions.margin_right = margin;
+            dimensions.margin_top = margin;
+            dimensions.margin_bottom = margin;
+        }
+        dimensions.width = self.width.unwrap_or(dimensions.width);
+        dimensions.height = self.height.unwrap_or(dimensions.height);
+
+        if let Some(ip) = self.ip {
+            profile.addr = ip.into();
+        }
+        profile.image_compression =
+            self.compression.unwrap_or(profile.image_compression);
+
+        Ok(profile)
+    }
+}
+
+pub async fn make_label(
+    args: &Args,
+    profile: &PrinterProfile,
+    dpmm_autodetect: Option<u32>,
+) -> anyhow::Result<CommandSequence> {
+    let label = match read_stdin(args).await? {
+        Some(Document::Zpl(code)) => return Ok(raw_commands(code)),
+        Some(Document::Svg(code)) => {
+            svg_label(code, args, profile, dpmm_autodetect)?
+        }
+        None => compose_label(args, profile, dpmm_autodetect).await?,
+    };
+    label.print(&print_options(args, profile))
+}
+
+/// A document read as text, told apart by its start.
+enum Document {
+    Svg(String),
+    Zpl(String),
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        match text.trim_start().starts_with(['^', '~']) {
+            true => Document::Zpl(text),
+            false => Document::Svg(text),
+        }
+    }
+}
+
+/// The document on standard input, if asked to read it.
+async fn read_stdin(args: &Args) -> anyhow::Result<Option<Document>> {
+    if !args.stdin {
+        return Ok(None);
+    }
+
+    let text = tokio::task::spawn_blocking(|| {
+        std::io::read_to_string(std::io::stdin())
+    })
+    .await??;
+    Ok(Some(Document::new(text)))
+}
+
+/// ZPL commands passed to the printer as they are.
+fn raw_commands(code: String) -> CommandSequence {
+    CommandSequence(vec![command::ZplCommand::Raw {
+        command: code,
+        response: command::ResponseSpec::None,
+    }])
+}
+
+fn print_options(args: &Args, profile: &PrinterProfile) -> label::PrintOptions {
+    label::PrintOptions {
+        copies: args.copies.get(),
+        compression: profile.image_compression,
+        calibration: profile.calibration.as_ref().map(|c| c.to_options()),
+        stock: profile.stock.to_options(),
+        transform: profile.coordinates,
+        ..Default::default()
+    }
+}
+
+/// Place the selected image or SVG on a label, within the margins.
+pub async fn compose_label(
+    args: &Args,
+    profile: &PrinterProfile,
+    dpmm_autodetect: Option<u32>,
+) -> anyhow::Result<Label> {
+    match read_stdin(args).await? {
+        Some(Document::Svg(code)) => {
+            return svg_label(code, args, profile, dpmm_autodetect)
+        }
+        Some(Document::Zpl(_)) => bail!("ZPL commands can only be printed"),
+        None => {}
+    }
+
+    if let Some(image) = &args.image {
+        let img = ::image::open(image).expect("Image file not found");
+        let mut label = blank_label(args, profile, dpmm_autodetect)?;
+        let (x, y, w, h) = content_area(profile);
+
+        label.content.push(LabelContent::Image {
+            img,
+            x,
+            y,
+            w,
+            h,
+            fit: Fit::Cover,
+        });
+        Ok(label)
+    } else if let Some(path) = &args.svg {
+        let code = tokio::fs::read_to_string(path)
+            .await
+            .expect("SVG file not found");
+
+        svg_label(code, args, profile, dpmm_autodetect)
+    } else {
+        bail!("No image/vector source selected");
+    }
+}
+
+/// Place an SVG document on a label, within the margins.
+fn svg_label(
+    code: String,
+    args: &Args,
+    profile: &PrinterProfile,
+    dpmm_autodetect: Option<u32>,
+) -> anyhow::Result<Label> {
+    let mut label = blank_label(args, profile, dpmm_autodetect)?;
+    let (x, y, w, h) = content_area(profile);
+
+    label.content.push(LabelContent::Svg {
+        code,
+        x,
+        y,
+        w,
+        h,
+        shrink: args.shrink_to_fit,
+    });
+
+    if args.shrink_to_fit {
+        let render = label::RenderOptions::default();
+        if let Some(factor) = label.content.last().and_then(|content| {
+            content
+                .shrink_factor(&render)
+                .filter(|factor| *factor < 1.0)
+        }) {
+            log::info!(
+                "Shrunk the SVG content to {:.0}% to fit",
+                factor * 100.0
+            );
+        }
+    }
+
+    Ok(label)
+}
+
+/// A label of the profile's size and margins, at the resolution given or detected.
+fn blank_label(
+    args: &Args,
+    profile: &PrinterProfile,
+    dpmm_autodetect: Option<u32>,
+) -> anyhow::Result<Label> {
+    let dimensions = &profile.dimensions;
+
+    let dpmm = if let Some(v) = args.dpmm {
+        v
+    } else if let Some(v) = dpmm_autodetect {
+        v
+    } else {
+        bail!("Can't ascertain resolution, please supply dpmm");
+    };
+
+    let mut label = Label::new(dimensions.width, dimensions.height, dpmm);
+    label.margins = dimensions.margins();
+    Ok(label)
+}
+
+/// The position and size of the area within the margins.
+fn content_area(profile: &PrinterProfile) -> (Length, Length, Length, Length) {
+    let dimensions = &profile.dimensions;
+    let content_width =
+        dimensions.width - dimensions.margin_left - dimensions.margin_right;
+    let content_height =
+        dimensions.height - dimensions.margin_top - dimensions.margin_bottom;
+
+    (
+        Length::mm(dimensions.margin_left),
+        Length::mm(dimensions.margin_top),
+        Length::mm(content_width),
+        Length::mm(content_height),
+    )
+}
+
+pub async fn run(cli: Cli) -> anyhow::Result<()> {
+    let output = cli.output;
+
+    match cli.command {
+        None => print(cli.print, output).await,
+        Some(Command::Print(args)) => print(args, output).await,
+        Some(Command::Status { ip }) => status(ip, output).await,
+        Some(Command::Discover {
+            network,
+            port,
+            timeout,
+            concurrency,
+        }) => discover(&network, port, timeout, concurrency, output).await,
+        Some(Command::Calibrate {
+            ip,
+            feed,
+            on_power_up,
+        }) => calibrate(ip, feed, on_power_up, output).await,
+        Some(Command::SetClock { ip, utc_offset }) => {
+            set_clock(ip, utc_offset, output).await
+        }
+        Some(Command::Diagnose {
+            ip,
+            test_label,
+            width,
+            height,
+            communications,
+        }) => {
+            let test_label = test_label.then_some((width, height));
+            diagnose(ip, test_label, communications, output).await
+        }
+        Some(Command::HwTest {
+            ip,
+            no_print,
+            width,
+            height,
+            timeout,
+        }) => {
+            let test_label = (!no_print).then_some((width, height));
+            hw_test(ip, test_label, timeout, output).await
+        }
+        Some(Command::Backup { ip, file }) => backup(ip, &file, output).await,
+        Some(Command::Restore { ip, file, force }) => {
+            restore(ip, &file, force, output).await
+        }
+        Some(Command::Lint(args)) => lint(args, output).await,
+        Some(Command::Batch(args)) => batch(args, output).await,
+        Some(Command::Watch {
+            dir,
+            interval,
+            print,
+        }) => watch(&dir, interval, print, output).await,
+        Some(Command::Completions { shell }) => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(
+                shell,
+                &mut command,
+                name,
+                &mut std::io::stdout(),
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn print(args: Args, output: Output) -> anyhow::Result<()> {
+    if let Some(path) = args.preview.clone() {
+        return preview(args, &path, output).await;
+    }
+
+    if args.output_zpl_only {
+        return run_output_zpl_only(args, output).await;
+    }
+
+    let mut profile = args.profile()?;
+    let ip = profile.addr.resolve().await?;
+    let mut device = ZplPrinter::with_address(ip).await?;
+    let config = device.request_device_status().await?;
+    let dpmm = config.identification.dpmm;
+
+    profile.image_compression = profile
+        .image_compression
+        .supported_by(&config.identification);
+    let label = make_label(&args, &profile, Some(dpmm)).await?;
+    let bytes = label.encoded(command::Separator::None).len();
+    device.send(label).await?;
+
+    match output {
+        Output::Text => println!("Sent {bytes} bytes to {ip}"),
+        Output::Json => println!(
+            "{}",
+            serde_json::json!({ "printer": ip, "dpmm": dpmm, "bytes": bytes })
+        ),
+    }
+
+    Ok(())
+}
+
+async fn batch(args: BatchArgs, output: Output) -> anyhow::Result<()> {
+    let template = tokio::fs::read_to_string(&args.template)
+        .await
+        .with_context(|| {
+            format!("Could not read {}", args.template.display())
+        })?;
+    let rows = read_rows(&args.data).await?;
+    let total = rows.len();
+
+    let documents = rows
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            util::svg::fill_template(&template, |name| row.get(name).cloned())
+                .with_context(|| format!("Row {} of the data", index + 1))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let progress = |done: usize| {
+        eprint!("\rPrinted {done} of {total} labels");
+        if done == total {
+            eprintln!();
+        }
+    };
+
+    let window = args.window;
+    let stall = std::time::Duration::from_secs(args.stall_timeout);
+    let args = args.print;
+    let mut profile = args.profile()?;
+
+    if args.output_zpl_only {
+        let options = print_options(&args, &profile);
+        let mut commands = CommandSequence(vec![]);
+        for (index, code) in documents.into_iter().enumerate() {
+            let label = svg_label(code, &args, &profile, None)?;
+            commands.append(label.print(&options)?);
+            progress(index + 1);
+        }
+
+        match output {
+            Output::Text => println!("{commands}"),
+            Output::Json => println!(
+                "{}",
+                serde_json::json!({ "labels": total, "zpl": commands.to_string() })
+            ),
+        }
+        return Ok(());
+    }
+
+    let ip = profile.addr.resolve().await?;
+    let mut device = ZplPrinter::with_address(ip).await?;
+    let config = device.request_device_status().await?;
+    let dpmm = config.identification.dpmm;
+
+    profile.image_compression = profile
+        .image_compression
+        .supported_by(&config.identification);
+    let labels = documents
+        .into_iter()
+        .map(|code| svg_label(code, &args, &profile, Some(dpmm)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let options = print_options(&args, &profile);
+    let sent = device::stream::print_batch(
+        &mut device,
+        labels,
+        options,
+        window,
+        stall,
+        |sent| progress(sent as usize),
+    )
+    .await?;
+
+    match output {
+        Output::Text => println!("Sent {sent} labels to {ip}"),
+        Output::Json => println!(
+            "{}",
+            serde_json::json!({ "printer": ip, "dpmm": dpmm, "labels": sent })
+        ),
+    }
+
+    Ok(())
+}
+
+/// Print the files put into a folder, forever.
+async fn watch(
+    dir: &Path,
+    interval: u64,
+    args: Args,
+    output: Output,
+) -> anyhow::Result<()> {
+    let done = dir.join("done");
+    let failed = dir.join("failed");
+    tokio::fs::create_dir_all(&done).await?;
+    tokio::fs::create_dir_all(&failed).await?;
+
+    // The size of each file when last seen, to tell when it is written completely.
+    let mut sizes = BTreeMap::new();
+    let mut poll =
+        tokio::time::interval(Duration::from_millis(interval.max(100)));
+
+    loop {
+        poll.tick().await;
+
+        let mut seen = BTreeMap::new();
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            let metadata = entry.metadata().await?;
+            if hidden || !metadata.is_file() {
+                continue;
+            }
+            seen.insert(path, metadata.len());
+        }
+
+        for (path, size) in &seen {
+            if sizes.get(path) != Some(size) {
+                continue;
+            }
+
+            let result = print_file(path, &args).await;
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let target = match &result {
+                Ok(_) => done.join(name),
+                Err(_) => failed.join(name),
+            };
+            tokio::fs::rename(path, &target).await?;
+
+            match (output, result) {
+                (Output::Text, Ok((ip, bytes))) => {
+                    println!("Sent {} ({bytes} bytes) to {ip}", path.display())
+                }
+                (Output::Text, Err(error)) => {
+                    eprintln!("Could not print {}: {error:#}", path.display())
+                }
+                (Output::Json, Ok((ip, bytes))) => println!(
+                    "{}",
+                    serde_json::json!({ "file": path, "printer": ip, "bytes": bytes })
+                ),
+                (Output::Json, Err(error)) => println!(
+                    "{}",
+                    serde_json::json!({ "file": path, "error": format!("{error:#}") })
+                ),
+            }
+        }
+
+        // Printed files are gone, others are compared on the next poll.
+        sizes = seen;
+    }
+}
+
+/// Print a file by its type, returning the printer and the bytes sent to it.
+async fn print_file(
+    path: &Path,
+    args: &Args,
+) -> anyhow::Result<(SocketAddr, usize)> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    let mut args = args.clone();
+    (args.image, args.svg, args.stdin) = (None, None, false);
+    let zpl = match extension.as_deref() {
+        Some("zpl" | "prn") => Some(tokio::fs::read_to_string(path).await?),
+        Some("svg") => {
+            args.svg = Some(path.to_path_buf());
+            None
+        }
+        _ => {
+            // Checked here, composing the label expects a readable image.
+            ::image::open(path)?;
+            args.image = Some(path.to_path_buf());
+            None
+        }
+    };
+
+    let mut profile = args.profile()?;
+    let ip = profile.addr.resolve().await?;
+    let mut device = ZplPrinter::with_address(ip).await?;
+
+    let commands = match zpl {
+        Some(code) => raw_commands(code),
+        None => {
+            let config = device.request_device_status().await?;
+            profile.image_compression = profile
+                .image_compression
+                .supported_by(&config.identification);
+            make_label(&args, &profile, Some(config.identification.dpmm))
+                .await?
+        }
+    };
+
+    let bytes = commands.encoded(command::Separator::None).len();
+    device.send(commands).await?;
+    Ok((ip, bytes))
+}
+
+/// The rows of a table by the names of their columns, from CSV with a header line or from a
+/// JSON array of objects.
+async fn read_rows(
+    path: &Path,
+) -> anyhow::Result<Vec<BTreeMap<String, String>>> {
+    let data = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Could not read {}", path.display()))?;
+
+    let json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    if !json {
+        let rows = csv::Reader::from_reader(data.as_slice())
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+        return Ok(rows);
+    }
+
+    let rows: Vec<serde_json::Map<String, serde_json::Value>> =
+        serde_json::from_slice(&data)?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        serde_json::Value::String(text) => text,
+                        serde_json::Value::Null => String::new(),
+                        value => value.to_string(),
+                    };
+                    (name, value)
+                })
+                .collect()
+        })
+        .collect())
+}
+
+pub async fn run_output_zpl_only(
+    args: Args,
+    output: Output,
+) -> anyhow::Result<()> {
+    let label = make_label(&args, &args.profile()?, None).await?;
+
+    match output {
+        Output::Text => println!("{}", label),
+        Output::Json => {
+            println!("{}", serde_json::json!({ "zpl": label.to_string() }))
+        }
+    }
+
+    Ok(())
+}
+
+async fn calibrate(
+    ip: SocketAddr,
+    feed_only: bool,
+    on_power_up: Option<command::MediaFeed>,
+    output: Output,
+) -> anyhow::Result<()> {
+    let mut device = ZplPrinter::with_address(ip).await?;
+    device
+        .send(CommandSequence::calibration(feed_only, on_power_up))
+        .await?;
+
+    let action = if feed_only {
+        "Fed a label"
+    } else {
+        "Calibrated"
+    };
+    match output {
+        Output::Text => println!("{action} at {ip}"),
+        Output::Json => println!(
+            "{}",
+            serde_json::json!({ "printer": ip, "feed_only": feed_only })
+        ),
+    }
+
+    Ok(())
+}
+
+async fn set_clock(
+    ip: SocketAddr,
+    utc_offset: i32,
+    output: Output,
+) -> anyhow::Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let time = command::ClockTime::from_unix(now as i64, utc_offset);
+
+    let mut device = ZplPrinter::with_address(ip).await?;
+    device.send(CommandSequence::set_clock(time)).await?;
+
+    let stamp = format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        time.year, time.month, time.day, time.hour, time.minute, time.second
+    );
+    match output {
+        Output::Text => println!("Set the clock of {ip} to {stamp}"),
+        Output::Json => {
+            println!("{}", serde_json::json!({ "printer": ip, "time": stamp }))
+        }
+    }
+
+    Ok(())
+}
+
+async fn diagnose(
+    ip: SocketAddr,
+    test_label: Option<(f32, f32)>,
+    communications: Option<bool>,
+    output: Output,
+) -> anyhow::Result<()> {
+    let mut device = ZplPrinter::with_address(ip).await?;
+
+    if let Some(on) = communications {
+        device.set_diagnostics(on).await?;
+    }
+
+    if let Some((width, height)) = test_label {
+        let status = device.request_device_status().await?;
+        let dpmm = status.identification.dpmm as f32;
+        let pattern = CommandSequence::head_test(
+            (width * dpmm) as u32,
+            (height * dpmm) as u32,
+        );
+        device.send(pattern).await?;
+    }
+
+    let report = device.request_head_diagnostic().await?;
+
+    match output {
+        Output::Text => {
+            for (name, value) in &report.entries {
+                println!("{name}: {value}");
+            }
+        }
+        Output::Json => println!("{}", serde_json::to_string(&report)?),
+    }
+
+    Ok(())
+}
+
+async fn hw_test(
+    ip: SocketAddr,
+    test_label: Option<(f32, f32)>,
+    timeout: u64,
+    output: Output,
+) -> anyhow::Result<()> {
+    // The size is only known in dots once the printer told its resolution.
+    let dpmm = ZplPrinter::with_address(ip)
+        .await?
+        .request_device_status()
+        .await?
+        .identification
+        .dpmm as f32;
+    let (width, height) = test_label.unwrap_or_default();
+    let options = device::hwtest::TestOptions {
+        width: (width * dpmm) as u32,
+        height: (height * dpmm) as u32,
+        print: test_label.is_some(),
+        timeout: Duration::from_secs(timeout),
+    };
+
+    let report = device::hwtest::run(ip, &options).await?;
+
+    match output {
+        Output::Text => {
+            println!(
+                "{} {} at {ip}, {} dpmm",
+                report.model, report.version, report.dpmm
+            );
+            if let Some(languages) = &report.languages {
+                println!("Languages: {languages}");
+            }
+            for check in &report.checks {
+                let outcome = match &check.outcome {
+                    device::hwtest::Outcome::Passed => "passed".to_string(),
+                    device::hwtest::Outcome::Failed(why) => {
+                        format!("failed: {why}")
+                    }
+                    device::hwtest::Outcome::Skipped(why) => {
+                        format!("skipped: {why}")
+                    }
+                };
+                println!("{}: {outcome} ({} ms)", check.name, check.millis);
+            }
+        }
+        Output::Json => println!("{}", serde_json::to_string(&report)?),
+    }
+
+    if report.failures() > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn backup(
+    ip: SocketAddr,
+    file: &std::path::Path,
+    output: Output,
+) -> anyhow::Result<()> {
+    let mut device = ZplPrinter::with_address(ip).await?;
+    let backup = device.download_configuration().await?;
+    if backup.settings.is_empty() {
+        bail!("{ip} did not report any settings");
+    }
+
+    std::fs::write(file, serde_json::to_string_pretty(&backup)?)?;
+
+    match output {
+        Output::Text => println!(
+            "Saved {} settings of {} {} at {ip} to {}",
+            backup.settings.len(),
+            backup.model,
+            backup.version,
+            file.display()
+        ),
+        Output::Json => println!(
+            "{}",
+            serde_json::json!({
+                "printer": ip,
+                "model": backup.model,
+                "version": backup.version,
+                "settings": backup.settings.len(),
+            })
+        ),
+    }
+
+    Ok(())
+}
+
+async fn restore(
+    ip: SocketAddr,
+    file: &std::path::Path,
+    force: bool,
+    output: Output,
+) -> anyhow::Result<()> {
+    let backup: command::ConfigurationBackup =
+        serde_json::from_str(&std::fs::read_to_string(file)?)?;
+
+    let mut device = ZplPrinter::with_address(ip).await?;
+    let identification =
+        device.request_device_status().await?.identification.clone();
+    if identification.model != backup.model && !force {
+        bail!(
+            "Settings are of a {}, {ip} is a {}, restore with --force",
+            backup.model,
+            identification.model
+        );
+    }
+    if identification.version != backup.version {
+        log::warn!(
+            "Settings were saved from firmware {}, {ip} runs {}",
+            backup.version,
+            identification.version
+        );
+    }
+
+    device
+        .restore_configuration(CommandSequence::restore_configuration(&backup))
+        .await?;
+
+    match output {
+        Output::Text => {
+            println!("Restored {} settings onto {ip}", backup.settings.len())
+        }
+        Output::Json => println!(
+            "{}",
+            serde_json::json!({
+                "printer": ip,
+                "settings": backup.settings.len(),
+            })
+        ),
+    }
+
+    Ok(())
+}
+
+async fn lint(args: LintArgs, output: Output) -> anyhow::Result<()> {
+    let LintArgs {
+        file,
+        margin,
+        width,
+        height,
+        dpmm,
+        strict,
+    } = args;
+
+    let (mut left, mut top) = (margin, margin);
+    let (mut right, mut bottom) = (margin, margin);
+    let (mut width, mut height) = (width, height);
+
+    let extension = file
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    let content = match extension.as_str() {
+        "svg" => Some(tokio::fs::read_to_string(&file).await?),
+        "json" => {
+            let job: serde_json::Value =
+                serde_json::from_str(&tokio::fs::read_to_string(&file).await?)?;
+
+            // A job may require the dimensions of the label it is printed on.
+            if let Some(dimensions) = job.get("dimensions") {
+                let mm = |key: &str| {
+                    dimensions
+                        .get(key)
+                        .and_then(|v| v.as_f64())
+                        .map(|v| v as f32)
+                };
+
+                width = mm("width").unwrap_or(width);
+                height = mm("height").unwrap_or(height);
+                left = mm("margin_left").unwrap_or(left);
+                right = mm("margin_right").unwrap_or(right);
+                top = mm("margin_top").unwrap_or(top);
+                bottom = mm("margin_bottom").unwrap_or(bottom);
+            }
+
+            let Some(code) = job.pointer("/svg/code").and_then(|v| v.as_str())
+            else {
+                bail!("Only jobs with SVG content can be checked");
+            };
+
+            Some(code.to_string())
+        }
+        _ => None,
+    };
+
+    let x = Length::mm(left);
+    let y = Length::mm(top);
+    let w = Length::mm((width - left - right).max(0.0));
+    let h = Length::mm((height - top - bottom).max(0.0));
+
+    let mut label = Label::new(width, height, dpmm);
+    label.content.push(match content {
+        Some(code) => LabelContent::Svg {
+            code,
+            x,
+            y,
+            w,
+            h,
+            shrink: false,
+        },
+        None => {
+            let img = ::image::open(&file).map_err(|err| {
+                anyhow::anyhow!("Unsupported file {}: {err}", file.display())
+            })?;
+            LabelContent::Image {
+                img,
+                x,
+                y,
+                w,
+                h,
+                fit: Fit::Cover,
+            }
+        }
+    });
+
+    let findings = lint::check(&label, &crate::util::svg::system_options());
+
+    match output {
+        Output::Text => {
+            for finding in &findings {
+                let severity = match finding.severity {
+                    lint::Severity::Warning => "warning",
+                    lint::Severity::Error => "error",
+                };
+                println!("{}: {severity}: {}", file.display(), finding.message);
+            }
+        }
+        Output::Json => println!(
+            "{}",
+            serde_json::json!({ "file": file, "findings": findings })
+        ),
+    }
+
+    let failed = findings
+        .iter()
+        .any(|finding| strict || finding.severity == lint::Severity::Error);
+
+    if failed {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn preview(
+    args: Args,
+    path: &std::path::Path,
+    output: Output,
+) -> anyhow::Result<()> {
+    let label = compose_label(&args, &args.profile()?, None).await?;
+    let mut image = label.preview()?.into_luma8();
+    let boxes = label.bounding_boxes();
+
+    if args.debug_layout {
+        for area in &boxes {
+            util::image::outline(&mut image, area);
+        }
+    }
+
+    image.save(path)?;
+
+    match output {
+        Output::Text => {
+            for (idx, area) in boxes.iter().enumerate() {
+                println!(
+                    "Item {}: {}x{} dots at {}, {}",
+                    idx + 1,
+                    area.width,
+                    area.height,
+                    area.x,
+                    area.y
+                );
+            }
+        }
+        Output::Json => println!(
+            "{}",
+            serde_json::json!({ "preview": path, "items": boxes })
+        ),
+    }
+
+    Ok(())
+}
+
+async fn status(ip: SocketAddr, output: Output) -> anyhow::Result<()> {
+    let mut device = ZplPrinter::with_address(ip).await?;
+    let status = device.request_device_status().await?;
+
+    match output {
+        Output::Text => {
+            let id = &status.identification;
+            println!("{} {} at {ip}", id.model, id.version);
+            println!("Resolution: {} dpmm", id.dpmm);
+            println!("Paper out: {}", status.string1.b_paper_out);
+            println!("Paused: {}", status.string1.c_pause);
+            println!("Head up: {}", status.string2.o_head_up);
+            println!("Ribbon out: {}", status.string2.p_ribbon_out);
+            println!("Labels remaining: {}", status.string2.u_labels_remaining);
+        }
+        Output::Json => println!("{}", serde_json::to_string(status)?),
+    }
+
+    Ok(())
+}
+
+async fn discover(
+    network: &str,
+    port: u16,
+    timeout: u64,
+    concurrency: usize,
+    output: Output,
+) -> anyhow::Result<()> {
+    let hosts = device::discover::network_hosts(network)
+        .map_err(|err| anyhow::anyhow!("Invalid network {network}: {err}"))?;
+    let timeout = Duration::from_millis(timeout);
+    let found = device::discover::scan(hosts, port, timeout, concurrency).await;
+
+    match output {
+        Output::Text => {
+            for printer in &found {
+                let id = &printer.identification;
+                println!(
+                    "{}\t{}\t{}\t{} dpmm",
+                    printer.addr, id.model, id.version, id.dpmm
+                );
+            }
+        }
+        Output::Json => println!("{}", serde_json::to_string(&found)?),
+    }
+
+    Ok(())
+}
@@ -1,11 +1,25 @@
+use crate::label::{QrErrorCorrection, QrMode};
 use crate::util::image::SerializedImage;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Write as _},
+    io,
+    time::Duration,
+};
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PostPrintAction {
     /// Present only, let user tear off.
     TearOff,
     /// Present and cut.
     Cut,
+    /// Peel the label off its liner, holding the next one until it is taken.
+    PeelOff,
+    /// Keep the labels on the liner and rewind them, for batches.
+    RewindBatch,
+    /// Hand each label to an applicator, which signals when it has taken it.
+    Applicator,
 }
 
 #[derive(Clone)]
@@ -16,7 +30,7 @@ pub enum MediaType {
     Transfer,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum BackfeedSequence {
     /// 100 percent backfeed after printing and cutting
     AfterPrinting,
@@ -34,6 +48,63 @@ pub enum BackfeedSequence {
     Percent(u8),
 }
 
+/// A date and time on the printer's real-time clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl ClockTime {
+    /// The civil time of a Unix timestamp, shifted by an offset from UTC in minutes.
+    pub fn from_unix(seconds: i64, utc_offset_minutes: i32) -> Self {
+        let seconds = seconds + i64::from(utc_offset_minutes) * 60;
+        let (days, time) =
+            (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+        // Days since 1970-01-01 to a date, after Howard Hinnant's `civil_from_days`.
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460
+            + day_of_era / 36_524
+            - day_of_era / 146_096)
+            / 365;
+        let day_of_year = day_of_era
+            - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        ClockTime {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+            hour: (time / 3600) as u32,
+            minute: (time / 60 % 60) as u32,
+            second: (time % 60) as u32,
+        }
+    }
+}
+
+/// Which time the printer stamps into clock fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockMode {
+    /// When the printer started processing the label.
+    StartTime,
+    /// When the label is printed, also for labels waiting in the printer's buffer.
+    TimeNow,
+}
+
 #[derive(Clone)]
 pub enum MediaTracking {
     /// Continuous media
@@ -59,15 +130,29 @@ pub enum MediaTracking {
     Autodetect,
 }
 
+/// What the printer does with the media when it is turned on or its head is closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum MediaFeed {
+    /// Feed to the first web after the sensor.
+    Feed,
+    /// Calibrate the sensors, and determine the label length.
+    Calibrate,
+    /// Determine the label length, without calibrating the sensors.
+    Length,
+    /// Do not move the media.
+    None,
+    /// Set the sensor levels without determining the label length.
+    ShortCalibration,
+}
+
 #[derive(Clone)]
 pub enum ZplCommand {
     Raw {
         command: String,
-        /// How many 'lines' / fields of text to read back after the sequence,
-        /// to purge what the printer would send from the buffer. If we want to
-        /// supply raw commands that send non-text delimited fields then we
-        /// should look into having a proper sequence encoded in this field.
-        response_lines: u32,
+        /// What the printer sends back, to be read such that it does not linger in the buffer.
+        response: ResponseSpec,
     },
     StartLabel,
     EndLabel,
@@ -118,16 +203,114 @@ pub enum ZplCommand {
     },
     RenderImage(crate::util::image::SerializedImage),
     FieldOrigin(u32, u32),
-    FieldData(String),
+    FieldData(FieldData),
+    /// End the current field.
+    FieldSeparator,
+    /// Draw the next field as a QR code, its data as made by [`FieldData::qr_code`].
     FieldModeQRCode {
         zoom: u32,
+        error_correction: QrErrorCorrection,
+    },
+    /// Draw the next field as an Aztec code.
+    FieldModeAztec {
+        zoom: u32,
+        /// Percent of the symbol given to error correction, the printer's default if zero.
+        error_correction: u32,
     },
+    /// Draw the next field as a PDF417 code, its modules as wide as set by
+    /// [`ZplCommand::BarcodeModuleWidth`].
+    FieldModePdf417 {
+        /// Height of each row in dots.
+        row_height: u32,
+        /// Level 0 to 8, each doubling the codewords of error correction.
+        security: u32,
+        /// Columns of data codewords per row, 1 to 30. The printer adds rows as needed.
+        columns: u32,
+    },
+    /// Draw the next field as a Code 128 barcode, its data naming subsets and function
+    /// characters with invocation codes such as `>8` for FNC1.
+    FieldModeCode128 {
+        /// Height of the bars in dots.
+        height: u32,
+        /// Whether to print the data in plain text below the bars.
+        interpretation: bool,
+    },
+    /// Draw the next field as a Data Matrix code of ECC 200.
+    FieldModeDataMatrix {
+        /// Size of each module in dots.
+        zoom: u32,
+        /// The character that starts escape sequences in the data, such as a FNC1 by `1`.
+        escape: char,
+    },
+    /// Width of the narrowest bar of the barcodes following, in dots.
+    BarcodeModuleWidth(u32),
+    /// Select the scalable font for the next field, in dots.
+    ScalableFont {
+        height: u32,
+        width: u32,
+    },
+    /// Replace clock placeholders starting with `%` in the next field's data by the time.
+    FieldClock,
+    /// Field data counting up or down by the increment on each label of a batch.
+    ///
+    /// Takes the place of the field data. With leading zeros, the number keeps the width of the
+    /// start value.
+    SerialNumber {
+        start: String,
+        increment: i64,
+        leading_zeros: bool,
+    },
+    /// Count up the digits of the preceding field data matching `d` in the mask, by the increment
+    /// aligned to the right of the mask, on each label of a batch.
+    SerializeField {
+        mask: String,
+        increment: String,
+    },
+    SetClock(ClockTime),
+    SetClockMode(ClockMode),
+    /// A box with borders of the given thickness, filled when as thick as it is small.
+    GraphicBox {
+        width: u32,
+        height: u32,
+        thickness: u32,
+    },
+    /// Calibrate the media and ribbon sensors, feeding a few labels.
+    CalibrateMedia,
+    /// Set what happens to the media on power up and when closing the head.
+    SetMediaFeedOnPowerUp {
+        power_up: MediaFeed,
+        head_close: MediaFeed,
+    },
+    /// Feed one blank label.
+    FeedLabel,
+    /// Stop printing after the current label, until resumed.
+    Pause,
+    Resume,
+    /// Drop all formats in the printer's buffer, including the rest of a batch.
+    CancelAll,
+    /// Print all received data as hex instead of interpreting it, until ended.
+    StartDiagnostics,
+    EndDiagnostics,
+    /// Report the head temperature, element test result and current settings.
+    RequestHeadDiagnostic,
     RequestHostIdentification,
     RequestHostRamStatus,
     RequestHostStatus,
+    /// Read a Set-Get-Do variable, answered by its value in double quotes.
+    GetVariable(String),
+    /// Set a Set-Get-Do variable, taking effect right away.
+    SetVariable(String, String),
+    /// Read every Set-Get-Do variable, with its value and the values it can take.
+    RequestAllVariables,
+    /// Report the configuration as on the printed configuration label.
+    RequestConfiguration,
+    /// List the objects stored in memory matching a pattern such as `E:*.GRF`.
+    ListObjects(String),
+    /// Delete the objects stored in memory matching a pattern, within a format.
+    DeleteObject(String),
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, Serialize)]
 pub struct HostStatus {
     pub string1: HostStatus1,
     pub string2: HostStatus2,
@@ -136,7 +319,7 @@ pub struct HostStatus {
     pub ram_status: HostRamStatus,
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, Serialize)]
 pub struct HostStatus1 {
     pub a_communication: u32,
     pub b_paper_out: bool,
@@ -151,7 +334,7 @@ pub struct HostStatus1 {
     pub l_temperature_high: bool,
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, Serialize)]
 pub struct HostStatus2 {
     pub m_settings: u8,
     pub o_head_up: bool,
@@ -165,20 +348,87 @@ pub struct HostStatus2 {
     pub w_number_graphics_stored: u32,
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, Serialize)]
 pub struct HostStatus3 {
     pub x_password: String,
     pub y_static_ram: bool,
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, Serialize)]
 pub struct HostRamStatus {
     pub total: u32,
     pub maximum_to_user: u64,
     pub available_to_user: u64,
 }
 
-#[derive(Clone, Default, Debug)]
+/// The response to `~HD`.
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct HeadDiagnostic {
+    /// In degrees Celsius.
+    pub head_temperature: Option<i32>,
+    pub ambient_temperature: Option<i32>,
+    /// Whether all elements of the head passed the last test, if one ran.
+    pub head_test_passed: Option<bool>,
+    /// Every line of the report, by its name.
+    pub entries: std::collections::BTreeMap<String, String>,
+}
+
+/// The settings of a printer, to be put onto another of the same model.
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+pub struct ConfigurationBackup {
+    pub model: String,
+    pub version: String,
+    /// The Set-Get-Do variables which can be set, by their name.
+    ///
+    /// Counters, sensor readings and actions such as `device.reset` are left out.
+    pub settings: std::collections::BTreeMap<String, String>,
+    /// The response to `^HH`, for reference only.
+    pub report: String,
+}
+
+/// The response to `^HW`, the objects stored on the printer's drives.
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct ObjectDirectory {
+    pub objects: Vec<StoredObject>,
+    /// What is left on the drives listed, in bytes.
+    pub free_bytes: Option<u64>,
+}
+
+/// A graphic, font or format downloaded to the printer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StoredObject {
+    /// The name with drive and extension, such as `R:LOGO.GRF`.
+    pub name: String,
+    pub size: u64,
+}
+
+/// The network interface of the printer in use, as read through Set-Get-Do variables.
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct NetworkSettings {
+    pub ip_address: Option<String>,
+    pub netmask: Option<String>,
+    pub gateway: Option<String>,
+    pub mac_address: Option<String>,
+    /// How the address was assigned, such as `dhcp` or `permanent`.
+    pub protocol: Option<String>,
+    /// Whether the interface is `wired` or `wlan`.
+    pub interface: Option<String>,
+    pub wlan: Option<WlanStatus>,
+    /// Every variable read, by its name, as answered by the printer.
+    pub entries: std::collections::BTreeMap<String, String>,
+}
+
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct WlanStatus {
+    pub essid: Option<String>,
+    pub associated: Option<bool>,
+    /// In percent.
+    pub signal_strength: Option<u32>,
+    /// In percent.
+    pub signal_quality: Option<u32>,
+}
+
+#[derive(Clone, Default, Debug, Serialize)]
 pub struct HostIdentification {
     pub model: String,
     pub version: String,
@@ -187,138 +437,558 @@ pub struct HostIdentification {
     pub memory: String,
 }
 
+/// What a printer sends back in response to a command.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResponseSpec {
+    /// Nothing.
+    #[default]
+    None,
+    /// Lines of text, each enclosed in `STX` and `ETX`.
+    Lines(u32),
+    /// Anything, until the printer has been silent for a while, such as Set-Get-Do JSON.
+    UntilIdle(Duration),
+    /// A number of bytes without delimiters, such as binary readbacks.
+    Bytes(usize),
+}
+
 impl ZplCommand {
+    /// What to expect in response to a command.
+    pub fn expected_response(&self) -> ResponseSpec {
+        match self {
+            ZplCommand::RequestHostIdentification => ResponseSpec::Lines(1),
+            ZplCommand::RequestHostRamStatus => ResponseSpec::Lines(1),
+            ZplCommand::RequestHostStatus => ResponseSpec::Lines(3),
+            ZplCommand::RequestHeadDiagnostic => ResponseSpec::Lines(1),
+            ZplCommand::ListObjects(_) => ResponseSpec::Lines(1),
+            ZplCommand::RequestConfiguration => ResponseSpec::Lines(1),
+            // The listing is neither quoted nor delimited, only followed by silence.
+            ZplCommand::RequestAllVariables => {
+                ResponseSpec::UntilIdle(Duration::from_secs(3))
+            }
+            ZplCommand::Raw { response, .. } => *response,
+            _ => ResponseSpec::None,
+        }
+    }
+
     /// How many lines of data to expect in response to a command
     pub fn expected_response_lines(&self) -> u32 {
-        match self {
-            ZplCommand::RequestHostIdentification => 1,
-            ZplCommand::RequestHostRamStatus => 1,
-            ZplCommand::RequestHostStatus => 3,
-            ZplCommand::Raw { response_lines, .. } => *response_lines,
+        match self.expected_response() {
+            ResponseSpec::Lines(lines) => lines,
             _ => 0,
         }
     }
 }
 
-impl From<ZplCommand> for String {
-    fn from(value: ZplCommand) -> Self {
-        match value {
-            ZplCommand::Raw { command: text, .. } => text,
+impl ZplCommand {
+    /// Write the command as the printer reads it, without allocating for it.
+    // Fixed parameters are passed as arguments to name them, see `^BQ`.
+    #[allow(clippy::write_literal)]
+    pub fn encode(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        let flag = |b: bool| if b { "Y" } else { "N" };
+
+        match self {
+            ZplCommand::Raw { command: text, .. } => out.write_str(text),
             // Removed:
             // -
             // - ^PON -> rotate by 180 degrees
-            ZplCommand::StartLabel => "^XA".to_string(),
-            ZplCommand::EndLabel => "^XZ".to_string(),
-            ZplCommand::PersistConfiguration => "^JUS".to_string(),
-            ZplCommand::SetDelimiter(delimiter) => format!("~CD{delimiter}"),
-            ZplCommand::SetControlCommandPrefix(prefix) => format!("~CT{prefix}"),
-            ZplCommand::SetFormatCommandPrefix(prefix) => format!("~CC{prefix}"),
-            ZplCommand::SetBackfeedSequence(sequence) => {
-                let value = match sequence {
-                    BackfeedSequence::AfterPrinting => "A".to_string(),
-                    BackfeedSequence::BeforePrinting => "B".to_string(),
-                    BackfeedSequence::Default => "N".to_string(),
-                    BackfeedSequence::Off => "O".to_string(),
-                    BackfeedSequence::Percent(p) => format!("{p}")
-                };
-
-                format!("~JS{value}")
+            ZplCommand::StartLabel => out.write_str("^XA"),
+            ZplCommand::EndLabel => out.write_str("^XZ"),
+            ZplCommand::PersistConfiguration => out.write_str("^JUS"),
+            ZplCommand::SetDelimiter(delimiter) => write!(out, "~CD{delimiter}"),
+            ZplCommand::SetControlCommandPrefix(prefix) => write!(out, "~CT{prefix}"),
+            ZplCommand::SetFormatCommandPrefix(prefix) => write!(out, "~CC{prefix}"),
+            ZplCommand::SetBackfeedSequence(sequence) => match sequence {
+                BackfeedSequence::AfterPrinting => out.write_str("~JSA"),
+                BackfeedSequence::BeforePrinting => out.write_str("~JSB"),
+                BackfeedSequence::Default => out.write_str("~JSN"),
+                BackfeedSequence::Off => out.write_str("~JSO"),
+                BackfeedSequence::Percent(p) => write!(out, "~JS{p}"),
             },
-            ZplCommand::SetMediaTracking(tracking) => {
-                let t = match tracking {
-                    MediaTracking::Continuous => "N".to_string(),
-                    MediaTracking::ContinuousVariableLength => "V".to_string(),
-                    MediaTracking::NonContinuousWebSensing => "W".to_string(),
-                    MediaTracking::NonContinuousMarked(offset) => format!("M,{offset}"),
-                    MediaTracking::Autodetect => "A".to_string(),
-                };
-
-                format!("^MN{t}")
-            }
-            ZplCommand::SetHalfDensity(d) => format!("^JM{}", if d { "B" } else { "A" }),
-            ZplCommand::SetDarkness(e) => format!("~SD{}", e),
-            ZplCommand::SetEncoding(e) => format!("^CI{}", e),
-            ZplCommand::SetHome(x, y) => format!("^LH{},{}", x, y),
-            ZplCommand::SetInverted(i) => {
-                format!("^LR{}", if i { "Y" } else { "N" })
-            }
+            ZplCommand::SetMediaTracking(tracking) => match tracking {
+                MediaTracking::Continuous => out.write_str("^MNN"),
+                MediaTracking::ContinuousVariableLength => out.write_str("^MNV"),
+                MediaTracking::NonContinuousWebSensing => out.write_str("^MNW"),
+                MediaTracking::NonContinuousMarked(offset) => write!(out, "^MNM,{offset}"),
+                MediaTracking::Autodetect => out.write_str("^MNA"),
+            },
+            ZplCommand::SetHalfDensity(d) => write!(out, "^JM{}", if *d { "B" } else { "A" }),
+            ZplCommand::SetDarkness(e) => write!(out, "~SD{}", e),
+            ZplCommand::SetEncoding(e) => write!(out, "^CI{}", e),
+            ZplCommand::SetHome(x, y) => write!(out, "^LH{},{}", x, y),
+            ZplCommand::SetInverted(i) => write!(out, "^LR{}", flag(*i)),
             ZplCommand::SetMediaType(t) => {
                 let t = match t {
                     MediaType::Direct => "D",
                     MediaType::Transfer => "T",
                 };
-                format!("^MT{}", t)
+                write!(out, "^MT{}", t)
             }
-            ZplCommand::SetSpeed { print, slew } => format!("^PR{},{}", print, slew),
-            ZplCommand::SetPrintWidth(w) => format!("^PW{:0>3}", w),
-            ZplCommand::SetLabelLength(l) => format!("^LL{:0>4}", l),
+            ZplCommand::SetSpeed { print, slew } => write!(out, "^PR{},{}", print, slew),
+            ZplCommand::SetPrintWidth(w) => write!(out, "^PW{:0>3}", w),
+            ZplCommand::SetLabelLength(l) => write!(out, "^LL{:0>4}", l),
             ZplCommand::SetPostPrintAction(a) => {
                 let c = match a {
                     PostPrintAction::TearOff => "T",
                     PostPrintAction::Cut => "C",
+                    PostPrintAction::PeelOff => "P",
+                    PostPrintAction::RewindBatch => "R",
+                    PostPrintAction::Applicator => "A",
                 };
 
-                format!("^MM{}", c)
+                write!(out, "^MM{}", c)
             }
-            ZplCommand::SetHorizontalShift(s) => format!("^LS{}", s),
-            ZplCommand::SetVerticalShift(s) => format!("^LT{}", s),
-            ZplCommand::SetTearOffPosition(p) => format!("~TA{:>+04}", p),
-            ZplCommand::SetMirrored(enabled) => {
-                let mirrored = match enabled {
-                    true => "Y",
-                    false => "N",
-                };
-
-                format!("^PM{}", mirrored)
-            },
+            ZplCommand::SetHorizontalShift(s) => write!(out, "^LS{}", s),
+            ZplCommand::SetVerticalShift(s) => write!(out, "^LT{}", s),
+            ZplCommand::SetTearOffPosition(p) => write!(out, "~TA{:>+04}", p),
+            ZplCommand::SetMirrored(enabled) => write!(out, "^PM{}", flag(*enabled)),
             ZplCommand::SetFlipped(enabled) => {
-                let flipped = match enabled {
-                    true => "I",
-                    false => "N",
-                };
-
-                format!("^PO{}", flipped)
-            },
-            ZplCommand::MoveOrigin(x, y) => format!("^FO{},{}", x, y),
+                write!(out, "^PO{}", if *enabled { "I" } else { "N" })
+            }
+            ZplCommand::MoveOrigin(x, y) => write!(out, "^FO{},{}", x, y),
             ZplCommand::PrintQuantity {
                 total,
                 pause_and_cut_after,
                 replicates_per_serial: replicates,
                 cut_only,
-            } => {
-                format!(
-                    "^PQ{},{},{},{}",
-                    total,
-                    pause_and_cut_after,
-                    replicates,
-                    if cut_only { "Y" } else { "N" }
-                )
-            }
+            } => write!(
+                out,
+                "^PQ{},{},{},{}",
+                total,
+                pause_and_cut_after,
+                replicates,
+                flag(*cut_only)
+            ),
             ZplCommand::RenderImage(SerializedImage {
                 byte_count,
                 total_field_count,
                 bytes_per_row,
                 data,
-            }) => format!("^GFA,{byte_count},{total_field_count},{bytes_per_row},{data}^FS"),
-            ZplCommand::FieldOrigin(x, y) => format!("^FO{x},{y}"),
-            ZplCommand::FieldData(data) => format!("^FD{data}"),
-            ZplCommand::FieldModeQRCode { zoom } => {
-                format!(
+                ..
+            }) => write!(
+                out,
+                "^GFA,{byte_count},{total_field_count},{bytes_per_row},{data}^FS"
+            ),
+            ZplCommand::FieldOrigin(x, y) => write!(out, "^FO{x},{y}"),
+            ZplCommand::FieldData(data) => match data.hex {
+                true => write!(out, "^FH{HEX_INDICATOR}^FD{}", data.text),
+                false => write!(out, "^FD{}", data.text),
+            },
+            ZplCommand::FieldSeparator => out.write_str("^FS"),
+            ZplCommand::ScalableFont { height, width } => {
+                write!(out, "^A0N,{height},{width}")
+            }
+            ZplCommand::FieldClock => out.write_str("^FC%"),
+            ZplCommand::SerialNumber {
+                start,
+                increment,
+                leading_zeros,
+            } => write!(out, "^SN{start},{increment},{}", flag(*leading_zeros)),
+            ZplCommand::SerializeField { mask, increment } => {
+                write!(out, "^SF{mask},{increment}")
+            }
+            ZplCommand::SetClock(time) => write!(
+                out,
+                "^ST{:02},{:02},{:04},{:02},{:02},{:02},M",
+                time.month, time.day, time.year, time.hour, time.minute, time.second
+            ),
+            ZplCommand::SetClockMode(mode) => match mode {
+                ClockMode::StartTime => out.write_str("^SLS"),
+                ClockMode::TimeNow => out.write_str("^SLT"),
+            },
+            ZplCommand::GraphicBox { width, height, thickness } => {
+                write!(out, "^GB{width},{height},{thickness}^FS")
+            }
+            ZplCommand::FieldModeQRCode {
+                zoom,
+                error_correction,
+            } => {
+                write!(
+                    out,
                     "^BQ{},{},{},{},{}",
-                    "N",  // Orientation
-                    2,    // Model
-                    zoom, // Magnification (1-100)
-                    "Q",  // Error correction
-                    7     // Mask
+                    "N",                       // Orientation
+                    2,                         // Model
+                    zoom,                      // Magnification (1-100)
+                    error_correction.letter(), // Error correction
+                    7                          // Mask
+                )
+            }
+            ZplCommand::FieldModeAztec {
+                zoom,
+                error_correction,
+            } => {
+                write!(
+                    out,
+                    "^B0{},{},{},{},{},{}",
+                    "N",              // Orientation
+                    zoom,             // Magnification (1-10)
+                    "N",              // Extended channel interpretation codes
+                    error_correction, // Error correction percentage
+                    "N",              // Menu symbol
+                    1                 // Symbols of a structured append
+                )
+            }
+            ZplCommand::FieldModePdf417 {
+                row_height,
+                security,
+                columns,
+            } => {
+                write!(
+                    out,
+                    "^B7{},{},{},{},{},{}",
+                    "N",        // Orientation
+                    row_height, // Height of each row
+                    security,   // Security level
+                    columns,    // Data columns
+                    "",         // Rows, as many as needed
+                    "N"         // Truncated
+                )
+            }
+            ZplCommand::FieldModeCode128 {
+                height,
+                interpretation,
+            } => {
+                write!(
+                    out,
+                    "^BC{},{},{},{},{},{}",
+                    "N",                                  // Orientation
+                    height,                               // Bar code height
+                    if *interpretation { "Y" } else { "N" }, // Interpretation line
+                    "N",                                  // Interpretation line above
+                    "N",                                  // UCC check digit
+                    "N"                                   // Mode, with invocation codes
+                )
+            }
+            ZplCommand::FieldModeDataMatrix { zoom, escape } => {
+                write!(
+                    out,
+                    "^BX{},{},{},{},{},{},{}",
+                    "N",    // Orientation
+                    zoom,   // Module size
+                    200,    // Quality level
+                    "",     // Columns, as many as needed
+                    "",     // Rows, as many as needed
+                    "",     // Format ID, unused with ECC 200
+                    escape  // Escape sequence control character
                 )
             }
-            ZplCommand::RequestHostIdentification => "~HI".to_string(),
-            ZplCommand::RequestHostRamStatus => "~HM".to_string(),
-            ZplCommand::RequestHostStatus => "~HS".to_string(),
+            ZplCommand::BarcodeModuleWidth(width) => write!(out, "^BY{width}"),
+            ZplCommand::CalibrateMedia => out.write_str("~JC"),
+            ZplCommand::SetMediaFeedOnPowerUp { power_up, head_close } => {
+                let feed = |feed: &MediaFeed| match feed {
+                    MediaFeed::Feed => "F",
+                    MediaFeed::Calibrate => "C",
+                    MediaFeed::Length => "L",
+                    MediaFeed::None => "N",
+                    MediaFeed::ShortCalibration => "S",
+                };
+
+                write!(out, "^MF{},{}", feed(power_up), feed(head_close))
+            }
+            ZplCommand::FeedLabel => out.write_str("~PH"),
+            ZplCommand::Pause => out.write_str("~PP"),
+            ZplCommand::Resume => out.write_str("~PS"),
+            ZplCommand::CancelAll => out.write_str("~JA"),
+            ZplCommand::StartDiagnostics => out.write_str("~JD"),
+            ZplCommand::EndDiagnostics => out.write_str("~JE"),
+            ZplCommand::RequestHeadDiagnostic => out.write_str("~HD"),
+            ZplCommand::RequestHostIdentification => out.write_str("~HI"),
+            ZplCommand::RequestHostRamStatus => out.write_str("~HM"),
+            ZplCommand::RequestHostStatus => out.write_str("~HS"),
+            ZplCommand::GetVariable(name) => {
+                write!(out, "! U1 getvar \"{name}\"\r\n")
+            }
+            ZplCommand::SetVariable(name, value) => {
+                write!(out, "! U1 setvar \"{name}\" \"{value}\"\r\n")
+            }
+            ZplCommand::RequestAllVariables => {
+                out.write_str("! U1 getvar \"allcv\"\r\n")
+            }
+            ZplCommand::RequestConfiguration => out.write_str("^HH"),
+            ZplCommand::ListObjects(pattern) => write!(out, "^HW{pattern}"),
+            ZplCommand::DeleteObject(pattern) => write!(out, "^ID{pattern}"),
+        }
+    }
+}
+
+impl fmt::Display for ZplCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.encode(f)
+    }
+}
+
+impl From<ZplCommand> for String {
+    fn from(value: ZplCommand) -> Self {
+        match value {
+            ZplCommand::Raw { command, .. } => command,
+            command => command.to_string(),
         }
     }
 }
 
+/// Starts an escaped character in field data, followed by its code in two hex digits (`^FH`).
+const HEX_INDICATOR: char = '_';
+
+/// Text for a field, with characters which would end the field or corrupt it escaped.
+///
+/// A caret or tilde starts a new command even within field data, and control characters are
+/// taken by some firmwares as such. These are written as hex codes, announced by `^FH`, along
+/// with the hex indicator itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldData {
+    text: String,
+    /// Whether the text holds hex codes.
+    hex: bool,
+}
+
+impl FieldData {
+    /// Escape the text, passing other characters to the printer as they are.
+    pub fn new(text: &str) -> Self {
+        if !text.contains(is_escaped) {
+            return FieldData {
+                text: text.to_string(),
+                hex: false,
+            };
+        }
+
+        let mut out = String::with_capacity(text.len() + 8);
+        for c in text.chars() {
+            if is_escaped(c) {
+                let _ = write!(out, "{HEX_INDICATOR}{:02X}", c as u32);
+            } else {
+                out.push(c);
+            }
+        }
+
+        FieldData {
+            text: out,
+            hex: true,
+        }
+    }
+
+    /// Escape the text and write characters beyond ASCII as the bytes of the character set.
+    ///
+    /// The bytes are hex codes as well, such that they pass through print servers and
+    /// firmwares which only handle ASCII intact. Fails for characters the set lacks.
+    pub fn encoded(text: &str, charset: CharacterSet) -> anyhow::Result<Self> {
+        let mut out = String::with_capacity(text.len() + 8);
+        let mut hex = false;
+
+        for c in text.chars() {
+            if is_escaped(c) {
+                hex = true;
+                let _ = write!(out, "{HEX_INDICATOR}{:02X}", c as u32);
+                continue;
+            }
+
+            if c.is_ascii() {
+                out.push(c);
+                continue;
+            }
+
+            hex = true;
+            match charset {
+                CharacterSet::Ascii => {
+                    anyhow::bail!("{c:?} can not be printed in ASCII")
+                }
+                CharacterSet::Cp1252 => {
+                    let Some(byte) = cp1252(c) else {
+                        anyhow::bail!("{c:?} is not in code page 1252");
+                    };
+                    let _ = write!(out, "{HEX_INDICATOR}{byte:02X}");
+                }
+                CharacterSet::Utf8 => {
+                    for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                        let _ = write!(out, "{HEX_INDICATOR}{byte:02X}");
+                    }
+                }
+            }
+        }
+
+        Ok(FieldData { text: out, hex })
+    }
+
+    /// The data of a `^BQ` QR code, naming its error correction and the mode of its content.
+    ///
+    /// The printer is told the mode rather than left to guess it, as it does with `A` for
+    /// automatic. Bytes are counted out, such that commas and other content pass as they are.
+    pub fn qr_code(
+        content: &str,
+        level: QrErrorCorrection,
+        charset: CharacterSet,
+    ) -> anyhow::Result<Self> {
+        let data = FieldData::encoded(content, charset)?;
+        let mode = match QrMode::of(content) {
+            QrMode::Numeric => "N".to_string(),
+            QrMode::Alphanumeric => "A".to_string(),
+            QrMode::Byte => {
+                // Each hex code of three characters is a single byte.
+                let escapes = data.text.matches(HEX_INDICATOR).count();
+                let bytes = data.text.len() - 2 * escapes;
+                if bytes > 9999 {
+                    anyhow::bail!("QR code of {bytes} bytes is too long");
+                }
+                format!("B{bytes:04}")
+            }
+        };
+
+        Ok(FieldData {
+            text: format!("{}M,{mode}{}", level.letter(), data.text),
+            hex: data.hex,
+        })
+    }
+
+    /// The text as written into the command, with escapes.
+    pub fn as_escaped(&self) -> &str {
+        &self.text
+    }
+}
+
+impl From<&str> for FieldData {
+    fn from(text: &str) -> Self {
+        FieldData::new(text)
+    }
+}
+
+impl From<String> for FieldData {
+    fn from(text: String) -> Self {
+        FieldData::new(&text)
+    }
+}
+
+/// Characters which would end or corrupt the field data, and the hex indicator itself.
+fn is_escaped(c: char) -> bool {
+    matches!(c, '^' | '~' | HEX_INDICATOR) || c.is_ascii_control()
+}
+
+/// How the printer reads the bytes of field data, as selected by `^CI`.
+///
+/// The characters are only printed if the font has glyphs for them, the built-in fonts lack
+/// most beyond Western European languages.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum CharacterSet {
+    /// ASCII only (`^CI0`), as the preamble selects.
+    Ascii,
+    /// Windows code page 1252 (`^CI27`), for firmware predating UTF-8.
+    Cp1252,
+    /// UTF-8 (`^CI28`), any character.
+    #[default]
+    Utf8,
+}
+
+impl CharacterSet {
+    /// The argument of `^CI` selecting the set.
+    pub fn code(self) -> usize {
+        match self {
+            CharacterSet::Ascii => 0,
+            CharacterSet::Cp1252 => 27,
+            CharacterSet::Utf8 => 28,
+        }
+    }
+
+    /// This set, if the printer's firmware supports it, or the closest one it does.
+    ///
+    /// Both code page 1252 and UTF-8 arrived with firmware x.14, like Base64 graphics.
+    pub fn supported_by(self, identification: &HostIdentification) -> Self {
+        let minor = identification
+            .version
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .split('.')
+            .nth(1)
+            .and_then(|minor| minor.parse::<u32>().ok());
+
+        match minor {
+            Some(minor) if minor < 14 => CharacterSet::Ascii,
+            _ => self,
+        }
+    }
+}
+
+/// The byte of a character in code page 1252, which matches Latin-1 except for 0x80 to 0x9F.
+fn cp1252(c: char) -> Option<u8> {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ',
+        '\u{8D}', 'Ž', '\u{8F}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—',
+        '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+    ];
+
+    match c as u32 {
+        0xA0..=0xFF => Some(c as u8),
+        _ => HIGH
+            .iter()
+            .position(|&high| high == c && !high.is_control())
+            .map(|idx| 0x80 + idx as u8),
+    }
+}
+
+/// What goes between the commands of an encoded sequence.
+///
+/// Printers need nothing between commands, and some firmwares take a line feed following `^FD`
+/// as part of the field data. Line feeds are only for people reading the commands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Separator {
+    #[default]
+    None,
+    Newline,
+}
+
+/// Encodes commands straight into a byte sink, such as a socket or the buffer for one.
+///
+/// Commands follow each other without separators, as the printer needs none.
+pub struct ZplWriter<W> {
+    inner: W,
+    /// The failure of the sink, which formatting can only report as such.
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> ZplWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ZplWriter { inner, error: None }
+    }
+
+    pub fn write_command(&mut self, command: &ZplCommand) -> io::Result<()> {
+        command.encode(self).map_err(|fmt::Error| self.take_error())
+    }
+
+    fn take_error(&mut self) -> io::Error {
+        self.error
+            .take()
+            .unwrap_or_else(|| io::Error::other("Command not encodable"))
+    }
+
+    /// Write the commands back to back, exactly the bytes the printer is to receive.
+    pub fn write_sequence(
+        &mut self,
+        commands: &CommandSequence,
+    ) -> io::Result<()> {
+        let encoded = commands.encode(self, Separator::None);
+        encoded.map_err(|fmt::Error| self.take_error())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: io::Write> fmt::Write for ZplWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
+
 pub fn total_expected_response_lines(commands: &[ZplCommand]) -> u32 {
     commands
         .iter()
@@ -330,12 +1000,74 @@ pub fn total_expected_response_lines(commands: &[ZplCommand]) -> u32 {
 fn test_raw() {
     let c = ZplCommand::Raw {
         command: "Abc".to_string(),
-        response_lines: 0,
+        response: ResponseSpec::None,
     };
 
     assert_eq!(String::from(c), "Abc");
 }
 
+#[test]
+fn test_writer() {
+    let c = CommandSequence(vec![
+        ZplCommand::StartLabel,
+        ZplCommand::FieldData("Abc".into()),
+        ZplCommand::GetVariable("device.languages".to_string()),
+    ]);
+
+    let mut writer = ZplWriter::new(Vec::new());
+    writer.write_sequence(&c).unwrap();
+    assert_eq!(
+        writer.into_inner(),
+        b"^XA^FDAbc! U1 getvar \"device.languages\"\r\n"
+    );
+    assert_eq!(c.0[1].to_string(), "^FDAbc");
+    assert_eq!(
+        c.encoded(Separator::None),
+        "^XA^FDAbc! U1 getvar \"device.languages\"\r\n"
+    );
+}
+
+#[test]
+fn test_field_data() {
+    let plain = ZplCommand::FieldData("Abc, 12".into());
+    assert_eq!(plain.to_string(), "^FDAbc, 12");
+
+    let escaped = ZplCommand::FieldData("a^XZ~JA_b\n".into());
+    assert_eq!(escaped.to_string(), "^FH_^FDa_5EXZ_7EJA_5Fb_0A");
+}
+
+#[test]
+fn test_character_sets() {
+    let utf8 = FieldData::encoded("Müller 東", CharacterSet::Utf8).unwrap();
+    assert_eq!(
+        ZplCommand::FieldData(utf8).to_string(),
+        "^FH_^FDM_C3_BCller _E6_9D_B1"
+    );
+
+    let cp1252 = FieldData::encoded("Müller €5", CharacterSet::Cp1252).unwrap();
+    assert_eq!(cp1252.as_escaped(), "M_FCller _805");
+    assert!(FieldData::encoded("東", CharacterSet::Cp1252).is_err());
+    assert!(FieldData::encoded("ü", CharacterSet::Ascii).is_err());
+
+    let plain = FieldData::encoded("A_1", CharacterSet::Ascii).unwrap();
+    assert_eq!(ZplCommand::FieldData(plain).to_string(), "^FH_^FDA_5F1");
+}
+
+#[test]
+fn qr_code_field_data() {
+    let qr = |content| {
+        FieldData::qr_code(content, QrErrorCorrection::High, CharacterSet::Utf8)
+            .unwrap()
+            .as_escaped()
+            .to_string()
+    };
+
+    assert_eq!(qr("0123"), "HM,N0123");
+    assert_eq!(qr("ZPL-1"), "HM,AZPL-1");
+    // Commas and escapes are part of the counted bytes.
+    assert_eq!(qr("a,b^ü"), "HM,B0006a,b_5E_C3_BC");
+}
+
 #[test]
 fn test_setup() {
     let c = CommandSequence(vec![
@@ -345,9 +1077,130 @@ fn test_setup() {
     assert_eq!(String::from(c), "^PW684\n^LL0384");
 }
 
+#[test]
+fn test_calibration() {
+    let c = CommandSequence::calibration(false, Some(MediaFeed::Length));
+    assert_eq!(String::from(c), "^XA\n^MFL,L\n^JUS\n^XZ\n~JC");
+
+    let c = CommandSequence::calibration(true, None);
+    assert_eq!(String::from(c), "~PH");
+}
+
+#[test]
+fn test_set_clock() {
+    // 2024-02-29 13:05:09 UTC, an hour ahead.
+    let time = ClockTime::from_unix(1_709_211_909, 60);
+    assert_eq!(
+        String::from(CommandSequence::set_clock(time)),
+        "^XA\n^ST02,29,2024,14,05,09,M\n^XZ"
+    );
+}
+
+#[test]
+fn test_head_test() {
+    let c = String::from(CommandSequence::head_test(40, 80));
+    assert!(c.starts_with("^XA\n^PW040\n^LL0080\n^FO0,10\n^GB40,20,20^FS"));
+    assert_eq!(c.matches("^GB8,8,8^FS").count(), 2);
+    assert!(c.ends_with("^XZ"));
+}
+
 pub struct CommandSequence(pub Vec<ZplCommand>);
 
 impl CommandSequence {
+    /// Calibrate the media sensors, or only feed a blank label, after changing the roll.
+    ///
+    /// What to do on power up and on closing the head is optionally changed as well, and kept
+    /// by the printer.
+    pub fn calibration(
+        feed_only: bool,
+        on_power_up: Option<MediaFeed>,
+    ) -> Self {
+        let mut commands = CommandSequence(vec![]);
+
+        if let Some(feed) = on_power_up {
+            commands.append(CommandSequence(vec![
+                ZplCommand::StartLabel,
+                ZplCommand::SetMediaFeedOnPowerUp {
+                    power_up: feed,
+                    head_close: feed,
+                },
+                ZplCommand::PersistConfiguration,
+                ZplCommand::EndLabel,
+            ]));
+        }
+
+        commands.push(match feed_only {
+            true => ZplCommand::FeedLabel,
+            false => ZplCommand::CalibrateMedia,
+        });
+
+        commands
+    }
+
+    /// Put the settings of a backup into effect, saving them across restarts.
+    pub fn restore_configuration(backup: &ConfigurationBackup) -> Self {
+        let mut commands = CommandSequence(
+            backup
+                .settings
+                .iter()
+                .map(|(name, value)| {
+                    ZplCommand::SetVariable(name.clone(), value.clone())
+                })
+                .collect(),
+        );
+
+        commands.append(CommandSequence(vec![
+            ZplCommand::StartLabel,
+            ZplCommand::PersistConfiguration,
+            ZplCommand::EndLabel,
+        ]));
+        commands
+    }
+
+    /// Set the printer's real-time clock, kept by the printer.
+    pub fn set_clock(time: ClockTime) -> Self {
+        CommandSequence(vec![
+            ZplCommand::StartLabel,
+            ZplCommand::SetClock(time),
+            ZplCommand::EndLabel,
+        ])
+    }
+
+    /// A label to spot failed head elements by.
+    ///
+    /// A solid band shows elements that no longer heat as white streaks, a row of squares below
+    /// shows elements stuck on as dark streaks through the gaps.
+    pub fn head_test(width: u32, height: u32) -> Self {
+        const SQUARE: u32 = 8;
+
+        let band = height / 4;
+        let mut commands = CommandSequence(vec![
+            ZplCommand::StartLabel,
+            ZplCommand::SetPrintWidth(width),
+            ZplCommand::SetLabelLength(height),
+            ZplCommand::MoveOrigin(0, band / 2),
+            ZplCommand::GraphicBox {
+                width,
+                height: band,
+                thickness: band,
+            },
+        ]);
+
+        let top = band * 2;
+        for x in (0..width.saturating_sub(SQUARE)).step_by(2 * SQUARE as usize)
+        {
+            commands.push(ZplCommand::MoveOrigin(x, top));
+            commands.push(ZplCommand::GraphicBox {
+                width: SQUARE,
+                height: SQUARE,
+                thickness: SQUARE,
+            });
+        }
+
+        commands.push(ZplCommand::EndLabel);
+        commands
+    }
+
     pub fn append(&mut self, mut c: Self) {
         self.0.append(&mut c.0)
     }
@@ -359,12 +1212,51 @@ impl CommandSequence {
     pub fn expected_response_lines(&self) -> u32 {
         total_expected_response_lines(&self.0)
     }
+
+    /// Dots printed black by the images among the commands.
+    pub fn inked_dots(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|command| match command {
+                ZplCommand::RenderImage(image) => image.inked_dots,
+                _ => 0,
+            })
+            .sum()
+    }
 }
 
+impl CommandSequence {
+    /// Write the commands, with the separator between each two.
+    pub fn encode(
+        &self,
+        out: &mut impl fmt::Write,
+        separator: Separator,
+    ) -> fmt::Result {
+        for (idx, command) in self.0.iter().enumerate() {
+            if idx > 0 && separator == Separator::Newline {
+                out.write_char('\n')?;
+            }
+
+            command.encode(out)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn encoded(&self, separator: Separator) -> String {
+        let mut out = String::new();
+        self.encode(&mut out, separator)
+            .expect("Writing to a string does not fail");
+        out
+    }
+}
+
+/// Shows the commands one per line, for reading them. Printers get [`Separator::None`].
 impl core::fmt::Display for CommandSequence {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for inner in &self.0 {
-            writeln!(f, "{}", String::from(inner.clone()))?;
+        self.encode(f, Separator::Newline)?;
+        if !self.0.is_empty() {
+            f.write_str("\n")?;
         }
 
         Ok(())
@@ -373,12 +1265,6 @@ impl core::fmt::Display for CommandSequence {
 
 impl From<CommandSequence> for String {
     fn from(sequence: CommandSequence) -> Self {
-        let commands = sequence.0;
-
-        commands
-            .into_iter()
-            .map(String::from)
-            .collect::<Vec<String>>()
-            .join("\n")
+        sequence.encoded(Separator::Newline)
     }
 }
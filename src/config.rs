@@ -0,0 +1,612 @@
+//! Labels and printers as defined in the server's configuration files.
+//!
+//! The definitions shared by the server and the CLI live here, such that both read a printer's
+//! label stock and calibration alike. Files are TOML or YAML by their extension and JSON
+//! otherwise, with `${VAR}` in any string replaced by the environment variable.
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::label::{self, CoordinateTransform, PrintCalibration};
+use crate::length::{Length, LengthUnit};
+use crate::util::image::ImageCompression;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LabelDimensions {
+    /// Width of the label in mm.
+    pub width: f32,
+    /// Height of the label in mm.
+    pub height: f32,
+    /// Space to reserve on the left of the label, as by printed direction.
+    pub margin_left: f32,
+    /// Space to reserve on the right of the label, as by printed direction.
+    pub margin_right: f32,
+    /// Space to reserve on top of the label, as by printed direction.
+    pub margin_top: f32,
+    /// Space to reserve at the bottom of the label, as by printed direction.
+    pub margin_bottom: f32,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum LabelStock {
+    /// Labels of the configured height, separated by gaps.
+    #[default]
+    Gapped,
+    /// Continuous media such as receipts, each label as long as its content.
+    ///
+    /// The height of the label bounds the space content is drawn into, only the inked part of it
+    /// is printed.
+    Continuous {
+        /// The longest label to print, in mm.
+        max_length: f32,
+    },
+    /// Labels of the configured height, found by black marks on the back.
+    Marked {
+        /// The distance in mm from the mark to where labels separate, in the printing
+        /// direction.
+        #[serde(default)]
+        offset: f32,
+    },
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct LabelCalibration {
+    /// Offset of the label towards the right (positive width) in mm.
+    pub home_x: f32,
+    /// Offset of the label downwards (positive height) in mm.
+    #[serde(default)]
+    pub home_y: f32,
+    /// Where labels stop for tearing off in mm, past the tear bar if positive.
+    #[serde(default)]
+    pub tear_off_offset: Option<f32>,
+    /// Steps of darkness added to what the printer or job sets, from -30 to 30.
+    #[serde(default)]
+    pub darkness_offset: i32,
+}
+
+impl LabelDimensions {
+    /// Compare sizes, approximately considering json serialization semantics on either side of
+    /// Rust or HTML / JS may not be exactly the same. That is, only compare like 5 digits which is
+    /// far too good for measurement anyhow.
+    /// The dimensions, in mm, converted to another unit.
+    pub fn in_units(&self, units: LengthUnit) -> Self {
+        self.map(|mm| units.from_mm(mm))
+    }
+
+    /// The dimensions, given in some unit, converted to mm.
+    pub fn in_millimetres(&self, units: LengthUnit) -> Self {
+        self.map(|value| units.to_mm(value))
+    }
+
+    fn map(&self, convert: impl Fn(f32) -> f32) -> Self {
+        LabelDimensions {
+            width: convert(self.width),
+            height: convert(self.height),
+            margin_left: convert(self.margin_left),
+            margin_right: convert(self.margin_right),
+            margin_top: convert(self.margin_top),
+            margin_bottom: convert(self.margin_bottom),
+        }
+    }
+
+    pub fn approx_cmp(&self, other: &Self) -> bool {
+        fn to_5digits(lhs: f32, rhs: f32) -> Option<core::cmp::Ordering> {
+            // Can underflow but that's fine. An 'eps' of 0.0 is just a very harsh requirement.
+            let eps = lhs.abs().max(rhs.abs()) * 2.0f32.powi(-16);
+            let diff = lhs - rhs;
+
+            if diff.abs() <= eps {
+                Some(core::cmp::Ordering::Equal)
+            } else if diff < 0.0 {
+                Some(core::cmp::Ordering::Less)
+            } else if diff > 0.0 {
+                Some(core::cmp::Ordering::Greater)
+            } else {
+                None
+            }
+        }
+
+        fn to_5digits_eq(lhs: f32, rhs: f32) -> bool {
+            matches!(to_5digits(lhs, rhs), Some(core::cmp::Ordering::Equal))
+        }
+
+        let LabelDimensions {
+            width,
+            height,
+            margin_left,
+            margin_right,
+            margin_top,
+            margin_bottom,
+        } = *self;
+
+        to_5digits_eq(width, other.width)
+            && to_5digits_eq(height, other.height)
+            && to_5digits_eq(margin_left, other.margin_left)
+            && to_5digits_eq(margin_right, other.margin_right)
+            && to_5digits_eq(margin_top, other.margin_top)
+            && to_5digits_eq(margin_bottom, other.margin_bottom)
+    }
+
+    /// The margins, as lengths for a [`label::Label`].
+    pub fn margins(&self) -> label::Margins {
+        label::Margins {
+            left: Length::mm(self.margin_left),
+            right: Length::mm(self.margin_right),
+            top: Length::mm(self.margin_top),
+            bottom: Length::mm(self.margin_bottom),
+        }
+    }
+}
+
+impl LabelStock {
+    /// The stock, as print options name it.
+    pub fn to_options(self) -> label::LabelStock {
+        match self {
+            LabelStock::Gapped => label::LabelStock::Gapped,
+            LabelStock::Continuous { max_length } => {
+                label::LabelStock::Continuous {
+                    max_length: Length::mm(max_length),
+                }
+            }
+            LabelStock::Marked { offset } => label::LabelStock::Marked {
+                offset: Length::mm(offset),
+            },
+        }
+    }
+}
+
+impl LabelCalibration {
+    pub fn to_options(&self) -> PrintCalibration {
+        PrintCalibration {
+            home_x: Length::mm(self.home_x),
+            home_y: Length::mm(self.home_y),
+            tear_off_offset: self.tear_off_offset.map(Length::mm),
+            darkness_offset: self.darkness_offset,
+        }
+    }
+}
+
+/// Where a printer listens, by address or by a name resolved on each connection.
+///
+/// Names are also resolved again while connected, as DHCP or DNS may move the printer.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct PrinterAddress {
+    host: String,
+    port: u16,
+}
+
+/// The files a configuration file includes, directly or through other files.
+///
+/// Relative paths are resolved against the directory of the file naming them. A file included
+/// twice would define all of its entries twice and is refused, which also rules out cycles.
+pub struct Includes {
+    pending: Vec<PathBuf>,
+    sources: Vec<PathBuf>,
+}
+
+/// A printer of a configuration file together with its label, for printing from the CLI.
+///
+/// Only what the CLI needs is read, all other settings are left to the server to check.
+pub struct PrinterProfile {
+    pub addr: PrinterAddress,
+    pub dimensions: LabelDimensions,
+    pub stock: LabelStock,
+    pub calibration: Option<LabelCalibration>,
+    pub image_compression: ImageCompression,
+    pub coordinates: CoordinateTransform,
+}
+
+#[derive(Deserialize)]
+struct ProfileFile {
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    labels: HashMap<String, ProfileLabel>,
+    #[serde(default)]
+    printers: HashMap<String, ProfilePrinter>,
+}
+
+#[derive(Deserialize)]
+struct ProfileLabel {
+    dimensions: LabelDimensions,
+    #[serde(default)]
+    stock: LabelStock,
+}
+
+#[derive(Deserialize)]
+struct ProfilePrinter {
+    label: String,
+    addr: PrinterAddress,
+    #[serde(default)]
+    calibration: Option<LabelCalibration>,
+    #[serde(default)]
+    image_compression: ImageCompression,
+    #[serde(default)]
+    coordinates: CoordinateTransform,
+}
+
+impl PrinterAddress {
+    /// The address, if the printer is configured by one rather than by a name.
+    pub fn fixed(&self) -> Option<SocketAddr> {
+        let ip: IpAddr = self.host.parse().ok()?;
+        Some(SocketAddr::new(ip, self.port))
+    }
+
+    pub fn is_name(&self) -> bool {
+        self.fixed().is_none()
+    }
+
+    /// Look up the address the printer can be reached at now.
+    #[cfg(feature = "device")]
+    pub async fn resolve(&self) -> std::io::Result<SocketAddr> {
+        if let Some(addr) = self.fixed() {
+            return Ok(addr);
+        }
+
+        tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await?
+            .next()
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("No address found for `{}`", self.host),
+                )
+            })
+    }
+}
+
+impl From<SocketAddr> for PrinterAddress {
+    fn from(addr: SocketAddr) -> Self {
+        PrinterAddress {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+        }
+    }
+}
+
+impl TryFrom<String> for PrinterAddress {
+    type Error = String;
+
+    fn try_from(addr: String) -> Result<Self, Self::Error> {
+        let Some((host, port)) = addr.rsplit_once(':') else {
+            return Err(format!("Address `{addr}` names no port"));
+        };
+
+        let port = port
+            .parse()
+            .map_err(|_| format!("Address `{addr}` has an invalid port"))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        if host.is_empty() {
+            return Err(format!("Address `{addr}` names no host"));
+        }
+
+        Ok(PrinterAddress {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl From<PrinterAddress> for String {
+    fn from(addr: PrinterAddress) -> String {
+        addr.to_string()
+    }
+}
+
+impl std::fmt::Display for PrinterAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl Includes {
+    /// Start from the main configuration file, which counts as included.
+    pub fn new(main: &Path) -> anyhow::Result<Self> {
+        Ok(Includes {
+            pending: vec![],
+            sources: vec![std::fs::canonicalize(main)?],
+        })
+    }
+
+    /// Queue the files named by the `include` of a file.
+    pub fn extend(&mut self, origin: &Path, include: Vec<PathBuf>) {
+        let base = origin.parent().unwrap_or(Path::new(""));
+        self.pending
+            .extend(include.into_iter().map(|path| base.join(path)));
+    }
+
+    /// The next file to read, unless all have been.
+    pub fn next_file(&mut self) -> anyhow::Result<Option<PathBuf>> {
+        let Some(next) = self.pending.pop() else {
+            return Ok(None);
+        };
+
+        let canonical = std::fs::canonicalize(&next).map_err(|err| {
+            anyhow::anyhow!("Failed to include {}: {err}", next.display())
+        })?;
+
+        if self.sources.contains(&canonical) {
+            anyhow::bail!("{} is included more than once", next.display());
+        }
+
+        self.sources.push(canonical);
+        Ok(Some(next))
+    }
+
+    /// All files read, the main file first, by their canonical paths.
+    pub fn into_sources(self) -> Vec<PathBuf> {
+        self.sources
+    }
+}
+
+/// Add the labels or printers of an included file, refusing to redefine any name.
+pub fn merge_defined<K: Eq + Hash + std::fmt::Display, V>(
+    defined: &mut HashMap<K, V>,
+    other: HashMap<K, V>,
+    kind: &str,
+    origin: &Path,
+) -> anyhow::Result<()> {
+    for (name, value) in other {
+        if defined.contains_key(&name) {
+            anyhow::bail!(
+                "{kind} `{name}` from {} is already defined",
+                origin.display()
+            );
+        }
+
+        defined.insert(name, value);
+    }
+
+    Ok(())
+}
+
+impl PrinterProfile {
+    /// Find a printer and its label in a configuration file or the files it includes.
+    pub fn load(path: &Path, printer: &str) -> anyhow::Result<Self> {
+        let ProfileFile {
+            include,
+            mut labels,
+            mut printers,
+        } = ProfileFile::read(path)?;
+        let mut includes = Includes::new(path)?;
+        includes.extend(path, include);
+
+        while let Some(next) = includes.next_file()? {
+            let file = ProfileFile::read(&next)?;
+            includes.extend(&next, file.include);
+            merge_defined(&mut labels, file.labels, "Label", &next)?;
+            merge_defined(&mut printers, file.printers, "Printer", &next)?;
+        }
+
+        let Some(found) = printers.remove(printer) else {
+            anyhow::bail!("No printer `{printer}` in {}", path.display());
+        };
+        let Some(label) = labels.remove(&found.label) else {
+            anyhow::bail!(
+                "No label `{}` for printer `{printer}` in {}",
+                found.label,
+                path.display()
+            );
+        };
+
+        Ok(PrinterProfile {
+            addr: found.addr,
+            dimensions: label.dimensions,
+            stock: label.stock,
+            calibration: found.calibration,
+            image_compression: found.image_compression,
+            coordinates: found.coordinates,
+        })
+    }
+}
+
+impl ProfileFile {
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!("Failed to read {}: {err}", path.display())
+        })?;
+
+        serde_json::from_value(parse(&data, path)?).map_err(|err| {
+            anyhow::anyhow!("Invalid configuration {}: {err}", path.display())
+        })
+    }
+}
+
+/// Parse the contents of one configuration file, as TOML or YAML by extension and JSON otherwise.
+pub fn parse(data: &str, path: &Path) -> anyhow::Result<serde_json::Value> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        anyhow::anyhow!("Invalid configuration {}: {err}", path.display())
+    };
+
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let mut value: serde_json::Value = match extension {
+        Some("toml") => toml::from_str(data).map_err(|e| invalid(&e))?,
+        Some("yaml" | "yml") => {
+            serde_yaml::from_str(data).map_err(|e| invalid(&e))?
+        }
+        _ => serde_json::from_str(data).map_err(|e| invalid(&e))?,
+    };
+
+    interpolate_strings(&mut value).map_err(|e| invalid(&e))?;
+    Ok(value)
+}
+
+/// Replace `${VAR}` in all string values by the environment variable `VAR`.
+fn interpolate_strings(value: &mut serde_json::Value) -> anyhow::Result<()> {
+    match value {
+        serde_json::Value::String(text) => {
+            *text = interpolate_env(text, |name| std::env::var(name).ok())?;
+        }
+        serde_json::Value::Array(values) => {
+            values.iter_mut().try_for_each(interpolate_strings)?
+        }
+        serde_json::Value::Object(values) => {
+            values.values_mut().try_for_each(interpolate_strings)?
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Substitute `${VAR}` references, with `$${` standing for a literal `${`.
+fn interpolate_env(
+    text: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        let tail = &rest[start..];
+
+        if let Some(escaped) = tail.strip_prefix("$${") {
+            output.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = tail.strip_prefix("${") {
+            let Some(end) = reference.find('}') else {
+                anyhow::bail!("Unterminated variable reference in `{text}`");
+            };
+
+            let name = &reference[..end];
+            let Some(value) = lookup(name) else {
+                anyhow::bail!("Environment variable `{name}` is not set");
+            };
+
+            output.push_str(&value);
+            rest = &reference[end + 1..];
+        } else {
+            output.push('$');
+            rest = &tail[1..];
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[test]
+fn validate_dimensions() {
+    let lhs = LabelDimensions {
+        width: 51.,
+        height: 51.,
+        margin_left: 1.,
+        margin_right: 1.,
+        margin_top: 1.,
+        margin_bottom: 1.,
+    };
+
+    assert!(lhs.approx_cmp(&lhs), "Does not equal itself?");
+
+    assert!(
+        !LabelDimensions { width: 50., ..lhs }.approx_cmp(&lhs),
+        "Does equal another"
+    );
+
+    assert!(
+        !LabelDimensions { height: 50., ..lhs }.approx_cmp(&lhs),
+        "Does equal another"
+    );
+}
+
+#[test]
+fn environment_interpolation() {
+    let lookup = |name: &str| (name == "HOST").then(|| "10.0.0.2".to_string());
+
+    assert_eq!(
+        interpolate_env("${HOST}:9100", lookup).unwrap(),
+        "10.0.0.2:9100"
+    );
+    assert_eq!(
+        interpolate_env("$${HOST} $5", lookup).unwrap(),
+        "${HOST} $5"
+    );
+    assert!(interpolate_env("${MISSING}", lookup).is_err());
+    assert!(interpolate_env("${HOST", lookup).is_err());
+}
+
+#[test]
+fn printer_profile_from_includes() {
+    let dir = std::env::temp_dir()
+        .join(format!("zpl-profile-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(
+        dir.join("labels.yaml"),
+        "labels:\n  receipt:\n    dimensions: { width: 80, height: 200, \
+         margin_left: 2, margin_right: 2, margin_top: 0, margin_bottom: 0 }\n    \
+         stock: { continuous: { max_length: 300 } }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("server.json"),
+        r#"{
+            "include": ["labels.yaml"],
+            "watch": true,
+            "printers": {
+                "till": {
+                    "label": "receipt",
+                    "addr": "till.local:9100",
+                    "calibration": { "home_x": 1.5 },
+                    "coordinates": { "flip_x": true }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let profile =
+        PrinterProfile::load(&dir.join("server.json"), "till").unwrap();
+    assert_eq!(profile.addr.to_string(), "till.local:9100");
+    assert_eq!(profile.dimensions.width, 80.0);
+    assert!(matches!(profile.stock, LabelStock::Continuous { .. }));
+    assert_eq!(profile.calibration.unwrap().home_x, 1.5);
+    assert!(profile.coordinates.flip_x);
+
+    assert!(PrinterProfile::load(&dir.join("server.json"), "other").is_err());
+
+    std::fs::write(
+        dir.join("again.json"),
+        r#"{ "include": ["server.json"], "printers": { "till": { "label": "receipt", "addr": "10.0.0.8:9100" } } }"#,
+    )
+    .unwrap();
+    let error = PrinterProfile::load(&dir.join("again.json"), "till")
+        .err()
+        .expect("Duplicate printer accepted");
+    assert!(error.to_string().contains("`till`"), "{error}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "device")]
+#[tokio::test]
+async fn printer_addresses() {
+    let by_ip = PrinterAddress::try_from("10.0.0.7:9100".to_string()).unwrap();
+    assert_eq!(by_ip.fixed(), Some("10.0.0.7:9100".parse().unwrap()));
+    assert_eq!(by_ip.resolve().await.unwrap(), by_ip.fixed().unwrap());
+
+    let v6 = PrinterAddress::try_from("[::1]:9100".to_string()).unwrap();
+    assert_eq!(v6.to_string(), "[::1]:9100");
+    assert!(!v6.is_name());
+    assert_eq!(PrinterAddress::from(v6.fixed().unwrap()), v6);
+
+    let by_name =
+        PrinterAddress::try_from("shelf.local:9100".to_string()).unwrap();
+    assert!(by_name.is_name());
+    assert_eq!(String::from(by_name), "shelf.local:9100");
+
+    assert!(PrinterAddress::try_from("shelf.local".to_string()).is_err());
+    assert!(PrinterAddress::try_from(":9100".to_string()).is_err());
+}
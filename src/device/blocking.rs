@@ -0,0 +1,200 @@
+//! Talk to the device without an async runtime.
+//!
+//! Mirrors [`super::ZplPrinter`] over a [`std::net::TcpStream`], for callers that can not run
+//! tokio. Requests and the parsing of responses are shared with the async client.
+use std::{
+    io::{self, Write as _},
+    net::{SocketAddr, TcpStream},
+    time::Instant,
+};
+
+use log::debug;
+
+use super::{
+    read, PrintError, PrintedOutcome, Response, WaitForPrinted,
+    HOST_STATUS_LINES,
+};
+use crate::command;
+
+pub struct ZplPrinter {
+    connection: TcpStream,
+    status: Option<command::HostStatus>,
+}
+
+impl ZplPrinter {
+    pub fn with_address(addr: SocketAddr) -> io::Result<Self> {
+        let socket = TcpStream::connect(addr)?;
+        Ok(Self::with_socket(socket))
+    }
+
+    pub fn with_socket(socket: TcpStream) -> Self {
+        Self {
+            connection: socket,
+            status: None,
+        }
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.connection
+    }
+
+    pub fn request_device_status(
+        &mut self,
+    ) -> io::Result<&command::HostStatus> {
+        let commands = super::status_request();
+        let lines = self
+            .send_with_response(&commands)?
+            .into_iter()
+            .flat_map(Response::into_lines)
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len() as u32, commands.expected_response_lines());
+        Ok(self.status.insert(super::parse_device_status(&lines)))
+    }
+
+    /// Poll the host status until the printer has no more labels to print, it is paused, or the
+    /// time is up.
+    ///
+    /// See [`super::ZplPrinter::wait_for_printed`].
+    pub fn wait_for_printed(
+        &mut self,
+        action: &command::PostPrintAction,
+        wait: &WaitForPrinted,
+        progress: impl FnMut(u32),
+    ) -> Result<PrintedOutcome, PrintError> {
+        let deadline = Instant::now() + wait.timeout;
+        let outcome = self.poll_until_printed(action, wait, deadline, progress);
+        self.connection.set_read_timeout(None)?;
+
+        match outcome {
+            Err(PrintError::Io(error))
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(PrintedOutcome::TimedOut {
+                    remaining: self
+                        .status
+                        .as_ref()
+                        .map_or(0, |status| status.string2.u_labels_remaining),
+                })
+            }
+            outcome => outcome,
+        }
+    }
+
+    /// The host status seen last, by any request.
+    pub fn status(&self) -> Option<&command::HostStatus> {
+        self.status.as_ref()
+    }
+
+    /// Poll with reads timing out at the deadline.
+    fn poll_until_printed(
+        &mut self,
+        action: &command::PostPrintAction,
+        wait: &WaitForPrinted,
+        deadline: Instant,
+        mut progress: impl FnMut(u32),
+    ) -> Result<PrintedOutcome, PrintError> {
+        let request = super::host_status_request();
+        let mut buf = vec![];
+        let mut remaining = None;
+
+        loop {
+            let left_to_wait =
+                deadline.saturating_duration_since(Instant::now());
+            if left_to_wait.is_zero() {
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
+            self.connection.set_read_timeout(Some(left_to_wait))?;
+
+            command::ZplWriter::new(&mut self.connection)
+                .write_sequence(&request)?;
+
+            let mut lines = vec![];
+            for _ in 0..HOST_STATUS_LINES {
+                let line =
+                    read::line_with_blocking(&mut buf, &mut self.connection)?;
+                lines.push(line.string);
+            }
+
+            let status = self.status.get_or_insert_with(Default::default);
+            super::parse_host_status(status, &lines);
+
+            if super::is_printed(status, action) {
+                return Ok(PrintedOutcome::Completed);
+            }
+
+            let left = status.string2.u_labels_remaining;
+            if remaining.replace(left) != Some(left) {
+                progress(left);
+            }
+
+            if let Some(error) = PrintError::of_status(status) {
+                return Err(error);
+            }
+            if status.string1.c_pause {
+                return Ok(PrintedOutcome::PrinterPaused);
+            }
+
+            let next = Instant::now() + wait.interval;
+            std::thread::sleep(
+                next.min(deadline).saturating_duration_since(Instant::now()),
+            );
+            if next >= deadline {
+                return Ok(PrintedOutcome::TimedOut { remaining: left });
+            }
+        }
+    }
+
+    pub fn send(
+        &mut self,
+        commands: command::CommandSequence,
+    ) -> io::Result<()> {
+        let responses = self.send_with_response(&commands)?;
+        for response in &responses {
+            debug!("{response:?}");
+        }
+
+        if responses.iter().all(|response| *response == Response::None) {
+            std::thread::sleep(std::time::Duration::from_millis(10_000));
+        }
+
+        Ok(())
+    }
+
+    /// Send commands, reading the response to each, see [`super::ZplPrinter::send_with_response`].
+    pub fn send_with_response(
+        &mut self,
+        commands: &command::CommandSequence,
+    ) -> io::Result<Vec<Response>> {
+        let mut writer = command::ZplWriter::new(vec![]);
+        let mut buf = vec![];
+        let mut responses = Vec::with_capacity(commands.0.len());
+
+        for cmd in &commands.0 {
+            writer.write_command(cmd)?;
+
+            let spec = cmd.expected_response();
+            if spec == command::ResponseSpec::None {
+                responses.push(Response::None);
+                continue;
+            }
+
+            self.connection.write_all(writer.get_ref())?;
+            writer.get_mut().clear();
+
+            let response = read::response_with_blocking(
+                &mut buf,
+                &mut self.connection,
+                spec,
+            )?;
+            responses.push(response);
+        }
+
+        self.connection.write_all(writer.get_ref())?;
+        self.connection.flush()?;
+        Ok(responses)
+    }
+}
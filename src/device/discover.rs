@@ -0,0 +1,106 @@
+//! Find printers on a network by probing their raw port.
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use super::ZplPrinter;
+use crate::command::HostIdentification;
+
+#[derive(Serialize)]
+pub struct Discovered {
+    pub addr: SocketAddr,
+    pub identification: HostIdentification,
+}
+
+/// All host addresses of an IPv4 network given as `address/prefix`.
+pub fn network_hosts(cidr: &str) -> Result<Vec<Ipv4Addr>, String> {
+    let (addr, prefix) = cidr
+        .split_once('/')
+        .ok_or_else(|| "expected `address/prefix`".to_string())?;
+    let addr: Ipv4Addr = addr.parse().map_err(|err| format!("{err}"))?;
+    let prefix: u32 = prefix.parse().map_err(|err| format!("{err}"))?;
+
+    if !(16..=32).contains(&prefix) {
+        return Err("prefix must be between 16 and 32".to_string());
+    }
+
+    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    let network = u32::from(addr) & mask;
+    let broadcast = network | !mask;
+
+    // Networks of one or two addresses have no network and broadcast address.
+    let hosts = if prefix >= 31 {
+        network..=broadcast
+    } else {
+        network + 1..=broadcast - 1
+    };
+
+    Ok(hosts.map(Ipv4Addr::from).collect())
+}
+
+/// Identify the printer at an address, if one answers in time.
+pub async fn probe(addr: SocketAddr, timeout: Duration) -> Option<Discovered> {
+    let identify = async {
+        let mut printer = ZplPrinter::with_address(addr).await.ok()?;
+        let status = printer.request_device_status().await.ok()?;
+        Some(status.identification.clone())
+    };
+
+    let identification =
+        tokio::time::timeout(timeout, identify).await.ok()??;
+    Some(Discovered {
+        addr,
+        identification,
+    })
+}
+
+/// Probe all hosts on a port, at most `concurrency` at once, in the order of their addresses.
+///
+/// Each probe holds a socket until it times out, which a large network would otherwise run out
+/// of.
+pub async fn scan(
+    hosts: Vec<Ipv4Addr>,
+    port: u16,
+    timeout: Duration,
+    concurrency: usize,
+) -> Vec<Discovered> {
+    let mut probes = tokio::task::JoinSet::new();
+    let mut found = vec![];
+    let mut hosts = hosts.into_iter();
+
+    loop {
+        while probes.len() < concurrency.max(1) {
+            let Some(host) = hosts.next() else { break };
+            probes.spawn(probe(SocketAddr::from((host, port)), timeout));
+        }
+
+        let Some(probed) = probes.join_next().await else {
+            break;
+        };
+        if let Ok(Some(discovered)) = probed {
+            found.push(discovered);
+        }
+    }
+
+    found.sort_by_key(|discovered| discovered.addr);
+    found
+}
+
+#[test]
+fn hosts_of_network() {
+    let hosts = network_hosts("192.168.1.77/30").unwrap();
+    assert_eq!(
+        hosts,
+        [
+            Ipv4Addr::new(192, 168, 1, 77),
+            Ipv4Addr::new(192, 168, 1, 78)
+        ]
+    );
+
+    assert_eq!(network_hosts("10.0.0.0/24").unwrap().len(), 254);
+    assert!(network_hosts("10.0.0.0/8").is_err());
+    assert!(network_hosts("10.0.0.0").is_err());
+}
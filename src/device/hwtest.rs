@@ -0,0 +1,435 @@
+//! A scripted battery of checks against a real printer, for verifying new models.
+//!
+//! Each check exercises one thing this crate relies on: the status queries, graphics in each
+//! encoding, native barcodes and text, and the post-print actions. The report tells which of
+//! them work, such that the quirks of a model can be recorded from it. The labels printed should
+//! still be looked at, a printer may well take a graphic it then prints garbled.
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+
+use super::{read, PrintError, PrintedOutcome, WaitForPrinted, ZplPrinter};
+use crate::{
+    command::{
+        CharacterSet, CommandSequence, FieldData, HostIdentification,
+        HostStatus, PostPrintAction, ZplCommand, ZplWriter,
+    },
+    label::QrErrorCorrection,
+    util::image::{ImageCompression, SerializedImage},
+};
+
+/// How long a printer that does not know a variable may take to stay silent about it.
+const VARIABLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct TestOptions {
+    /// The size of the labels printed, in dots.
+    pub width: u32,
+    pub height: u32,
+    /// Whether to print at all, or only run the queries.
+    pub print: bool,
+    /// How long each label may take to come out.
+    pub timeout: Duration,
+}
+
+#[derive(Serialize)]
+pub struct CompatibilityReport {
+    pub model: String,
+    pub version: String,
+    pub dpmm: u32,
+    /// The languages the printer reports to understand, if it answers `device.languages`.
+    pub languages: Option<String>,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    pub millis: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", content = "detail", rename_all = "snake_case")]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+impl CompatibilityReport {
+    pub fn failures(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, Outcome::Failed(_)))
+            .count()
+    }
+}
+
+/// Run all checks, failing only if the printer cannot be reached or identified.
+pub async fn run(
+    addr: SocketAddr,
+    options: &TestOptions,
+) -> std::io::Result<CompatibilityReport> {
+    let mut device = ZplPrinter::with_address(addr).await?;
+    let mut checks = vec![];
+
+    let started = Instant::now();
+    let status = device.request_device_status().await?.clone();
+    checks.push(Check::new("device_status", started, Outcome::Passed));
+
+    let started = Instant::now();
+    let outcome = match device.request_host_status().await {
+        Ok(status) => status_outcome(status),
+        Err(error) => Outcome::Failed(error.to_string()),
+    };
+    checks.push(Check::new("host_status", started, outcome));
+
+    let started = Instant::now();
+    let outcome = match tokio::time::timeout(
+        VARIABLE_TIMEOUT,
+        device.request_head_diagnostic(),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Outcome::Passed,
+        Ok(Err(error)) => Outcome::Failed(error.to_string()),
+        Err(_) => Outcome::Failed("No answer to `~HD`".to_string()),
+    };
+    checks.push(Check::new("head_diagnostic", started, outcome));
+
+    // On a connection of its own, an answer arriving late must not be taken for a status.
+    let started = Instant::now();
+    let languages = match languages(addr).await {
+        Ok(languages) => {
+            checks.push(Check::new("variables", started, Outcome::Passed));
+            languages
+        }
+        Err(error) => {
+            let outcome = Outcome::Failed(error.to_string());
+            checks.push(Check::new("variables", started, outcome));
+            None
+        }
+    };
+
+    let identification = &status.identification;
+    if options.print {
+        let previous = post_print_action(status.string2.r_print_mode);
+
+        for (name, content) in
+            printed_checks(identification, &languages, options)
+        {
+            let started = Instant::now();
+            let outcome = match content {
+                Ok((action, commands)) => {
+                    let label = test_label(options, action.clone(), commands);
+                    print(&mut device, label, &action, options.timeout).await
+                }
+                Err(reason) => Outcome::Skipped(reason),
+            };
+            checks.push(Check::new(name, started, outcome));
+        }
+
+        // The actions tried stay in effect, the one found before is put back.
+        if let Some(action) = previous {
+            let restore = CommandSequence(vec![
+                ZplCommand::StartLabel,
+                ZplCommand::SetPostPrintAction(action),
+                ZplCommand::EndLabel,
+            ]);
+            write(&mut device, &restore).await?;
+        }
+    }
+
+    Ok(CompatibilityReport {
+        model: identification.model.clone(),
+        version: identification.version.clone(),
+        dpmm: identification.dpmm,
+        languages,
+        checks,
+    })
+}
+
+type LabelCheck = Result<(PostPrintAction, Vec<ZplCommand>), String>;
+
+/// The labels to print, with the post-print action for each, or why it is skipped.
+fn printed_checks(
+    identification: &HostIdentification,
+    languages: &Option<String>,
+    options: &TestOptions,
+) -> Vec<(&'static str, LabelCheck)> {
+    let graphic = |compression| {
+        let image = test_graphic(options.width / 2, options.height / 2);
+        vec![
+            ZplCommand::FieldOrigin(options.width / 4, options.height / 4),
+            ZplCommand::RenderImage(SerializedImage::with_compression(
+                &image,
+                compression,
+            )),
+        ]
+    };
+
+    let z64 = match ImageCompression::Z64.supported_by(identification) {
+        ImageCompression::Z64 => {
+            Ok((PostPrintAction::TearOff, graphic(ImageCompression::Z64)))
+        }
+        _ => Err(format!("Firmware {} predates Z64", identification.version)),
+    };
+
+    let zpl = languages
+        .as_ref()
+        .is_none_or(|languages| languages.to_lowercase().contains("zpl"));
+    let text = if zpl {
+        Ok((
+            PostPrintAction::TearOff,
+            vec![
+                ZplCommand::FieldOrigin(10, 10),
+                ZplCommand::ScalableFont {
+                    height: 30,
+                    width: 30,
+                },
+                ZplCommand::FieldData("zpl hw-test".into()),
+                ZplCommand::FieldSeparator,
+            ],
+        ))
+    } else {
+        Err("The printer does not interpret ZPL fonts".to_string())
+    };
+
+    let small = || {
+        vec![
+            ZplCommand::MoveOrigin(10, 10),
+            ZplCommand::GraphicBox {
+                width: options.width.saturating_sub(20).max(1),
+                height: 20,
+                thickness: 4,
+            },
+        ]
+    };
+
+    vec![
+        (
+            "graphic_hex",
+            Ok((
+                PostPrintAction::TearOff,
+                graphic(ImageCompression::AsciiHex),
+            )),
+        ),
+        ("graphic_z64", z64),
+        (
+            "qr_code",
+            FieldData::qr_code(
+                "zpl hw-test",
+                QrErrorCorrection::Quartile,
+                CharacterSet::Ascii,
+            )
+            .map(|data| {
+                (
+                    PostPrintAction::TearOff,
+                    vec![
+                        ZplCommand::FieldOrigin(10, 10),
+                        ZplCommand::FieldModeQRCode {
+                            zoom: 4,
+                            error_correction: QrErrorCorrection::Quartile,
+                        },
+                        ZplCommand::FieldData(data),
+                        ZplCommand::FieldSeparator,
+                    ],
+                )
+            })
+            .map_err(|error| error.to_string()),
+        ),
+        ("native_text", text),
+        ("cut", Ok((PostPrintAction::Cut, small()))),
+        ("tear_off", Ok((PostPrintAction::TearOff, small()))),
+    ]
+}
+
+fn test_label(
+    options: &TestOptions,
+    action: PostPrintAction,
+    content: Vec<ZplCommand>,
+) -> CommandSequence {
+    let mut label = CommandSequence(vec![
+        ZplCommand::StartLabel,
+        ZplCommand::SetPrintWidth(options.width),
+        ZplCommand::SetLabelLength(options.height),
+        ZplCommand::SetPostPrintAction(action),
+    ]);
+    label.0.extend(content);
+    label.push(ZplCommand::EndLabel);
+    label
+}
+
+/// A frame with a diagonal, showing both skewed rows and a shifted origin.
+fn test_graphic(width: u32, height: u32) -> image::DynamicImage {
+    let (width, height) = (width.max(8), height.max(8));
+    let image = image::GrayImage::from_fn(width, height, |x, y| {
+        let border = x < 4 || y < 4 || x >= width - 4 || y >= height - 4;
+        let diagonal = (x * height).abs_diff(y * width) < 2 * width.max(height);
+        image::Luma([if border || diagonal { 0 } else { 255 }])
+    });
+
+    image::DynamicImage::ImageLuma8(image)
+}
+
+async fn print(
+    device: &mut ZplPrinter,
+    label: CommandSequence,
+    action: &PostPrintAction,
+    timeout: Duration,
+) -> Outcome {
+    if let Err(error) = write(device, &label).await {
+        return Outcome::Failed(error.to_string());
+    }
+
+    let wait = WaitForPrinted {
+        timeout,
+        ..Default::default()
+    };
+    match device.wait_for_printed(action, &wait, |_| {}).await {
+        Err(PrintError::Io(error)) => Outcome::Failed(error.to_string()),
+        // Printers that can not go on are reported with every problem of their status.
+        Ok(
+            PrintedOutcome::Completed
+            | PrintedOutcome::PrinterPaused
+            | PrintedOutcome::Cancelled,
+        )
+        | Err(_) => device.status().map_or(Outcome::Passed, status_outcome),
+        Ok(PrintedOutcome::TimedOut { .. }) => {
+            let problems =
+                device.status.as_ref().map(problems).unwrap_or_default();
+            Outcome::Failed(format!(
+                "Not printed within {timeout:?}{}",
+                if problems.is_empty() {
+                    String::new()
+                } else {
+                    format!(": {}", problems.join(", "))
+                }
+            ))
+        }
+    }
+}
+
+async fn write(
+    device: &mut ZplPrinter,
+    commands: &CommandSequence,
+) -> std::io::Result<()> {
+    let mut data = ZplWriter::new(vec![]);
+    data.write_sequence(commands)?;
+    device.connection.write_all(data.get_ref()).await?;
+    device.connection.flush().await
+}
+
+async fn languages(addr: SocketAddr) -> std::io::Result<Option<String>> {
+    let mut device = ZplPrinter::with_address(addr).await?;
+    device
+        .control(ZplCommand::GetVariable("device.languages".to_string()))
+        .await?;
+
+    let mut buf = vec![];
+    let value = tokio::time::timeout(
+        VARIABLE_TIMEOUT,
+        read::quoted_with(&mut buf, &mut device.connection),
+    )
+    .await
+    .map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "No answer to `getvar`",
+        )
+    })??;
+
+    Ok(Some(value).filter(|value| value != "?"))
+}
+
+fn status_outcome(status: &HostStatus) -> Outcome {
+    let problems = problems(status);
+    if problems.is_empty() {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(problems.join(", "))
+    }
+}
+
+/// What keeps the printer from printing, as reported in its host status.
+fn problems(status: &HostStatus) -> Vec<&'static str> {
+    let flags = [
+        (status.string1.b_paper_out, "paper out"),
+        (status.string1.c_pause, "paused"),
+        (status.string1.f_buffer_full, "buffer full"),
+        (status.string1.j_corrupt_ram, "corrupt RAM"),
+        (status.string1.k_temperature_low, "under temperature"),
+        (status.string1.l_temperature_high, "over temperature"),
+        (status.string2.o_head_up, "head up"),
+        (
+            status.string2.q_thermal_transfer_mode
+                && status.string2.p_ribbon_out,
+            "ribbon out",
+        ),
+    ];
+
+    flags
+        .into_iter()
+        .filter_map(|(set, problem)| set.then_some(problem))
+        .collect()
+}
+
+/// The post-print action of the print mode in a host status.
+fn post_print_action(mode: u32) -> Option<PostPrintAction> {
+    match mode {
+        0 => Some(PostPrintAction::RewindBatch),
+        1 => Some(PostPrintAction::PeelOff),
+        2 => Some(PostPrintAction::TearOff),
+        3 => Some(PostPrintAction::Cut),
+        4 => Some(PostPrintAction::Applicator),
+        _ => None,
+    }
+}
+
+impl Check {
+    fn new(name: &'static str, started: Instant, outcome: Outcome) -> Self {
+        Check {
+            name,
+            outcome,
+            millis: started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+#[test]
+fn skipped_without_support() {
+    let identification = HostIdentification {
+        version: "V60.13.0".to_string(),
+        ..Default::default()
+    };
+    let options = TestOptions {
+        width: 400,
+        height: 200,
+        print: true,
+        timeout: Duration::from_secs(1),
+    };
+
+    let checks = printed_checks(
+        &identification,
+        &Some("line_print".to_string()),
+        &options,
+    );
+    let skipped: Vec<_> = checks
+        .iter()
+        .filter(|(_, check)| check.is_err())
+        .map(|(name, _)| *name)
+        .collect();
+    assert_eq!(skipped, ["graphic_z64", "native_text"]);
+
+    let mut status = HostStatus::default();
+    status.string2.o_head_up = true;
+    assert_eq!(
+        status_outcome(&status),
+        Outcome::Failed("head up".to_string())
+    );
+}
@@ -0,0 +1,27 @@
+//! Failures of the printer or the network, made up on a working connection to rehearse how they
+//! are handled.
+//!
+//! An [`Interceptor`] set on a [`ZplPrinter`](super::ZplPrinter) sees the bytes it sends and
+//! receives, after they passed the connection, and may fail them as the connection would or change
+//! what was received. It is asked before connecting again after failures, too.
+use tokio::io;
+
+/// Alters the traffic of a connection.
+pub trait Interceptor: Send + Sync {
+    /// Before connecting again, failing the attempt with an error.
+    fn connect(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Bytes that were written, failing the write with an error although they are out.
+    fn sent(&self, bytes: &[u8]) -> io::Result<()> {
+        let _ = bytes;
+        Ok(())
+    }
+
+    /// Bytes that were read, changed in place or failing the read with an error.
+    fn received(&self, bytes: &mut [u8]) -> io::Result<()> {
+        let _ = bytes;
+        Ok(())
+    }
+}
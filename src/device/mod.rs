@@ -1,18 +1,131 @@
 /// Talk to the device.
 use crate::command;
 use log::debug;
+use quick_error::quick_error;
 use tokio::{
     self,
     io::{self, AsyncWriteExt},
 };
 
+pub mod blocking;
+pub mod discover;
+pub mod hwtest;
+pub mod intercept;
 mod read;
+pub mod retry;
+pub mod stream;
+pub mod tap;
+
+/// The lines in response to `~HS`.
+const HOST_STATUS_LINES: usize = 3;
+
+/// What a printer sent back in response to a command, see [`command::ResponseSpec`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Response {
+    #[default]
+    None,
+    /// The text of each line, without `STX` and `ETX`.
+    Lines(Vec<Vec<u8>>),
+    Bytes(Vec<u8>),
+}
+
+impl Response {
+    /// The lines of text, none for other responses.
+    pub fn into_lines(self) -> Vec<Vec<u8>> {
+        match self {
+            Response::Lines(lines) => lines,
+            Response::None | Response::Bytes(_) => vec![],
+        }
+    }
+}
+
+/// How to poll for labels to be printed, see [`ZplPrinter::wait_for_printed`].
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct WaitForPrinted {
+    /// The time between polls.
+    pub interval: std::time::Duration,
+    /// How long to wait at most, in all.
+    pub timeout: std::time::Duration,
+}
+
+quick_error! {
+    /// What keeps labels from being printed, see [`ZplPrinter::wait_for_printed`].
+    #[derive(Debug)]
+    pub enum PrintError {
+        Io(err: io::Error) {
+            from()
+            display("{err}")
+            source(err)
+        }
+        MediaOut {
+            display("Out of media, reload the printer")
+        }
+        RibbonOut {
+            display("Out of ribbon, replace it")
+        }
+        HeadOpen {
+            display("The print head is open, close it")
+        }
+    }
+}
+
+impl PrintError {
+    /// The condition of a host status keeping the printer from printing, if any.
+    ///
+    /// Printers pause on these, so they are told apart from a pause alone. The ribbon counts
+    /// only in thermal transfer mode, where it is used.
+    pub fn of_status(status: &command::HostStatus) -> Option<Self> {
+        if status.string1.b_paper_out {
+            Some(PrintError::MediaOut)
+        } else if status.string2.q_thermal_transfer_mode
+            && status.string2.p_ribbon_out
+        {
+            Some(PrintError::RibbonOut)
+        } else if status.string2.o_head_up {
+            Some(PrintError::HeadOpen)
+        } else {
+            None
+        }
+    }
+}
+
+/// How waiting for labels to be printed ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrintedOutcome {
+    Completed,
+    /// The time was up, with labels left as last seen.
+    TimedOut {
+        remaining: u32,
+    },
+    /// The printer is paused with labels left, which it prints only once resumed.
+    PrinterPaused,
+    /// Everything left was cancelled by an interjection, see [`ZplPrinter::interjections`].
+    Cancelled,
+}
+
+impl Default for WaitForPrinted {
+    fn default() -> Self {
+        WaitForPrinted {
+            interval: std::time::Duration::from_millis(500),
+            timeout: std::time::Duration::from_secs(120),
+        }
+    }
+}
 
 pub struct ZplPrinter {
-    connection: tokio::net::TcpStream,
+    connection: tap::Tapped<tokio::net::TcpStream>,
     status: Option<command::HostStatus>,
+    /// Where to connect again after failures, if known, looked up afresh each time.
+    host: Option<String>,
+    retry: retry::RetryPolicy,
+    /// Device controls to send in between requests, see [`Self::interjections`].
+    interjections: Option<tokio::sync::mpsc::Receiver<command::ZplCommand>>,
 }
 
+/// Whether a device control was sent in between that cancelled printing.
+type Cancelled = bool;
+
 impl ZplPrinter {
     pub async fn with_address(addr: std::net::SocketAddr) -> io::Result<Self> {
         let socket = tokio::net::TcpStream::connect(addr).await?;
@@ -21,149 +134,763 @@ impl ZplPrinter {
 
     pub async fn with_socket(socket: tokio::net::TcpStream) -> Self {
         Self {
-            connection: socket,
+            host: socket.peer_addr().ok().map(|addr| addr.to_string()),
+            connection: tap::Tapped::new(socket),
             status: None,
+            retry: retry::RetryPolicy::default(),
+            interjections: None,
         }
     }
 
+    /// Pause, resume or cancel while the connection is in use, such as waiting for labels.
+    ///
+    /// Commands sent are written between the requests of whatever is under way, right away while
+    /// waiting for labels or after commands were sent. A cancel ends waiting for labels with
+    /// [`PrintedOutcome::Cancelled`]. Replaces the sender of an earlier call.
+    pub fn interjections(
+        &mut self,
+    ) -> tokio::sync::mpsc::Sender<command::ZplCommand> {
+        let (send, recv) = tokio::sync::mpsc::channel(4);
+        self.interjections = Some(recv);
+        send
+    }
+
     pub fn stream(&self) -> &tokio::net::TcpStream {
-        &self.connection
+        self.connection.get_ref()
+    }
+
+    /// Copy every byte sent and received from now on to a tap, or stop with `None`.
+    pub fn set_tap(&mut self, tap: Option<std::sync::Arc<dyn tap::Tap>>) {
+        self.connection.tap = tap;
+    }
+
+    /// Pass all traffic from now on through an interceptor, or stop with `None`.
+    pub fn set_interceptor(
+        &mut self,
+        interceptor: Option<std::sync::Arc<dyn intercept::Interceptor>>,
+    ) {
+        self.connection.interceptor = interceptor;
+    }
+
+    /// Try failed requests again on a fresh connection, see [`retry`].
+    ///
+    /// Commands are sent again only if none of them went out before the failure, lest labels
+    /// print twice.
+    pub fn set_retry_policy(&mut self, policy: retry::RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Connect again to `host`, such as `printer.local:9100`, rather than to the address
+    /// connected to first, for printers that DHCP or DNS may move.
+    pub fn set_reconnect_host(&mut self, host: impl Into<String>) {
+        self.host = Some(host.into());
     }
 
     pub async fn request_device_status(
         &mut self,
     ) -> std::io::Result<&command::HostStatus> {
-        let commands = command::CommandSequence(vec![
-            command::ZplCommand::RequestHostIdentification,
-            command::ZplCommand::RequestHostStatus,
-            command::ZplCommand::RequestHostRamStatus,
-        ]);
+        let commands = status_request();
+        let mut failures = 0;
+        let responses = loop {
+            match self.send_with_response(&commands).await {
+                Ok(responses) => break responses,
+                Err(error) => self.reconnect(&mut failures, error).await?,
+            }
+        };
 
-        let (mut rx, mut tx) = tokio::io::split(&mut self.connection);
+        let lines = responses
+            .into_iter()
+            .flat_map(Response::into_lines)
+            .collect::<Vec<_>>();
 
-        let mut lines = vec![];
-        let mut buf = vec![];
-        let total_expected_response_lines = commands.expected_response_lines();
+        assert_eq!(lines.len() as u32, commands.expected_response_lines());
+        Ok(self.status.insert(parse_device_status(&lines)))
+    }
+
+    /// Poll the host status until the printer has no more labels to print, it is paused, or the
+    /// time is up.
+    ///
+    /// See [`is_printed`] for how labels held by the printer count. `progress` is told the
+    /// labels remaining whenever they change. Polls failing for the connection are tried again
+    /// as the retry policy allows. The status last seen is kept, see [`Self::status`].
+    ///
+    /// Fails as soon as the printer reports a condition it can not print in until someone sees
+    /// to it, see [`PrintError::of_status`].
+    pub async fn wait_for_printed(
+        &mut self,
+        action: &command::PostPrintAction,
+        wait: &WaitForPrinted,
+        mut progress: impl FnMut(u32),
+    ) -> Result<PrintedOutcome, PrintError> {
+        let deadline = tokio::time::Instant::now() + wait.timeout;
+        let mut remaining = None;
+
+        loop {
+            let poll = self.request_host_status();
+            let Ok(status) = tokio::time::timeout_at(deadline, poll).await
+            else {
+                return Ok(PrintedOutcome::TimedOut {
+                    remaining: self.labels_remaining(),
+                });
+            };
+
+            let status = status?;
+            if is_printed(status, action) {
+                return Ok(PrintedOutcome::Completed);
+            }
+
+            let left = status.string2.u_labels_remaining;
+            if remaining.replace(left) != Some(left) {
+                progress(left);
+            }
+
+            if let Some(error) = PrintError::of_status(status) {
+                return Err(error);
+            }
+            if status.string1.c_pause {
+                return Ok(PrintedOutcome::PrinterPaused);
+            }
+
+            let next = tokio::time::Instant::now() + wait.interval;
+            if self.sleep_until(next.min(deadline)).await? {
+                return Ok(PrintedOutcome::Cancelled);
+            }
+            if next >= deadline {
+                return Ok(PrintedOutcome::TimedOut { remaining: left });
+            }
+        }
+    }
+
+    /// The host status seen last, by any request.
+    pub fn status(&self) -> Option<&command::HostStatus> {
+        self.status.as_ref()
+    }
+
+    fn labels_remaining(&self) -> u32 {
+        self.status
+            .as_ref()
+            .map_or(0, |status| status.string2.u_labels_remaining)
+    }
 
-        // We send-and-read in sequence. Otherwise the print-back may be unordered.. Oh my.
-        for cmd in commands.0 {
-            let command = command::CommandSequence(vec![cmd]);
+    /// Ask for the host status alone, updating the status last seen.
+    pub async fn request_host_status(
+        &mut self,
+    ) -> std::io::Result<&command::HostStatus> {
+        let mut failures = 0;
+        let lines = loop {
+            match self.read_host_status().await {
+                Ok(lines) => break lines,
+                Err(error) => self.reconnect(&mut failures, error).await?,
+            }
+        };
 
-            let expected_response_lines = command.expected_response_lines();
-            let data = String::from(command).into_bytes();
+        let status = self.status.get_or_insert_with(Default::default);
+        parse_host_status(status, &lines);
+        Ok(status)
+    }
 
-            // TODO: Evaluate if these things should really run in parallel?
-            tokio::try_join!(async { tx.write_all(&data).await }, async {
-                for _ in 0..expected_response_lines {
-                    let line = match read::line_with(&mut buf, &mut rx).await {
-                        Ok(line) => line,
-                        Err(err) => return Err(err),
-                    };
+    async fn read_host_status(&mut self) -> std::io::Result<Vec<Vec<u8>>> {
+        let mut request = command::ZplWriter::new(vec![]);
+        request.write_sequence(&host_status_request())?;
+        self.connection.write_all(request.get_ref()).await?;
 
-                    lines.push(line.string);
+        let mut buf = vec![];
+        let mut lines = vec![];
+        for _ in 0..HOST_STATUS_LINES {
+            let line = read::line_with(&mut buf, &mut self.connection).await?;
+            lines.push(line.string);
+        }
+
+        Ok(lines)
+    }
+
+    pub async fn send(
+        &mut self,
+        commands: command::CommandSequence,
+    ) -> std::io::Result<()> {
+        let mut failures = 0;
+        let responses = loop {
+            let sent = self.connection.sent;
+            match self.send_with_response(&commands).await {
+                Ok(responses) => break responses,
+                Err(error) if self.connection.sent != sent => {
+                    return Err(error)
                 }
+                Err(error) => self.reconnect(&mut failures, error).await?,
+            }
+        };
+
+        for response in &responses {
+            debug!("{response:?}");
+        }
 
-                Ok(())
-            })?;
+        if responses.iter().all(|response| *response == Response::None) {
+            let until = tokio::time::Instant::now()
+                + std::time::Duration::from_millis(10_000);
+            self.sleep_until(until).await?;
+        } else {
+            self.interject().await?;
         }
 
-        assert_eq!(lines.len() as u32, total_expected_response_lines);
-        let mut info = command::HostStatus::default();
+        Ok(())
+    }
 
+    /// Send the interjections waiting.
+    async fn interject(&mut self) -> io::Result<Cancelled> {
+        let mut cancelled = false;
+        while let Some(command) = self
+            .interjections
+            .as_mut()
+            .and_then(|interjections| interjections.try_recv().ok())
         {
-            let hi = &mut info.identification;
-            split_line(
-                &lines[0],
-                [&mut hi.model, &mut hi.version, &mut hi.dpmm, &mut hi.memory],
-            );
+            cancelled |= matches!(command, command::ZplCommand::CancelAll);
+            self.control(command).await?;
         }
 
-        {
-            let s1 = &mut info.string1;
-
-            split_line(
-                &lines[1],
-                [
-                    &mut s1.a_communication,
-                    &mut s1.b_paper_out,
-                    &mut s1.c_pause,
-                    &mut s1.d_label_length,
-                    &mut s1.e_number_formats,
-                    &mut s1.f_buffer_full,
-                    &mut s1.g_communication_diagnostics,
-                    &mut s1.h_partial_format,
-                    &mut Ignore,
-                    &mut s1.j_corrupt_ram,
-                    &mut s1.k_temperature_low,
-                    &mut s1.l_temperature_high,
-                ],
-            );
+        Ok(cancelled)
+    }
+
+    /// Wait, sending interjections as they come in, until the time is up or printing cancelled.
+    async fn sleep_until(
+        &mut self,
+        until: tokio::time::Instant,
+    ) -> io::Result<Cancelled> {
+        loop {
+            let Some(interjections) = &mut self.interjections else {
+                tokio::time::sleep_until(until).await;
+                return Ok(false);
+            };
+
+            let command = tokio::select! {
+                _ = tokio::time::sleep_until(until) => return Ok(false),
+                command = interjections.recv() => command,
+            };
+
+            match command {
+                Some(command) => {
+                    let cancel =
+                        matches!(command, command::ZplCommand::CancelAll);
+                    self.control(command).await?;
+                    if cancel {
+                        return Ok(true);
+                    }
+                }
+                // Nobody left to send any.
+                None => self.interjections = None,
+            }
         }
+    }
 
-        {
-            let s2 = &mut info.string2;
-
-            split_line(
-                &lines[2],
-                [
-                    &mut s2.m_settings,
-                    &mut Ignore,
-                    &mut s2.o_head_up,
-                    &mut s2.p_ribbon_out,
-                    &mut s2.q_thermal_transfer_mode,
-                    &mut s2.r_print_mode,
-                    &mut s2.s_print_width_mode,
-                    &mut s2.t_label_waiting,
-                    &mut s2.u_labels_remaining,
-                    &mut s2.v_format_printing,
-                    &mut s2.w_number_graphics_stored,
-                ],
-            );
+    /// Connect afresh after a failed attempt, waiting as the retry policy asks, or give up with
+    /// the error once it has no more attempts.
+    async fn reconnect(
+        &mut self,
+        failures: &mut u32,
+        mut error: io::Error,
+    ) -> io::Result<()> {
+        loop {
+            *failures += 1;
+            let (Some(host), Some(delay)) =
+                (self.host.clone(), self.retry.retry_after(*failures, &error))
+            else {
+                return Err(error);
+            };
+
+            debug!("Connecting again in {delay:?} after: {error}");
+            tokio::time::sleep(delay).await;
+            if let Some(interceptor) = &self.connection.interceptor {
+                if let Err(next) = interceptor.connect() {
+                    error = next;
+                    continue;
+                }
+            }
+            match tokio::net::TcpStream::connect(host.as_str()).await {
+                Ok(socket) => {
+                    self.connection.replace(socket);
+                    return Ok(());
+                }
+                Err(next) => error = next,
+            }
         }
+    }
 
-        {
-            let s3 = &mut info.string3;
+    /// Send commands, reading the response to each as it expects, one per command.
+    ///
+    /// Commands are sent up to one expecting a response, which is read before going on, such
+    /// that responses are not confused with one another.
+    pub async fn send_with_response(
+        &mut self,
+        commands: &command::CommandSequence,
+    ) -> std::io::Result<Vec<Response>> {
+        let mut writer = command::ZplWriter::new(vec![]);
+        let mut buf = vec![];
+        let mut responses = Vec::with_capacity(commands.0.len());
+
+        for cmd in &commands.0 {
+            writer.write_command(cmd)?;
+
+            let spec = cmd.expected_response();
+            if spec == command::ResponseSpec::None {
+                responses.push(Response::None);
+                continue;
+            }
 
-            split_line(&lines[3], [&mut s3.x_password, &mut s3.y_static_ram]);
+            self.connection.write_all(writer.get_ref()).await?;
+            writer.get_mut().clear();
+
+            let response =
+                read::response_with(&mut buf, &mut self.connection, spec)
+                    .await?;
+            responses.push(response);
         }
 
-        {
-            let ram = &mut info.ram_status;
-            split_line(
-                &lines[4],
-                [
-                    &mut ram.total,
-                    &mut ram.maximum_to_user,
-                    &mut ram.available_to_user,
-                ],
-            );
+        self.connection.write_all(writer.get_ref()).await?;
+        self.connection.flush().await?;
+        Ok(responses)
+    }
+
+    /// Stop printing after the current label, until resumed.
+    pub async fn pause(&mut self) -> std::io::Result<()> {
+        self.control(command::ZplCommand::Pause).await
+    }
+
+    pub async fn resume(&mut self) -> std::io::Result<()> {
+        self.control(command::ZplCommand::Resume).await
+    }
+
+    /// Measure the labels and the gaps between them, feeding a few blank labels.
+    pub async fn calibrate(&mut self) -> std::io::Result<()> {
+        self.control(command::ZplCommand::CalibrateMedia).await
+    }
+
+    /// Feed a blank label.
+    pub async fn feed_label(&mut self) -> std::io::Result<()> {
+        self.control(command::ZplCommand::FeedLabel).await
+    }
+
+    /// Drop everything waiting in the printer's buffer, including the rest of a batch.
+    pub async fn cancel_all(&mut self) -> std::io::Result<()> {
+        self.control(command::ZplCommand::CancelAll).await
+    }
+
+    /// Turn communications diagnostics on or off, printing all data received as hex.
+    pub async fn set_diagnostics(&mut self, on: bool) -> std::io::Result<()> {
+        self.control(match on {
+            true => command::ZplCommand::StartDiagnostics,
+            false => command::ZplCommand::EndDiagnostics,
+        })
+        .await
+    }
+
+    /// Ask for the head temperature, the result of its element test and the settings.
+    pub async fn request_head_diagnostic(
+        &mut self,
+    ) -> std::io::Result<command::HeadDiagnostic> {
+        self.control(command::ZplCommand::RequestHeadDiagnostic)
+            .await?;
+
+        let mut buf = vec![];
+        let line = read::line_with(&mut buf, &mut self.connection).await?;
+        Ok(parse_head_diagnostic(&line.string))
+    }
+
+    /// List the graphics, fonts and formats stored on the printer, matching a pattern such as
+    /// `*:*.*` for all of them.
+    pub async fn list_objects(
+        &mut self,
+        pattern: &str,
+    ) -> std::io::Result<command::ObjectDirectory> {
+        let request = command::CommandSequence(vec![
+            command::ZplCommand::StartLabel,
+            command::ZplCommand::ListObjects(pattern.to_string()),
+            command::ZplCommand::EndLabel,
+        ]);
+
+        let lines = self
+            .send_with_response(&request)
+            .await?
+            .into_iter()
+            .flat_map(Response::into_lines)
+            .collect::<Vec<_>>();
+
+        Ok(parse_object_directory(&lines.concat()))
+    }
+
+    /// Delete the objects stored on the printer matching a pattern, such as `E:LOGO.GRF`.
+    ///
+    /// Objects in use by a format being printed are deleted once it is done.
+    pub async fn delete_object(
+        &mut self,
+        pattern: &str,
+    ) -> std::io::Result<()> {
+        let request = command::CommandSequence(vec![
+            command::ZplCommand::StartLabel,
+            command::ZplCommand::DeleteObject(pattern.to_string()),
+            command::ZplCommand::EndLabel,
+        ]);
+
+        self.send_with_response(&request).await?;
+        Ok(())
+    }
+
+    /// Read the settings of the printer, such that they can be restored onto a replacement.
+    pub async fn download_configuration(
+        &mut self,
+    ) -> std::io::Result<command::ConfigurationBackup> {
+        let identification =
+            self.request_device_status().await?.identification.clone();
+
+        let request = command::CommandSequence(vec![
+            command::ZplCommand::StartLabel,
+            command::ZplCommand::RequestConfiguration,
+            command::ZplCommand::EndLabel,
+            command::ZplCommand::RequestAllVariables,
+        ]);
+        let mut report = vec![];
+        let mut variables = vec![];
+        for response in self.send_with_response(&request).await? {
+            match response {
+                Response::None => {}
+                Response::Lines(lines) => report.extend(lines.concat()),
+                Response::Bytes(bytes) => variables = bytes,
+            }
         }
 
-        Ok(self.status.insert(info))
+        Ok(command::ConfigurationBackup {
+            model: identification.model,
+            version: identification.version,
+            settings: parse_settings(&variables),
+            report: String::from_utf8_lossy(&report).into_owned(),
+        })
     }
 
-    pub async fn send(
+    /// Send the commands restoring a configuration, see
+    /// [`command::CommandSequence::restore_configuration`].
+    ///
+    /// Settings of the interface in use, such as its address, may end the connection.
+    pub async fn restore_configuration(
         &mut self,
         commands: command::CommandSequence,
     ) -> std::io::Result<()> {
-        // Send data to the printer
-        let response_lines = commands.expected_response_lines();
-        for command in String::from(commands).lines() {
-            self.connection.write_all(command.as_bytes()).await?;
-        }
+        self.send_with_response(&commands).await?;
+        Ok(())
+    }
 
-        // Wait for incoming data
+    /// Read the address of the printer and, on a wireless interface, its association and signal.
+    ///
+    /// Printers answer `?` for variables they do not know, which are left out.
+    pub async fn request_network_settings(
+        &mut self,
+    ) -> std::io::Result<command::NetworkSettings> {
         let mut buf = vec![];
-        for _ in 0..response_lines {
-            let line = read::line_with(&mut buf, &mut self.connection).await?;
-            debug!("{}", String::from_utf8_lossy(&line.string));
+        let mut entries = std::collections::BTreeMap::new();
+
+        for name in NETWORK_VARIABLES {
+            self.control(command::ZplCommand::GetVariable(name.to_string()))
+                .await?;
+
+            let value =
+                read::quoted_with(&mut buf, &mut self.connection).await?;
+            entries.insert(name.to_string(), value);
         }
 
-        if response_lines == 0 {
-            tokio::time::sleep(std::time::Duration::from_millis(10_000)).await;
+        Ok(parse_network_settings(entries))
+    }
+
+    /// Send a control command, which the printer acts on right away without answering.
+    async fn control(
+        &mut self,
+        command: command::ZplCommand,
+    ) -> std::io::Result<()> {
+        self.connection
+            .write_all(command.to_string().as_bytes())
+            .await?;
+        self.connection.flush().await
+    }
+
+    /// Send a firmware update file as it is, reporting the bytes sent so far after each chunk.
+    ///
+    /// The printer restarts once it has applied the update, the connection is not usable after.
+    pub async fn send_firmware(
+        &mut self,
+        firmware: &[u8],
+        mut progress: impl FnMut(usize),
+    ) -> std::io::Result<()> {
+        const CHUNK: usize = 16 * 1024;
+
+        let mut sent = 0;
+        for chunk in firmware.chunks(CHUNK) {
+            self.connection.write_all(chunk).await?;
+            sent += chunk.len();
+            progress(sent);
         }
 
-        Ok(())
+        self.connection.flush().await
+    }
+}
+
+/// Identification, host status and memory, as parsed by [`parse_device_status`].
+fn status_request() -> command::CommandSequence {
+    command::CommandSequence(vec![
+        command::ZplCommand::RequestHostIdentification,
+        command::ZplCommand::RequestHostStatus,
+        command::ZplCommand::RequestHostRamStatus,
+    ])
+}
+
+fn host_status_request() -> command::CommandSequence {
+    command::CommandSequence(vec![command::ZplCommand::RequestHostStatus])
+}
+
+fn parse_device_status(lines: &[Vec<u8>]) -> command::HostStatus {
+    let mut info = command::HostStatus::default();
+
+    {
+        let hi = &mut info.identification;
+        split_line(
+            &lines[0],
+            [&mut hi.model, &mut hi.version, &mut hi.dpmm, &mut hi.memory],
+        );
+    }
+
+    parse_host_status(&mut info, &lines[1..1 + HOST_STATUS_LINES]);
+
+    {
+        let ram = &mut info.ram_status;
+        split_line(
+            &lines[1 + HOST_STATUS_LINES],
+            [
+                &mut ram.total,
+                &mut ram.maximum_to_user,
+                &mut ram.available_to_user,
+            ],
+        );
+    }
+
+    info
+}
+
+/// The Set-Get-Do variables read by [`ZplPrinter::request_network_settings`].
+const NETWORK_VARIABLES: [&str; 10] = [
+    "interface.network.active.ip_addr",
+    "interface.network.active.netmask",
+    "interface.network.active.gateway",
+    "interface.network.active.mac_addr",
+    "interface.network.active.protocol",
+    "interface.network.active.cable_type",
+    "wlan.essid",
+    "wlan.associated",
+    "wlan.signal_strength",
+    "wlan.signal_quality",
+];
+
+fn parse_network_settings(
+    mut entries: std::collections::BTreeMap<String, String>,
+) -> command::NetworkSettings {
+    entries.retain(|_, value| {
+        *value = value.trim().to_string();
+        !value.is_empty() && value != "?"
+    });
+
+    let text = |name: &str| entries.get(name).cloned();
+    let percent =
+        |name: &str| entries.get(name)?.trim_end_matches('%').parse().ok();
+
+    let interface = text("interface.network.active.cable_type");
+    let wlan =
+        (interface.as_deref() == Some("wlan")).then(|| command::WlanStatus {
+            essid: text("wlan.essid"),
+            associated: match entries.get("wlan.associated").map(String::as_str)
+            {
+                Some("yes") => Some(true),
+                Some("no") => Some(false),
+                _ => None,
+            },
+            signal_strength: percent("wlan.signal_strength"),
+            signal_quality: percent("wlan.signal_quality"),
+        });
+
+    command::NetworkSettings {
+        ip_address: text("interface.network.active.ip_addr"),
+        netmask: text("interface.network.active.netmask"),
+        gateway: text("interface.network.active.gateway"),
+        mac_address: text("interface.network.active.mac_addr"),
+        protocol: text("interface.network.active.protocol"),
+        interface,
+        wlan,
+        entries,
+    }
+}
+
+/// Lines of `name = value` in a `~HD` response.
+fn parse_head_diagnostic(report: &[u8]) -> command::HeadDiagnostic {
+    let report = String::from_utf8_lossy(report);
+    let entries: std::collections::BTreeMap<String, String> = report
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, value)| {
+            (name.trim().to_string(), value.trim().to_string())
+        })
+        .collect();
+
+    let number = |name: &str| entries.get(name)?.parse().ok();
+
+    command::HeadDiagnostic {
+        head_temperature: number("Head Temperature"),
+        ambient_temperature: number("Ambient Temperature"),
+        head_test_passed: match entries.get("Head Test").map(String::as_str) {
+            Some("Passed") => Some(true),
+            Some("Failed") => Some(false),
+            _ => None,
+        },
+        entries,
+    }
+}
+
+/// Variables which are read-only, count or measure, or act when set.
+const UNRESTORED_VARIABLES: [&str; 11] = [
+    "odometer.",
+    "sensor.",
+    "head.",
+    "power.",
+    "device.reset",
+    "device.restore_defaults",
+    "device.unique_id",
+    "device.host_status",
+    "file.",
+    "interface.network.active.",
+    ".mac_addr",
+];
+
+/// Read the listing of `allcv`, a line per variable such as
+/// `media.speed : 4.0 , Choices: 2.0-6.0`, keeping those which can be set.
+///
+/// Variables without choices, or whose choices are `read-only`, can not be set. Quotes can not be escaped in `setvar`, values
+/// containing them are left out as well.
+fn parse_settings(
+    listing: &[u8],
+) -> std::collections::BTreeMap<String, String> {
+    let listing = String::from_utf8_lossy(listing);
+
+    listing
+        .lines()
+        .filter_map(|line| {
+            let (name, rest) = line.split_once(" : ")?;
+            let (value, choices) = rest.rsplit_once(" , Choices:")?;
+            Some((name.trim(), value.trim(), choices.trim()))
+        })
+        .filter(|(name, value, choices)| {
+            !choices.is_empty()
+                && !choices.eq_ignore_ascii_case("read-only")
+                && !value.contains('"')
+                && !UNRESTORED_VARIABLES
+                    .iter()
+                    .any(|unrestored| name.contains(unrestored))
+        })
+        .map(|(name, value, _)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Read the listing of `^HW`: a line per object, such as `*R:LOGO.GRF 1234`, and the space
+/// left, such as `-794624 bytes free R:RAM`.
+fn parse_object_directory(listing: &[u8]) -> command::ObjectDirectory {
+    let listing = String::from_utf8_lossy(listing);
+    let mut directory = command::ObjectDirectory::default();
+
+    for line in listing.lines().map(str::trim) {
+        if let Some(entry) = line.strip_prefix('*') {
+            let mut fields = entry.split_whitespace();
+            let (Some(name), Some(size)) = (fields.next(), fields.next())
+            else {
+                continue;
+            };
+
+            directory.objects.push(command::StoredObject {
+                name: name.to_string(),
+                size: size.parse().unwrap_or_default(),
+            });
+        } else if let Some(free) = line.strip_prefix('-') {
+            let free = free
+                .split_whitespace()
+                .next()
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(free) = free {
+                *directory.free_bytes.get_or_insert(0) += free;
+            }
+        }
+    }
+
+    directory
+}
+
+/// Whether all labels are out, for the post-print action they were printed with.
+///
+/// When peeling, the last label may still wait on the peel bar, it is printed nonetheless and
+/// someone has to take it. An applicator, however, has only received the label once it no longer
+/// waits.
+pub fn is_printed(
+    status: &command::HostStatus,
+    action: &command::PostPrintAction,
+) -> bool {
+    let remaining = status.string2.u_labels_remaining;
+
+    match action {
+        command::PostPrintAction::Applicator => {
+            remaining == 0 && !status.string2.t_label_waiting
+        }
+        _ => remaining == 0,
+    }
+}
+
+/// Update the three strings of a `~HS` response.
+fn parse_host_status(info: &mut command::HostStatus, lines: &[Vec<u8>]) {
+    {
+        let s1 = &mut info.string1;
+
+        split_line(
+            &lines[0],
+            [
+                &mut s1.a_communication,
+                &mut s1.b_paper_out,
+                &mut s1.c_pause,
+                &mut s1.d_label_length,
+                &mut s1.e_number_formats,
+                &mut s1.f_buffer_full,
+                &mut s1.g_communication_diagnostics,
+                &mut s1.h_partial_format,
+                &mut Ignore,
+                &mut s1.j_corrupt_ram,
+                &mut s1.k_temperature_low,
+                &mut s1.l_temperature_high,
+            ],
+        );
+    }
+
+    {
+        let s2 = &mut info.string2;
+
+        split_line(
+            &lines[1],
+            [
+                &mut s2.m_settings,
+                &mut Ignore,
+                &mut s2.o_head_up,
+                &mut s2.p_ribbon_out,
+                &mut s2.q_thermal_transfer_mode,
+                &mut s2.r_print_mode,
+                &mut s2.s_print_width_mode,
+                &mut s2.t_label_waiting,
+                &mut s2.u_labels_remaining,
+                &mut s2.v_format_printing,
+                &mut s2.w_number_graphics_stored,
+            ],
+        );
+    }
+
+    {
+        let s3 = &mut info.string3;
+
+        split_line(&lines[2], [&mut s3.x_password, &mut s3.y_static_ram]);
     }
 }
 
@@ -220,3 +947,229 @@ impl FromField for String {
         self.replace_range(.., st);
     }
 }
+
+#[test]
+fn held_labels_by_action() {
+    use command::PostPrintAction;
+
+    let mut status = command::HostStatus::default();
+    status.string2.t_label_waiting = true;
+
+    assert!(is_printed(&status, &PostPrintAction::PeelOff));
+    assert!(!is_printed(&status, &PostPrintAction::Applicator));
+
+    status.string2.u_labels_remaining = 1;
+    assert!(!is_printed(&status, &PostPrintAction::PeelOff));
+}
+
+#[test]
+fn head_diagnostic_report() {
+    let report = b"Head Temperature = 21\r\nAmbient Temperature = 5\r\n\
+        Head Test = Passed\r\nDarkness Adjust = 23\r\n\
+        COMMAND PFX = ~ : FORMAT PFX = ^ : DELIMITER = ,\r\n";
+    let diagnostic = parse_head_diagnostic(report);
+
+    assert_eq!(diagnostic.head_temperature, Some(21));
+    assert_eq!(diagnostic.ambient_temperature, Some(5));
+    assert_eq!(diagnostic.head_test_passed, Some(true));
+    assert_eq!(diagnostic.entries["Darkness Adjust"], "23");
+}
+
+#[test]
+fn object_listing() {
+    let listing = b"\r\n- DIR R:*.*\r\n*R:LOGO.GRF 1234\r\n\
+        *R:ARIAL.TTF 65536\r\n-794624 bytes free R:RAM\r\n";
+    let directory = parse_object_directory(listing);
+
+    assert_eq!(directory.objects.len(), 2);
+    assert_eq!(directory.objects[0].name, "R:LOGO.GRF");
+    assert_eq!(directory.objects[1].size, 65536);
+    assert_eq!(directory.free_bytes, Some(794624));
+}
+
+#[test]
+fn restorable_settings() {
+    let listing = b"media.speed : 4.0 , Choices: 2.0-6.0\r\n\
+        odometer.total_print_length : 120 , Choices: \r\n\
+        device.reset :  , Choices: \r\n\
+        device.friendly_name : Shelf 2, left , Choices: 0-17 characters\r\n\
+        internal_wired.mac_addr : 00:07:4d:aa:bb:cc , Choices: read-only\r\n\
+        appl.name : V87.21.17Z , Choices: read-only\r\n";
+    let settings = parse_settings(listing);
+
+    assert_eq!(settings.len(), 2);
+    assert_eq!(settings["media.speed"], "4.0");
+    assert_eq!(settings["device.friendly_name"], "Shelf 2, left");
+
+    let backup = command::ConfigurationBackup {
+        settings,
+        ..Default::default()
+    };
+    let restore = command::CommandSequence::restore_configuration(&backup);
+    assert!(restore.to_string().starts_with(
+        "! U1 setvar \"device.friendly_name\" \"Shelf 2, left\"\r\n"
+    ));
+}
+
+#[test]
+fn network_settings_of_wlan() {
+    let entries = NETWORK_VARIABLES
+        .iter()
+        .zip([
+            "10.0.0.5",
+            "255.255.255.0",
+            "10.0.0.1",
+            "00:07:4d:aa:bb:cc",
+            "dhcp",
+            "wlan",
+            "carts",
+            "yes",
+            "62",
+            "?",
+        ])
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+    let settings = parse_network_settings(entries);
+    assert_eq!(settings.gateway.as_deref(), Some("10.0.0.1"));
+    let wlan = settings.wlan.unwrap();
+    assert_eq!(wlan.associated, Some(true));
+    assert_eq!(wlan.signal_strength, Some(62));
+    assert_eq!(wlan.signal_quality, None);
+    assert!(!settings.entries.contains_key("wlan.signal_quality"));
+}
+
+#[tokio::test]
+async fn host_status_on_a_fresh_connection() {
+    use tokio::io::AsyncReadExt as _;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Where the printer moved meanwhile, reached again by its name.
+    let moved = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let host = format!("localhost:{}", moved.local_addr().unwrap().port());
+    tokio::spawn(async move {
+        // The first connection breaks, the second one answers.
+        drop(listener.accept().await.unwrap());
+        let (mut socket, _) = moved.accept().await.unwrap();
+        let mut request = [0; 3];
+        socket.read_exact(&mut request).await.unwrap();
+        socket
+            .write_all(b"\x02030,0,0,1245,000,0,0,0,000,0,0,0\x03\r\n\x02000,0,0,0,0,2,4,0,00000002,1,000\x03\r\n\x021234,0\x03\r\n")
+            .await
+            .unwrap();
+    });
+
+    let mut printer = ZplPrinter::with_address(addr).await.unwrap();
+    printer.set_retry_policy(retry::RetryPolicy {
+        max_attempts: 2,
+        backoff: retry::Backoff {
+            initial: std::time::Duration::from_millis(10),
+            ..Default::default()
+        },
+    });
+    printer.set_reconnect_host(host);
+
+    let status = printer.request_host_status().await.unwrap();
+    assert_eq!(status.string2.u_labels_remaining, 2);
+}
+
+#[tokio::test]
+async fn waiting_for_labels() {
+    use tokio::io::AsyncReadExt as _;
+
+    let host_status = |remaining: u32, paper_out: u32, paused: u32| {
+        format!(
+            "\x02030,{paper_out},{paused},1245,000,0,0,0,000,0,0,0\x03\r\n\
+             \x02000,0,0,0,0,2,4,0,{remaining:08},1,000\x03\r\n\x021234,0\x03\r\n"
+        )
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let answers = [
+        host_status(2, 0, 0),
+        host_status(1, 0, 0),
+        host_status(1, 0, 1),
+        host_status(1, 1, 1),
+    ];
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        for answer in answers {
+            let mut request = [0; 3];
+            socket.read_exact(&mut request).await.unwrap();
+            socket.write_all(answer.as_bytes()).await.unwrap();
+        }
+        // Silent from now on, without closing the connection.
+        std::future::pending::<()>().await;
+        drop(socket);
+    });
+
+    let mut printer = ZplPrinter::with_address(addr).await.unwrap();
+    let wait = WaitForPrinted {
+        interval: std::time::Duration::from_millis(1),
+        ..Default::default()
+    };
+    let mut seen = vec![];
+    let action = command::PostPrintAction::TearOff;
+    let outcome = printer
+        .wait_for_printed(&action, &wait, |remaining| seen.push(remaining))
+        .await
+        .unwrap();
+    assert_eq!(outcome, PrintedOutcome::PrinterPaused);
+    assert_eq!(seen, [2, 1]);
+
+    // Out of paper is told apart from a pause.
+    let outcome = printer.wait_for_printed(&action, &wait, |_| {}).await;
+    assert!(matches!(outcome, Err(PrintError::MediaOut)));
+
+    // No more answers.
+    let wait = WaitForPrinted {
+        timeout: std::time::Duration::from_millis(50),
+        ..wait
+    };
+    let outcome = printer.wait_for_printed(&action, &wait, |_| {}).await;
+    assert_eq!(outcome.unwrap(), PrintedOutcome::TimedOut { remaining: 1 });
+}
+
+#[tokio::test]
+async fn interjected_cancel() {
+    use tokio::io::AsyncReadExt as _;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (cancelled, received) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        loop {
+            let mut request = [0; 3];
+            socket.read_exact(&mut request).await.unwrap();
+            if &request == b"~JA" {
+                break;
+            }
+            socket
+                .write_all(b"\x02030,0,0,1245,000,0,0,0,000,0,0,0\x03\r\n\x02000,0,0,0,0,2,4,0,00000003,1,000\x03\r\n\x021234,0\x03\r\n")
+                .await
+                .unwrap();
+        }
+        let _ = cancelled.send(());
+        std::future::pending::<()>().await;
+        drop(socket);
+    });
+
+    let mut printer = ZplPrinter::with_address(addr).await.unwrap();
+    let interjections = printer.interjections();
+    let wait = WaitForPrinted {
+        interval: std::time::Duration::from_secs(60),
+        ..Default::default()
+    };
+
+    // Sent while waiting for the next poll, long before it is due.
+    interjections
+        .send(command::ZplCommand::CancelAll)
+        .await
+        .unwrap();
+    let action = command::PostPrintAction::TearOff;
+    let outcome = printer.wait_for_printed(&action, &wait, |_| {}).await;
+    assert_eq!(outcome.unwrap(), PrintedOutcome::Cancelled);
+    received.await.unwrap();
+}
@@ -1,6 +1,9 @@
-use std::io;
+use std::{io, net::TcpStream};
 use tokio::io::AsyncReadExt;
 
+use super::Response;
+use crate::command::ResponseSpec;
+
 pub struct DiagnosticString {
     #[allow(dead_code)]
     pub start: Vec<u8>,
@@ -11,33 +14,194 @@ pub async fn line_with(
     buf: &mut Vec<u8>,
     rx: &mut (impl AsyncReadExt + core::marker::Unpin),
 ) -> Result<DiagnosticString, io::Error> {
-    let post_etx = 'brk: {
-        if let Some(pos) = buf.iter().position(|c| *c == b'\x03') {
-            break 'brk pos + 1;
+    let mut read_buf = [0; 128];
+
+    loop {
+        if let Some(line) = take_line(buf) {
+            return Ok(line);
+        }
+
+        let n = rx.read(&mut read_buf).await?;
+
+        if n == 0 {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        buf.extend_from_slice(&read_buf[..n]);
+    }
+}
+
+pub fn line_with_blocking(
+    buf: &mut Vec<u8>,
+    rx: &mut impl io::Read,
+) -> Result<DiagnosticString, io::Error> {
+    let mut read_buf = [0; 128];
+
+    loop {
+        if let Some(line) = take_line(buf) {
+            return Ok(line);
         }
 
-        let mut read_buf = [0; 128];
+        let n = rx.read(&mut read_buf)?;
+
+        if n == 0 {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        buf.extend_from_slice(&read_buf[..n]);
+    }
+}
+
+/// Read the response to a command as described, leaving whatever follows in the buffer.
+pub async fn response_with(
+    buf: &mut Vec<u8>,
+    rx: &mut (impl AsyncReadExt + core::marker::Unpin),
+    spec: ResponseSpec,
+) -> Result<Response, io::Error> {
+    let mut read_buf = [0; 128];
+
+    match spec {
+        ResponseSpec::None => Ok(Response::None),
+        ResponseSpec::Lines(count) => {
+            let mut lines = vec![];
+            for _ in 0..count {
+                lines.push(line_with(buf, rx).await?.string);
+            }
+
+            Ok(Response::Lines(lines))
+        }
+        ResponseSpec::Bytes(count) => loop {
+            if let Some(bytes) = take_bytes(buf, count) {
+                return Ok(Response::Bytes(bytes));
+            }
 
-        loop {
             let n = rx.read(&mut read_buf).await?;
 
             if n == 0 {
-                return Err(io::ErrorKind::BrokenPipe)?;
+                return Err(io::ErrorKind::BrokenPipe.into());
             }
 
-            let post_fin = read_buf[..n]
-                .iter()
-                .position(|c| *c == b'\x03')
-                .map(|x| x + 1 + buf.len());
-
             buf.extend_from_slice(&read_buf[..n]);
+        },
+        ResponseSpec::UntilIdle(idle) => {
+            // Silence ends the response, as does the printer closing the connection.
+            while let Ok(n) =
+                tokio::time::timeout(idle, rx.read(&mut read_buf)).await
+            {
+                match n? {
+                    0 => break,
+                    n => buf.extend_from_slice(&read_buf[..n]),
+                }
+            }
+
+            Ok(Response::Bytes(core::mem::take(buf)))
+        }
+    }
+}
+
+/// Read the response to a command as described, see [`response_with`].
+pub fn response_with_blocking(
+    buf: &mut Vec<u8>,
+    rx: &mut TcpStream,
+    spec: ResponseSpec,
+) -> Result<Response, io::Error> {
+    use io::Read as _;
+
+    let mut read_buf = [0; 128];
+
+    match spec {
+        ResponseSpec::None => Ok(Response::None),
+        ResponseSpec::Lines(count) => {
+            let mut lines = vec![];
+            for _ in 0..count {
+                lines.push(line_with_blocking(buf, rx)?.string);
+            }
+
+            Ok(Response::Lines(lines))
+        }
+        ResponseSpec::Bytes(count) => loop {
+            if let Some(bytes) = take_bytes(buf, count) {
+                return Ok(Response::Bytes(bytes));
+            }
+
+            let n = rx.read(&mut read_buf)?;
 
-            if let Some(fin) = post_fin {
-                break 'brk fin;
+            if n == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
             }
+
+            buf.extend_from_slice(&read_buf[..n]);
+        },
+        ResponseSpec::UntilIdle(idle) => {
+            rx.set_read_timeout(Some(idle))?;
+            let read = loop {
+                match rx.read(&mut read_buf) {
+                    Ok(0) => break Ok(()),
+                    Ok(n) => buf.extend_from_slice(&read_buf[..n]),
+                    Err(error)
+                        if matches!(
+                            error.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        break Ok(())
+                    }
+                    Err(error) => break Err(error),
+                }
+            };
+            rx.set_read_timeout(None)?;
+            read?;
+
+            Ok(Response::Bytes(core::mem::take(buf)))
+        }
+    }
+}
+
+/// Split the first bytes off the buffer, once there are enough of them.
+fn take_bytes(buf: &mut Vec<u8>, count: usize) -> Option<Vec<u8>> {
+    if buf.len() < count {
+        return None;
+    }
+
+    let tail = buf.split_off(count);
+    Some(core::mem::replace(buf, tail))
+}
+
+/// Read the value of a Set-Get-Do response, which is enclosed in double quotes.
+pub async fn quoted_with(
+    buf: &mut Vec<u8>,
+    rx: &mut (impl AsyncReadExt + core::marker::Unpin),
+) -> Result<String, io::Error> {
+    let mut read_buf = [0; 128];
+
+    loop {
+        if let Some(value) = take_quoted(buf) {
+            return Ok(value);
         }
-    };
 
+        let n = rx.read(&mut read_buf).await?;
+
+        if n == 0 {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        buf.extend_from_slice(&read_buf[..n]);
+    }
+}
+
+/// Split the first complete quoted value off the buffer, if any.
+fn take_quoted(buf: &mut Vec<u8>) -> Option<String> {
+    let open = buf.iter().position(|c| *c == b'"')?;
+    let close = open + 1 + buf[open + 1..].iter().position(|c| *c == b'"')?;
+
+    let value = String::from_utf8_lossy(&buf[open + 1..close]).into_owned();
+    buf.drain(..=close);
+    Some(value)
+}
+
+/// Split the first complete `STX ... ETX` response off the buffer, if any.
+fn take_line(buf: &mut Vec<u8>) -> Option<DiagnosticString> {
+    let post_etx = buf.iter().position(|c| *c == b'\x03')? + 1;
     let tail = buf.split_off(post_etx);
 
     let mut line = core::mem::replace(buf, tail);
@@ -47,8 +211,47 @@ pub async fn line_with(
     let start = line.iter().position(|c| *c == b'\x02').map_or(0, |n| n + 1);
     let string = line.split_off(start);
 
-    Ok(DiagnosticString {
+    Some(DiagnosticString {
         start: line,
         string,
     })
 }
+
+#[test]
+fn split_responses() {
+    let mut buf = b"\x02A,1\x03\r\n\x02B".to_vec();
+
+    assert_eq!(take_line(&mut buf).unwrap().string, b"A,1");
+    assert!(take_line(&mut buf).is_none());
+
+    buf.extend_from_slice(b",2\x03");
+    let line = line_with_blocking(&mut buf, &mut io::empty()).unwrap();
+    assert_eq!(line.string, b"B,2");
+    assert!(line_with_blocking(&mut buf, &mut io::empty()).is_err());
+
+    let mut buf = b"\"10.0.0.5\"\"?".to_vec();
+    assert_eq!(take_quoted(&mut buf).unwrap(), "10.0.0.5");
+    assert!(take_quoted(&mut buf).is_none());
+}
+
+#[tokio::test]
+async fn responses_by_spec() {
+    let mut rx: &[u8] = b"\x02A\x03\x02B\x03\x00\x01\x02{\"a\": 1}";
+    let mut buf = vec![];
+
+    let lines = response_with(&mut buf, &mut rx, ResponseSpec::Lines(2));
+    assert_eq!(
+        lines.await.unwrap(),
+        Response::Lines(vec![b"A".to_vec(), b"B".to_vec()])
+    );
+
+    let bytes = response_with(&mut buf, &mut rx, ResponseSpec::Bytes(3));
+    assert_eq!(bytes.await.unwrap(), Response::Bytes(vec![0, 1, 2]));
+
+    let idle = ResponseSpec::UntilIdle(std::time::Duration::from_millis(10));
+    let rest = response_with(&mut buf, &mut rx, idle).await.unwrap();
+    assert_eq!(rest, Response::Bytes(b"{\"a\": 1}".to_vec()));
+
+    let missing = response_with(&mut buf, &mut rx, ResponseSpec::Bytes(1));
+    assert!(missing.await.is_err());
+}
@@ -0,0 +1,136 @@
+//! How often, and after how long, to try again when talking to a printer fails.
+//!
+//! Printers on a flaky network, such as WiFi, drop connections now and then. A [`RetryPolicy`]
+//! set on a [`ZplPrinter`](super::ZplPrinter) has a failed request tried again on a fresh
+//! connection, waiting longer after each failure. The waits are shortened by a random fraction,
+//! such that printers losing the network at once do not all come back at the same instant.
+use std::{io, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RetryPolicy {
+    /// How often a request is tried in all, at least once.
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+}
+
+/// The waits between attempts, growing from `initial` by `factor` up to `max`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub factor: f64,
+    /// The fraction a wait is shortened by at most, at random, from `0` to `1`.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    /// Try once, without retries.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+impl Default for Backoff {
+    /// A second, every time.
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(1),
+            factor: 2.0,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before trying again after `failures` failed attempts, `None` if there
+    /// are no attempts left or the error would not go away by trying again.
+    pub fn retry_after(
+        &self,
+        failures: u32,
+        error: &io::Error,
+    ) -> Option<Duration> {
+        (failures < self.max_attempts && is_transient(error))
+            .then(|| self.backoff.delay(failures))
+    }
+}
+
+impl Backoff {
+    /// The wait after `failures` failed attempts in a row, the first one after one failure.
+    ///
+    /// Settings making no sense of a wait, such as a jitter that is not a number, wait `max`.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let grown =
+            self.initial.as_secs_f64() * self.factor.max(1.0).powi(exponent);
+        let capped = grown.min(self.max.as_secs_f64()).max(0.0);
+
+        let shortened =
+            capped * (1.0 - self.jitter.clamp(0.0, 1.0) * fastrand::f64());
+        Duration::try_from_secs_f64(shortened).unwrap_or(self.max)
+    }
+}
+
+/// Whether an error is one of the connection, which a fresh connection may not have.
+///
+/// Errors of the data sent or received would only happen again.
+pub fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::Interrupted
+    )
+}
+
+#[test]
+fn backoff_grows_up_to_max() {
+    let backoff = Backoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+        factor: 3.0,
+        jitter: 0.0,
+    };
+    let delays = (1..=4).map(|failures| backoff.delay(failures).as_millis());
+    assert_eq!(delays.collect::<Vec<_>>(), [100, 300, 900, 1000]);
+
+    let jittered = Backoff {
+        jitter: 0.5,
+        ..backoff
+    };
+    for _ in 0..32 {
+        let delay = jittered.delay(3);
+        assert!(
+            delay >= Duration::from_millis(450)
+                && delay <= Duration::from_millis(900)
+        );
+    }
+
+    let garbage = Backoff {
+        jitter: f64::NAN,
+        ..backoff.clone()
+    };
+    assert_eq!(garbage.delay(1), backoff.max);
+
+    let policy = RetryPolicy {
+        max_attempts: 2,
+        backoff: jittered,
+    };
+    let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+    assert!(policy.retry_after(1, &reset).is_some());
+    assert!(policy.retry_after(2, &reset).is_none());
+    let garbled = io::Error::from(io::ErrorKind::InvalidData);
+    assert!(policy.retry_after(1, &garbled).is_none());
+}
@@ -0,0 +1,213 @@
+//! Send a batch of labels while the rest of it is still being rendered.
+//!
+//! Rendering a whole batch before sending it delays the first label, and sending it in one block
+//! makes the printer hold all of its formats in memory. Instead, each label is sent once rendered
+//! and only while the printer holds fewer formats than a small window.
+use std::time::Duration;
+
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+use super::ZplPrinter;
+use crate::{
+    command::{CommandSequence, HostStatus, ZplWriter},
+    label::{Label, PrintOptions},
+};
+
+/// How long to wait before asking a printer with a full window again.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The formats a printer holds, whether waiting or being printed.
+pub fn held_formats(status: &HostStatus) -> u32 {
+    status.string1.e_number_formats
+        + u32::from(status.string2.v_format_printing)
+}
+
+impl ZplPrinter {
+    /// Send labels as they arrive, each once the printer holds fewer than `window` formats.
+    ///
+    /// Reports the number of labels sent after each one, and returns it once all are. Fails with
+    /// [`std::io::ErrorKind::TimedOut`] if the printer holds a full window for longer than
+    /// `stall`, such as while it is paused or out of paper.
+    pub async fn send_streamed(
+        &mut self,
+        mut labels: mpsc::Receiver<CommandSequence>,
+        window: u32,
+        stall: Duration,
+        mut progress: impl FnMut(u32),
+    ) -> std::io::Result<u32> {
+        let window = window.max(1);
+        let mut sent = 0;
+
+        while let Some(label) = labels.recv().await {
+            let deadline = tokio::time::Instant::now() + stall;
+            loop {
+                let held = held_formats(self.request_host_status().await?);
+                if held < window {
+                    break;
+                }
+
+                if tokio::time::Instant::now() >= deadline {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!(
+                            "Printer held {held} formats for over {stall:?} after {sent} labels"
+                        ),
+                    ));
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+
+            let mut data = ZplWriter::new(vec![]);
+            data.write_sequence(&label)?;
+            self.connection.write_all(data.get_ref()).await?;
+            self.connection.flush().await?;
+
+            sent += 1;
+            progress(sent);
+        }
+
+        Ok(sent)
+    }
+}
+
+/// Print labels in order, rendering each while the ones before are sent and printed.
+///
+/// At most one label is rendered ahead of the printer, the window bounds those it holds. See
+/// [`ZplPrinter::send_streamed`] for how long the window may stay full.
+pub async fn print_batch(
+    printer: &mut ZplPrinter,
+    labels: Vec<Label>,
+    options: PrintOptions,
+    window: u32,
+    stall: Duration,
+    progress: impl FnMut(u32),
+) -> anyhow::Result<u32> {
+    let (rendered, to_send) = mpsc::channel(1);
+
+    let render = tokio::task::spawn_blocking(move || {
+        for label in labels {
+            let commands = label.print(&options)?;
+
+            // The printer failed, its error is reported instead.
+            if rendered.blocking_send(commands).is_err() {
+                break;
+            }
+        }
+
+        Ok::<_, anyhow::Error>(())
+    });
+
+    let sent = printer
+        .send_streamed(to_send, window, stall, progress)
+        .await;
+    render.await??;
+    Ok(sent?)
+}
+
+#[tokio::test]
+async fn streams_labels_in_order() {
+    use tokio::io::AsyncReadExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    // Answers every status request with an empty buffer.
+    let device = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+
+        let (mut received, mut answered) = (String::new(), 0);
+        let mut buf = [0; 4096];
+
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            if n == 0 {
+                return received;
+            }
+
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+            while answered < received.matches("~HS").count() {
+                socket
+                    .write_all(
+                        b"\x02030,0,0,0800,000,0,0,0,000,0,0,0\x03\r\n\
+                          \x02000,0,0,0,0,2,4,0,00000000,1,000\x03\r\n\
+                          \x021234,0\x03\r\n",
+                    )
+                    .await
+                    .unwrap();
+                answered += 1;
+            }
+        }
+    });
+
+    let labels: Vec<_> = (1..=3)
+        .map(|n| {
+            let mut label = Label::new(10.0, 10.0, 8);
+            label.content.push(crate::label::LabelContent::QrCode {
+                content: format!("label {n}"),
+                x: crate::length::Length::dots(0),
+                y: crate::length::Length::dots(0),
+                zoom: 1,
+                error_correction: Default::default(),
+                size: None,
+            });
+            label
+        })
+        .collect();
+
+    let mut printer = ZplPrinter::with_address(address).await.unwrap();
+    let mut reported = vec![];
+    let stall = Duration::from_millis(500);
+    let sent = print_batch(
+        &mut printer,
+        labels.clone(),
+        PrintOptions {
+            copies: 1,
+            ..PrintOptions::default()
+        },
+        2,
+        stall,
+        |n| reported.push(n),
+    )
+    .await
+    .unwrap();
+    drop(printer);
+
+    assert_eq!(sent, 3);
+    assert_eq!(reported, [1, 2, 3]);
+
+    let received = device.await.unwrap();
+    let first = received.find("label 1").unwrap();
+    let third = received.find("label 3").unwrap();
+    assert!(first < third);
+    // Each with the preamble in a format of its own.
+    assert_eq!(received.matches("^XZ").count(), 6);
+
+    // A printer that holds two formats and never takes another.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 4096];
+        while socket.read(&mut buf).await.unwrap_or(0) > 0 {
+            let status = b"\x02030,0,0,0800,002,0,0,0,000,0,0,0\x03\r\n\
+                \x02000,0,0,0,0,2,4,0,00000000,1,000\x03\r\n\
+                \x021234,0\x03\r\n";
+            if socket.write_all(status).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut printer = ZplPrinter::with_address(address).await.unwrap();
+    let stuck = print_batch(
+        &mut printer,
+        labels,
+        PrintOptions::default(),
+        2,
+        stall,
+        |_| {},
+    )
+    .await;
+    let error = stuck.unwrap_err().downcast::<std::io::Error>().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+}
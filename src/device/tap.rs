@@ -0,0 +1,337 @@
+//! Copies of the bytes exchanged with a printer, to find out what exactly was sent when it
+//! misbehaves.
+//!
+//! A [`Tap`] set on a [`ZplPrinter`](super::ZplPrinter) sees every byte written to the printer
+//! and read from it, in order, as the connection passes them on.
+use std::{
+    collections::VecDeque,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use serde::Serialize;
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt as _, ReadBuf},
+    sync::mpsc,
+};
+
+use super::intercept::Interceptor;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Receives a copy of the bytes exchanged with a printer.
+pub trait Tap: Send + Sync {
+    fn record(&self, direction: Direction, bytes: &[u8]);
+}
+
+/// Bytes passed in one direction at once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+/// Keeps the most recent bytes in memory, forgetting the oldest.
+pub struct RingBuffer {
+    capacity: usize,
+    chunks: Mutex<(VecDeque<Chunk>, usize)>,
+}
+
+/// Appends the bytes to a file, each chunk after a line naming its direction and length.
+///
+/// Written by a task of its own, as the connection must not wait for the file. Chunks arriving
+/// while [`FILE_TAP_CHUNKS`] still wait to be written are left out, and a line `!!! <length>`
+/// marks where.
+pub struct FileTap {
+    /// Each chunk with the bytes left out before it.
+    chunks: mpsc::Sender<(usize, Chunk)>,
+    /// The bytes left out since the last chunk passed on.
+    dropped: AtomicUsize,
+}
+
+/// How many chunks wait for the file at most.
+pub const FILE_TAP_CHUNKS: usize = 256;
+
+/// A connection passing the bytes it sends and receives on to a tap, if one is set.
+///
+/// Bytes received pass an interceptor before the tap, which sees what the printer was taken to
+/// send.
+pub(crate) struct Tapped<S> {
+    stream: S,
+    pub(crate) tap: Option<Arc<dyn Tap>>,
+    pub(crate) interceptor: Option<Arc<dyn Interceptor>>,
+    /// The bytes written so far, whether tapped or not.
+    pub(crate) sent: u64,
+}
+
+impl RingBuffer {
+    /// Keep up to `capacity` bytes, of both directions together.
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            capacity,
+            chunks: Mutex::default(),
+        }
+    }
+
+    /// The kept bytes, oldest first.
+    pub fn chunks(&self) -> Vec<Chunk> {
+        self.chunks.lock().unwrap().0.iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        *self.chunks.lock().unwrap() = Default::default();
+    }
+}
+
+impl Tap for RingBuffer {
+    fn record(&self, direction: Direction, bytes: &[u8]) {
+        let bytes = &bytes[bytes.len().saturating_sub(self.capacity)..];
+        if bytes.is_empty() {
+            return;
+        }
+
+        let mut guard = self.chunks.lock().unwrap();
+        let (chunks, size) = &mut *guard;
+
+        match chunks.back_mut() {
+            Some(last) if last.direction == direction => {
+                last.bytes.extend_from_slice(bytes)
+            }
+            _ => chunks.push_back(Chunk {
+                direction,
+                bytes: bytes.to_vec(),
+            }),
+        }
+        *size += bytes.len();
+
+        while *size > self.capacity {
+            let Some(first) = chunks.front_mut() else {
+                break;
+            };
+
+            let excess = *size - self.capacity;
+            if first.bytes.len() > excess {
+                first.bytes.drain(..excess);
+                *size -= excess;
+            } else {
+                *size -= first.bytes.len();
+                chunks.pop_front();
+            }
+        }
+    }
+}
+
+impl FileTap {
+    /// Open the file for appending, and spawn the task writing to it on the current runtime.
+    pub async fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        let (chunks, mut pending) =
+            mpsc::channel::<(usize, Chunk)>(FILE_TAP_CHUNKS);
+
+        tokio::spawn(async move {
+            let mut file = io::BufWriter::new(file);
+
+            while let Some((dropped, chunk)) = pending.recv().await {
+                let marker = match chunk.direction {
+                    Direction::Sent => ">>>",
+                    Direction::Received => "<<<",
+                };
+                let mut header = format!("{marker} {}\n", chunk.bytes.len());
+                if dropped > 0 {
+                    header = format!("!!! {dropped}\n{header}");
+                }
+
+                let written = async {
+                    file.write_all(header.as_bytes()).await?;
+                    file.write_all(&chunk.bytes).await?;
+                    file.write_all(b"\n").await?;
+                    if pending.is_empty() {
+                        file.flush().await?;
+                    }
+                    io::Result::Ok(())
+                };
+
+                // Failures to write a copy must not fail the connection.
+                if written.await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(FileTap {
+            chunks,
+            dropped: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl Tap for FileTap {
+    fn record(&self, direction: Direction, bytes: &[u8]) {
+        let chunk = Chunk {
+            direction,
+            bytes: bytes.to_vec(),
+        };
+
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if self.chunks.try_send((dropped, chunk)).is_err() {
+            self.dropped
+                .fetch_add(dropped + bytes.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Passes chunks on while the receiver keeps up, leaving out those arriving while it is full.
+impl Tap for mpsc::Sender<Chunk> {
+    fn record(&self, direction: Direction, bytes: &[u8]) {
+        let _ = self.try_send(Chunk {
+            direction,
+            bytes: bytes.to_vec(),
+        });
+    }
+}
+
+impl<S> Tapped<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        Tapped {
+            stream,
+            tap: None,
+            interceptor: None,
+            sent: 0,
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Carry on over another stream, with the same tap.
+    pub(crate) fn replace(&mut self, stream: S) {
+        self.stream = stream;
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tapped<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.stream).poll_read(cx, buf);
+        if !matches!(result, Poll::Ready(Ok(()))) {
+            return result;
+        }
+
+        if let Some(interceptor) = &this.interceptor {
+            interceptor.received(&mut buf.filled_mut()[before..])?;
+        }
+
+        let read = &buf.filled()[before..];
+        if let (Some(tap), false) = (&this.tap, read.is_empty()) {
+            tap.record(Direction::Received, read);
+        }
+
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tapped<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.stream).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = &result {
+            this.sent += *written as u64;
+            if let (Some(tap), true) = (&this.tap, *written > 0) {
+                tap.record(Direction::Sent, &buf[..*written]);
+            }
+            if let Some(interceptor) = &this.interceptor {
+                interceptor.sent(&buf[..*written])?;
+            }
+        }
+
+        result
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[test]
+fn ring_buffer_keeps_recent_bytes() {
+    let ring = RingBuffer::new(8);
+    ring.record(Direction::Sent, b"~HS");
+    ring.record(Direction::Sent, b"^XA");
+    ring.record(Direction::Received, b"\x02030\x03");
+
+    let chunk = |direction, bytes: &[u8]| Chunk {
+        direction,
+        bytes: bytes.to_vec(),
+    };
+    assert_eq!(
+        ring.chunks(),
+        [
+            chunk(Direction::Sent, b"^XA"),
+            chunk(Direction::Received, b"\x02030\x03"),
+        ]
+    );
+
+    ring.record(Direction::Sent, b"^XA^FO0,0^XZ");
+    assert_eq!(ring.chunks(), [chunk(Direction::Sent, b"FO0,0^XZ")]);
+}
+
+#[tokio::test]
+async fn file_tap_leaves_out_chunks_while_full() {
+    let path = std::env::temp_dir()
+        .join(format!("zpl-tap-{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let tap = FileTap::create(&path).await.unwrap();
+
+    // Recorded before the writer task had a chance to run.
+    for _ in 0..FILE_TAP_CHUNKS + 2 {
+        tap.record(Direction::Sent, b"^XA");
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    tap.record(Direction::Received, b"\x02030\x03");
+    drop(tap);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(written.matches(">>> 3\n^XA\n").count(), FILE_TAP_CHUNKS);
+    assert!(
+        written.ends_with("!!! 6\n<<< 5\n\x02030\x03\n"),
+        "{written}"
+    );
+}
@@ -9,20 +9,7 @@ pub async fn make_label(
         output_zpl_only: _,
     } = args;
 
-    let width: f32 = 100.0;
-    let height: f32 = 50.0;
-
-    let margin_x: f32 = 3.0; //32.0 / 12.0;
-    let margin_y: f32 = 3.0; //32.0 / 12.0;
-    let content_width = width - 2.0 * margin_x;
-    let content_height = height - 2.0 * margin_y;
-    let offset_y = 0.0;
-
-    let mut label = Label::new(
-        (width.floor() as i32).try_into().unwrap(),
-        (height.floor() as i32).try_into().unwrap(),
-        dpmm,
-    );
+    let mut label = Label::new(100.0, 50.0, dpmm);
 
     let logo = tokio::fs::read_to_string("logo-cert.svg")
         .await
@@ -33,54 +20,21 @@ pub async fn make_label(
         .expect("SVG file not found");
     let text_code = str::replace(&text_code, "b1234", &id);
 
-    use qrcode::render::svg;
-    use qrcode::{EcLevel, QrCode};
-
     let qr_contents = format!("https://urn.ccc.de/cert:{}", id).to_uppercase();
     info!("Content: {:?}", qr_contents);
     info!("Content length: {:?}", qr_contents.len());
 
-    let qr = QrCode::with_error_correction_level(
-        qr_contents, //
-        EcLevel::Q,
-    )
-    .unwrap();
-
-    let qr_svg = qr
-        .render()
-        .min_dimensions(200, 200)
-        .quiet_zone(false)
-        .dark_color(svg::Color("#000000"))
-        .light_color(svg::Color("#ffffff"))
-        .build();
-
-    let block_margin = 1.5;
-    label.content.push(LabelContent::Svg {
-        code: logo,
-        x: Unit::Millimetres(margin_x + content_height + 6.0 + block_margin),
-        y: Unit::Millimetres(margin_y + offset_y),
-        w: Unit::Millimetres(35.0 - 2.0 * block_margin),
-        h: Unit::Millimetres(content_height),
-    });
-    let block_margin = 2.0;
-    label.content.push(LabelContent::Svg {
-        code: text_code,
-        x: Unit::Millimetres(
-            margin_x + content_height + 35.0 + 2.0 + block_margin,
-        ),
-        y: Unit::Millimetres(margin_y + offset_y),
-        w: Unit::Millimetres(
-            content_width - content_height - 35.0 - 2.0 * block_margin,
-        ),
-        h: Unit::Millimetres(content_height),
-    });
-    label.content.push(LabelContent::Svg {
-        code: qr_svg,
-        x: Unit::Millimetres(margin_x + 0.0),
-        y: Unit::Millimetres(margin_y + offset_y),
-        w: Unit::Millimetres(content_height),
-        h: Unit::Millimetres(content_height),
-    });
+    // The QR code as a square the height of the label, the logo and the code beside it.
+    label.content = Region::row([
+        Region::content(QrContent::builder(qr_contents)).fixed_mm(44.0),
+        Region::empty().fixed_mm(6.0),
+        Region::content(SvgContent::builder(logo))
+            .fixed_mm(35.0)
+            .padding_mm(1.5),
+        Region::content(SvgContent::builder(text_code)).padding_mm(2.0),
+    ])
+    .padding_mm(3.0)
+    .place(&label);
 
     let commands = label.print(2).await?;
 
@@ -1,223 +1,2859 @@
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
 use anyhow::Context;
+use rayon::prelude::*;
 
+use crate::builder::LabelBuilder;
 use crate::command::{
-    self, BackfeedSequence, CommandSequence, MediaTracking, MediaType,
-    PostPrintAction, ZplCommand,
+    self, BackfeedSequence, CharacterSet, ClockMode, CommandSequence,
+    FieldData, MediaTracking, MediaType, PostPrintAction, ZplCommand,
 };
+use crate::length::Length;
+use crate::lint::Severity;
+use crate::util::cache::{CachedRaster, RasterCache};
+use crate::util::image::{ImageCompression, SerializedImage};
 
 #[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum LabelContent {
     Image {
         img: ::image::DynamicImage,
-        x: Unit,
-        y: Unit,
-        w: Unit,
-        h: Unit,
+        x: Length,
+        y: Length,
+        w: Length,
+        h: Length,
+        fit: Fit,
     },
     Svg {
         code: String,
-        x: Unit,
-        y: Unit,
-        w: Unit,
-        h: Unit,
+        x: Length,
+        y: Length,
+        w: Length,
+        h: Length,
+        /// Shrink content running past the edges of the document, such as long text, until it
+        /// fits, see [`LabelContent::shrink_factor`].
+        shrink: bool,
     },
     SvgTree {
         tree: resvg::usvg::Tree,
-        x: Unit,
-        y: Unit,
-        w: Unit,
-        h: Unit,
+        /// Identifies the document the tree was parsed from, such that its raster can be cached.
+        key: Option<u64>,
+        x: Length,
+        y: Length,
+        w: Length,
+        h: Length,
+        /// Shrink content running past the edges of the document until it fits.
+        shrink: bool,
     },
     QrCode {
         content: String,
-        x: Unit,
-        y: Unit,
+        x: Length,
+        y: Length,
+        /// Dots per module, 1 to 10.
+        zoom: u32,
+        error_correction: QrErrorCorrection,
+        /// Fill this width and height instead of using the zoom and error correction given, see
+        /// [`qr_fit`].
+        size: Option<Length>,
+    },
+    /// An Aztec code, drawn by the printer.
+    Aztec {
+        content: String,
+        x: Length,
+        y: Length,
+        /// Dots per module, 1 to 10.
         zoom: u32,
+        /// Percent of the symbol given to error correction, 1 to 99, or zero for the printer's
+        /// default of 23.
+        error_correction: u32,
+    },
+    /// A PDF417 code of stacked rows, drawn by the printer.
+    Pdf417 {
+        content: String,
+        x: Length,
+        y: Length,
+        /// Width of the narrowest bar in dots.
+        module_width: u32,
+        row_height: Length,
+        /// Level 0 to 8, each doubling the codewords of error correction.
+        security: u32,
+        /// Columns of data per row, 1 to 30. Rows are added as the content needs.
+        columns: u32,
+    },
+    /// Text with the date and time, stamped in by the printer from its clock when printing.
+    ///
+    /// The format holds placeholders such as `%Y-%m-%d %H:%M`, in the printer's notation.
+    ClockField {
+        format: String,
+        x: Length,
+        y: Length,
+        /// Height of the characters.
+        height: Length,
+    },
+    /// A number counted by the printer across the copies of a batch.
+    ///
+    /// With padding, the number is kept to that many digits with leading zeros and wraps around
+    /// past the largest of them, it can then only count up.
+    SerialNumber {
+        start: u64,
+        increment: i64,
+        /// Width in digits, none if zero.
+        pad: u32,
+        x: Length,
+        y: Length,
+        /// Height of the characters.
+        height: Length,
+    },
+    /// Another item, printed darker than the rest of the label.
+    Emphasized {
+        content: Box<LabelContent>,
+        emphasis: Emphasis,
     },
 }
 
-#[derive(Clone, Debug)]
-pub enum Unit {
-    Dots(u32),
-    Millimetres(f32),
+/// How an image fills the box it is placed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Fit {
+    /// Scale to cover the whole box, cutting off what sticks out.
+    #[default]
+    Cover,
+    /// Scale to fit within the box, centered and leaving the rest blank.
+    Contain,
+    /// Scale both directions to the box on their own, distorting the image.
+    Stretch,
+}
+
+/// Extra darkness for a single item, where raising the darkness of the whole label would make
+/// large black areas bleed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Emphasis {
+    /// Print the item twice, the second time offset by one dot to the right.
+    DoubleStrike,
+    /// Grow the dark parts of the item by some dots in every direction.
+    ///
+    /// Only possible for rasterized content.
+    Thicken { dots: u32 },
+}
+
+impl LabelContent {
+    /// The position of the upper left corner of the item.
+    pub fn origin(&self) -> (&Length, &Length) {
+        match self {
+            LabelContent::Image { x, y, .. }
+            | LabelContent::Svg { x, y, .. }
+            | LabelContent::SvgTree { x, y, .. }
+            | LabelContent::QrCode { x, y, .. }
+            | LabelContent::Aztec { x, y, .. }
+            | LabelContent::Pdf417 { x, y, .. }
+            | LabelContent::ClockField { x, y, .. }
+            | LabelContent::SerialNumber { x, y, .. } => (x, y),
+            LabelContent::Emphasized { content, .. } => content.origin(),
+        }
+    }
+
+    /// Whether the printer renders the item itself, such that it can not be rasterized.
+    pub fn is_native(&self) -> bool {
+        match self {
+            LabelContent::Image { .. }
+            | LabelContent::Svg { .. }
+            | LabelContent::SvgTree { .. } => false,
+            LabelContent::QrCode { .. }
+            | LabelContent::Aztec { .. }
+            | LabelContent::Pdf417 { .. }
+            | LabelContent::ClockField { .. }
+            | LabelContent::SerialNumber { .. } => true,
+            LabelContent::Emphasized { content, .. } => content.is_native(),
+        }
+    }
+
+    /// The text the printer is sent as field data, for native content.
+    fn field_text(&self) -> Option<&str> {
+        match self {
+            LabelContent::QrCode { content, .. }
+            | LabelContent::Aztec { content, .. }
+            | LabelContent::Pdf417 { content, .. } => Some(content),
+            LabelContent::ClockField { format, .. } => Some(format),
+            LabelContent::Emphasized { content, .. } => content.field_text(),
+            _ => None,
+        }
+    }
+
+    /// The factor an SVG document set to shrink is scaled by to fit, `1.0` if it fits as it is.
+    ///
+    /// `None` for other content, or documents which do not parse.
+    pub fn shrink_factor(&self, options: &RenderOptions) -> Option<f32> {
+        match self {
+            LabelContent::Svg {
+                code, shrink: true, ..
+            } => {
+                let tree =
+                    resvg::usvg::Tree::from_str(code, &options.svg_options())
+                        .ok()?;
+                Some(crate::util::svg::shrink_factor(&tree))
+            }
+            LabelContent::SvgTree {
+                tree, shrink: true, ..
+            } => Some(crate::util::svg::shrink_factor(tree)),
+            LabelContent::Emphasized { content, .. } => {
+                content.shrink_factor(options)
+            }
+            _ => None,
+        }
+    }
+
+    /// Hash what the raster of a rasterized item depends on, `None` for content which can not
+    /// be hashed, such as SVG documents parsed without a key.
+    fn hash_raster(&self, dpmm: u32, state: &mut impl Hasher) -> Option<()> {
+        let (x, y) = self.origin();
+        (x.to_dots(dpmm), y.to_dots(dpmm)).hash(state);
+
+        match self {
+            LabelContent::Image { img, w, h, fit, .. } => {
+                (0u8, img.color(), img.width(), img.height()).hash(state);
+                img.as_bytes().hash(state);
+                (w.to_dots(dpmm), h.to_dots(dpmm), fit).hash(state);
+            }
+            LabelContent::Svg {
+                code, w, h, shrink, ..
+            } => {
+                (1u8, code, w.to_dots(dpmm), h.to_dots(dpmm), shrink)
+                    .hash(state);
+            }
+            LabelContent::SvgTree {
+                key: Some(key),
+                w,
+                h,
+                shrink,
+                ..
+            } => {
+                (2u8, key, w.to_dots(dpmm), h.to_dots(dpmm), shrink)
+                    .hash(state);
+            }
+            LabelContent::Emphasized { content, emphasis } => {
+                (3u8, emphasis).hash(state);
+                content.hash_raster(dpmm, state)?;
+            }
+            LabelContent::SvgTree { key: None, .. }
+            | LabelContent::QrCode { .. }
+            | LabelContent::Aztec { .. }
+            | LabelContent::Pdf417 { .. }
+            | LabelContent::ClockField { .. }
+            | LabelContent::SerialNumber { .. } => return None,
+        }
+
+        Some(())
+    }
+
+    /// Whether the item differs between the copies of a batch.
+    pub fn is_serialized(&self) -> bool {
+        match self {
+            LabelContent::SerialNumber { .. } => true,
+            LabelContent::Emphasized { content, .. } => content.is_serialized(),
+            _ => false,
+        }
+    }
+
+    /// Wrap the item such that it is printed with emphasis.
+    pub fn emphasized(self, emphasis: Emphasis) -> Self {
+        LabelContent::Emphasized {
+            content: Box::new(self),
+            emphasis,
+        }
+    }
+
+    /// The item as printed on a later label, its serial number counted on by some labels and
+    /// then stepping over a number of labels at a time.
+    fn counted_on(&self, labels: u64, stride: u32) -> anyhow::Result<Self> {
+        Ok(match self {
+            LabelContent::SerialNumber {
+                start,
+                increment,
+                pad,
+                x,
+                y,
+                height,
+            } => {
+                let start = i128::from(*start)
+                    + i128::from(labels) * i128::from(*increment);
+                let Ok(start) = u64::try_from(start) else {
+                    anyhow::bail!("Serial number counts below zero");
+                };
+
+                LabelContent::SerialNumber {
+                    start,
+                    increment: increment.saturating_mul(stride.into()),
+                    pad: *pad,
+                    x: *x,
+                    y: *y,
+                    height: *height,
+                }
+            }
+            LabelContent::Emphasized { content, emphasis } => {
+                LabelContent::Emphasized {
+                    content: Box::new(content.counted_on(labels, stride)?),
+                    emphasis: *emphasis,
+                }
+            }
+            item => item.clone(),
+        })
+    }
 }
 
 #[derive(Clone)]
 pub struct Label {
     pub content: Vec<LabelContent>,
-    pub width: u32,
-    pub height: u32,
+    pub width: Length,
+    pub height: Length,
     pub dpmm: u32,
+    /// Edges of the label content should keep clear of, none by default.
+    pub margins: Margins,
+}
+
+/// Distances from the edges of a label, inside of which content is printed reliably.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Margins {
+    pub left: Length,
+    pub right: Length,
+    pub top: Length,
+    pub bottom: Length,
 }
 
 #[derive(Default)]
 pub struct PrintOptions {
     pub copies: u32,
     pub calibration: Option<PrintCalibration>,
+    pub orientation: Orientation,
+    /// Override the darkness of the preamble for this label.
+    pub darkness: Option<usize>,
+    /// Override print and slew speed of the preamble for this label.
+    pub speed: Option<usize>,
+    /// What to do after printing, cutting by default.
+    pub post_print: Option<PostPrintAction>,
+    /// Override the backfeed sequence of the preamble for this label.
+    pub backfeed: Option<BackfeedSequence>,
+    /// How the printer finds the end of the label.
+    pub stock: LabelStock,
+    /// How rasterized content is encoded.
+    pub compression: ImageCompression,
+    /// What to do about content beyond the label or its margins.
+    pub bounds: BoundsPolicy,
+    /// Render the same commands on every machine, see [`RenderOptions::deterministic`].
+    pub deterministic: bool,
+    /// How SVG documents are parsed, see [`RenderOptions::svg`].
+    pub svg: Option<Arc<resvg::usvg::Options<'static>>>,
+    /// Move all content and the home offset into the frame of the printer.
+    pub transform: CoordinateTransform,
+    /// Reuse rasterized content of earlier labels, see [`RenderOptions::cache`].
+    pub cache: Option<Arc<RasterCache>>,
+    /// Whether QR codes are drawn by the printer, see [`RenderOptions::qr`].
+    pub qr: QrRendering,
+    /// How text beyond ASCII is sent, see [`RenderOptions::charset`].
+    pub charset: CharacterSet,
+    /// Print several labels side by side, for media with more than one label across the web.
+    pub across: Option<Across>,
+}
+
+/// Labels side by side across the web, as on stock of small jewelry or asset tags.
+///
+/// Each column is printed from a home position (`^LH`) further right, all in one format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Across {
+    /// Labels in a row, at least one.
+    pub count: u32,
+    /// Width of each label on the web, at least that of the label printed.
+    pub column_width: Length,
+    /// Space between the labels of a row.
+    pub gutter: Length,
+    /// Spread the copies over the columns, counting serial numbers on from one to the next,
+    /// rather than printing each copy as a row of the same label.
+    pub distribute: bool,
+}
+
+impl Across {
+    /// Distance from the left edge of one label to the next, in dots.
+    fn pitch(&self, dpmm: u32) -> u32 {
+        (self.column_width.to_dots(dpmm))
+            .saturating_add(self.gutter.to_dots(dpmm))
+    }
+
+    /// Width of the whole row in dots, from the first label to the end of the last.
+    fn web_width(&self, label: &Label) -> anyhow::Result<u32> {
+        let column = self.column_width.to_dots(label.dpmm);
+        if self.count == 0 {
+            anyhow::bail!("No labels across");
+        }
+        if column < label.width_dots() {
+            anyhow::bail!(
+                "Label of {} dots wider than its column of {column} dots",
+                label.width_dots()
+            );
+        }
+
+        Ok(self.pitch(label.dpmm).saturating_mul(self.count - 1) + column)
+    }
+}
+
+/// The kind of media labels are printed on.
+#[derive(Clone, Debug, Default)]
+pub enum LabelStock {
+    /// Separate labels of the label's height, found by the gaps between them.
+    #[default]
+    Gapped,
+    /// Continuous media such as receipts, cut to the length of the content.
+    ///
+    /// The label ends below the lowest inked row of rasterized content. Content the printer
+    /// renders natively, such as QR codes, is only accounted for by its origin.
+    Continuous { max_length: Length },
+    /// Separate labels of the label's height, found by black marks on the back.
+    ///
+    /// The offset is the distance from the mark to where labels separate, in the printing
+    /// direction.
+    Marked { offset: Length },
+}
+
+impl LabelStock {
+    /// How the printer is to sense the media, in dots of the label.
+    fn tracking(&self, label: &Label) -> anyhow::Result<MediaTracking> {
+        Ok(match self {
+            LabelStock::Gapped => MediaTracking::NonContinuousWebSensing,
+            LabelStock::Continuous { .. } => MediaTracking::Continuous,
+            LabelStock::Marked { offset } => {
+                let offset = offset.to_signed_dots(label.dpmm);
+                if !(-80..=283).contains(&offset) {
+                    anyhow::bail!(
+                        "Mark offset of {offset} dots outside of -80 to 283 dots"
+                    );
+                }
+
+                MediaTracking::NonContinuousMarked(offset as i16)
+            }
+        })
+    }
+}
+
+/// Which way round a label is printed.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+pub struct Orientation {
+    /// Print the label mirrored, e.g. to be applied behind a transparent surface.
+    pub mirrored: Option<Mirroring>,
+    /// Print the label rotated by 180 degrees, e.g. to come out head first when applied.
+    pub flipped: bool,
+}
+
+/// How to produce a mirrored label.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Mirroring {
+    /// Have the printer mirror the whole format (`^PM`).
+    Native,
+    /// Flip the label as one picture ourselves, for firmware without `^PM`.
+    ///
+    /// QR codes are drawn into the picture if built with the `qrcode` feature. Labels with
+    /// other content only the printer draws, such as Aztec codes, are mirrored by `^PM`.
+    Raster,
+}
+
+/// How QR codes are put on a label.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum QrRendering {
+    /// Have the printer draw them (`^BQ`).
+    #[default]
+    Native,
+    /// Draw them ourselves and send them as graphics, for firmware mis-rendering `^BQ`.
+    ///
+    /// These can be mirrored and clipped like other rasterized content.
+    Raster,
+}
+
+/// Options influencing how content is turned into commands.
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// Flip all content horizontally across the label.
+    pub mirror: bool,
+    /// How rasterized content is encoded.
+    pub compression: ImageCompression,
+    /// What to do about content beyond the label or its margins.
+    pub bounds: BoundsPolicy,
+    /// Render the same commands on every machine, for comparison against stored output.
+    ///
+    /// SVG documents are set in the bundled font instead of those installed. Content parsed
+    /// beforehand, such as [`LabelContent::SvgTree`], keeps the fonts it was parsed with, see
+    /// [`crate::util::svg::bundled_options`].
+    pub deterministic: bool,
+    /// How SVG documents are parsed, such as the fonts their text is set in.
+    ///
+    /// The fonts installed on the system unless given. Rasters are cached by the document, so
+    /// clear the cache when the fonts change.
+    pub svg: Option<Arc<resvg::usvg::Options<'static>>>,
+    /// Move all content into the frame of the printer.
+    pub transform: CoordinateTransform,
+    /// Reuse rasterized content of earlier renders.
+    pub cache: Option<Arc<RasterCache>>,
+    /// Whether QR codes are drawn by the printer, see [`crate::quirks`].
+    pub qr: QrRendering,
+    /// How text beyond ASCII is sent to the printer, if any is.
+    ///
+    /// The labels select the set (`^CI`) for themselves, the preamble keeps to ASCII.
+    pub charset: CharacterSet,
+}
+
+impl RenderOptions {
+    /// How SVG documents are parsed, by the bundled font if deterministic.
+    fn svg_options(&self) -> Arc<resvg::usvg::Options<'static>> {
+        match (self.deterministic, &self.svg) {
+            (true, _) => Arc::new(crate::util::svg::bundled_options()),
+            (false, Some(svg)) => svg.clone(),
+            (false, None) => Arc::new(crate::util::svg::system_options()),
+        }
+    }
+}
+
+/// Where a printer has the origin of its labels, for applicators fed from another side.
+///
+/// Positions of content are given from the top left of the label, the transform moves them into
+/// the frame of the printer: axes are swapped first, then mirrored across the label. Content keeps
+/// its orientation, only its placement changes. The size of native content is estimated as by
+/// [`Label::bounding_boxes`].
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoordinateTransform {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub swap_axes: bool,
+}
+
+impl CoordinateTransform {
+    /// The top left corner of content covering an area, within a label of a size in dots.
+    pub fn place(
+        &self,
+        area: &BoundingBox,
+        width: u32,
+        height: u32,
+    ) -> (u32, u32) {
+        let (x, y) = match self.swap_axes {
+            true => (area.y, area.x),
+            false => (area.x, area.y),
+        };
+
+        let x = match self.flip_x {
+            true => width.saturating_sub(x.saturating_add(area.width)),
+            false => x,
+        };
+        let y = match self.flip_y {
+            true => height.saturating_sub(y.saturating_add(area.height)),
+            false => y,
+        };
+
+        (x, y)
+    }
+
+    /// A shift of the whole label, in dots along the x and y axis of the printer.
+    pub fn offset(&self, x: i32, y: i32) -> (i32, i32) {
+        let (x, y) = match self.swap_axes {
+            true => (y, x),
+            false => (x, y),
+        };
+
+        (
+            if self.flip_x { -x } else { x },
+            if self.flip_y { -y } else { y },
+        )
+    }
+}
+
+/// How rendering treats the problems found by [`Label::validate`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BoundsPolicy {
+    /// Log them and render the content as it is, the printer cuts off what lies beyond the label.
+    #[default]
+    Warn,
+    /// Fail on content beyond the label or in its margins.
+    Reject,
+    /// Cut rasterized content off at the margins, fail on native content beyond them.
+    Clip,
+}
+
+/// A problem with the placement of a content item, found by [`Label::validate`].
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Violation {
+    /// The index of the item in the content of the label.
+    pub item: usize,
+    pub severity: Severity,
+    #[serde(flatten)]
+    pub kind: ViolationKind,
+    /// Where the item lies, in dots.
+    pub area: BoundingBox,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// The item extends beyond the edges and is cut off by the printer.
+    OutsideLabel,
+    /// The item extends into the margins, where printing may be unreliable.
+    InMargin,
+    /// Fields the printer renders are printed over another one.
+    Overlap { other: usize },
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let BoundingBox {
+            x,
+            y,
+            width,
+            height,
+        } = self.area;
+        write!(
+            f,
+            "Item {} of {width} by {height} dots at {x}, {y} ",
+            self.item
+        )?;
+
+        match self.kind {
+            ViolationKind::OutsideLabel => {
+                write!(f, "extends beyond the label")
+            }
+            ViolationKind::InMargin => write!(f, "extends into the margins"),
+            ViolationKind::Overlap { other } => {
+                write!(f, "overlaps item {other}")
+            }
+        }
+    }
 }
 
+/// Fine adjustments for where a particular printer puts the content on its labels.
+#[derive(Clone, Debug, Default)]
 pub struct PrintCalibration {
-    pub home_x: Unit,
+    /// Move the content right, or left if negative (`^LH`, `^LS` if negative).
+    pub home_x: Length,
+    /// Move the content down, or up if negative (`^LH`, `^LT` if negative).
+    pub home_y: Length,
+    /// Where labels stop for tearing off, relative to the tear bar (`~TA`).
+    pub tear_off_offset: Option<Length>,
+    /// Print darker or lighter than otherwise, in steps of `~SD`.
+    pub darkness_offset: i32,
+}
+
+/// The area a content item covers on the label, in dots from the top left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl BoundingBox {
+    fn right(&self) -> u32 {
+        self.x.saturating_add(self.width)
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+
+    /// Whether the boxes share any dots.
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    /// Whether the other box lies entirely within this one.
+    pub fn contains(&self, other: &BoundingBox) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// The smallest box containing both.
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+
+        BoundingBox {
+            x,
+            y,
+            width: right.saturating_sub(x),
+            height: bottom.saturating_sub(y),
+        }
+    }
+}
+
+/// Data codewords of a QR code by version, at error correction levels L, M, Q and H.
+const QR_DATA_CODEWORDS: [[usize; 4]; 40] = [
+    [19, 16, 13, 9],
+    [34, 28, 22, 16],
+    [55, 44, 34, 26],
+    [80, 64, 48, 36],
+    [108, 86, 62, 46],
+    [136, 108, 76, 60],
+    [156, 124, 88, 66],
+    [194, 154, 110, 86],
+    [232, 182, 132, 100],
+    [274, 216, 154, 122],
+    [324, 254, 180, 140],
+    [370, 290, 206, 158],
+    [428, 334, 244, 180],
+    [461, 365, 261, 197],
+    [523, 415, 295, 223],
+    [589, 453, 325, 253],
+    [647, 507, 367, 283],
+    [721, 563, 397, 313],
+    [795, 627, 445, 341],
+    [861, 669, 485, 385],
+    [932, 714, 512, 406],
+    [1006, 782, 568, 442],
+    [1094, 860, 614, 464],
+    [1174, 914, 664, 514],
+    [1276, 1000, 718, 538],
+    [1370, 1062, 754, 596],
+    [1468, 1128, 808, 628],
+    [1531, 1193, 871, 661],
+    [1631, 1267, 911, 701],
+    [1735, 1373, 985, 745],
+    [1843, 1455, 1033, 793],
+    [1955, 1541, 1115, 845],
+    [2071, 1631, 1171, 901],
+    [2191, 1725, 1231, 961],
+    [2306, 1812, 1286, 986],
+    [2434, 1914, 1354, 1054],
+    [2566, 1992, 1426, 1096],
+    [2702, 2102, 1502, 1142],
+    [2812, 2216, 1582, 1222],
+    [2956, 2334, 1666, 1276],
+];
+
+/// How much of a QR code may be damaged while it still scans, at the expense of capacity.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum QrErrorCorrection {
+    /// About 7% of the code.
+    Low,
+    /// About 15% of the code.
+    Medium,
+    /// About 25% of the code.
+    #[default]
+    Quartile,
+    /// About 30% of the code.
+    High,
+}
+
+impl QrErrorCorrection {
+    pub const ALL: [QrErrorCorrection; 4] = [
+        QrErrorCorrection::Low,
+        QrErrorCorrection::Medium,
+        QrErrorCorrection::Quartile,
+        QrErrorCorrection::High,
+    ];
+
+    /// The letter naming the level, as in the field data of `^BQ`.
+    pub fn letter(&self) -> char {
+        match self {
+            QrErrorCorrection::Low => 'L',
+            QrErrorCorrection::Medium => 'M',
+            QrErrorCorrection::Quartile => 'Q',
+            QrErrorCorrection::High => 'H',
+        }
+    }
+}
+
+/// The encoding mode of QR code content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QrMode {
+    Numeric,
+    Alphanumeric,
+    Byte,
+}
+
+impl QrMode {
+    /// The most compact mode able to encode the content.
+    pub fn of(content: &str) -> Self {
+        const ALPHANUMERIC_EXTRA: &str = " $%*+-./:";
+
+        if content.bytes().all(|b| b.is_ascii_digit()) {
+            QrMode::Numeric
+        } else if content.chars().all(|c| {
+            c.is_ascii_digit()
+                || c.is_ascii_uppercase()
+                || ALPHANUMERIC_EXTRA.contains(c)
+        }) {
+            QrMode::Alphanumeric
+        } else {
+            QrMode::Byte
+        }
+    }
+
+    /// The most characters of this mode any QR code holds at the level, as we encode them.
+    pub fn capacity(&self, level: QrErrorCorrection) -> usize {
+        self.capacity_of(40, level)
+    }
+
+    fn capacity_of(&self, version: usize, level: QrErrorCorrection) -> usize {
+        let codewords = QR_DATA_CODEWORDS[version - 1][level as usize];
+        // A single segment, its mode indicator and character count before the data.
+        let count_bits = match (self, version) {
+            (QrMode::Numeric, ..=9) => 10,
+            (QrMode::Numeric, ..=26) => 12,
+            (QrMode::Numeric, _) => 14,
+            (QrMode::Alphanumeric, ..=9) => 9,
+            (QrMode::Alphanumeric, ..=26) => 11,
+            (QrMode::Alphanumeric, _) => 13,
+            (QrMode::Byte, ..=9) => 8,
+            (QrMode::Byte, _) => 16,
+        };
+        let bits = codewords * 8 - 4 - count_bits;
+
+        match self {
+            // Groups of three digits in 10 bits, the rest in 4 or 7.
+            QrMode::Numeric => {
+                3 * (bits / 10)
+                    + match bits % 10 {
+                        7.. => 2,
+                        4.. => 1,
+                        _ => 0,
+                    }
+            }
+            // Pairs of characters in 11 bits, a last one in 6.
+            QrMode::Alphanumeric => {
+                2 * (bits / 11) + usize::from(bits % 11 >= 6)
+            }
+            QrMode::Byte => bits / 8,
+        }
+    }
+}
+
+/// The smallest QR code version holding the content at the level, if any does.
+pub fn qr_version(content: &str, level: QrErrorCorrection) -> Option<u32> {
+    let mode = QrMode::of(content);
+    (1..=40)
+        .find(|&version| mode.capacity_of(version, level) >= content.len())
+        .map(|version| version as u32)
+}
+
+/// The zoom and error correction of a QR code filling a square of this many dots.
+///
+/// Modules are made as large as the content allows, then the highest level of error correction
+/// fitting at that zoom is chosen. `None` if the content does not fit even at a single dot per
+/// module.
+pub fn qr_fit(content: &str, size: u32) -> Option<(u32, QrErrorCorrection)> {
+    QrErrorCorrection::ALL
+        .into_iter()
+        .filter_map(|level| {
+            let modules = 17 + 4 * qr_version(content, level)?;
+            let zoom = (size / modules).min(10);
+            (zoom > 0).then_some((zoom, level))
+        })
+        .max()
+}
+
+/// The modules across an Aztec code of the content, as the printer sizes it.
+///
+/// Estimated for content in byte mode, the printer may choose a smaller symbol for text. Beyond
+/// the largest symbol, larger than any.
+fn aztec_modules(content: &str, error_correction: u32) -> u32 {
+    let percent = match error_correction {
+        1..=99 => error_correction,
+        _ => 23,
+    };
+    // Bits of the content, a byte shift before it and three codewords of error correction.
+    let data = 8 * content.len() as u32 + 21;
+    let needed = data * 100 / (100 - percent) + 18;
+
+    // Compact symbols of up to 4 layers, then full-range ones of up to 32.
+    if let Some(layers) =
+        (1..=4).find(|layers| (88 + 16 * layers) * layers >= needed)
+    {
+        return 11 + 4 * layers;
+    }
+
+    let layers = (1..=32)
+        .find(|layers| (112 + 16 * layers) * layers >= needed)
+        .unwrap_or(33);
+    // With lines of the reference grid every 16 modules.
+    let base = 14 + 4 * layers;
+    base + 1 + 2 * ((base / 2 - 1) / 15)
+}
+
+/// The columns and rows of a PDF417 code of the content, estimated for content in byte mode.
+///
+/// The rows are not limited to the 90 of the largest symbol.
+pub(crate) fn pdf417_shape(
+    content: &str,
+    security: u32,
+    columns: u32,
+) -> (u32, u32) {
+    let columns = columns.clamp(1, 30);
+    let bytes = content.len() as u32;
+    // Six bytes in five codewords, a length and a mode latch before them.
+    let data = 2 + 5 * (bytes / 6) + bytes % 6;
+    let codewords = data + (2 << security.min(8));
+    (columns, codewords.div_ceil(columns).max(3))
+}
+
+/// A QR code as a graphic, see [`crate::util::image::qr_code`].
+#[cfg(feature = "qrcode")]
+fn qr_graphic(
+    content: &str,
+    zoom: u32,
+    level: QrErrorCorrection,
+) -> anyhow::Result<::image::DynamicImage> {
+    Ok(crate::util::image::qr_code(content, zoom, level)?)
+}
+
+#[cfg(not(feature = "qrcode"))]
+fn qr_graphic(
+    _: &str,
+    _: u32,
+    _: QrErrorCorrection,
+) -> anyhow::Result<::image::DynamicImage> {
+    anyhow::bail!("Built without the qrcode feature, QR codes can only be drawn by the printer")
+}
+
+/// The characters printed for a clock field format, with placeholders expanded.
+fn clock_field_length(format: &str) -> u32 {
+    let mut length = 0;
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        length += match c {
+            '%' => match chars.next() {
+                Some('Y') => 4,
+                Some('a' | 'b' | 'j') => 3,
+                Some(_) => 2,
+                None => 1,
+            },
+            _ => 1,
+        };
+    }
+
+    length
 }
 
 impl Label {
-    pub fn new(width: u32, height: u32, dpmm: u32) -> Self {
+    /// An empty label, of a width and height in mm.
+    pub fn new(width: f32, height: f32, dpmm: u32) -> Self {
         Self {
             content: vec![],
-            width,
-            height,
+            width: Length::mm(width),
+            height: Length::mm(height),
             dpmm,
+            margins: Margins::default(),
         }
     }
 
-    pub fn unit_to_dots(&self, u: &Unit) -> u32 {
-        match u {
-            Unit::Dots(d) => *d,
-            Unit::Millimetres(mm) => (mm * self.dpmm as f32).floor() as u32,
-        }
+    /// Start a label to be sized and filled step by step, see [`LabelBuilder`].
+    pub fn builder() -> LabelBuilder {
+        LabelBuilder::default()
     }
 
-    pub fn signed_unit_to_dots(&self, u: &Unit) -> i32 {
-        match u {
-            &Unit::Dots(d) => d.try_into().unwrap_or(i32::MAX),
-            // conversion from float to integer is specified to clamp.
-            Unit::Millimetres(mm) => (mm * self.dpmm as f32) as i32,
-        }
+    /// The label width in whole dots.
+    pub fn width_dots(&self) -> u32 {
+        self.width.to_dots(self.dpmm)
     }
 
-    pub async fn render(&self) -> anyhow::Result<command::CommandSequence> {
-        let mut output = CommandSequence(vec![]);
+    /// The label height in whole dots.
+    pub fn height_dots(&self) -> u32 {
+        self.height.to_dots(self.dpmm)
+    }
 
-        for c in &self.content {
-            match c {
-                LabelContent::Image { img, x, y, w, h } => {
-                    let img = img.resize_to_fill(
-                        self.unit_to_dots(w),
-                        self.unit_to_dots(h),
-                        ::image::imageops::FilterType::Lanczos3,
-                    );
+    pub fn render(&self) -> anyhow::Result<command::CommandSequence> {
+        self.render_with(&RenderOptions::default())
+    }
 
-                    let img_serialized =
-                        crate::util::image::SerializedImage::from_image(&img);
+    /// Turn all content into commands, on the calling thread.
+    ///
+    /// Rendering is CPU bound, callers on an async runtime should move it off the runtime.
+    pub fn render_with(
+        &self,
+        options: &RenderOptions,
+    ) -> anyhow::Result<command::CommandSequence> {
+        let (output, _) = self.render_measured(options)?;
+        Ok(output)
+    }
 
-                    output.push(ZplCommand::MoveOrigin(
-                        self.unit_to_dots(x),
-                        self.unit_to_dots(y),
-                    ));
-                    output.push(ZplCommand::RenderImage(img_serialized));
-                }
-                LabelContent::Svg { code, x, y, w, h } => {
-                    let img_serialized =
-                        crate::util::image::SerializedImage::from_svg(
-                            code.to_string(),
-                            self.unit_to_dots(w),
-                            self.unit_to_dots(h),
-                        )
-                        .context("Could not load SVG")?;
+    /// Render all content mirrored as asked, along with how it is mirrored in the end.
+    ///
+    /// Raster mirroring flips the label as one picture, as the printer would. Labels with content
+    /// only the printer draws are left to the printer to mirror after all, by `^PM` in the
+    /// format. `options.mirror` is ignored.
+    pub fn render_mirrored(
+        &self,
+        options: &RenderOptions,
+        mirror: Option<Mirroring>,
+    ) -> anyhow::Result<(CommandSequence, Option<Mirroring>)> {
+        let (output, _, mirror) =
+            self.render_mirrored_measured(options, mirror)?;
+        Ok((output, mirror))
+    }
 
-                    output.push(ZplCommand::MoveOrigin(
-                        self.unit_to_dots(x),
-                        self.unit_to_dots(y),
-                    ));
-                    output.push(ZplCommand::RenderImage(img_serialized));
-                }
-                LabelContent::SvgTree { tree, x, y, w, h } => {
-                    let img_serialized =
-                        crate::util::image::SerializedImage::from_svg_tree(
-                            tree.clone(),
-                            self.unit_to_dots(w),
-                            self.unit_to_dots(h),
-                        )
-                        .context("Could not load SVG")?;
+    fn render_mirrored_measured(
+        &self,
+        options: &RenderOptions,
+        mirror: Option<Mirroring>,
+    ) -> anyhow::Result<(CommandSequence, u32, Option<Mirroring>)> {
+        let composite = match mirror {
+            Some(Mirroring::Raster) => self.composite(options)?,
+            _ => None,
+        };
+        let mirror = match (mirror, &composite) {
+            (Some(Mirroring::Raster), None) => {
+                log::warn!(
+                    "Mirroring by the printer, the label has content only the printer draws"
+                );
+                Some(Mirroring::Native)
+            }
+            (mirror, _) => mirror,
+        };
+        let options = RenderOptions {
+            mirror: mirror == Some(Mirroring::Raster),
+            ..options.clone()
+        };
 
-                    output.push(ZplCommand::MoveOrigin(
-                        self.unit_to_dots(x),
-                        self.unit_to_dots(y),
-                    ));
-                    output.push(ZplCommand::RenderImage(img_serialized));
+        let label = composite.as_ref().unwrap_or(self);
+        let (output, bottom) = label.render_measured(&options)?;
+        Ok((output, bottom, mirror))
+    }
+
+    /// Render all content, along with the row in dots below which nothing is inked.
+    fn render_measured(
+        &self,
+        options: &RenderOptions,
+    ) -> anyhow::Result<(CommandSequence, u32)> {
+        let mut output = CommandSequence(vec![]);
+        let mut bottom = 0;
+
+        let content = self
+            .content
+            .iter()
+            .map(|c| self.substitute(c, options.qr))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let violations = self.validate();
+        let beyond = |violation: &&Violation| match violation.kind {
+            ViolationKind::OutsideLabel | ViolationKind::InMargin => true,
+            ViolationKind::Overlap { .. } => false,
+        };
+
+        match options.bounds {
+            BoundsPolicy::Warn => {
+                for violation in &violations {
+                    log::warn!("{violation}");
                 }
-                LabelContent::QrCode {
-                    content,
-                    x,
-                    y,
-                    zoom,
-                } => {
-                    output.push(ZplCommand::MoveOrigin(
-                        self.unit_to_dots(x),
-                        self.unit_to_dots(y),
+            }
+            BoundsPolicy::Reject => {
+                if let Some(violation) = violations.iter().find(beyond) {
+                    anyhow::bail!("{violation}");
+                }
+            }
+            BoundsPolicy::Clip => {
+                if let Some(violation) = violations
+                    .iter()
+                    .filter(beyond)
+                    .find(|violation| content[violation.item].is_native())
+                {
+                    anyhow::bail!("{violation}, and can not be clipped");
+                }
+            }
+        }
+
+        let beyond_ascii = content
+            .iter()
+            .any(|c| c.field_text().is_some_and(|text| !text.is_ascii()));
+        if beyond_ascii {
+            output.push(ZplCommand::SetEncoding(options.charset.code()));
+        }
+
+        // Items are rasterized and encoded independently, then emitted in order.
+        let rasters = content
+            .par_iter()
+            .map(|c| self.raster_commands(c, options))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for (c, raster) in content.iter().zip(rasters) {
+            if let Some((commands, inked)) = raster {
+                bottom = bottom.max(inked);
+                output.append(commands);
+                continue;
+            }
+
+            // Native content keeps its orientation, and so its height.
+            let area = self.bounding_box(c);
+            let (_, top) = self.to_printer(&area, options);
+            bottom = bottom.max(top.saturating_add(area.height));
+            self.place_native(&mut output, c, 0, options)?;
+        }
+
+        Ok((output, bottom))
+    }
+
+    /// Find content beyond the label or its margins, and native fields over one another.
+    ///
+    /// Rasterized content is composed on purpose, only overlaps of fields the printer renders
+    /// are reported as these become unreadable.
+    pub fn validate(&self) -> Vec<Violation> {
+        let areas = self.bounding_boxes();
+        let label = BoundingBox {
+            x: 0,
+            y: 0,
+            width: self.width_dots(),
+            height: self.height_dots(),
+        };
+        let printable = self.printable_area();
+
+        let mut violations = vec![];
+        for (item, area) in areas.iter().enumerate() {
+            let found = |severity, kind| Violation {
+                item,
+                severity,
+                kind,
+                area: *area,
+            };
+
+            if !label.contains(area) {
+                violations
+                    .push(found(Severity::Error, ViolationKind::OutsideLabel));
+            } else if !printable.contains(area) {
+                violations
+                    .push(found(Severity::Warning, ViolationKind::InMargin));
+            }
+
+            if !self.content[item].is_native() {
+                continue;
+            }
+
+            for (other, other_area) in areas.iter().enumerate().skip(item + 1) {
+                if self.content[other].is_native()
+                    && area.intersects(other_area)
+                {
+                    violations.push(found(
+                        Severity::Warning,
+                        ViolationKind::Overlap { other },
                     ));
-                    output.push(ZplCommand::FieldModeQRCode { zoom: *zoom });
-                    output.push(ZplCommand::FieldData(format!(
-                        "{}A,{}",
-                        "Q", // Error correction level
-                        content
-                    )));
                 }
             }
         }
 
-        Ok(output)
+        violations
+    }
+
+    /// The label without its margins, in dots.
+    fn printable_area(&self) -> BoundingBox {
+        let m = &self.margins;
+        let x = m.left.to_dots(self.dpmm).min(self.width_dots());
+        let y = m.top.to_dots(self.dpmm).min(self.height_dots());
+
+        BoundingBox {
+            x,
+            y,
+            width: (self.width_dots() - x)
+                .saturating_sub(m.right.to_dots(self.dpmm)),
+            height: (self.height_dots() - y)
+                .saturating_sub(m.bottom.to_dots(self.dpmm)),
+        }
     }
 
-    pub async fn print(
+    /// Cut a rasterized item at its position down to the printable area.
+    ///
+    /// `None` if nothing of it remains.
+    fn clip(
         &self,
-        options: &PrintOptions,
-    ) -> anyhow::Result<CommandSequence> {
-        let mut commands = make_preamble();
-        let copies = options.copies;
+        img: ::image::DynamicImage,
+        x: &Length,
+        y: &Length,
+    ) -> Option<(::image::DynamicImage, Length, Length)> {
+        let printable = self.printable_area();
+        let (x, y) = (x.to_dots(self.dpmm), y.to_dots(self.dpmm));
 
-        commands.append(CommandSequence(vec![
-            ZplCommand::StartLabel,
-            ZplCommand::SetPostPrintAction(PostPrintAction::Cut),
-            ZplCommand::SetPrintWidth(self.width * self.dpmm),
-            ZplCommand::SetLabelLength(self.height * self.dpmm),
-            ZplCommand::SetHorizontalShift(0),
-        ]));
+        let left = x.max(printable.x);
+        let top = y.max(printable.y);
+        let right = x.saturating_add(img.width()).min(printable.right());
+        let bottom = y.saturating_add(img.height()).min(printable.bottom());
 
-        if let Some(calib) = &options.calibration {
-            let home_x = self.signed_unit_to_dots(&calib.home_x);
-            commands.append(CommandSequence(vec![
-                ZplCommand::SetVerticalShift(home_x),
-            ]));
+        if left >= right || top >= bottom {
+            return None;
         }
 
-        commands.append(self.render().await?);
-
-        commands.append(CommandSequence(vec![
-            ZplCommand::PrintQuantity {
-                total: copies,
-                pause_and_cut_after: copies,
-                replicates_per_serial: copies,
-                cut_only: true,
-            },
-            ZplCommand::EndLabel,
-        ]));
+        let img = img.crop_imm(left - x, top - y, right - left, bottom - top);
+        Some((img, Length::dots(left), Length::dots(top)))
+    }
 
-        Ok(commands)
+    /// The top left corner of an area of content, in the frame of the printer.
+    fn to_printer(
+        &self,
+        area: &BoundingBox,
+        options: &RenderOptions,
+    ) -> (u32, u32) {
+        options
+            .transform
+            .place(area, self.width_dots(), self.height_dots())
     }
-}
 
-pub fn make_preamble() -> CommandSequence {
-    CommandSequence(vec![
-        ZplCommand::SetDelimiter(','),
-        ZplCommand::SetControlCommandPrefix('~'),
-        ZplCommand::SetFormatCommandPrefix('^'),
-        ZplCommand::SetEncoding(0),
-        ZplCommand::StartLabel,
-        ZplCommand::SetTearOffPosition(0),
-        ZplCommand::SetVerticalShift(0),
-        ZplCommand::SetMediaType(MediaType::Transfer),
-        ZplCommand::SetMediaTracking(MediaTracking::NonContinuousWebSensing),
-        ZplCommand::SetBackfeedSequence(BackfeedSequence::Default),
-        ZplCommand::SetHome(0, 0),
-        ZplCommand::SetDarkness(25),
-        ZplCommand::SetHalfDensity(false),
-        ZplCommand::SetSpeed { print: 4, slew: 4 },
+    /// Emit a content item the printer renders itself, shifted right by some dots.
+    fn place_native(
+        &self,
+        output: &mut CommandSequence,
+        content: &LabelContent,
+        shift: u32,
+        options: &RenderOptions,
+    ) -> anyhow::Result<()> {
+        let (left, top) = self.to_printer(&self.bounding_box(content), options);
+        let origin = ZplCommand::MoveOrigin(left + shift, top);
+
+        match content {
+            LabelContent::Image { .. }
+            | LabelContent::Svg { .. }
+            | LabelContent::SvgTree { .. } => {
+                unreachable!("Rasterized above")
+            }
+            LabelContent::QrCode {
+                content,
+                zoom,
+                error_correction,
+                size,
+                ..
+            } => {
+                if options.mirror {
+                    anyhow::bail!(
+                        "QR codes can only be mirrored by the printer"
+                    );
+                }
+
+                let (zoom, level) =
+                    self.qr_settings(content, *zoom, *error_correction, size)?;
+                output.push(origin.clone());
+                output.push(ZplCommand::FieldModeQRCode {
+                    zoom,
+                    error_correction: level,
+                });
+                output.push(ZplCommand::FieldData(FieldData::qr_code(
+                    content,
+                    level,
+                    options.charset,
+                )?));
+            }
+            LabelContent::Aztec {
+                content,
+                zoom,
+                error_correction,
+                ..
+            } => {
+                if options.mirror {
+                    anyhow::bail!(
+                        "Aztec codes can only be mirrored by the printer"
+                    );
+                }
+
+                output.push(origin.clone());
+                output.push(ZplCommand::FieldModeAztec {
+                    zoom: *zoom,
+                    error_correction: *error_correction,
+                });
+                output.push(ZplCommand::FieldData(FieldData::encoded(
+                    content,
+                    options.charset,
+                )?));
+                output.push(ZplCommand::FieldSeparator);
+            }
+            LabelContent::Pdf417 {
+                content,
+                module_width,
+                row_height,
+                security,
+                columns,
+                ..
+            } => {
+                if options.mirror {
+                    anyhow::bail!(
+                        "PDF417 codes can only be mirrored by the printer"
+                    );
+                }
+
+                output.push(origin.clone());
+                output.push(ZplCommand::BarcodeModuleWidth(*module_width));
+                output.push(ZplCommand::FieldModePdf417 {
+                    row_height: row_height.to_dots(self.dpmm),
+                    security: *security,
+                    columns: *columns,
+                });
+                output.push(ZplCommand::FieldData(FieldData::encoded(
+                    content,
+                    options.charset,
+                )?));
+                output.push(ZplCommand::FieldSeparator);
+            }
+            LabelContent::ClockField { format, height, .. } => {
+                if options.mirror {
+                    anyhow::bail!(
+                        "Clock fields can only be mirrored by the printer"
+                    );
+                }
+
+                let height = height.to_dots(self.dpmm);
+                // Stamp the time of printing, not of processing the label.
+                output.push(ZplCommand::SetClockMode(ClockMode::TimeNow));
+                output.push(origin.clone());
+                output.push(ZplCommand::ScalableFont {
+                    height,
+                    width: height,
+                });
+                output.push(ZplCommand::FieldClock);
+                output.push(ZplCommand::FieldData(FieldData::encoded(
+                    format,
+                    options.charset,
+                )?));
+                output.push(ZplCommand::FieldSeparator);
+            }
+            LabelContent::SerialNumber {
+                start,
+                increment,
+                pad,
+                height,
+                ..
+            } => {
+                if options.mirror {
+                    anyhow::bail!(
+                        "Serial numbers can only be mirrored by the printer"
+                    );
+                }
+
+                let height = height.to_dots(self.dpmm);
+                let width = *pad as usize;
+                output.push(origin.clone());
+                output.push(ZplCommand::ScalableFont {
+                    height,
+                    width: height,
+                });
+
+                if *pad == 0 {
+                    output.push(ZplCommand::SerialNumber {
+                        start: start.to_string(),
+                        increment: *increment,
+                        leading_zeros: false,
+                    });
+                } else {
+                    let Ok(increment) = u64::try_from(*increment) else {
+                        anyhow::bail!(
+                            "Padded serial numbers can only count up"
+                        );
+                    };
+
+                    let start = format!("{start:0width$}");
+                    if start.len() > width {
+                        anyhow::bail!(
+                            "Serial number {start} exceeds {width} digits"
+                        );
+                    }
+
+                    output.push(ZplCommand::FieldData(start.into()));
+                    output.push(ZplCommand::SerializeField {
+                        mask: "d".repeat(width),
+                        increment: increment.to_string(),
+                    });
+                }
+
+                output.push(ZplCommand::FieldSeparator);
+            }
+            LabelContent::Emphasized { content, emphasis } => match emphasis {
+                Emphasis::DoubleStrike => {
+                    self.place_native(output, content, shift, options)?;
+                    self.place_native(output, content, shift + 1, options)?;
+                }
+                Emphasis::Thicken { .. } => {
+                    anyhow::bail!("Only rasterized content can be thickened")
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// The share of the label's dots printed black by the rendered commands, from 0 to 1.
+    ///
+    /// Only rasterized content counts, that which the printer renders itself is left out.
+    pub fn coverage(&self, commands: &CommandSequence) -> f64 {
+        let dots = u64::from(self.width_dots()) * u64::from(self.height_dots());
+        commands.inked_dots() as f64 / dots.max(1) as f64
+    }
+
+    /// The area of each content item, in order, as placed when rendering.
+    ///
+    /// Rasterized content covers its whole box, even where it stays blank. The size of QR codes
+    /// is estimated from their content, as the printer encodes them.
+    pub fn bounding_boxes(&self) -> Vec<BoundingBox> {
+        self.content
+            .iter()
+            .map(|content| self.bounding_box(content))
+            .collect()
+    }
+
+    /// The area covered by all content together, in dots.
+    ///
+    /// `None` for a label without content.
+    pub fn estimate_dots(&self) -> Option<BoundingBox> {
+        self.bounding_boxes()
+            .into_iter()
+            .reduce(|all, next| all.union(&next))
+    }
+
+    fn bounding_box(&self, content: &LabelContent) -> BoundingBox {
+        let (x, y) = content.origin();
+        let (x, y) = (x.to_dots(self.dpmm), y.to_dots(self.dpmm));
+
+        match content {
+            LabelContent::Image { w, h, .. }
+            | LabelContent::Svg { w, h, .. }
+            | LabelContent::SvgTree { w, h, .. } => BoundingBox {
+                x,
+                y,
+                width: w.to_dots(self.dpmm),
+                height: h.to_dots(self.dpmm),
+            },
+            LabelContent::QrCode {
+                content,
+                zoom,
+                error_correction,
+                size,
+                ..
+            } => {
+                let (zoom, level) = self
+                    .qr_settings(content, *zoom, *error_correction, size)
+                    .unwrap_or((*zoom, *error_correction));
+                // Larger than any version if it does not fit.
+                let version = qr_version(content, level).unwrap_or(41);
+                let size = (17 + 4 * version) * zoom;
+
+                BoundingBox {
+                    x,
+                    y,
+                    width: size,
+                    height: size,
+                }
+            }
+            LabelContent::Aztec {
+                content,
+                zoom,
+                error_correction,
+                ..
+            } => {
+                let size = aztec_modules(content, *error_correction) * zoom;
+                BoundingBox {
+                    x,
+                    y,
+                    width: size,
+                    height: size,
+                }
+            }
+            LabelContent::Pdf417 {
+                content,
+                module_width,
+                row_height,
+                security,
+                columns,
+                ..
+            } => {
+                let (columns, rows) =
+                    pdf417_shape(content, *security, *columns);
+                BoundingBox {
+                    x,
+                    y,
+                    // Start, stop and row indicators around the data.
+                    width: (17 * columns + 69) * module_width,
+                    height: rows * row_height.to_dots(self.dpmm),
+                }
+            }
+            LabelContent::ClockField { format, height, .. } => {
+                let height = height.to_dots(self.dpmm);
+                BoundingBox {
+                    x,
+                    y,
+                    // Characters of the scalable font are about 0.6 as wide as high.
+                    width: clock_field_length(format) * height * 3 / 5,
+                    height,
+                }
+            }
+            LabelContent::SerialNumber {
+                start, pad, height, ..
+            } => {
+                let height = height.to_dots(self.dpmm);
+                let digits = start.to_string().len() as u32;
+                BoundingBox {
+                    x,
+                    y,
+                    width: digits.max(*pad) * height * 3 / 5,
+                    height,
+                }
+            }
+            LabelContent::Emphasized { content, emphasis } => {
+                let mut area = self.bounding_box(content);
+                // Printed a second time, a dot to the right.
+                if content.is_native() && *emphasis == Emphasis::DoubleStrike {
+                    area.width += 1;
+                }
+
+                area
+            }
+        }
+    }
+
+    /// Compose all rasterizable content into a picture of the label.
+    ///
+    /// Content the printer renders natively, such as QR codes and clock fields, is left out.
+    pub fn preview(&self) -> anyhow::Result<::image::DynamicImage> {
+        let mut canvas = ::image::GrayImage::from_pixel(
+            self.width_dots(),
+            self.height_dots(),
+            ::image::Luma([255]),
+        );
+
+        for c in &self.content {
+            let Some(img) = self.rasterize(c, &RenderOptions::default())?
+            else {
+                continue;
+            };
+
+            let (x, y) = c.origin();
+            ::image::imageops::overlay(
+                &mut canvas,
+                &img.into_luma8(),
+                x.to_dots(self.dpmm).into(),
+                y.to_dots(self.dpmm).into(),
+            );
+        }
+
+        Ok(canvas.into())
+    }
+
+    /// Replace all rasterized content by a single picture of the label, passed through a change.
+    ///
+    /// Content the printer renders natively is kept as is, after the picture.
+    pub fn flatten(
+        &mut self,
+        change: impl FnOnce(
+            ::image::GrayImage,
+        ) -> anyhow::Result<::image::GrayImage>,
+    ) -> anyhow::Result<()> {
+        let image = change(self.preview()?.into_luma8())?;
+
+        let (width, height) = image.dimensions();
+        let native = self.content.drain(..).filter(LabelContent::is_native);
+        self.content = std::iter::once(LabelContent::Image {
+            img: image.into(),
+            x: Length::dots(0),
+            y: Length::dots(0),
+            w: Length::dots(width),
+            h: Length::dots(height),
+            fit: Fit::Cover,
+        })
+        .chain(native)
+        .collect();
+
+        Ok(())
+    }
+
+    /// All content as one picture, cut down to where it is inked, for raster mirroring.
+    ///
+    /// QR codes are drawn into it if built with the `qrcode` feature. `None` if other content
+    /// only the printer draws is on the label.
+    fn composite(
+        &self,
+        options: &RenderOptions,
+    ) -> anyhow::Result<Option<Label>> {
+        let items = self
+            .content
+            .iter()
+            .map(|c| self.substitute(c, QrRendering::Raster))
+            .collect::<anyhow::Result<Vec<_>>>();
+        let Ok(items) = items else {
+            return Ok(None);
+        };
+        if items.iter().any(|c| c.is_native()) {
+            return Ok(None);
+        }
+
+        let mut canvas = ::image::GrayImage::from_pixel(
+            self.width_dots(),
+            self.height_dots(),
+            ::image::Luma([255]),
+        );
+        for c in &items {
+            let Some(img) = self.rasterize(c, options)? else {
+                continue;
+            };
+
+            let (x, y) = c.origin();
+            ::image::imageops::overlay(
+                &mut canvas,
+                &img.into_luma8(),
+                x.to_dots(self.dpmm).into(),
+                y.to_dots(self.dpmm).into(),
+            );
+        }
+
+        let inked = canvas
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0[0] < 255)
+            .fold(None, |area: Option<(u32, u32, u32, u32)>, (x, y, _)| {
+                Some(match area {
+                    None => (x, y, x, y),
+                    Some((left, top, right, bottom)) => {
+                        (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                    }
+                })
+            });
+
+        let content = inked.map(|(left, top, right, bottom)| {
+            let (width, height) = (right - left + 1, bottom - top + 1);
+            let img =
+                ::image::imageops::crop_imm(&canvas, left, top, width, height);
+            LabelContent::Image {
+                img: img.to_image().into(),
+                x: Length::dots(left),
+                y: Length::dots(top),
+                w: Length::dots(width),
+                h: Length::dots(height),
+                fit: Fit::Stretch,
+            }
+        });
+
+        Ok(Some(Label {
+            content: content.into_iter().collect(),
+            width: self.width,
+            height: self.height,
+            dpmm: self.dpmm,
+            margins: self.margins,
+        }))
+    }
+
+    /// Turn a content item into pixels, at its size on the label.
+    ///
+    /// Returns `None` for content the printer renders natively.
+    fn rasterize(
+        &self,
+        content: &LabelContent,
+        options: &RenderOptions,
+    ) -> anyhow::Result<Option<::image::DynamicImage>> {
+        Ok(Some(match content {
+            LabelContent::Image { img, w, h, fit, .. } => {
+                fit_image(img, w.to_dots(self.dpmm), h.to_dots(self.dpmm), *fit)
+            }
+            LabelContent::Svg {
+                code, w, h, shrink, ..
+            } => {
+                let tree =
+                    resvg::usvg::Tree::from_str(code, &options.svg_options())
+                        .context("Could not load SVG")?;
+                self.render_svg(tree, w, h, *shrink)?
+            }
+            LabelContent::SvgTree {
+                tree, w, h, shrink, ..
+            } => self.render_svg(tree.clone(), w, h, *shrink)?,
+            LabelContent::QrCode { .. }
+            | LabelContent::Aztec { .. }
+            | LabelContent::Pdf417 { .. }
+            | LabelContent::ClockField { .. }
+            | LabelContent::SerialNumber { .. } => return Ok(None),
+            LabelContent::Emphasized { content, emphasis } => {
+                let Some(img) = self.rasterize(content, options)? else {
+                    return Ok(None);
+                };
+
+                crate::util::image::emphasize(&img, *emphasis)
+            }
+        }))
+    }
+
+    fn render_svg(
+        &self,
+        tree: resvg::usvg::Tree,
+        w: &Length,
+        h: &Length,
+        shrink: bool,
+    ) -> anyhow::Result<::image::DynamicImage> {
+        let (w, h) = (w.to_dots(self.dpmm), h.to_dots(self.dpmm));
+        match shrink {
+            true => crate::util::svg::render_svg_tree_shrunk(tree, w, h),
+            false => crate::util::svg::render_svg_tree(tree, w, h),
+        }
+        .context("Could not load SVG")
+    }
+
+    /// The zoom and error correction a QR code is drawn with, chosen to fill its size if given.
+    fn qr_settings(
+        &self,
+        content: &str,
+        zoom: u32,
+        error_correction: QrErrorCorrection,
+        size: &Option<Length>,
+    ) -> anyhow::Result<(u32, QrErrorCorrection)> {
+        let Some(size) = size else {
+            return Ok((zoom, error_correction));
+        };
+
+        let dots = size.to_dots(self.dpmm);
+        qr_fit(content, dots).ok_or_else(|| {
+            anyhow::anyhow!("QR code does not fit within {dots} dots")
+        })
+    }
+
+    /// The item as it is rendered, with QR codes drawn as images unless the printer draws them.
+    fn substitute<'a>(
+        &self,
+        item: &'a LabelContent,
+        qr: QrRendering,
+    ) -> anyhow::Result<Cow<'a, LabelContent>> {
+        Ok(match (item, qr) {
+            (
+                LabelContent::QrCode {
+                    content,
+                    x,
+                    y,
+                    zoom,
+                    error_correction,
+                    size,
+                },
+                QrRendering::Raster,
+            ) => {
+                let (zoom, level) =
+                    self.qr_settings(content, *zoom, *error_correction, size)?;
+                let img = qr_graphic(content, zoom, level)?;
+                let size = Length::dots(img.width());
+                Cow::Owned(LabelContent::Image {
+                    img,
+                    x: *x,
+                    y: *y,
+                    w: size,
+                    h: size,
+                    fit: Fit::Stretch,
+                })
+            }
+            (LabelContent::Emphasized { content, emphasis }, _) => {
+                match self.substitute(content, qr)? {
+                    Cow::Borrowed(_) => Cow::Borrowed(item),
+                    Cow::Owned(substituted) => {
+                        Cow::Owned(LabelContent::Emphasized {
+                            content: Box::new(substituted),
+                            emphasis: *emphasis,
+                        })
+                    }
+                }
+            }
+            _ => Cow::Borrowed(item),
+        })
+    }
+
+    /// Rasterize a content item and emit it at its position, with the lowest row inked.
+    ///
+    /// Native content is left to [`Label::place_native`], as `None`.
+    fn raster_commands(
+        &self,
+        content: &LabelContent,
+        options: &RenderOptions,
+    ) -> anyhow::Result<Option<(CommandSequence, u32)>> {
+        if content.is_native() {
+            return Ok(None);
+        }
+
+        let cache = options.cache.as_deref().and_then(|cache| {
+            Some((cache, self.raster_key(content, options)?))
+        });
+        let raster = match cache.and_then(|(cache, key)| cache.get(key)) {
+            Some(raster) => Some(raster),
+            None => {
+                let raster = self.encode_raster(content, options)?;
+                if let (Some((cache, key)), Some(raster)) = (cache, &raster) {
+                    cache.insert(key, raster.clone());
+                }
+                raster
+            }
+        };
+
+        // Clipped away entirely.
+        let Some(raster) = raster else {
+            return Ok(Some((CommandSequence(vec![]), 0)));
+        };
+
+        let commands = CommandSequence(vec![
+            ZplCommand::MoveOrigin(raster.x, raster.y),
+            ZplCommand::RenderImage(raster.image),
+        ]);
+        Ok(Some((commands, raster.inked)))
+    }
+
+    /// Rasterize and encode a content item, `None` if nothing of it remains after clipping.
+    fn encode_raster(
+        &self,
+        content: &LabelContent,
+        options: &RenderOptions,
+    ) -> anyhow::Result<Option<CachedRaster>> {
+        let (x, y) = content.origin();
+        let Some(img) = self.rasterize(content, options)? else {
+            return Ok(None);
+        };
+
+        let (img, x, y) = match options.bounds {
+            BoundsPolicy::Clip => match self.clip(img, x, y) {
+                Some(clipped) => clipped,
+                None => return Ok(None),
+            },
+            _ => (img, *x, *y),
+        };
+
+        let area = BoundingBox {
+            x: x.to_dots(self.dpmm),
+            y: y.to_dots(self.dpmm),
+            width: img.width(),
+            height: img.height(),
+        };
+        let (x, y) = self.to_printer(&area, options);
+        let inked = y + inked_rows(&img);
+
+        let (img, x) = if options.mirror {
+            // Mirror the item itself and its position across the label.
+            let right = x + img.width();
+            let x = self.width_dots().saturating_sub(right);
+            (img.fliph(), x)
+        } else {
+            (img, x)
+        };
+
+        Ok(Some(CachedRaster {
+            x,
+            y,
+            image: SerializedImage::with_compression(&img, options.compression),
+            inked,
+        }))
+    }
+
+    /// A hash of all that goes into the raster of an item, if it can be hashed.
+    fn raster_key(
+        &self,
+        content: &LabelContent,
+        options: &RenderOptions,
+    ) -> Option<u64> {
+        let mut hasher = std::hash::DefaultHasher::new();
+        content.hash_raster(self.dpmm, &mut hasher)?;
+
+        let printable = self.printable_area();
+        (self.dpmm, self.width_dots(), self.height_dots()).hash(&mut hasher);
+        (printable.x, printable.y, printable.width, printable.height)
+            .hash(&mut hasher);
+        (
+            options.mirror,
+            options.compression,
+            options.bounds,
+            options.deterministic,
+            options.transform,
+        )
+            .hash(&mut hasher);
+
+        Some(hasher.finish())
+    }
+
+    pub fn print(
+        &self,
+        options: &PrintOptions,
+    ) -> anyhow::Result<CommandSequence> {
+        let mut commands = make_preamble(options.stock.tracking(self)?);
+        let copies = options.copies;
+
+        // These follow the preamble, which resets them for the next label.
+        let darkness_offset = options
+            .calibration
+            .as_ref()
+            .map_or(0, |calibration| calibration.darkness_offset);
+        if options.darkness.is_some() || darkness_offset != 0 {
+            let darkness = options.darkness.unwrap_or(PREAMBLE_DARKNESS) as i32;
+            commands.push(ZplCommand::SetDarkness(
+                (darkness + darkness_offset).clamp(0, 30) as usize,
+            ));
+        }
+
+        if let Some(tear_off) = options
+            .calibration
+            .as_ref()
+            .and_then(|calibration| calibration.tear_off_offset)
+        {
+            let tear_off = tear_off.to_signed_dots(self.dpmm);
+            if !(-120..=120).contains(&tear_off) {
+                anyhow::bail!(
+                    "Tear-off offset of {tear_off} dots outside of -120 to 120 dots"
+                );
+            }
+            commands.push(ZplCommand::SetTearOffPosition(tear_off as isize));
+        }
+
+        if let Some(backfeed) = &options.backfeed {
+            commands.push(ZplCommand::SetBackfeedSequence(backfeed.clone()));
+        }
+
+        let post_print = options.post_print.clone();
+
+        let render = RenderOptions {
+            mirror: options.orientation.mirrored == Some(Mirroring::Raster),
+            compression: options.compression,
+            bounds: options.bounds,
+            deterministic: options.deterministic,
+            svg: options.svg.clone(),
+            transform: options.transform,
+            cache: options.cache.clone(),
+            qr: options.qr,
+            charset: options.charset,
+        };
+
+        let (content, bottom, mirror) = self
+            .render_mirrored_measured(&render, options.orientation.mirrored)?;
+
+        let length = match &options.stock {
+            LabelStock::Gapped | LabelStock::Marked { .. } => {
+                self.height_dots()
+            }
+            LabelStock::Continuous { max_length } => {
+                let max_length = max_length.to_dots(self.dpmm);
+                if bottom > max_length {
+                    anyhow::bail!(
+                        "Content of {bottom} dots exceeds the maximum length of {max_length} dots"
+                    );
+                }
+
+                bottom.max(1)
+            }
+        };
+
+        let (width, formats) = match &options.across {
+            None => (self.width_dots(), vec![(vec![content], copies)]),
+            Some(across) => {
+                let width = across.web_width(self)?;
+                (width, self.columns(across, content, copies, &render)?)
+            }
+        };
+
+        let serialized = self.content.iter().any(LabelContent::is_serialized);
+        for (columns, copies) in formats {
+            commands.push(ZplCommand::StartLabel);
+
+            commands.append(CommandSequence(vec![
+                ZplCommand::SetPostPrintAction(
+                    post_print.clone().unwrap_or(PostPrintAction::Cut),
+                ),
+                ZplCommand::SetPrintWidth(width),
+                ZplCommand::SetLabelLength(length),
+                ZplCommand::SetHorizontalShift(0),
+                // Always explicit, the setting would otherwise carry over from
+                // an earlier mirrored label.
+                ZplCommand::SetMirrored(mirror == Some(Mirroring::Native)),
+                ZplCommand::SetFlipped(options.orientation.flipped),
+            ]));
+
+            if let Some(speed) = options.speed {
+                commands.push(ZplCommand::SetSpeed {
+                    print: speed,
+                    slew: speed,
+                });
+            }
+
+            let (x, y) = match &options.calibration {
+                Some(calib) => options.transform.offset(
+                    calib.home_x.to_signed_dots(self.dpmm),
+                    calib.home_y.to_signed_dots(self.dpmm),
+                ),
+                None => (0, 0),
+            };
+
+            // The home position can only move into the label, shifts move it out.
+            if options.across.is_none() && (x > 0 || y > 0) {
+                commands.push(ZplCommand::SetHome(
+                    x.max(0) as u32,
+                    y.max(0) as u32,
+                ));
+            }
+            if x < 0 {
+                commands.push(ZplCommand::SetHorizontalShift(-x));
+            }
+            if y < 0 {
+                let shift = PREAMBLE_VERTICAL_SHIFT + y;
+                if shift < -120 {
+                    anyhow::bail!(
+                        "Home position of {y} dots beyond the label top"
+                    );
+                }
+                commands.push(ZplCommand::SetVerticalShift(shift));
+            }
+
+            for (column, content) in columns.into_iter().enumerate() {
+                // Each column is a label of its own, from a home further right.
+                if let Some(across) = &options.across {
+                    let pitch = across.pitch(self.dpmm) * column as u32;
+                    commands.push(ZplCommand::SetHome(
+                        x.max(0) as u32 + pitch,
+                        y.max(0) as u32,
+                    ));
+                }
+                commands.append(content);
+            }
+
+            commands.append(CommandSequence(vec![
+                ZplCommand::PrintQuantity {
+                    total: copies,
+                    pause_and_cut_after: copies,
+                    // Otherwise all copies repeat the first serial number.
+                    replicates_per_serial: if serialized { 0 } else { copies },
+                    cut_only: true,
+                },
+                ZplCommand::EndLabel,
+            ]));
+        }
+
+        Ok(commands)
+    }
+
+    /// The content of each column of the formats printing the copies, along with the copies
+    /// of each format.
+    fn columns(
+        &self,
+        across: &Across,
+        content: CommandSequence,
+        copies: u32,
+        options: &RenderOptions,
+    ) -> anyhow::Result<Vec<(Vec<CommandSequence>, u32)>> {
+        let count = across.count;
+        if !across.distribute {
+            let columns = (0..count)
+                .map(|_| CommandSequence(content.0.clone()))
+                .collect();
+            return Ok(vec![(columns, copies)]);
+        }
+
+        // Serial numbers count on from one column to the next.
+        let serialized = self.content.iter().any(LabelContent::is_serialized);
+        let column = |label: u32| -> anyhow::Result<CommandSequence> {
+            if !serialized {
+                return Ok(CommandSequence(content.0.clone()));
+            }
+
+            let content = self
+                .content
+                .iter()
+                .map(|item| item.counted_on(label.into(), count))
+                .collect::<anyhow::Result<_>>()?;
+            let label = Label {
+                content,
+                width: self.width,
+                height: self.height,
+                dpmm: self.dpmm,
+                margins: self.margins,
+            };
+            Ok(label.render_measured(options)?.0)
+        };
+
+        // A partial row of the remaining copies takes a format of its own.
+        let copies = copies.max(1);
+        let (rows, rest) = (copies / count, copies % count);
+        let mut formats = vec![];
+        if rows > 0 {
+            let columns =
+                (0..count).map(&column).collect::<anyhow::Result<_>>()?;
+            formats.push((columns, rows));
+        }
+        if rest > 0 {
+            let columns = (0..rest)
+                .map(|index| column(rows * count + index))
+                .collect::<anyhow::Result<_>>()?;
+            formats.push((columns, 1));
+        }
+
+        Ok(formats)
+    }
+}
+
+/// Scale an image to the size of its box.
+fn fit_image(
+    img: &::image::DynamicImage,
+    width: u32,
+    height: u32,
+    fit: Fit,
+) -> ::image::DynamicImage {
+    use ::image::imageops::FilterType::Lanczos3;
+
+    match fit {
+        Fit::Cover => img.resize_to_fill(width, height, Lanczos3),
+        Fit::Stretch => img.resize_exact(width, height, Lanczos3),
+        Fit::Contain => {
+            let scaled = img.resize(width, height, Lanczos3);
+            let mut canvas = ::image::RgbaImage::from_pixel(
+                width,
+                height,
+                ::image::Rgba([255; 4]),
+            );
+            ::image::imageops::overlay(
+                &mut canvas,
+                &scaled,
+                ((width - scaled.width()) / 2).into(),
+                ((height - scaled.height()) / 2).into(),
+            );
+            canvas.into()
+        }
+    }
+}
+
+/// The number of rows from the top down to the last one with any ink.
+fn inked_rows(img: &::image::DynamicImage) -> u32 {
+    let img = img.to_luma8();
+
+    img.rows()
+        .rposition(|mut row| row.any(|pixel| pixel.0[0] < 255))
+        .map_or(0, |last| last as u32 + 1)
+}
+
+/// The darkness set by the preamble, which calibrations and jobs adjust.
+const PREAMBLE_DARKNESS: usize = 25;
+
+/// The shift of the label down, which the preamble adjusts all labels by.
+const PREAMBLE_VERTICAL_SHIFT: i32 = 12;
+
+pub fn make_preamble(tracking: MediaTracking) -> CommandSequence {
+    CommandSequence(vec![
+        ZplCommand::SetDelimiter(','),
+        ZplCommand::SetControlCommandPrefix('~'),
+        ZplCommand::SetFormatCommandPrefix('^'),
+        ZplCommand::SetEncoding(0),
+        ZplCommand::StartLabel,
+        ZplCommand::SetTearOffPosition(0),
+        ZplCommand::SetVerticalShift(0),
+        ZplCommand::SetMediaType(MediaType::Transfer),
+        ZplCommand::SetMediaTracking(tracking),
+        ZplCommand::SetBackfeedSequence(BackfeedSequence::Default),
+        ZplCommand::SetHome(0, 0),
+        ZplCommand::SetDarkness(PREAMBLE_DARKNESS),
+        ZplCommand::SetHalfDensity(false),
+        ZplCommand::SetSpeed { print: 4, slew: 4 },
         ZplCommand::PersistConfiguration,
         ZplCommand::SetInverted(false),
         // Adjustments
-        ZplCommand::SetVerticalShift(12),
+        ZplCommand::SetVerticalShift(PREAMBLE_VERTICAL_SHIFT),
         ZplCommand::SetTearOffPosition(-20),
         ZplCommand::EndLabel,
     ])
 }
+
+#[test]
+fn fractional_dimensions() {
+    // 2.25" x 1.25" stock.
+    let label = Label::new(57.15, 31.75, 8);
+    assert_eq!(label.width_dots(), 457);
+    assert_eq!(label.height_dots(), 254);
+}
+
+#[test]
+fn preview_composes_content() {
+    let mut label = Label::new(10.0, 10.0, 8);
+    label.content.push(LabelContent::Image {
+        img: ::image::GrayImage::new(4, 4).into(),
+        x: Length::mm(1.0),
+        y: Length::mm(1.0),
+        w: Length::dots(4),
+        h: Length::dots(4),
+        fit: Fit::Cover,
+    });
+
+    let preview = label.preview().unwrap().into_luma8();
+    assert_eq!(preview.dimensions(), (80, 80));
+    assert_eq!(preview.get_pixel(0, 0).0, [255]);
+    assert_eq!(preview.get_pixel(9, 9).0, [0]);
+}
+
+#[test]
+fn double_strike_native_content() {
+    let mut label = Label::new(10.0, 10.0, 8);
+    label.content.push(
+        LabelContent::QrCode {
+            content: "zpl".to_string(),
+            x: Length::dots(10),
+            y: Length::dots(20),
+            zoom: 2,
+            error_correction: QrErrorCorrection::Quartile,
+            size: None,
+        }
+        .emphasized(Emphasis::DoubleStrike),
+    );
+
+    let commands = label.render().unwrap();
+    let origins: Vec<_> = commands
+        .0
+        .iter()
+        .filter_map(|command| match command {
+            ZplCommand::MoveOrigin(x, y) => Some((*x, *y)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(origins, [(10, 20), (11, 20)]);
+}
+
+#[test]
+#[cfg(feature = "qrcode")]
+fn raster_qr_codes() {
+    let mut label = Label::new(10.0, 10.0, 8);
+    label.content.push(LabelContent::QrCode {
+        content: "zpl".to_string(),
+        x: Length::dots(10),
+        y: Length::dots(20),
+        zoom: 2,
+        error_correction: QrErrorCorrection::Quartile,
+        size: None,
+    });
+
+    let options = RenderOptions {
+        qr: QrRendering::Raster,
+        mirror: true,
+        ..RenderOptions::default()
+    };
+    let commands = label.render_with(&options).unwrap();
+
+    // Version 1 at two dots per module, mirrored across the 80 dots of the label.
+    assert!(matches!(commands.0[0], ZplCommand::MoveOrigin(28, 20)));
+    match &commands.0[1] {
+        ZplCommand::RenderImage(image) => assert_eq!(image.bytes_per_row, 6),
+        command => panic!("Expected a graphic, got {command}"),
+    }
+    assert_eq!(commands.0.len(), 2);
+}
+
+#[test]
+fn utf8_field_data() {
+    let mut label = Label::new(10.0, 10.0, 8);
+    label.content.push(LabelContent::QrCode {
+        content: "Müller".to_string(),
+        x: Length::dots(10),
+        y: Length::dots(20),
+        zoom: 2,
+        error_correction: QrErrorCorrection::Quartile,
+        size: None,
+    });
+
+    let commands = label.render_with(&RenderOptions::default()).unwrap();
+    assert!(matches!(commands.0[0], ZplCommand::SetEncoding(28)));
+    assert!(commands.to_string().contains("^FH_^FDQM,B0007M_C3_BCller"));
+
+    let ascii = RenderOptions {
+        charset: CharacterSet::Ascii,
+        ..RenderOptions::default()
+    };
+    assert!(label.render_with(&ascii).is_err());
+}
+
+#[test]
+fn calibration_offsets() {
+    let label = Label::new(10.0, 10.0, 8);
+    let options = PrintOptions {
+        calibration: Some(PrintCalibration {
+            home_x: Length::mm(2.0),
+            home_y: Length::mm(-0.5),
+            tear_off_offset: Some(Length::mm(-1.25)),
+            darkness_offset: 3,
+        }),
+        darkness: Some(20),
+        ..PrintOptions::default()
+    };
+
+    let commands = label.print(&options).unwrap().to_string();
+    assert!(commands.contains("~SD23\n~TA-010"));
+    assert!(commands.contains("^LH16,0\n^LT8"));
+    assert!(!commands.contains("^LS16"));
+}
+
+#[test]
+fn continuous_length() {
+    let mut img = ::image::GrayImage::from_pixel(8, 40, ::image::Luma([255]));
+    img.put_pixel(0, 9, ::image::Luma([0]));
+
+    let mut label = Label::new(10.0, 100.0, 8);
+    label.content.push(LabelContent::Image {
+        img: img.into(),
+        x: Length::dots(0),
+        y: Length::dots(30),
+        w: Length::dots(8),
+        h: Length::dots(40),
+        fit: Fit::Cover,
+    });
+
+    let mut options = PrintOptions {
+        copies: 1,
+        stock: LabelStock::Continuous {
+            max_length: Length::mm(50.0),
+        },
+        ..PrintOptions::default()
+    };
+
+    let commands = label.print(&options).unwrap().to_string();
+    assert!(commands.contains("^MNN"));
+    assert!(commands.contains("^LL0040"));
+
+    options.stock = LabelStock::Continuous {
+        max_length: Length::dots(20),
+    };
+    assert!(label.print(&options).is_err());
+
+    // Fields the printer renders count to their lower edge.
+    label.content.push(LabelContent::SerialNumber {
+        start: 1,
+        increment: 1,
+        pad: 0,
+        x: Length::dots(0),
+        y: Length::dots(60),
+        height: Length::dots(16),
+    });
+    options.stock = LabelStock::Continuous {
+        max_length: Length::mm(50.0),
+    };
+    let commands = label.print(&options).unwrap().to_string();
+    assert!(commands.contains("^LL0076"));
+}
+
+#[test]
+fn marked_media() {
+    let label = Label::new(10.0, 10.0, 8);
+    let mut options = PrintOptions {
+        copies: 1,
+        stock: LabelStock::Marked {
+            offset: Length::mm(-2.0),
+        },
+        ..PrintOptions::default()
+    };
+
+    let commands = label.print(&options).unwrap().to_string();
+    assert!(commands.contains("^MNM,-16"));
+    assert!(!commands.contains("^MNW"));
+    assert!(commands.contains("^PON"));
+
+    options.stock = LabelStock::Marked {
+        offset: Length::dots(300),
+    };
+    assert!(label.print(&options).is_err());
+}
+
+#[test]
+fn content_bounding_boxes() {
+    let mut label = Label::new(20.0, 20.0, 8);
+    label.content.push(LabelContent::Image {
+        img: ::image::GrayImage::new(4, 4).into(),
+        x: Length::mm(1.0),
+        y: Length::dots(2),
+        w: Length::dots(10),
+        h: Length::mm(2.0),
+        fit: Fit::Cover,
+    });
+    label.content.push(
+        LabelContent::QrCode {
+            content: "zpl".to_string(),
+            x: Length::dots(40),
+            y: Length::dots(50),
+            zoom: 2,
+            error_correction: QrErrorCorrection::Quartile,
+            size: None,
+        }
+        .emphasized(Emphasis::DoubleStrike),
+    );
+
+    let boxes = label.bounding_boxes();
+    assert_eq!(
+        boxes[0],
+        BoundingBox {
+            x: 8,
+            y: 2,
+            width: 10,
+            height: 16
+        }
+    );
+    // Version 1 has 21 modules.
+    assert_eq!((boxes[1].width, boxes[1].height), (43, 42));
+
+    let all = label.estimate_dots().unwrap();
+    assert_eq!((all.x, all.y, all.width, all.height), (8, 2, 75, 90));
+
+    let far = BoundingBox {
+        x: u32::MAX - 1,
+        y: 0,
+        width: 10,
+        height: 1,
+    };
+    assert_eq!(all.union(&far).width, u32::MAX - 8);
+}
+
+#[test]
+fn validate_and_clip() {
+    let mut label = Label::new(10.0, 10.0, 8);
+    label.margins.right = Length::mm(1.0);
+    label.content.push(LabelContent::Image {
+        img: ::image::GrayImage::new(4, 4).into(),
+        x: Length::dots(56),
+        y: Length::dots(0),
+        w: Length::dots(24),
+        h: Length::dots(16),
+        fit: Fit::Cover,
+    });
+    let clock = |x| LabelContent::ClockField {
+        format: "%H:%M".to_string(),
+        x: Length::dots(x),
+        y: Length::dots(40),
+        height: Length::dots(10),
+    };
+    label.content.push(clock(0));
+    label.content.push(clock(10));
+
+    let violations = label.validate();
+    assert_eq!(violations.len(), 2);
+    assert_eq!(violations[0].kind, ViolationKind::InMargin);
+    assert_eq!(violations[1].kind, ViolationKind::Overlap { other: 2 });
+
+    let reject = RenderOptions {
+        bounds: BoundsPolicy::Reject,
+        ..RenderOptions::default()
+    };
+    assert!(label.render_with(&reject).is_err());
+
+    let clip = RenderOptions {
+        bounds: BoundsPolicy::Clip,
+        ..RenderOptions::default()
+    };
+    let commands = label.render_with(&clip).unwrap();
+    let ZplCommand::RenderImage(image) = &commands.0[1] else {
+        panic!("Expected the image first");
+    };
+    // Cut off at the margin of 8 dots, 16 of 24 dots remain.
+    assert_eq!(image.bytes_per_row, 2);
+
+    label.content[0] = clock(76);
+    assert_eq!(label.validate()[0].kind, ViolationKind::OutsideLabel);
+    assert!(label.render_with(&clip).is_err());
+}
+
+#[test]
+fn coordinate_transforms() {
+    let mut label = Label::new(10.0, 10.0, 8);
+    label.content.push(LabelContent::ClockField {
+        format: "%H:%M".to_string(),
+        x: Length::dots(0),
+        y: Length::dots(40),
+        height: Length::dots(10),
+    });
+    let width = label.bounding_box(&label.content[0]).width;
+
+    let origin = |transform| {
+        let options = RenderOptions {
+            transform,
+            ..RenderOptions::default()
+        };
+        let commands = label.render_with(&options).unwrap();
+        match commands.0[1] {
+            ZplCommand::MoveOrigin(x, y) => (x, y),
+            _ => panic!("Expected the field origin first"),
+        }
+    };
+
+    assert_eq!(origin(CoordinateTransform::default()), (0, 40));
+    let flip_x = CoordinateTransform {
+        flip_x: true,
+        ..CoordinateTransform::default()
+    };
+    assert_eq!(origin(flip_x), (80 - width, 40));
+    let swap = CoordinateTransform {
+        swap_axes: true,
+        ..CoordinateTransform::default()
+    };
+    assert_eq!(origin(swap), (40, 0));
+
+    assert_eq!(flip_x.offset(16, 0), (-16, 0));
+    assert_eq!(swap.offset(16, 0), (0, 16));
+}
+
+#[test]
+fn rasters_keep_their_order() {
+    let mut label = Label::new(40.0, 40.0, 8);
+    for idx in 0..8 {
+        label.content.push(LabelContent::Image {
+            img: ::image::GrayImage::new(4, 4).into(),
+            x: Length::dots(idx * 32),
+            y: Length::dots(idx * 8),
+            w: Length::dots(16),
+            h: Length::dots(8),
+            fit: Fit::Cover,
+        });
+    }
+
+    let commands = label.render().unwrap();
+    let origins: Vec<_> = commands
+        .0
+        .iter()
+        .filter_map(|command| match command {
+            ZplCommand::MoveOrigin(x, y) => Some((*x, *y)),
+            _ => None,
+        })
+        .collect();
+    let expected: Vec<_> = (0..8).map(|idx| (idx * 32, idx * 8)).collect();
+    assert_eq!(origins, expected);
+}
+
+#[test]
+fn cached_rasters() {
+    let mut label = Label::new(20.0, 10.0, 8);
+    label.content.push(LabelContent::Svg {
+        code: r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2">
+            <rect width="2" height="2"/></svg>"#
+            .to_string(),
+        x: Length::dots(8),
+        y: Length::dots(8),
+        w: Length::dots(64),
+        h: Length::dots(32),
+        shrink: false,
+    });
+
+    let cache = Arc::new(RasterCache::new(8));
+    let options = RenderOptions {
+        cache: Some(cache.clone()),
+        ..RenderOptions::default()
+    };
+
+    let first = label.render_with(&options).unwrap().to_string();
+    let second = label.render_with(&options).unwrap().to_string();
+    assert_eq!(first, second);
+    assert_eq!(first, label.render().unwrap().to_string());
+    assert_eq!((cache.statistics().hits, cache.statistics().misses), (1, 1));
+
+    let mirrored = RenderOptions {
+        mirror: true,
+        ..options
+    };
+    label.render_with(&mirrored).unwrap();
+    assert_eq!(cache.statistics().entries, 2);
+}
+
+#[test]
+fn deterministic_fonts() {
+    let mut label = Label::new(20.0, 10.0, 8);
+    label.content.push(LabelContent::Svg {
+        code: r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20">
+            <text x="2" y="16" font-family="No Such Font" font-size="16">Zpl</text>
+        </svg>"#
+            .to_string(),
+        x: Length::ZERO,
+        y: Length::ZERO,
+        w: Length::mm(20.0),
+        h: Length::mm(10.0),
+        shrink: false,
+    });
+
+    let options = RenderOptions {
+        deterministic: true,
+        ..RenderOptions::default()
+    };
+    let first = label.render_with(&options).unwrap();
+    let second = label.render_with(&options).unwrap();
+
+    // Set in the bundled font, though the family is not installed anywhere.
+    assert!(first.inked_dots() > 0);
+    assert_eq!(String::from(first), String::from(second));
+
+    // Without any fonts given, the text is left out.
+    let fontless = RenderOptions {
+        svg: Some(Arc::new(resvg::usvg::Options::default())),
+        ..RenderOptions::default()
+    };
+    assert_eq!(label.render_with(&fontless).unwrap().inked_dots(), 0);
+}
+
+#[test]
+fn clock_field() {
+    let mut label = Label::new(20.0, 20.0, 8);
+    label.content.push(LabelContent::ClockField {
+        format: "%Y-%m-%d".to_string(),
+        x: Length::dots(10),
+        y: Length::dots(20),
+        height: Length::dots(30),
+    });
+
+    let commands = String::from(label.render().unwrap());
+    assert!(commands
+        .ends_with("^SLT\n^FO10,20\n^A0N,30,30\n^FC%\n^FD%Y-%m-%d\n^FS"));
+    // Ten characters once stamped.
+    assert_eq!(label.bounding_boxes()[0].width, 180);
+}
+
+#[test]
+fn serial_number() {
+    let mut label = Label::new(20.0, 20.0, 8);
+    label.content.push(LabelContent::SerialNumber {
+        start: 7,
+        increment: 2,
+        pad: 4,
+        x: Length::dots(10),
+        y: Length::dots(20),
+        height: Length::dots(30),
+    });
+
+    let options = PrintOptions {
+        copies: 5,
+        ..Default::default()
+    };
+    let commands = String::from(label.print(&options).unwrap());
+    assert!(commands.contains("^FO10,20\n^A0N,30,30\n^FD0007\n^SFdddd,2\n^FS"));
+    assert!(commands.contains("^PQ5,5,0,Y"));
+
+    label.content[0] = LabelContent::SerialNumber {
+        start: 100,
+        increment: -1,
+        pad: 0,
+        x: Length::dots(10),
+        y: Length::dots(20),
+        height: Length::dots(30),
+    };
+    let commands = String::from(label.render().unwrap());
+    assert!(commands.ends_with("^SN100,-1,N\n^FS"));
+    assert_eq!(label.bounding_boxes()[0].width, 54);
+}
+
+#[test]
+fn labels_across() {
+    let mut label = Label::new(20.0, 10.0, 8);
+    label.content.push(LabelContent::SerialNumber {
+        start: 7,
+        increment: 2,
+        pad: 4,
+        x: Length::dots(10),
+        y: Length::dots(20),
+        height: Length::dots(30),
+    });
+
+    let mut options = PrintOptions {
+        copies: 7,
+        across: Some(Across {
+            count: 3,
+            column_width: Length::mm(22.0),
+            gutter: Length::mm(2.0),
+            distribute: false,
+        }),
+        ..Default::default()
+    };
+
+    // Seven rows of the same three labels.
+    let commands = String::from(label.print(&options).unwrap());
+    assert!(commands.contains("^PW560\n"));
+    assert!(commands.contains("^LH192,0\n^FO10,20\n^A0N,30,30\n^FD0007\n"));
+    assert_eq!(commands.matches("^FD0007").count(), 3);
+    assert!(commands.contains("^PQ7,7,0,Y"));
+
+    // Two rows of three labels and one of a single label, all numbered in turn.
+    options.across.as_mut().unwrap().distribute = true;
+    let commands = String::from(label.print(&options).unwrap());
+    let formats: Vec<_> = commands.split("^XA").skip(2).collect();
+    assert_eq!(formats.len(), 2);
+    assert!(formats[0]
+        .contains("^LH384,0\n^FO10,20\n^A0N,30,30\n^FD0011\n^SFdddd,6\n"));
+    assert!(formats[0].contains("^PQ2,2,0,Y"));
+    assert!(formats[1].contains("^FD0019\n"));
+    assert!(!formats[1].contains("^LH192,0"));
+    assert!(formats[1].contains("^PQ1,1,0,Y"));
+}
+
+#[test]
+fn shrink_to_fit() {
+    // A bar twice as wide as the document, as a long name would be.
+    let code = r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect x="0" y="0" width="40" height="10"/>
+    </svg>"#;
+    let svg = |shrink| LabelContent::Svg {
+        code: code.to_string(),
+        x: Length::ZERO,
+        y: Length::ZERO,
+        w: Length::mm(20.0),
+        h: Length::mm(10.0),
+        shrink,
+    };
+
+    let options = RenderOptions::default();
+    assert_eq!(svg(false).shrink_factor(&options), None);
+    assert_eq!(svg(true).shrink_factor(&options), Some(0.5));
+
+    let inked = |shrink| {
+        let mut label = Label::new(20.0, 10.0, 8);
+        label.content.push(svg(shrink));
+        label.render().unwrap().inked_dots()
+    };
+    // Cut off, the bar fills the label, shrunk it fills half of it.
+    assert_eq!(inked(false), 160 * 80);
+    assert_eq!(inked(true), 160 * 80 / 2);
+}
+
+#[test]
+fn qr_codes_by_size() {
+    // Capacities at the levels, as published for version 1.
+    assert_eq!(QrMode::Numeric.capacity_of(1, QrErrorCorrection::Low), 41);
+    assert_eq!(QrMode::Byte.capacity_of(1, QrErrorCorrection::High), 7);
+    assert_eq!(QrMode::Alphanumeric.capacity(QrErrorCorrection::Low), 4296);
+
+    // 21 modules of version 1 in 10 mm, the highest level still holding the content.
+    assert_eq!(qr_fit("zpl", 80), Some((3, QrErrorCorrection::High)));
+    assert_eq!(qr_fit(&"a".repeat(3000), 80), None);
+
+    let mut label = Label::new(20.0, 20.0, 8);
+    label.content.push(LabelContent::QrCode {
+        content: "zpl".to_string(),
+        x: Length::ZERO,
+        y: Length::ZERO,
+        zoom: 1,
+        error_correction: QrErrorCorrection::Low,
+        size: Some(Length::mm(10.0)),
+    });
+
+    assert_eq!(label.bounding_boxes()[0].width, 63);
+    let commands = label.render().unwrap().to_string();
+    assert!(commands.contains("^BQN,2,3,H,7\n^FDHM,B0003zpl"));
+}
+
+#[test]
+fn aztec_and_pdf417() {
+    let mut label = Label::new(50.0, 30.0, 8);
+    label.content.push(LabelContent::Aztec {
+        content: "ticket 42".to_string(),
+        x: Length::dots(10),
+        y: Length::dots(10),
+        zoom: 4,
+        error_correction: 0,
+    });
+    label.content.push(LabelContent::Pdf417 {
+        content: "shipment,1234".to_string(),
+        x: Length::dots(100),
+        y: Length::dots(10),
+        module_width: 2,
+        row_height: Length::dots(6),
+        security: 2,
+        columns: 3,
+    });
+
+    let commands = label.render().unwrap().to_string();
+    assert!(commands.contains("^FO10,10\n^B0N,4,N,0,N,1\n^FDticket 42\n^FS"));
+    assert!(commands
+        .contains("^FO100,10\n^BY2\n^B7N,6,2,3,,N\n^FDshipment,1234\n^FS"));
+
+    // A compact symbol of two layers, and 21 codewords in rows of three.
+    let boxes = label.bounding_boxes();
+    assert_eq!((boxes[0].width, boxes[0].height), (76, 76));
+    assert_eq!((boxes[1].width, boxes[1].height), (240, 42));
+}
+
+#[test]
+fn raster_mirroring_flips_the_whole_label() {
+    use crate::render::zpl_rasterizer::rasterize_commands;
+
+    let block =
+        |w, h| ::image::GrayImage::from_pixel(w, h, ::image::Luma([0])).into();
+    let mut label = Label::new(10.0, 5.0, 8);
+    label.content.push(LabelContent::Image {
+        img: block(30, 20),
+        x: Length::dots(4),
+        y: Length::dots(6),
+        w: Length::dots(30),
+        h: Length::dots(20),
+        fit: Fit::Stretch,
+    });
+    label.content.push(LabelContent::Image {
+        img: block(10, 10),
+        x: Length::dots(50),
+        y: Length::dots(24),
+        w: Length::dots(10),
+        h: Length::dots(10),
+        fit: Fit::Stretch,
+    });
+
+    let printed = |mirrored| {
+        let options = PrintOptions {
+            copies: 1,
+            orientation: Orientation {
+                mirrored,
+                flipped: false,
+            },
+            ..PrintOptions::default()
+        };
+        let commands = label.print(&options).unwrap();
+        let size = (label.width_dots(), label.height_dots());
+        rasterize_commands(&commands, size).unwrap().remove(0)
+    };
+
+    let plain = printed(None);
+    let flipped = ::image::imageops::flip_horizontal(&plain);
+    assert_ne!(plain, flipped);
+    assert_eq!(printed(Some(Mirroring::Raster)), flipped);
+    assert_eq!(printed(Some(Mirroring::Native)), flipped);
+
+    // Left to the printer with content only it draws.
+    label.content.push(LabelContent::Aztec {
+        content: "zpl".to_string(),
+        x: Length::dots(40),
+        y: Length::dots(4),
+        zoom: 2,
+        error_correction: 0,
+    });
+    let options = PrintOptions {
+        copies: 1,
+        orientation: Orientation {
+            mirrored: Some(Mirroring::Raster),
+            flipped: false,
+        },
+        ..PrintOptions::default()
+    };
+    let commands = label.print(&options).unwrap();
+    assert!(commands
+        .0
+        .iter()
+        .any(|command| matches!(command, ZplCommand::SetMirrored(true))));
+}
@@ -0,0 +1,289 @@
+//! Regions of a label sized relative to each other, resolved to the positions and sizes of their
+//! content once the size and resolution of the label are known.
+//!
+//! ```
+//! use zpl::builder::{QrContent, SvgContent};
+//! use zpl::label::Label;
+//! use zpl::layout::Region;
+//!
+//! let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+//! let label = Label::builder()
+//!     .size_mm(100.0, 50.0)
+//!     .layout(
+//!         Region::row([
+//!             // A square, the height of the label within the padding.
+//!             Region::content(QrContent::builder("https://example.com"))
+//!                 .fixed_mm(44.0),
+//!             Region::column([
+//!                 Region::content(SvgContent::builder(svg)),
+//!                 Region::content(SvgContent::builder(svg)).flex(2.0),
+//!             ]),
+//!         ])
+//!         .padding_mm(3.0)
+//!         .gap_mm(2.0),
+//!     )
+//!     .build()
+//!     .unwrap();
+//!
+//! let boxes = label.bounding_boxes();
+//! assert_eq!((boxes[1].x, boxes[1].y), (24 + 352 + 16, 24));
+//! assert_eq!((boxes[1].height, boxes[2].height), (117, 235));
+//! ```
+use crate::label::{BoundingBox, Label, LabelContent};
+use crate::length::Length;
+
+/// How much of its parent a region takes, along the direction of the parent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Size {
+    Fixed(Length),
+    /// A share of what the fixed regions and gaps leave, by weight among the flexible regions.
+    Flex(f32),
+}
+
+impl Default for Size {
+    fn default() -> Self {
+        Size::Flex(1.0)
+    }
+}
+
+/// A part of the label, holding a content item or further regions side by side or stacked.
+pub struct Region {
+    size: Size,
+    /// Space kept clear inside the region on all sides.
+    padding: Length,
+    /// Space between the regions within.
+    gap: Length,
+    kind: RegionKind,
+}
+
+enum RegionKind {
+    Row(Vec<Region>),
+    Column(Vec<Region>),
+    Content(Box<LabelContent>),
+}
+
+impl Region {
+    fn new(kind: RegionKind) -> Self {
+        Region {
+            size: Size::default(),
+            padding: Length::ZERO,
+            gap: Length::ZERO,
+            kind,
+        }
+    }
+
+    /// Regions side by side, from left to right.
+    pub fn row(regions: impl IntoIterator<Item = Region>) -> Self {
+        Self::new(RegionKind::Row(regions.into_iter().collect()))
+    }
+
+    /// Regions stacked from top to bottom.
+    pub fn column(regions: impl IntoIterator<Item = Region>) -> Self {
+        Self::new(RegionKind::Column(regions.into_iter().collect()))
+    }
+
+    /// A region filled by an item, its position and size set to the region's.
+    ///
+    /// Images and SVG documents take the whole region, QR codes are fitted to the largest square
+    /// within. Other items are only moved to the top left corner.
+    pub fn content(item: impl Into<LabelContent>) -> Self {
+        Self::new(RegionKind::Content(Box::new(item.into())))
+    }
+
+    /// A region left blank.
+    pub fn empty() -> Self {
+        Self::row([])
+    }
+
+    pub fn size(mut self, size: Size) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn fixed(self, length: Length) -> Self {
+        self.size(Size::Fixed(length))
+    }
+
+    pub fn fixed_mm(self, mm: f32) -> Self {
+        self.fixed(Length::mm(mm))
+    }
+
+    /// Take a share of the space left by this weight, 1 unless set.
+    pub fn flex(self, weight: f32) -> Self {
+        self.size(Size::Flex(weight))
+    }
+
+    pub fn padding(mut self, padding: Length) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn padding_mm(self, mm: f32) -> Self {
+        self.padding(Length::mm(mm))
+    }
+
+    pub fn gap(mut self, gap: Length) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    pub fn gap_mm(self, mm: f32) -> Self {
+        self.gap(Length::mm(mm))
+    }
+
+    /// The content items, placed on the label within its margins.
+    ///
+    /// The region fills the label whatever its own size.
+    pub fn place(self, label: &Label) -> Vec<LabelContent> {
+        let dots = |length: Length| length.to_dots(label.dpmm);
+        let margins = label.margins;
+        let area = BoundingBox {
+            x: dots(margins.left),
+            y: dots(margins.top),
+            width: label
+                .width_dots()
+                .saturating_sub(dots(margins.left) + dots(margins.right)),
+            height: label
+                .height_dots()
+                .saturating_sub(dots(margins.top) + dots(margins.bottom)),
+        };
+
+        let mut content = vec![];
+        self.resolve(area, label.dpmm, &mut content);
+        content
+    }
+
+    fn resolve(
+        self,
+        area: BoundingBox,
+        dpmm: u32,
+        out: &mut Vec<LabelContent>,
+    ) {
+        let padding = self.padding.to_dots(dpmm);
+        let inner = BoundingBox {
+            x: area.x.saturating_add(padding),
+            y: area.y.saturating_add(padding),
+            width: area.width.saturating_sub(padding.saturating_mul(2)),
+            height: area.height.saturating_sub(padding.saturating_mul(2)),
+        };
+
+        let (regions, horizontal) = match self.kind {
+            RegionKind::Content(mut content) => {
+                fit(&mut content, inner);
+                out.push(*content);
+                return;
+            }
+            RegionKind::Row(regions) => (regions, true),
+            RegionKind::Column(regions) => (regions, false),
+        };
+
+        let gap = self.gap.to_dots(dpmm);
+        let mut fixed = 0u32;
+        let mut weights = 0.0;
+        for region in &regions {
+            match region.size {
+                Size::Fixed(length) => {
+                    fixed = fixed.saturating_add(length.to_dots(dpmm))
+                }
+                Size::Flex(weight) => weights += weight.max(0.0),
+            }
+        }
+
+        let gaps = gap.saturating_mul(regions.len().saturating_sub(1) as u32);
+        let length = if horizontal {
+            inner.width
+        } else {
+            inner.height
+        };
+        let mut remaining = length.saturating_sub(fixed).saturating_sub(gaps);
+
+        let mut offset = 0u32;
+        for region in regions {
+            let size = match region.size {
+                Size::Fixed(length) => length.to_dots(dpmm),
+                Size::Flex(weight) => {
+                    // Shared among the regions still to come, such that no dots are lost to
+                    // rounding.
+                    let weight = weight.max(0.0);
+                    let share = match weights > 0.0 {
+                        true => {
+                            (remaining as f32 * weight / weights).round() as u32
+                        }
+                        false => 0,
+                    };
+                    weights -= weight;
+                    remaining = remaining.saturating_sub(share);
+                    share
+                }
+            };
+
+            let area = match horizontal {
+                true => BoundingBox {
+                    x: inner.x.saturating_add(offset),
+                    width: size,
+                    ..inner
+                },
+                false => BoundingBox {
+                    y: inner.y.saturating_add(offset),
+                    height: size,
+                    ..inner
+                },
+            };
+            region.resolve(area, dpmm, out);
+
+            offset = offset.saturating_add(size).saturating_add(gap);
+        }
+    }
+}
+
+/// Move the item into the area and size it to fill it, where it has a size.
+fn fit(content: &mut LabelContent, area: BoundingBox) {
+    let (left, top) = (Length::dots(area.x), Length::dots(area.y));
+
+    match content {
+        LabelContent::Image { x, y, w, h, .. }
+        | LabelContent::Svg { x, y, w, h, .. }
+        | LabelContent::SvgTree { x, y, w, h, .. } => {
+            (*x, *y) = (left, top);
+            (*w, *h) = (Length::dots(area.width), Length::dots(area.height));
+        }
+        LabelContent::QrCode { x, y, size, .. } => {
+            (*x, *y) = (left, top);
+            *size = Some(Length::dots(area.width.min(area.height)));
+        }
+        LabelContent::Aztec { x, y, .. }
+        | LabelContent::Pdf417 { x, y, .. }
+        | LabelContent::ClockField { x, y, .. }
+        | LabelContent::SerialNumber { x, y, .. } => {
+            (*x, *y) = (left, top);
+        }
+        LabelContent::Emphasized { content, .. } => fit(content, area),
+    }
+}
+
+#[test]
+fn flexible_regions() {
+    let label = Label::new(10.0, 5.0, 8);
+    let image = || {
+        Region::content(crate::builder::ImageContent::builder(
+            ::image::GrayImage::new(1, 1).into(),
+        ))
+    };
+
+    // 80 dots less 2 gaps of 4 and 10 fixed, shared 1:2 and rounded.
+    let content = Region::row([
+        image(),
+        image().fixed(Length::dots(10)),
+        Region::column([image().flex(2.0), Region::empty()]).flex(2.0),
+    ])
+    .gap(Length::dots(4))
+    .place(&label);
+
+    let label = Label { content, ..label };
+    let boxes: Vec<_> = label
+        .bounding_boxes()
+        .into_iter()
+        .map(|area| (area.x, area.y, area.width, area.height))
+        .collect();
+    assert_eq!(boxes, [(0, 0, 21, 40), (25, 0, 10, 40), (39, 0, 41, 27)]);
+}
@@ -0,0 +1,133 @@
+//! Lengths on a label, given in physical units or in dots of the printer.
+//!
+//! A length keeps its physical part in mm and its part in dots apart, such that lengths of either
+//! kind can be added up before the resolution of the printer is known.
+
+/// A length in mm plus a number of dots.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Length {
+    mm: f32,
+    dots: i32,
+}
+
+impl Length {
+    pub const ZERO: Self = Length { mm: 0.0, dots: 0 };
+
+    pub fn mm(mm: f32) -> Self {
+        Length { mm, dots: 0 }
+    }
+
+    pub fn inches(inches: f32) -> Self {
+        Self::mm(LengthUnit::Inches.to_mm(inches))
+    }
+
+    pub fn dots(dots: u32) -> Self {
+        Length {
+            mm: 0.0,
+            dots: dots.try_into().unwrap_or(i32::MAX),
+        }
+    }
+
+    /// The sum, unless the dots overflow or the mm are not finite.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        Some(Length {
+            mm: Some(self.mm + other.mm).filter(|mm| mm.is_finite())?,
+            dots: self.dots.checked_add(other.dots)?,
+        })
+    }
+
+    /// The difference, unless the dots overflow or the mm are not finite.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        Some(Length {
+            mm: Some(self.mm - other.mm).filter(|mm| mm.is_finite())?,
+            dots: self.dots.checked_sub(other.dots)?,
+        })
+    }
+
+    /// Whole dots at a resolution, rounding down and none for negative lengths.
+    pub fn to_dots(self, dpmm: u32) -> u32 {
+        let dots =
+            (self.mm * dpmm as f32).floor() as i64 + i64::from(self.dots);
+        dots.clamp(0, u32::MAX.into()) as u32
+    }
+
+    /// Whole dots at a resolution, rounding towards zero.
+    pub fn to_signed_dots(self, dpmm: u32) -> i32 {
+        // Conversion from float to integer is specified to clamp.
+        let dots = (self.mm * dpmm as f32) as i32;
+        dots.saturating_add(self.dots)
+    }
+}
+
+/// The unit people enter and read lengths in, while they are kept in mm.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LengthUnit {
+    #[default]
+    #[serde(rename = "mm")]
+    #[cfg_attr(feature = "cli", value(name = "mm"))]
+    Millimetres,
+    #[serde(rename = "in")]
+    #[cfg_attr(feature = "cli", value(name = "in"))]
+    Inches,
+}
+
+impl LengthUnit {
+    pub const MM_PER_INCH: f32 = 25.4;
+
+    /// A length in this unit.
+    pub fn length(self, value: f32) -> Length {
+        Length::mm(self.to_mm(value))
+    }
+
+    /// Convert a length in this unit to mm.
+    pub fn to_mm(self, value: f32) -> f32 {
+        match self {
+            LengthUnit::Millimetres => value,
+            LengthUnit::Inches => value * Self::MM_PER_INCH,
+        }
+    }
+
+    /// Convert a length in mm to this unit.
+    pub fn from_mm(self, mm: f32) -> f32 {
+        match self {
+            LengthUnit::Millimetres => mm,
+            LengthUnit::Inches => mm / Self::MM_PER_INCH,
+        }
+    }
+}
+
+#[test]
+fn mixed_lengths() {
+    let length = Length::mm(2.0).checked_add(Length::dots(3)).unwrap();
+    assert_eq!(length.to_dots(8), 19);
+    assert_eq!(length.to_dots(12), 27);
+
+    assert_eq!(Length::inches(1.0).to_dots(8), 203);
+    assert_eq!(LengthUnit::Inches.length(-0.5).to_signed_dots(8), -101);
+    assert_eq!(Length::mm(-1.0).to_dots(8), 0);
+    assert_eq!(LengthUnit::Inches.from_mm(50.8), 2.0);
+
+    assert!(Length::dots(u32::MAX)
+        .checked_add(Length::dots(1))
+        .is_none());
+    assert!(Length::mm(f32::MAX)
+        .checked_add(Length::mm(f32::MAX))
+        .is_none());
+    assert_eq!(
+        Length::dots(5)
+            .checked_sub(Length::dots(8))
+            .unwrap()
+            .to_signed_dots(8),
+        -3
+    );
+}
@@ -1,136 +1,32 @@
-use std::net::SocketAddr;
-use std::path::PathBuf;
-
-use anyhow::bail;
-use clap::Parser;
-use core::num::NonZeroU32;
-use label::{Label, LabelContent, Unit};
-
-use command::CommandSequence;
-use device::ZplPrinter;
-
+//! Labels for Zebra printers, described in ZPL and sent over the network.
+//!
+//! Rendering labels to commands needs neither a network nor files, so it also builds for
+//! WebAssembly without default features. The optional features add the rest:
+//!
+//! - `device` talks to printers, with tokio.
+//! - `fs` reads files: the fonts installed on the system and overlay pictures.
+//! - `config` reads the labels and printers of the server's configuration files.
+//! - `qrcode` draws QR codes as graphics, for printers whose `^BQ` can not be trusted.
+//! - `cli` is the command line interface, with all of the above.
+pub mod barcode;
+pub mod builder;
 pub mod command;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "device")]
 pub mod device;
 pub mod label;
+pub mod layout;
+pub mod length;
+pub mod lint;
+pub mod quirks;
+pub mod render;
+pub mod testing;
 pub mod util;
 
-pub use resvg;
-
-#[derive(Parser, Clone)]
-#[command(version, about)]
-pub struct Args {
-    #[arg(default_value = "192.168.1.39:9100")]
-    ip: SocketAddr,
-
-    #[arg(long = "image")]
-    image: Option<PathBuf>,
-
-    #[arg(long = "svg")]
-    svg: Option<PathBuf>,
-
-    #[arg(long = "copies", default_value = "1")]
-    copies: NonZeroU32,
-
-    #[arg(long = "margin", default_value = "5", help = "xy margin in mm")]
-    margin: u32,
-
-    #[arg(long = "width", default_value = "51", help = "label width in mm")]
-    width: u32,
-
-    #[arg(long = "height", default_value = "51", help = "label height in mm")]
-    height: u32,
-
-    #[arg(
-        long = "dpmm",
-        help = "print resolution in dots per mm (overrides printer autodetection)"
-    )]
-    dpmm: Option<u32>,
-
-    #[arg(long = "output-zpl-only", default_value = "false")]
-    output_zpl_only: bool,
-}
+#[cfg(feature = "cli")]
+mod cli;
 
-pub async fn make_label(
-    args: Args,
-    dpmm_autodetect: Option<u32>,
-) -> anyhow::Result<CommandSequence> {
-    let Args {
-        ip: _,
-        image,
-        svg,
-        copies,
-        margin,
-        width,
-        height,
-        dpmm: dpmm_override,
-        output_zpl_only: _,
-    } = args;
-
-    let dpmm = if let Some(v) = dpmm_override {
-        v
-    } else if let Some(v) = dpmm_autodetect {
-        v
-    } else {
-        bail!("Can't ascertain resolution, please supply dpmm");
-    };
-
-    let margin_x = margin as f32;
-    let margin_y = margin as f32;
-    let content_width = width as f32 - 2.0 * margin_x;
-    let content_height = height as f32 - 2.0 * margin_y;
-
-    let mut label = Label::new(width, height, dpmm);
-    // Resize image, or rasterize SVG
-    if let Some(image) = image {
-        let img = ::image::open(image).expect("Image file not found");
-
-        label.content.push(LabelContent::Image {
-            img,
-            x: Unit::Millimetres(margin_x),
-            y: Unit::Millimetres(margin_y),
-            w: Unit::Millimetres(content_width),
-            h: Unit::Millimetres(content_height),
-        });
-    } else if let Some(path) = svg {
-        let code = tokio::fs::read_to_string(path)
-            .await
-            .expect("SVG file not found");
-
-        label.content.push(LabelContent::Svg {
-            code,
-            x: Unit::Millimetres(margin_x),
-            y: Unit::Millimetres(margin_y),
-            w: Unit::Millimetres(content_width),
-            h: Unit::Millimetres(content_height),
-        });
-    } else {
-        bail!("No image/vector source selected");
-    };
-
-    let commands = label
-        .print(&{
-            let mut options = label::PrintOptions::default();
-            options.copies = copies.get();
-            options
-        })
-        .await?;
-
-    Ok(commands)
-}
-
-pub async fn run(args: Args) -> anyhow::Result<()> {
-    let mut device = ZplPrinter::with_address(args.ip).await?;
-    let config = device.request_device_status().await?;
-    let dpmm = config.identification.dpmm;
-
-    let label = make_label(args, Some(dpmm)).await?;
-
-    Ok(device.send(label).await?)
-}
-
-pub async fn run_output_zpl_only(args: Args) -> anyhow::Result<()> {
-    let label = make_label(args, None).await?;
-    println!("{}", label);
-
-    Ok(())
-}
+#[cfg(feature = "cli")]
+pub use cli::*;
+pub use resvg;
@@ -0,0 +1,435 @@
+//! Find problems with a label before it reaches a printer.
+//!
+//! These are the checks the server applies to every job, available on their own such that
+//! template repositories can run them in CI. Errors are problems that fail the job or lose
+//! content, warnings are likely surprises in the output.
+use std::sync::{Arc, Mutex};
+
+use resvg::usvg::{self, fontdb, FontFamily, FontResolver};
+use serde::Serialize;
+
+use crate::label::{
+    pdf417_shape, qr_fit, Emphasis, Label, LabelContent, QrErrorCorrection,
+    QrMode, ViolationKind,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Check the label and all its content, parsing documents with the fonts of these options.
+pub fn check(label: &Label, svg: &usvg::Options) -> Vec<Finding> {
+    let mut findings = vec![];
+
+    if label.dpmm == 0 {
+        findings.push(error("Resolution of 0 dots per mm".to_string()));
+    } else if label.width_dots() == 0 || label.height_dots() == 0 {
+        findings.push(error(format!(
+            "Label dimensions of {} by {} dots are not positive",
+            label.width_dots(),
+            label.height_dots()
+        )));
+    }
+
+    // Placement as checked when rendering, with items counted from 1.
+    for violation in label.validate() {
+        let what = match violation.kind {
+            ViolationKind::OutsideLabel => format!(
+                "overflows the label of {} by {} dots",
+                label.width_dots(),
+                label.height_dots()
+            ),
+            ViolationKind::InMargin => "extends into the margins".to_string(),
+            ViolationKind::Overlap { other } => {
+                format!("overlaps item {}", other + 1)
+            }
+        };
+        let area = violation.area;
+        findings.push(Finding {
+            severity: violation.severity,
+            message: format!(
+                "Item {} of {} by {} dots at {}, {} {what}",
+                violation.item + 1,
+                area.width,
+                area.height,
+                area.x,
+                area.y
+            ),
+        });
+    }
+
+    for (idx, content) in label.content.iter().enumerate() {
+        check_content(label, content, idx + 1, svg, &mut findings);
+    }
+
+    findings
+}
+
+fn check_content(
+    label: &Label,
+    content: &LabelContent,
+    item: usize,
+    svg: &usvg::Options,
+    findings: &mut Vec<Finding>,
+) {
+    match content {
+        LabelContent::Image { w, h, .. }
+        | LabelContent::Svg { w, h, .. }
+        | LabelContent::SvgTree { w, h, .. } => {
+            if w.to_dots(label.dpmm) == 0 || h.to_dots(label.dpmm) == 0 {
+                findings.push(error(format!("Item {item} has no area")));
+            }
+        }
+        LabelContent::QrCode { .. }
+        | LabelContent::Aztec { .. }
+        | LabelContent::Pdf417 { .. }
+        | LabelContent::ClockField { .. }
+        | LabelContent::SerialNumber { .. }
+        | LabelContent::Emphasized { .. } => {}
+    }
+
+    match content {
+        LabelContent::Image { .. } => {}
+        LabelContent::Svg { code, .. } => check_svg(code, item, svg, findings),
+        LabelContent::SvgTree { tree, .. } => check_fonts(tree, item, findings),
+        LabelContent::QrCode {
+            content,
+            zoom,
+            error_correction,
+            size,
+            ..
+        } => match size {
+            Some(size) => {
+                let dots = size.to_dots(label.dpmm);
+                match qr_fit(content, dots) {
+                    Some((zoom, level)) => {
+                        check_qr_code(content, zoom, level, item, findings)
+                    }
+                    None => findings.push(error(format!(
+                        "Item {item}: QR code does not fit within {dots} dots"
+                    ))),
+                }
+            }
+            None => {
+                check_qr_code(content, *zoom, *error_correction, item, findings)
+            }
+        },
+        LabelContent::Aztec {
+            content,
+            zoom,
+            error_correction,
+            ..
+        } => {
+            if content.is_empty() {
+                findings
+                    .push(error(format!("Item {item} is an empty Aztec code")));
+            }
+
+            if !(1..=10).contains(zoom) {
+                findings.push(error(format!(
+                    "Item {item}: Aztec code magnification {zoom} outside of 1 to 10"
+                )));
+            }
+
+            if *error_correction > 99 {
+                findings.push(error(format!(
+                    "Item {item}: Aztec error correction of {error_correction}% above 99%"
+                )));
+            }
+        }
+        LabelContent::Pdf417 {
+            content,
+            security,
+            columns,
+            ..
+        } => {
+            if content.is_empty() {
+                findings.push(error(format!(
+                    "Item {item} is an empty PDF417 code"
+                )));
+            }
+
+            if *security > 8 {
+                findings.push(error(format!(
+                    "Item {item}: PDF417 security level {security} above 8"
+                )));
+            }
+
+            if !(1..=30).contains(columns) {
+                findings.push(error(format!(
+                    "Item {item}: PDF417 code of {columns} columns, outside of 1 to 30"
+                )));
+            }
+
+            let (_, rows) = pdf417_shape(content, *security, *columns);
+            if rows > 90 {
+                findings.push(error(format!(
+                    "Item {item}: PDF417 code needs {rows} rows, more than 90"
+                )));
+            }
+        }
+        LabelContent::ClockField { format, .. } => {
+            if !format.contains('%') {
+                findings.push(warning(format!(
+                    "Item {item}: clock field without any % placeholder"
+                )));
+            }
+        }
+        LabelContent::SerialNumber {
+            start,
+            increment,
+            pad,
+            ..
+        } => {
+            if *pad > 0 && *increment < 0 {
+                findings.push(error(format!(
+                    "Item {item}: padded serial numbers can only count up"
+                )));
+            }
+
+            if *pad > 0 && start.to_string().len() > *pad as usize {
+                findings.push(error(format!(
+                    "Item {item}: serial number {start} exceeds {pad} digits"
+                )));
+            }
+        }
+        LabelContent::Emphasized { content, emphasis } => {
+            if content.is_native()
+                && matches!(emphasis, Emphasis::Thicken { .. })
+            {
+                findings.push(error(format!(
+                    "Item {item} is rendered by the printer and can not be thickened"
+                )));
+            }
+
+            check_content(label, content, item, svg, findings);
+        }
+    }
+}
+
+/// Parse the document as for printing, noting text left out for lack of fonts.
+fn check_svg(
+    code: &str,
+    item: usize,
+    svg: &usvg::Options,
+    findings: &mut Vec<Finding>,
+) {
+    let missing = Arc::new(Mutex::new(vec![]));
+    let mut options = usvg::Options {
+        font_family: svg.font_family.clone(),
+        fontdb: svg.fontdb.clone(),
+        ..Default::default()
+    };
+
+    let select = FontResolver::default_font_selector();
+    let record = missing.clone();
+    options.font_resolver.select_font = Box::new(move |font, fontdb| {
+        let id = select(font, fontdb);
+        if id.is_none() {
+            record.lock().unwrap().push(font.families().to_vec());
+        }
+        id
+    });
+
+    let tree = match usvg::Tree::from_str(code, &options) {
+        Ok(tree) => tree,
+        Err(err) => {
+            findings.push(error(format!("Item {item} is no valid SVG: {err}")));
+            return;
+        }
+    };
+
+    for families in missing.lock().unwrap().iter() {
+        findings.push(error(format!(
+            "Item {item}: no font of {} is available, its text is left out",
+            families
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    check_fonts(&tree, item, findings);
+}
+
+/// Text set in another font than its first choice.
+///
+/// Text without any available font is not part of the tree, only found while parsing.
+fn check_fonts(tree: &usvg::Tree, item: usize, findings: &mut Vec<Finding>) {
+    fn visit(
+        group: &usvg::Group,
+        fontdb: &fontdb::Database,
+        item: usize,
+        findings: &mut Vec<Finding>,
+    ) {
+        for node in group.children() {
+            match node {
+                usvg::Node::Group(group) => {
+                    visit(group, fontdb, item, findings)
+                }
+                usvg::Node::Text(text) => {
+                    for chunk in text.chunks() {
+                        for span in chunk.spans() {
+                            let families = span.font().families();
+                            let text = chunk
+                                .text()
+                                .get(span.start()..span.end())
+                                .unwrap_or_default();
+                            check_families(
+                                families, text, fontdb, item, findings,
+                            );
+                        }
+                    }
+                }
+                usvg::Node::Path(_) | usvg::Node::Image(_) => {}
+            }
+        }
+    }
+
+    visit(tree.root(), tree.fontdb(), item, findings);
+}
+
+fn check_families(
+    families: &[FontFamily],
+    text: &str,
+    fontdb: &fontdb::Database,
+    item: usize,
+    findings: &mut Vec<Finding>,
+) {
+    let available = |family: &FontFamily| {
+        let family = match family {
+            FontFamily::Serif => fontdb::Family::Serif,
+            FontFamily::SansSerif => fontdb::Family::SansSerif,
+            FontFamily::Cursive => fontdb::Family::Cursive,
+            FontFamily::Fantasy => fontdb::Family::Fantasy,
+            FontFamily::Monospace => fontdb::Family::Monospace,
+            FontFamily::Named(name) => fontdb::Family::Name(name),
+        };
+
+        fontdb
+            .query(&fontdb::Query {
+                families: &[family],
+                ..Default::default()
+            })
+            .is_some()
+    };
+
+    let Some(first) = families.first() else {
+        return;
+    };
+
+    if available(first) {
+        return;
+    }
+
+    // Otherwise the default font was used.
+    let fallback = families
+        .iter()
+        .find(|family| available(family))
+        .unwrap_or(&FontFamily::Serif);
+
+    findings.push(warning(format!(
+        "Item {item}: font {first} is not available, \"{text}\" is set in {fallback}"
+    )));
+}
+
+fn check_qr_code(
+    content: &str,
+    zoom: u32,
+    level: QrErrorCorrection,
+    item: usize,
+    findings: &mut Vec<Finding>,
+) {
+    if content.is_empty() {
+        findings.push(error(format!("Item {item} is an empty QR code")));
+    }
+
+    if !(1..=10).contains(&zoom) {
+        findings.push(error(format!(
+            "Item {item}: QR code magnification {zoom} outside of 1 to 10"
+        )));
+    }
+
+    let mode = QrMode::of(content);
+    if content.len() > mode.capacity(level) {
+        let mode = match mode {
+            QrMode::Numeric => "digits",
+            QrMode::Alphanumeric => "characters",
+            QrMode::Byte => "bytes",
+        };
+
+        findings.push(error(format!(
+            "Item {item}: QR code of {} {mode} exceeds the capacity of {}",
+            content.len(),
+            QrMode::of(content).capacity(level)
+        )));
+    }
+}
+
+fn error(message: String) -> Finding {
+    Finding {
+        severity: Severity::Error,
+        message,
+    }
+}
+
+fn warning(message: String) -> Finding {
+    Finding {
+        severity: Severity::Warning,
+        message,
+    }
+}
+
+#[test]
+fn overflow_and_qr_content() {
+    use crate::length::Length;
+
+    let mut label = Label::new(10.0, 10.0, 8);
+    label.content.push(LabelContent::Image {
+        img: ::image::GrayImage::new(4, 4).into(),
+        x: Length::mm(5.0),
+        y: Length::dots(0),
+        w: Length::mm(6.0),
+        h: Length::dots(4),
+        fit: crate::label::Fit::Cover,
+    });
+    label.content.push(LabelContent::QrCode {
+        content: "a^b".to_string(),
+        x: Length::dots(0),
+        y: Length::dots(0),
+        zoom: 2,
+        error_correction: QrErrorCorrection::Quartile,
+        size: None,
+    });
+    label.content.push(LabelContent::QrCode {
+        content: "0"
+            .repeat(QrMode::Numeric.capacity(QrErrorCorrection::Quartile)),
+        x: Length::dots(0),
+        y: Length::dots(0),
+        zoom: 2,
+        error_correction: QrErrorCorrection::Quartile,
+        size: None,
+    });
+
+    // Carets are escaped in the field data, the image and the largest code overflow.
+    let findings = check(&label, &crate::util::svg::bundled_options());
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .map(|finding| &finding.message[..7])
+        .collect::<Vec<_>>();
+    assert_eq!(errors, ["Item 1 ", "Item 3 "]);
+    assert!(findings
+        .iter()
+        .any(|finding| finding.message.ends_with("overlaps item 3")));
+}
@@ -4,5 +4,5 @@ use clap::Parser;
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    zpl::run(zpl::Args::parse()).await
+    zpl::run(zpl::Cli::parse()).await
 }
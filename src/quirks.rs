@@ -0,0 +1,72 @@
+//! Known defects of printer models, found by what they report for `~HI`.
+//!
+//! Entries are taken from the reports of `zpl hw-test`. What the configuration of a printer
+//! sets explicitly takes precedence over what is found here.
+use crate::{command::HostIdentification, label::QrRendering};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// `^BQ` draws QR codes garbled or not at all, as on some clones.
+    pub broken_qr: bool,
+}
+
+/// Models with a defect, by prefixes of their name and firmware version.
+struct Entry {
+    model: &'static str,
+    firmware: &'static str,
+    quirks: Quirks,
+}
+
+/// None confirmed yet, models are added as reports come in.
+const KNOWN: &[Entry] = &[];
+
+impl Quirks {
+    /// The defects of a model, none for models not known.
+    pub fn of(identification: &HostIdentification) -> Self {
+        lookup(KNOWN, identification)
+    }
+
+    /// How to put QR codes on labels for the model.
+    pub fn qr_rendering(&self) -> QrRendering {
+        match self.broken_qr {
+            true => QrRendering::Raster,
+            false => QrRendering::Native,
+        }
+    }
+}
+
+fn lookup(table: &[Entry], identification: &HostIdentification) -> Quirks {
+    table
+        .iter()
+        .find(|entry| {
+            identification.model.starts_with(entry.model)
+                && identification.version.starts_with(entry.firmware)
+        })
+        .map(|entry| entry.quirks)
+        .unwrap_or_default()
+}
+
+#[test]
+fn quirks_by_model_and_firmware() {
+    let table = [Entry {
+        model: "ZT-CLONE",
+        firmware: "V1.",
+        quirks: Quirks { broken_qr: true },
+    }];
+    let identification = |model: &str, version: &str| HostIdentification {
+        model: model.to_string(),
+        version: version.to_string(),
+        ..Default::default()
+    };
+
+    let clone = lookup(&table, &identification("ZT-CLONE 300", "V1.2"));
+    assert_eq!(clone.qr_rendering(), QrRendering::Raster);
+    assert_eq!(
+        lookup(&table, &identification("ZT-CLONE", "V2.0")),
+        Quirks::default()
+    );
+    assert_eq!(
+        Quirks::of(&identification("ZD421", "V84.20.21Z")),
+        Quirks::default()
+    );
+}
@@ -0,0 +1,2 @@
+//! Pictures of labels drawn from their commands, as a printer would print them.
+pub mod zpl_rasterizer;
@@ -0,0 +1,441 @@
+//! Draw ZPL into a picture, for the commands that place graphics: `^FO`, `^LH`, `^GF`, `^GB`
+//! and `^FR`, within the size set by `^PW` and `^LL` and turned by `^PM` and `^PO`.
+//!
+//! Text, barcodes and stored graphics are left out, as are commands not drawing anything. This
+//! is enough to see raw ZPL made from pictures, such as our own, and to compare it dot for dot.
+use std::io::Read as _;
+
+use base64::prelude::*;
+use image::{GrayImage, Luma};
+use quick_error::quick_error;
+
+use crate::command::CommandSequence;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        /// Parameters that are not numbers where numbers belong.
+        Parameter(command: String) {
+            display("Malformed parameters of ^{command}")
+        }
+        /// Graphic data that does not decode.
+        GraphicData(reason: &'static str) {
+            display("Malformed graphic data: {reason}")
+        }
+        /// A size beyond [`MAX_DOTS`].
+        Size(width: u32, height: u32) {
+            display("A label of {width} by {height} dots is too large to draw")
+        }
+        /// Binary graphic data, which can not be told apart from commands in text.
+        UnsupportedFormat(format: String) {
+            display("Graphic data in format {format} can not be drawn")
+        }
+    }
+}
+
+/// The most dots drawn for a label or a graphic, beyond what any printer prints at once.
+pub const MAX_DOTS: u64 = 1 << 26;
+
+const WHITE: Luma<u8> = Luma([255]);
+const BLACK: Luma<u8> = Luma([0]);
+
+/// What a format draws, in the order drawn.
+enum Shape {
+    /// Rows of bits, set for black.
+    Graphic { bytes_per_row: u32, bytes: Vec<u8> },
+    Box {
+        width: u32,
+        height: u32,
+        thickness: u32,
+        black: bool,
+    },
+}
+
+struct Field {
+    x: u32,
+    y: u32,
+    reverse: bool,
+    shape: Shape,
+}
+
+/// Settings kept by the printer from one format to the next.
+#[derive(Default)]
+struct Settings {
+    width: Option<u32>,
+    length: Option<u32>,
+    home: (u32, u32),
+    mirror: bool,
+    flip: bool,
+}
+
+/// Draw each format of the commands that draws anything, in order.
+///
+/// Formats without `^PW` or `^LL`, here or before, take their size from `fallback`, in dots.
+pub fn rasterize(
+    zpl: &str,
+    fallback: (u32, u32),
+) -> Result<Vec<GrayImage>, Error> {
+    let mut settings = Settings::default();
+    let mut fields = vec![];
+    let mut pages = vec![];
+
+    let mut origin = (0, 0);
+    let mut reverse = false;
+
+    for (name, parameters) in commands(zpl) {
+        let number = |index: usize| -> Result<Option<u32>, Error> {
+            match parameters.split(',').nth(index).map(str::trim) {
+                None | Some("") => Ok(None),
+                Some(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| Error::Parameter(name.clone())),
+            }
+        };
+
+        match name.as_str() {
+            "XA" => fields.clear(),
+            "XZ" => {
+                if !fields.is_empty() {
+                    pages.push(draw(&settings, &fields, fallback)?);
+                }
+                fields.clear();
+            }
+            "PW" => settings.width = number(0)?,
+            "LL" => settings.length = number(0)?,
+            "LH" => {
+                settings.home =
+                    (number(0)?.unwrap_or(0), number(1)?.unwrap_or(0))
+            }
+            "PM" => settings.mirror = parameters.trim() == "Y",
+            "PO" => settings.flip = parameters.trim() == "I",
+            "FO" => origin = (number(0)?.unwrap_or(0), number(1)?.unwrap_or(0)),
+            "FR" => reverse = true,
+            "FS" => reverse = false,
+            "GB" => {
+                let thickness = number(2)?.unwrap_or(1).max(1);
+                let color = parameters.split(',').nth(3).map(str::trim);
+                fields.push(Field {
+                    x: settings.home.0.saturating_add(origin.0),
+                    y: settings.home.1.saturating_add(origin.1),
+                    reverse,
+                    shape: Shape::Box {
+                        width: number(0)?.unwrap_or(1).max(thickness),
+                        height: number(1)?.unwrap_or(1).max(thickness),
+                        thickness,
+                        black: color != Some("W"),
+                    },
+                });
+            }
+            "GF" => {
+                let (bytes_per_row, bytes) = graphic(&parameters)?;
+                fields.push(Field {
+                    x: settings.home.0.saturating_add(origin.0),
+                    y: settings.home.1.saturating_add(origin.1),
+                    reverse,
+                    shape: Shape::Graphic {
+                        bytes_per_row,
+                        bytes,
+                    },
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // A format left open is drawn all the same.
+    if !fields.is_empty() {
+        pages.push(draw(&settings, &fields, fallback)?);
+    }
+
+    Ok(pages)
+}
+
+/// Draw the formats of commands, see [`rasterize`].
+pub fn rasterize_commands(
+    commands: &CommandSequence,
+    fallback: (u32, u32),
+) -> Result<Vec<GrayImage>, Error> {
+    rasterize(&commands.to_string(), fallback)
+}
+
+/// The commands in ZPL, by their name of two letters and the text of their parameters.
+///
+/// Only the default prefixes `^` and `~` are recognized.
+fn commands(zpl: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    zpl.split(['^', '~']).skip(1).filter_map(|command| {
+        let name: String = command.chars().take(2).collect();
+        let parameters = command.get(name.len()..)?;
+        Some((name.to_ascii_uppercase(), parameters.to_string()))
+    })
+}
+
+/// The bytes per row and the bits of a `^GF` command.
+fn graphic(parameters: &str) -> Result<(u32, Vec<u8>), Error> {
+    let malformed = || Error::Parameter("GF".into());
+    let mut parts = parameters.splitn(5, ',');
+    let mut next = || parts.next().map(str::trim).ok_or_else(malformed);
+
+    let format = next()?;
+    let _byte_count = next()?;
+    let total: usize = next()?.parse().map_err(|_| malformed())?;
+    let bytes_per_row: usize = next()?.parse().map_err(|_| malformed())?;
+    let data: String = next()?.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if format != "A" {
+        return Err(Error::UnsupportedFormat(format.into()));
+    }
+    if bytes_per_row == 0 {
+        return Err(Error::GraphicData("no bytes per row"));
+    }
+    if total as u64 * 8 > MAX_DOTS {
+        return Err(Error::GraphicData("larger than any label"));
+    }
+
+    let mut bytes = if let Some(encoded) = data.strip_prefix(":B64:") {
+        base64(encoded)?
+    } else if let Some(encoded) = data.strip_prefix(":Z64:") {
+        let mut inflated = vec![];
+        flate2::read::ZlibDecoder::new(base64(encoded)?.as_slice())
+            .take(total as u64)
+            .read_to_end(&mut inflated)
+            .map_err(|_| Error::GraphicData("invalid deflated data"))?;
+        inflated
+    } else {
+        hex(&data, bytes_per_row, total)?
+    };
+
+    bytes.resize(total, 0);
+    Ok((bytes_per_row as u32, bytes))
+}
+
+/// Base64 without the checksum following it.
+fn base64(encoded: &str) -> Result<Vec<u8>, Error> {
+    let encoded = encoded.rsplit_once(':').map_or(encoded, |(data, _)| data);
+    BASE64_STANDARD
+        .decode(encoded)
+        .map_err(|_| Error::GraphicData("invalid base64"))
+}
+
+/// Hex digits, with the run lengths and row abbreviations of ZPL's compression, up to `total`
+/// bytes.
+fn hex(
+    data: &str,
+    bytes_per_row: usize,
+    total: usize,
+) -> Result<Vec<u8>, Error> {
+    let digits_per_row = bytes_per_row * 2;
+    let mut digits: Vec<u8> = vec![];
+    let mut row: Vec<u8> = vec![];
+    let mut count = 0;
+
+    let end_row = |row: &mut Vec<u8>, fill: u8, digits: &mut Vec<u8>| {
+        row.resize(digits_per_row, fill);
+        digits.append(row);
+    };
+
+    for c in data.chars() {
+        if digits.len() >= total * 2 {
+            break;
+        }
+
+        match c {
+            'G'..='Y' => count += c as usize - 'F' as usize,
+            'g'..='z' => count += (c as usize - 'f' as usize) * 20,
+            ',' => end_row(&mut row, 0, &mut digits),
+            '!' => end_row(&mut row, 15, &mut digits),
+            ':' => {
+                let previous = digits.len().saturating_sub(digits_per_row);
+                row = digits[previous..].to_vec();
+                end_row(&mut row, 0, &mut digits);
+            }
+            _ => {
+                let digit = c
+                    .to_digit(16)
+                    .ok_or(Error::GraphicData("invalid hex digit"))?;
+                for _ in 0..count.clamp(1, total * 2) {
+                    row.push(digit as u8);
+                    if row.len() == digits_per_row {
+                        end_row(&mut row, 0, &mut digits);
+                    }
+                }
+                count = 0;
+            }
+        }
+    }
+
+    if !row.is_empty() {
+        end_row(&mut row, 0, &mut digits);
+    }
+
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+        .collect())
+}
+
+fn draw(
+    settings: &Settings,
+    fields: &[Field],
+    fallback: (u32, u32),
+) -> Result<GrayImage, Error> {
+    let width = settings.width.unwrap_or(fallback.0);
+    let height = settings.length.unwrap_or(fallback.1);
+    if u64::from(width) * u64::from(height) > MAX_DOTS {
+        return Err(Error::Size(width, height));
+    }
+    let mut canvas = GrayImage::from_pixel(width, height, WHITE);
+
+    let (width_limit, height_limit) = (width, height);
+    let mut paint = |x: u32, y: u32, black: bool, reverse: bool| {
+        let Some(pixel) = canvas.get_pixel_mut_checked(x, y) else {
+            return;
+        };
+        *pixel = match (reverse, black) {
+            // Reversed fields invert what is below where they ink.
+            (true, true) if *pixel == BLACK => WHITE,
+            (true, true) => BLACK,
+            (true, false) => *pixel,
+            (false, true) => BLACK,
+            (false, false) => WHITE,
+        };
+    };
+
+    for field in fields {
+        match &field.shape {
+            Shape::Graphic {
+                bytes_per_row,
+                bytes,
+            } => {
+                for (row, line) in
+                    bytes.chunks(*bytes_per_row as usize).enumerate()
+                {
+                    for (column, byte) in line.iter().enumerate() {
+                        for bit in 0..8 {
+                            if byte & (0x80 >> bit) != 0 {
+                                let x = field
+                                    .x
+                                    .saturating_add(column as u32 * 8 + bit);
+                                paint(
+                                    x,
+                                    field.y.saturating_add(row as u32),
+                                    true,
+                                    field.reverse,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            Shape::Box {
+                width,
+                height,
+                thickness,
+                black,
+            } => {
+                // Only the part on the label, a box may be far larger.
+                let rows = (*height).min(height_limit.saturating_sub(field.y));
+                let columns = (*width).min(width_limit.saturating_sub(field.x));
+                for y in 0..rows {
+                    for x in 0..columns {
+                        let border = x < *thickness
+                            || y < *thickness
+                            || x.saturating_add(*thickness) >= *width
+                            || y.saturating_add(*thickness) >= *height;
+                        if border {
+                            paint(
+                                field.x + x,
+                                field.y + y,
+                                *black,
+                                field.reverse,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if settings.mirror {
+        image::imageops::flip_horizontal_in_place(&mut canvas);
+    }
+    if settings.flip {
+        image::imageops::rotate180_in_place(&mut canvas);
+    }
+
+    Ok(canvas)
+}
+
+#[test]
+fn rasterize_rendered_label() {
+    use crate::label::{Fit, Label, LabelContent, RenderOptions};
+    use crate::length::Length;
+    use crate::util::image::ImageCompression;
+
+    let pattern =
+        GrayImage::from_fn(20, 12, |x, y| match (x / 3 + y / 2) % 2 {
+            0 => BLACK,
+            _ => WHITE,
+        });
+
+    let mut label = Label::new(10.0, 5.0, 8);
+    label.content.push(LabelContent::Image {
+        img: pattern.into(),
+        x: Length::dots(9),
+        y: Length::dots(5),
+        w: Length::dots(20),
+        h: Length::dots(12),
+        fit: Fit::Stretch,
+    });
+    let expected = label.preview().unwrap().into_luma8();
+    // Content alone, without the format around it setting the size.
+    let size = (label.width_dots(), label.height_dots());
+
+    for compression in [
+        ImageCompression::AsciiHex,
+        ImageCompression::CompressedHex,
+        ImageCompression::Base64,
+        ImageCompression::Z64,
+    ] {
+        let options = RenderOptions {
+            compression,
+            ..Default::default()
+        };
+        let commands = label.render_with(&options).unwrap();
+        let pages = rasterize_commands(&commands, size).unwrap();
+        assert!(pages == [expected.clone()], "{compression:?}");
+    }
+
+    let boxed = rasterize(
+        "^XA^PW10^LL6^FO1,1^GB5,4,1^FS^FR^FO0,0^GB3,3,3^FS^XZ",
+        (0, 0),
+    )
+    .unwrap();
+    let dark: Vec<_> = boxed[0]
+        .enumerate_pixels()
+        .filter(|(_, _, p)| **p == BLACK)
+        .map(|(x, y, _)| (x, y))
+        .collect();
+    assert_eq!(
+        dark,
+        [
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (0, 1),
+            (3, 1),
+            (4, 1),
+            (5, 1),
+            (0, 2),
+            (2, 2),
+            (5, 2),
+            (1, 3),
+            (5, 3),
+            (1, 4),
+            (2, 4),
+            (3, 4),
+            (4, 4),
+            (5, 4)
+        ]
+    );
+}
@@ -0,0 +1,182 @@
+//! Guard label designs against changes in how they print, by comparing the picture of the
+//! graphics sent to the printer with a reference picture kept next to the tests.
+//!
+//! ```no_run
+//! use zpl::label::Label;
+//! use zpl::testing::{assert_golden, Tolerance};
+//!
+//! let label = Label::new(50.0, 25.0, 8);
+//! assert_golden(&label, "tests/golden/shipping.png", Tolerance::default());
+//! ```
+//!
+//! References that do not exist yet are written from the label. Setting `ZPL_UPDATE_GOLDEN`
+//! rewrites all of them, after an intended change. On a mismatch, the picture of the label
+//! and one of the difference are written next to the reference, as `.actual.png` and
+//! `.diff.png`.
+use std::path::{Path, PathBuf};
+
+use image::{GrayImage, Rgb, RgbImage};
+
+use crate::label::{Label, RenderOptions};
+use crate::render::zpl_rasterizer;
+
+/// Set to rewrite all references from the labels tested.
+pub const UPDATE_VARIABLE: &str = "ZPL_UPDATE_GOLDEN";
+
+/// How far a picture may stray from its reference and still match.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tolerance {
+    /// The difference in brightness of a dot, out of 255, that is not counted.
+    pub luma: u8,
+    /// The dots allowed to differ.
+    pub dots: u64,
+}
+
+/// The dots that differ between two pictures of the same size.
+pub struct Difference {
+    pub differing: u64,
+    /// The reference faded, with the dots that differ in red.
+    pub image: RgbImage,
+}
+
+/// The graphics of a label as the printer receives them, drawn from the commands.
+///
+/// SVG documents are set in the bundled font, such that the picture is the same on every
+/// machine. Fields the printer renders itself, such as text and barcodes, are left out.
+pub fn rasterize(label: &Label) -> anyhow::Result<GrayImage> {
+    let options = RenderOptions {
+        deterministic: true,
+        ..Default::default()
+    };
+    let commands = label.render_with(&options)?;
+    let size = (label.width_dots(), label.height_dots());
+
+    let page = zpl_rasterizer::rasterize_commands(&commands, size)?
+        .into_iter()
+        .next();
+    Ok(page.unwrap_or_else(|| {
+        GrayImage::from_pixel(size.0, size.1, image::Luma([255]))
+    }))
+}
+
+/// Compare a picture with its reference, `None` if their sizes differ.
+pub fn compare(
+    actual: &GrayImage,
+    expected: &GrayImage,
+    tolerance: Tolerance,
+) -> Option<Difference> {
+    if actual.dimensions() != expected.dimensions() {
+        return None;
+    }
+
+    let mut differing = 0;
+    let image = RgbImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let (a, e) =
+            (actual.get_pixel(x, y).0[0], expected.get_pixel(x, y).0[0]);
+        if a.abs_diff(e) > tolerance.luma {
+            differing += 1;
+            Rgb([255, 0, 0])
+        } else {
+            let faded = 192 + e / 4;
+            Rgb([faded, faded, faded])
+        }
+    });
+
+    Some(Difference { differing, image })
+}
+
+/// Check a label against its reference picture, see the [module](self).
+pub fn check(
+    label: &Label,
+    reference: impl AsRef<Path>,
+    tolerance: Tolerance,
+) -> anyhow::Result<()> {
+    let reference = reference.as_ref();
+    let actual = rasterize(label)?;
+
+    if std::env::var_os(UPDATE_VARIABLE).is_some() || !reference.exists() {
+        if let Some(parent) = reference.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        actual.save(reference)?;
+        return Ok(());
+    }
+
+    let expected = image::open(reference)?.into_luma8();
+    let mismatch = match compare(&actual, &expected, tolerance) {
+        Some(difference) if difference.differing <= tolerance.dots => {
+            return Ok(())
+        }
+        Some(difference) => {
+            difference.image.save(sibling(reference, "diff"))?;
+            format!("{} dots differ", difference.differing)
+        }
+        None => format!(
+            "the label is {:?} dots, the reference {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        ),
+    };
+
+    let written = sibling(reference, "actual");
+    actual.save(&written)?;
+    anyhow::bail!(
+        "Label does not match {}: {mismatch}, see {}",
+        reference.display(),
+        written.display()
+    )
+}
+
+/// Panic unless a label matches its reference picture, see [`check`].
+#[track_caller]
+pub fn assert_golden(
+    label: &Label,
+    reference: impl AsRef<Path>,
+    tolerance: Tolerance,
+) {
+    if let Err(error) = check(label, reference, tolerance) {
+        panic!("{error}");
+    }
+}
+
+/// A picture next to the reference, `label.png` becoming `label.{kind}.png`.
+fn sibling(reference: &Path, kind: &str) -> PathBuf {
+    reference.with_extension(format!("{kind}.png"))
+}
+
+#[test]
+fn golden_mismatch() {
+    use crate::label::{Fit, LabelContent};
+    use crate::length::Length;
+
+    let dir =
+        std::env::temp_dir().join(format!("zpl-golden-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let reference = dir.join("square.png");
+
+    let mut label = Label::new(4.0, 4.0, 8);
+    label.content.push(LabelContent::Image {
+        img: GrayImage::new(8, 8).into(),
+        x: Length::dots(8),
+        y: Length::dots(8),
+        w: Length::dots(8),
+        h: Length::dots(8),
+        fit: Fit::Stretch,
+    });
+
+    // Written on first use, then matched.
+    check(&label, &reference, Tolerance::default()).unwrap();
+    check(&label, &reference, Tolerance::default()).unwrap();
+
+    if let LabelContent::Image { x, .. } = &mut label.content[0] {
+        *x = Length::dots(9);
+    }
+    let error = check(&label, &reference, Tolerance::default()).unwrap_err();
+    assert!(error.to_string().contains("16 dots differ"), "{error}");
+    assert!(dir.join("square.diff.png").exists());
+
+    let tolerance = Tolerance { luma: 0, dots: 16 };
+    check(&label, &reference, tolerance).unwrap();
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
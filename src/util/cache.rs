@@ -0,0 +1,141 @@
+//! Rasterized content kept across renders, such that repeated logos and templates are only
+//! dithered and encoded once.
+//!
+//! Entries are keyed by a hash of everything that goes into them: the content, its size and
+//! position on the label, and the options it was rendered with. See [`crate::label::Label`].
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::util::image::SerializedImage;
+
+/// An encoded content item, at its position in the frame of the printer.
+#[derive(Clone, Debug)]
+pub struct CachedRaster {
+    pub x: u32,
+    pub y: u32,
+    pub image: SerializedImage,
+    /// The lowest row inked, for labels as long as their content.
+    pub inked: u32,
+}
+
+/// The least recently used entries are dropped beyond a capacity.
+pub struct RasterCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Each raster with the tick it was last used at.
+    rasters: HashMap<u64, (u64, CachedRaster)>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+/// How well the cache works, for monitoring.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct CacheStatistics {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl RasterCache {
+    pub fn new(capacity: usize) -> Self {
+        RasterCache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    pub fn get(&self, key: u64) -> Option<CachedRaster> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        match entries.rasters.get_mut(&key) {
+            Some((used, raster)) => {
+                *used = tick;
+                let raster = raster.clone();
+                entries.hits += 1;
+                Some(raster)
+            }
+            None => {
+                entries.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: u64, raster: CachedRaster) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.rasters.insert(key, (tick, raster));
+
+        while entries.rasters.len() > self.capacity {
+            let oldest = entries
+                .rasters
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(key, _)| *key);
+
+            if let Some(oldest) = oldest {
+                entries.rasters.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forget all rasters, e.g. when the fonts they were rendered with changed.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().rasters.clear();
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        let entries = self.entries.lock().unwrap();
+        CacheStatistics {
+            entries: entries.rasters.len(),
+            capacity: self.capacity,
+            hits: entries.hits,
+            misses: entries.misses,
+        }
+    }
+}
+
+impl std::fmt::Debug for RasterCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RasterCache")
+            .field("statistics", &self.statistics())
+            .finish()
+    }
+}
+
+#[test]
+fn least_recently_used_dropped() {
+    let raster = |x| CachedRaster {
+        x,
+        y: 0,
+        image: SerializedImage::from_image(&::image::DynamicImage::new_luma8(
+            8, 1,
+        )),
+        inked: 0,
+    };
+
+    let cache = RasterCache::new(2);
+    cache.insert(1, raster(1));
+    cache.insert(2, raster(2));
+    assert!(cache.get(1).is_some());
+    cache.insert(3, raster(3));
+
+    assert!(cache.get(2).is_none());
+    assert_eq!(cache.get(1).map(|raster| raster.x), Some(1));
+    assert_eq!(cache.get(3).map(|raster| raster.x), Some(3));
+
+    let statistics = cache.statistics();
+    assert_eq!((statistics.entries, statistics.hits), (2, 3));
+}
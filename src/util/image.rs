@@ -1,9 +1,15 @@
-use std::sync::Arc;
+use std::{io::Write, sync::Arc};
 
+use base64::prelude::*;
 use image::{self, imageops};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
-use crate::util::svg;
+use crate::{
+    command::HostIdentification,
+    label::{BoundingBox, Emphasis},
+    util::svg,
+};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct SerializedImage {
@@ -11,52 +17,116 @@ pub struct SerializedImage {
     pub total_field_count: u32,
     pub bytes_per_row: u32,
     pub data: Arc<str>,
+    /// Dots printed black.
+    pub inked_dots: u64,
+}
+
+/// How image data is written into a `^GF` command.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize,
+)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum ImageCompression {
+    /// Two hex digits per byte, understood by all printers and passed intact by all
+    /// print servers, but the largest.
+    #[default]
+    AsciiHex,
+    /// Hex digits with runs of the same digit counted, and blank or repeated rows
+    /// abbreviated.
+    CompressedHex,
+    /// Base64 with a checksum (`:B64:`).
+    Base64,
+    /// Deflated and then Base64 with a checksum (`:Z64:`), the smallest.
+    Z64,
+}
+
+impl ImageCompression {
+    /// This compression, if the printer's firmware supports it, or the closest one it does.
+    ///
+    /// Base64 encodings arrived with firmware x.14, printers of unknown version are assumed
+    /// to be recent.
+    pub fn supported_by(self, identification: &HostIdentification) -> Self {
+        let minor = identification
+            .version
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .split('.')
+            .nth(1)
+            .and_then(|minor| minor.parse::<u32>().ok());
+
+        match (self, minor) {
+            (Self::Base64 | Self::Z64, Some(minor)) if minor < 14 => {
+                Self::CompressedHex
+            }
+            _ => self,
+        }
+    }
 }
 
 impl SerializedImage {
     pub fn from_image(img: &image::DynamicImage) -> Self {
+        Self::with_compression(img, ImageCompression::AsciiHex)
+    }
+
+    pub fn with_compression(
+        img: &image::DynamicImage,
+        compression: ImageCompression,
+    ) -> Self {
         let mut img = img.grayscale().into_luma8();
 
         imageops::dither(&mut img, &imageops::BiLevel);
 
-        let data = img
+        let bytes_per_row = img.width().div_ceil(8);
+        let total_field_count = bytes_per_row * img.height();
+
+        // One bit per pixel, set for black, each row padded to full bytes.
+        let bytes = img
             .pixels()
             .chunks(img.width() as usize)
             .into_iter()
-            .map(|row| {
-                // Take groups of 4 pixels, turning them into a single byte value using the lower 4
-                // bits of each such byte turn it into hex value.
-                let output = row
-                    .chunks(4)
+            .flat_map(|row| {
+                row.chunks(8)
                     .into_iter()
-                    .map(|quad| {
-                        quad.zip([8, 4, 2, 1])
-                            .map(|(luma, b)| (luma.0[0] < 128) as i32 * b)
-                            .sum()
+                    .map(|octet| {
+                        octet
+                            .zip((0..8).rev())
+                            .map(|(luma, bit)| ((luma.0[0] < 128) as u8) << bit)
+                            .sum::<u8>()
                     })
-                    .map(|p: i32| format!("{:x}", p))
-                    .collect::<Vec<String>>()
-                    .concat();
-                // Append another 0 texel group for somewhat unknown reasons
-                format!(
-                    "{output}{}",
-                    if output.len() % 2 == 0 { "" } else { "0" }
-                )
+                    .collect::<Vec<_>>()
             })
-            .collect::<Vec<String>>()
-            // ... missing grouping ...
-            .concat();
+            .collect::<Vec<u8>>();
 
-        let bytes_per_row = (img.width() + 7) / 8;
-        let total_field_count = bytes_per_row * img.height();
-        let byte_count = total_field_count * 2;
+        let (byte_count, data) = match compression {
+            ImageCompression::AsciiHex => (total_field_count * 2, hex(&bytes)),
+            ImageCompression::CompressedHex => (
+                total_field_count,
+                compress_hex(&bytes, bytes_per_row as usize),
+            ),
+            ImageCompression::Base64 => {
+                (total_field_count, with_crc(":B64:", &bytes))
+            }
+            ImageCompression::Z64 => {
+                let mut encoder = flate2::write::ZlibEncoder::new(
+                    vec![],
+                    flate2::Compression::best(),
+                );
+                // Writing to memory does not fail.
+                encoder.write_all(&bytes).unwrap();
+                let deflated = encoder.finish().unwrap();
+                (total_field_count, with_crc(":Z64:", &deflated))
+            }
+        };
+
+        let inked_dots =
+            bytes.iter().map(|byte| u64::from(byte.count_ones())).sum();
 
-        //format!("^GFA,{byte_count},{total_field_count},{bytes_per_row},{data}^FS")
         SerializedImage {
             byte_count,
             total_field_count,
             bytes_per_row,
             data: data.into(),
+            inked_dots,
         }
     }
 
@@ -78,3 +148,286 @@ impl SerializedImage {
         Ok(Self::from_image(&img))
     }
 }
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// ZPL's run length encoding of hex digits.
+///
+/// Counts precede the digit they repeat, `G` to `Y` for 1 to 19 and `g` to `z` for 20 to
+/// 400 in steps of 20, summed up. A row ending in zeros ends with `,`, one repeating the
+/// previous row is replaced by `:`.
+fn compress_hex(bytes: &[u8], bytes_per_row: usize) -> String {
+    fn count(mut n: usize, out: &mut String) {
+        while n >= 400 {
+            out.push('z');
+            n -= 400;
+        }
+        if n >= 20 {
+            out.push((b'f' + (n / 20) as u8) as char);
+            n %= 20;
+        }
+        if n > 0 {
+            out.push((b'F' + n as u8) as char);
+        }
+    }
+
+    let mut out = String::new();
+    let mut previous = None;
+
+    for row in bytes.chunks(bytes_per_row.max(1)) {
+        if previous == Some(row) {
+            out.push(':');
+            continue;
+        }
+        previous = Some(row);
+
+        let digits = hex(row);
+        let digits = match digits.trim_end_matches('0') {
+            trimmed if trimmed.len() < digits.len() => trimmed,
+            _ => &digits,
+        };
+
+        for (digit, run) in &digits.chars().chunk_by(|c| *c) {
+            let run = run.count();
+            if run > 1 {
+                count(run, &mut out);
+            }
+            out.push(digit);
+        }
+
+        if digits.len() < row.len() * 2 {
+            out.push(',');
+        }
+    }
+
+    out
+}
+
+/// Base64 encoded data, framed by its prefix and a CRC-16 of the encoded text.
+fn with_crc(prefix: &str, data: &[u8]) -> String {
+    let encoded = BASE64_STANDARD.encode(data);
+
+    // CRC-16/XMODEM, polynomial 0x1021 starting from zero.
+    let crc = encoded.bytes().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    });
+
+    format!("{prefix}{encoded}:{crc:04X}")
+}
+
+/// Draw the edges of an area in gray, clipped to the image.
+pub fn outline(img: &mut image::GrayImage, area: &BoundingBox) {
+    const EDGE: image::Luma<u8> = image::Luma([128]);
+
+    let (width, height) = img.dimensions();
+    let right = (area.x + area.width).min(width);
+    let bottom = (area.y + area.height).min(height);
+
+    for y in area.y.min(height)..bottom {
+        for x in area.x.min(width)..right {
+            let edge = x == area.x
+                || y == area.y
+                || x + 1 == area.x + area.width
+                || y + 1 == area.y + area.height;
+
+            if edge {
+                img.put_pixel(x, y, EDGE);
+            }
+        }
+    }
+}
+
+/// Darken an image for [`Emphasis`], keeping its size.
+pub fn emphasize(
+    img: &image::DynamicImage,
+    emphasis: Emphasis,
+) -> image::DynamicImage {
+    let img = img.to_luma8();
+
+    // The darkest pixel of a window around each pixel, in each direction.
+    let (left, right) = match emphasis {
+        Emphasis::DoubleStrike => (1, 0),
+        Emphasis::Thicken { dots } => (dots, dots),
+    };
+    let vertical = match emphasis {
+        Emphasis::DoubleStrike => 0,
+        Emphasis::Thicken { dots } => dots,
+    };
+
+    let (width, height) = img.dimensions();
+    let rows = image::GrayImage::from_fn(width, height, |x, y| {
+        let from = x.saturating_sub(left);
+        let to = x.saturating_add(right).min(width - 1);
+        (from..=to)
+            .map(|x| *img.get_pixel(x, y))
+            .min_by_key(|p| p.0)
+            .unwrap()
+    });
+
+    let out = image::GrayImage::from_fn(width, height, |x, y| {
+        let from = y.saturating_sub(vertical);
+        let to = y.saturating_add(vertical).min(height - 1);
+        (from..=to)
+            .map(|y| *rows.get_pixel(x, y))
+            .min_by_key(|p| p.0)
+            .unwrap()
+    });
+
+    out.into()
+}
+
+/// A PDF document of a single page showing the picture of a label at its physical size.
+pub fn pdf_page(img: &image::GrayImage, dpmm: u32) -> Vec<u8> {
+    let (width, height) = img.dimensions();
+    let points = |dots: u32| dots as f32 / dpmm.max(1) as f32 * 72.0 / 25.4;
+    let (page_width, page_height) = (points(width), points(height));
+
+    let mut encoder =
+        flate2::write::ZlibEncoder::new(vec![], flate2::Compression::best());
+    // Writing to memory does not fail.
+    encoder.write_all(img.as_raw()).unwrap();
+    let pixels = encoder.finish().unwrap();
+    let contents =
+        format!("q {page_width:.2} 0 0 {page_height:.2} 0 0 cm /Im0 Do Q");
+
+    let mut out = b"%PDF-1.4\n".to_vec();
+    let mut offsets = vec![];
+    let mut object = |out: &mut Vec<u8>, dictionary: String, stream: &[u8]| {
+        offsets.push(out.len());
+        let _ = write!(out, "{} 0 obj\n{dictionary}\n", offsets.len());
+        if !stream.is_empty() {
+            out.extend_from_slice(b"stream\n");
+            out.extend_from_slice(stream);
+            out.extend_from_slice(b"\nendstream\n");
+        }
+        out.extend_from_slice(b"endobj\n");
+    };
+
+    object(&mut out, "<< /Type /Catalog /Pages 2 0 R >>".into(), &[]);
+    object(
+        &mut out,
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".into(),
+        &[],
+    );
+    object(
+        &mut out,
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {page_width:.2} {page_height:.2}] \
+             /Resources << /XObject << /Im0 4 0 R >> >> /Contents 5 0 R >>"
+        ),
+        &[],
+    );
+    object(
+        &mut out,
+        format!(
+            "<< /Type /XObject /Subtype /Image /Width {width} /Height {height} \
+             /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /FlateDecode /Length {} >>",
+            pixels.len()
+        ),
+        &pixels,
+    );
+    object(
+        &mut out,
+        format!("<< /Length {} >>", contents.len()),
+        contents.as_bytes(),
+    );
+
+    let xref = out.len();
+    let _ = write!(out, "xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1);
+    for offset in &offsets {
+        let _ = writeln!(out, "{offset:010} 00000 n ");
+    }
+    let _ = write!(
+        out,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+        offsets.len() + 1
+    );
+
+    out
+}
+
+/// A QR code drawn as `^BQ` draws it, without a quiet zone, each module a square of `zoom`
+/// dots.
+#[cfg(feature = "qrcode")]
+pub fn qr_code(
+    content: &str,
+    zoom: u32,
+    level: crate::label::QrErrorCorrection,
+) -> Result<image::DynamicImage, qrcode::types::QrError> {
+    use crate::label::QrErrorCorrection;
+
+    let level = match level {
+        QrErrorCorrection::Low => qrcode::EcLevel::L,
+        QrErrorCorrection::Medium => qrcode::EcLevel::M,
+        QrErrorCorrection::Quartile => qrcode::EcLevel::Q,
+        QrErrorCorrection::High => qrcode::EcLevel::H,
+    };
+    let code = qrcode::QrCode::with_error_correction_level(content, level)?;
+    let zoom = zoom.max(1);
+    let img = code
+        .render::<image::Luma<u8>>()
+        .quiet_zone(false)
+        .module_dimensions(zoom, zoom)
+        .build();
+
+    Ok(img.into())
+}
+
+#[test]
+fn emphasize_dark_pixels() {
+    let mut img = image::GrayImage::from_pixel(5, 5, image::Luma([255]));
+    img.put_pixel(2, 2, image::Luma([0]));
+    let img = image::DynamicImage::from(img);
+
+    let struck = emphasize(&img, Emphasis::DoubleStrike).into_luma8();
+    let dark = |img: &image::GrayImage| {
+        img.enumerate_pixels()
+            .filter(|(_, _, p)| p.0[0] == 0)
+            .map(|(x, y, _)| (x, y))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(dark(&struck), [(2, 2), (3, 2)]);
+
+    let thick = emphasize(&img, Emphasis::Thicken { dots: 1 }).into_luma8();
+    assert_eq!(dark(&thick).len(), 9);
+
+    let wide = emphasize(&img, Emphasis::Thicken { dots: u32::MAX });
+    assert_eq!(dark(&wide.into_luma8()).len(), 25);
+}
+
+#[test]
+fn image_compressions() {
+    // Two rows of 12 pixels, the second repeating the first.
+    let mut img = image::GrayImage::from_pixel(12, 2, image::Luma([255]));
+    for y in 0..2 {
+        for x in 0..8 {
+            img.put_pixel(x, y, image::Luma([0]));
+        }
+    }
+    let img = image::DynamicImage::from(img);
+
+    assert_eq!(
+        SerializedImage::from_image(&img).inked_dots,
+        16,
+        "Padding is not inked"
+    );
+
+    let serialize = |compression| {
+        SerializedImage::with_compression(&img, compression)
+            .data
+            .to_string()
+    };
+
+    assert_eq!(serialize(ImageCompression::AsciiHex), "ff00ff00");
+    assert_eq!(serialize(ImageCompression::CompressedHex), "Hf,:");
+    assert_eq!(serialize(ImageCompression::Base64), ":B64:/wD/AA==:98F1");
+    assert!(serialize(ImageCompression::Z64).starts_with(":Z64:eN"));
+}
@@ -1,2 +1,4 @@
+pub mod cache;
 pub mod image;
 pub mod svg;
+pub mod transform;
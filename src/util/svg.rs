@@ -11,31 +11,159 @@ quick_error! {
         /// The SVG did not parse.
         Svg(err: resvg::usvg::Error) {
             from()
+            display("{}", err)
+        }
+        /// A placeholder of a template without a value.
+        Placeholder(name: String) {
+            display("No value for placeholder {{{{{}}}}}", name)
         }
     }
 }
 
+/// A font bundled with the crate, in the public domain.
+const BUNDLED_FONT: &[u8] = include_bytes!("../../fonts/Tuffy.ttf");
+const BUNDLED_FAMILY: &str = "Tuffy";
+
 pub fn render_svg(
     svg_data: String,
     canvas_px_width: u32,
     canvas_px_height: u32,
 ) -> Result<::image::DynamicImage, Error> {
+    render_svg_with(
+        svg_data,
+        canvas_px_width,
+        canvas_px_height,
+        &system_options(),
+    )
+}
+
+/// Render an SVG document, parsed with the given options.
+pub fn render_svg_with(
+    svg_data: String,
+    canvas_px_width: u32,
+    canvas_px_height: u32,
+    options: &Options,
+) -> Result<::image::DynamicImage, Error> {
+    let rtree = Tree::from_str(&svg_data, options)?;
+    render_svg_tree(rtree, canvas_px_width, canvas_px_height)
+}
+
+/// Fill the placeholders of a template, such as `{{name}}`, by the values of their names.
+///
+/// Values are escaped as XML text, such that they can not break the document.
+pub fn fill_template(
+    template: &str,
+    value: impl Fn(&str) -> Option<String>,
+) -> Result<String, Error> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+
+        let name = rest[start + 2..start + end].trim();
+        let Some(value) = value(name) else {
+            return Err(Error::Placeholder(name.to_string()));
+        };
+
+        out.push_str(&rest[..start]);
+        for c in value.chars() {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&apos;"),
+                c => out.push(c),
+            }
+        }
+        rest = &rest[start + end + 2..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Parse an SVG document, with the fonts installed on the system.
+pub fn parse_svg(svg_data: &str) -> Result<Tree, Error> {
+    Ok(Tree::from_str(svg_data, &system_options())?)
+}
+
+/// Parsing options with the fonts installed on the system.
+#[cfg(feature = "fs")]
+pub fn system_options() -> Options<'static> {
     let mut db = fontdb::Database::new();
     db.load_system_fonts();
 
-    let options = Options {
+    Options {
         fontdb: Arc::new(db),
         ..Default::default()
-    };
+    }
+}
 
-    let rtree = Tree::from_str(&svg_data, &options).ok().unwrap();
-    render_svg_tree(rtree, canvas_px_width, canvas_px_height)
+/// Without access to the system's fonts, e.g. in a browser, text is set in the bundled font.
+#[cfg(not(feature = "fs"))]
+pub fn system_options() -> Options<'static> {
+    bundled_options()
+}
+
+/// Parsing options with only the bundled font, which stands in for every family.
+///
+/// Text comes out the same on every machine, regardless of the fonts installed.
+pub fn bundled_options() -> Options<'static> {
+    let mut db = fontdb::Database::new();
+    db.load_font_data(BUNDLED_FONT.to_vec());
+    db.set_serif_family(BUNDLED_FAMILY);
+    db.set_sans_serif_family(BUNDLED_FAMILY);
+    db.set_cursive_family(BUNDLED_FAMILY);
+    db.set_fantasy_family(BUNDLED_FAMILY);
+    db.set_monospace_family(BUNDLED_FAMILY);
+
+    Options {
+        font_family: BUNDLED_FAMILY.to_string(),
+        fontdb: Arc::new(db),
+        ..Default::default()
+    }
 }
 
 pub fn render_svg_tree(
     rtree: Tree,
     canvas_px_width: u32,
     canvas_px_height: u32,
+) -> Result<::image::DynamicImage, Error> {
+    render_tree(rtree, canvas_px_width, canvas_px_height, 1.0)
+}
+
+/// Render a document with its content shrunk to fit within it, see [`shrink_factor`].
+pub fn render_svg_tree_shrunk(
+    rtree: Tree,
+    canvas_px_width: u32,
+    canvas_px_height: u32,
+) -> Result<::image::DynamicImage, Error> {
+    let shrink = shrink_factor(&rtree);
+    render_tree(rtree, canvas_px_width, canvas_px_height, shrink)
+}
+
+/// How much the content of a document must shrink to fit within its size, `1.0` if it does.
+///
+/// Text running past the edge of a template, such as a long name, is made smaller until all of
+/// it is visible. Content is shrunk towards the top left corner of the document.
+pub fn shrink_factor(rtree: &Tree) -> f32 {
+    let size = rtree.size();
+    let bounds = rtree.root().abs_stroke_bounding_box();
+
+    let width = bounds.right().max(size.width()) - bounds.left().min(0.0);
+    let height = bounds.bottom().max(size.height()) - bounds.top().min(0.0);
+    (size.width() / width).min(size.height() / height).min(1.0)
+}
+
+fn render_tree(
+    rtree: Tree,
+    canvas_px_width: u32,
+    canvas_px_height: u32,
+    shrink: f32,
 ) -> Result<::image::DynamicImage, Error> {
     let rtree_size = rtree.size();
 
@@ -51,17 +179,30 @@ pub fn render_svg_tree(
     let offset_x = (canvas_px_width as f32 - image_px_width) / 2.0;
     let offset_y = (canvas_px_height as f32 - image_px_height) / 2.0;
 
-    if !(offset_x >= 0.0) {
+    if offset_x.is_nan() || offset_x < 0.0 {
         log::warn!("SVG Rendering Offset X non-positive: {offset_x:?}");
     }
 
-    if !(offset_y >= 0.0) {
+    if offset_y.is_nan() || offset_y < 0.0 {
         log::warn!("SVG Rendering Offset Y non-positive: {offset_y:?}");
     }
 
+    let content = match shrink < 1.0 {
+        // Content sticking out to the top or left is moved in as well.
+        true => {
+            let bounds = rtree.root().abs_stroke_bounding_box();
+            tiny_skia::Transform::from_scale(shrink, shrink).post_translate(
+                -bounds.left().min(0.0) * shrink,
+                -bounds.top().min(0.0) * shrink,
+            )
+        }
+        false => tiny_skia::Transform::identity(),
+    };
+
     resvg::render(
         &rtree,
-        tiny_skia::Transform::from_scale(scale, scale)
+        content
+            .post_scale(scale, scale)
             .post_translate(offset_x, offset_y),
         &mut pixmap.as_mut(),
     );
@@ -81,3 +222,20 @@ pub fn render_svg_tree(
 
     Ok(image)
 }
+
+#[test]
+fn template_placeholders() {
+    let value =
+        |name: &str| (name == "name").then(|| "Tom & Jerry".to_string());
+
+    assert_eq!(
+        fill_template("<text>{{ name }}</text>", value).unwrap(),
+        "<text>Tom &amp; Jerry</text>"
+    );
+    assert_eq!(
+        fill_template("<text>{{other}}</text>", value)
+            .unwrap_err()
+            .to_string(),
+        "No value for placeholder {{other}}"
+    );
+}
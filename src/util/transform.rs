@@ -0,0 +1,169 @@
+//! Transformations of a rasterized label, applied one after another.
+//!
+//! Each works on a greyscale picture at the resolution of the printer, such that printers with
+//! quirks of their own can be given a pipeline of fixes after content was rendered.
+use std::path::PathBuf;
+
+use image::{imageops, GrayImage, Luma};
+use serde::{Deserialize, Serialize};
+
+use crate::length::Length;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Transform {
+    /// Turn the picture clockwise, by 90 degree steps.
+    ///
+    /// Quarter turns swap width and height, so they only apply to square labels.
+    Rotate { degrees: u32 },
+    /// Swap black and white.
+    Invert,
+    /// Print dots darker than the level black and all others white.
+    Threshold { level: u8 },
+    /// Draw a picture over the label, in its size in dots, at a position in mm.
+    Overlay {
+        path: PathBuf,
+        #[serde(default)]
+        x: f32,
+        #[serde(default)]
+        y: f32,
+    },
+    /// Blank the edges of the label, in mm, where the printer does not print reliably.
+    MarginClip {
+        #[serde(default)]
+        left: f32,
+        #[serde(default)]
+        right: f32,
+        #[serde(default)]
+        top: f32,
+        #[serde(default)]
+        bottom: f32,
+    },
+}
+
+impl Transform {
+    pub fn apply(
+        &self,
+        image: GrayImage,
+        dpmm: u32,
+    ) -> anyhow::Result<GrayImage> {
+        let mm = |mm: f32| Length::mm(mm).to_dots(dpmm);
+
+        Ok(match *self {
+            Transform::Rotate { degrees } => match degrees % 360 {
+                0 => image,
+                180 => imageops::rotate180(&image),
+                90 | 270 if image.width() != image.height() => {
+                    anyhow::bail!(
+                        "Can only rotate square labels by {degrees} degrees"
+                    )
+                }
+                90 => imageops::rotate90(&image),
+                270 => imageops::rotate270(&image),
+                _ => anyhow::bail!(
+                    "Can only rotate by multiples of 90 degrees, not {degrees}"
+                ),
+            },
+            Transform::Invert => {
+                let mut image = image;
+                imageops::invert(&mut image);
+                image
+            }
+            Transform::Threshold { level } => {
+                let mut image = image;
+                for pixel in image.pixels_mut() {
+                    pixel.0[0] = if pixel.0[0] < level { 0 } else { 255 };
+                }
+                image
+            }
+            #[cfg(not(feature = "fs"))]
+            Transform::Overlay { ref path, .. } => anyhow::bail!(
+                "Cannot load overlay {} without file access",
+                path.display()
+            ),
+            #[cfg(feature = "fs")]
+            Transform::Overlay { ref path, x, y } => {
+                let picture = image::open(path)
+                    .map_err(|error| {
+                        anyhow::anyhow!(
+                            "Failed to load overlay {}: {error}",
+                            path.display()
+                        )
+                    })?
+                    .into_luma8();
+
+                let mut image = image;
+                let (left, top) = (mm(x), mm(y));
+                for (px, py, pixel) in picture.enumerate_pixels() {
+                    let (Some(x), Some(y)) =
+                        (left.checked_add(px), top.checked_add(py))
+                    else {
+                        continue;
+                    };
+
+                    if x < image.width() && y < image.height() {
+                        let below = image.get_pixel_mut(x, y);
+                        below.0[0] = below.0[0].min(pixel.0[0]);
+                    }
+                }
+                image
+            }
+            Transform::MarginClip {
+                left,
+                right,
+                top,
+                bottom,
+            } => {
+                let (width, height) = image.dimensions();
+                let xs = mm(left)..width.saturating_sub(mm(right));
+                let ys = mm(top)..height.saturating_sub(mm(bottom));
+
+                let mut image = image;
+                for (x, y, pixel) in image.enumerate_pixels_mut() {
+                    if !xs.contains(&x) || !ys.contains(&y) {
+                        *pixel = Luma([255]);
+                    }
+                }
+                image
+            }
+        })
+    }
+}
+
+/// Apply all transformations in order.
+pub fn apply(
+    image: GrayImage,
+    transforms: &[Transform],
+    dpmm: u32,
+) -> anyhow::Result<GrayImage> {
+    transforms
+        .iter()
+        .try_fold(image, |image, transform| transform.apply(image, dpmm))
+}
+
+#[test]
+fn transform_pipeline() {
+    let mut image = GrayImage::from_pixel(16, 8, Luma([255]));
+    image.put_pixel(0, 0, Luma([100]));
+
+    let pipeline: Vec<Transform> = serde_json::from_str(
+        r#"[{"rotate": {"degrees": 180}}, {"threshold": {"level": 128}}]"#,
+    )
+    .unwrap();
+    let image = apply(image, &pipeline, 8).unwrap();
+    assert_eq!(image.get_pixel(15, 7).0[0], 0);
+    assert_eq!(image.get_pixel(0, 0).0[0], 255);
+
+    let clip = Transform::MarginClip {
+        left: 0.0,
+        right: 1.0,
+        top: 0.0,
+        bottom: 0.0,
+    };
+    let image = apply(image, &[Transform::Invert, clip], 8).unwrap();
+    assert_eq!(image.get_pixel(0, 0).0[0], 0);
+    assert_eq!(image.get_pixel(8, 0).0[0], 255);
+
+    let rotate = Transform::Rotate { degrees: 90 };
+    assert!(rotate.apply(image, 8).is_err());
+}